@@ -0,0 +1,1447 @@
+use super::permission::{self, PermissionDecision};
+use super::{AgentEvent, AgentState, AgentType, AiAgent, ModelInfo, TurnUsage};
+use super::{RunningGuard, CANCEL_WAIT};
+use crate::channels::ChannelRegistry;
+use crate::commands::agent::PermissionPolicy;
+use crate::agent::runtime;
+use crate::config::AcpAgentConfig;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::{broadcast, oneshot, watch, Mutex, RwLock};
+use tracing::{error, info, warn};
+
+// 每個 ACP agent（以 AgentType 字串區分）共用一個進程；結束後下一次取用時會重新啟動。
+// 每個 kind 各有一把鎖，啟動中的 agent 不會擋住其他 kind
+type RuntimeSlot = Arc<Mutex<Option<Arc<AcpRuntime>>>>;
+static ACP_RUNTIMES: Mutex<BTreeMap<String, RuntimeSlot>> = Mutex::const_new(BTreeMap::new());
+
+/// 啟動一個 ACP agent 所需的命令列
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcpCommand {
+    /// AgentType 字串（`copilot`、`acp:<name>`），也作為模型的 provider
+    pub kind: String,
+    /// log 與錯誤訊息中的名稱
+    pub label: String,
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+}
+
+impl AcpCommand {
+    pub fn from_config(name: &str, config: &AcpAgentConfig) -> Self {
+        Self {
+            kind: AgentType::Acp(name.to_string()).to_string(),
+            label: config.label(name),
+            program: runtime::resolve_binary_path(config.command.trim()),
+            args: config.args.clone(),
+            env: config.env.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct SessionInfoCache {
+    models: Vec<ModelInfo>,
+    current_model: Option<String>,
+    // usage_update 回報的 session 累計花費
+    cost: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+struct SessionBootstrap {
+    session_id: String,
+    info: SessionInfoCache,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SessionUpdateAction {
+    MessageUpdate {
+        thinking: String,
+        text: String,
+        is_delta: bool,
+        id: Option<String>,
+    },
+    ToolStart {
+        id: String,
+        name: String,
+    },
+    ToolUpdate {
+        id: String,
+        output: String,
+    },
+    Usage {
+        cost: f64,
+    },
+    Ignore,
+}
+
+struct AcpRuntime {
+    command: AcpCommand,
+    stdin: Mutex<ChildStdin>,
+    child: Mutex<Child>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<anyhow::Result<Value>>>>,
+    session_senders: RwLock<HashMap<String, broadcast::Sender<AgentEvent>>>,
+    session_channels: RwLock<HashMap<String, u64>>,
+    session_info: RwLock<HashMap<String, SessionInfoCache>>,
+    // 等待使用者回應的權限請求 token，session 被取消時一併結束
+    session_permissions: Mutex<HashMap<String, Vec<String>>>,
+    next_id: AtomicU64,
+    // stdout 關閉（進程結束）後填入
+    exit_reason: std::sync::Mutex<Option<String>>,
+    // 權限策略與模型選擇所在的頻道設定
+    channels: Arc<ChannelRegistry>,
+}
+
+/// 關機時結束所有 ACP 進程，回傳被結束的 kind
+pub async fn shutdown_runtimes() -> Vec<String> {
+    let slots = std::mem::take(&mut *ACP_RUNTIMES.lock().await);
+    let mut stopped = Vec::new();
+    for (kind, slot) in slots {
+        let Some(runtime) = slot.lock().await.take() else {
+            continue;
+        };
+        if runtime.exit_reason().is_none() {
+            let _ = runtime.child.lock().await.start_kill();
+            stopped.push(kind);
+        }
+    }
+    stopped
+}
+
+impl AcpRuntime {
+    async fn get(
+        command: &AcpCommand,
+        channels: &Arc<ChannelRegistry>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let slot = Arc::clone(
+            ACP_RUNTIMES
+                .lock()
+                .await
+                .entry(command.kind.clone())
+                .or_default(),
+        );
+        let mut current = slot.lock().await;
+        if let Some(runtime) = current.as_ref() {
+            if runtime.exit_reason().is_none() {
+                if &runtime.command == command {
+                    return Ok(Arc::clone(runtime));
+                }
+                // 設定改了（例如 reload 後）：結束舊進程，其 session 會由 supervisor 以新命令接回
+                info!("🔁 {} ACP command changed, restarting", command.label);
+                let _ = runtime.child.lock().await.start_kill();
+            }
+        }
+        let runtime = Self::spawn(command.clone(), Arc::clone(channels)).await?;
+        if let Err(e) = runtime
+            .request("initialize", json!({ "protocolVersion": 1 }))
+            .await
+        {
+            let _ = runtime.child.lock().await.start_kill();
+            return Err(e);
+        }
+        *current = Some(Arc::clone(&runtime));
+        Ok(runtime)
+    }
+
+    fn exit_reason(&self) -> Option<String> {
+        self.exit_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// stdout 關閉代表 ACP 進程已結束：記錄原因，並讓等待中的請求立即失敗
+    async fn mark_exited(&self) {
+        let reason = {
+            let mut child = self.child.lock().await;
+            match tokio::time::timeout(Duration::from_secs(1), child.wait()).await {
+                Ok(Ok(status)) => format!("exited with {}", status),
+                _ => "stdout closed".to_string(),
+            }
+        };
+        error!("❌ {} ACP {}", self.command.label, reason);
+        *self.exit_reason.lock().unwrap_or_else(|e| e.into_inner()) = Some(reason.clone());
+        for (_, tx) in self.pending.lock().await.drain() {
+            let _ = tx.send(Err(anyhow::anyhow!("{} ACP {}", self.command.label, reason)));
+        }
+    }
+
+    async fn spawn(
+        command: AcpCommand,
+        channels: Arc<ChannelRegistry>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let current_path = std::env::var("PATH").unwrap_or_default();
+        let mut cmd = Command::new(&command.program);
+        // 工具權限一律經由 session/request_permission 依頻道策略決定
+        cmd.args(&command.args)
+            .env("PATH", runtime::build_augmented_path(&current_path))
+            .envs(&command.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = cmd.spawn()?;
+        let label = command.label.clone();
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("{} ACP stdin not available", label))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("{} ACP stdout not available", label))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("{} ACP stderr not available", label))?;
+
+        let runtime = Arc::new(Self {
+            command,
+            stdin: Mutex::new(stdin),
+            child: Mutex::new(child),
+            pending: Mutex::new(HashMap::new()),
+            session_senders: RwLock::new(HashMap::new()),
+            session_channels: RwLock::new(HashMap::new()),
+            session_info: RwLock::new(HashMap::new()),
+            session_permissions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            exit_reason: std::sync::Mutex::new(None),
+            channels,
+        });
+
+        Self::spawn_stdout_reader(Arc::clone(&runtime), stdout);
+        Self::spawn_stderr_logger(runtime.command.kind.clone(), stderr);
+        info!("✅ {} ACP backend started", label);
+        Ok(runtime)
+    }
+
+    fn spawn_stdout_reader(runtime: Arc<Self>, stdout: ChildStdout) {
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(stdout);
+            let mut line = String::new();
+            while let Ok(n) = reader.read_line(&mut line).await {
+                if n == 0 {
+                    break;
+                }
+                let trimmed = line.trim();
+                if !trimmed.is_empty() {
+                    match serde_json::from_str::<Value>(trimmed) {
+                        Ok(msg) => runtime.handle_message(msg).await,
+                        Err(e) => warn!("{} ACP invalid JSON: {}", runtime.command.label, e),
+                    }
+                }
+                line.clear();
+            }
+            runtime.mark_exited().await;
+        });
+    }
+
+    fn spawn_stderr_logger(kind: String, stderr: ChildStderr) {
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(stderr);
+            let mut line = String::new();
+            while let Ok(n) = reader.read_line(&mut line).await {
+                if n == 0 {
+                    break;
+                }
+                let msg = line.trim();
+                if !msg.is_empty() {
+                    warn!("{}(acp): {}", kind, msg);
+                }
+                line.clear();
+            }
+        });
+    }
+
+    async fn ensure_alive(&self) -> anyhow::Result<()> {
+        let mut child = self.child.lock().await;
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("{} ACP exited: {}", self.command.label, status);
+        }
+        Ok(())
+    }
+
+    async fn handle_message(self: &Arc<Self>, msg: Value) {
+        if let Some(method) = msg.get("method").and_then(Value::as_str) {
+            match method {
+                "session/update" => self.handle_session_update(&msg).await,
+                "session/request_permission" => {
+                    // "ask" 可能要等使用者回應，不能卡住 stdout reader
+                    let runtime = Arc::clone(self);
+                    tokio::spawn(async move { runtime.handle_permission_request(&msg).await });
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if let Some(id) = msg.get("id").and_then(Value::as_u64) {
+            let tx = self.pending.lock().await.remove(&id);
+            if let Some(tx) = tx {
+                if let Some(err) = msg.get("error") {
+                    let _ = tx.send(Err(anyhow::anyhow!(Self::error_text(err))));
+                } else {
+                    let _ = tx.send(Ok(msg.get("result").cloned().unwrap_or(Value::Null)));
+                }
+            }
+        }
+    }
+
+    async fn handle_permission_request(&self, msg: &Value) {
+        let id = match msg.get("id").and_then(Value::as_u64) {
+            Some(v) => v,
+            None => return,
+        };
+
+        let session_id = msg["params"]["sessionId"].as_str().unwrap_or_default();
+        let channel_id = self.session_channels.read().await.get(session_id).copied();
+        let policy = match channel_id {
+            Some(ch) => self
+                .channels
+                .snapshot()
+                .get_permission_policy(&ch.to_string()),
+            None => PermissionPolicy::default(),
+        };
+
+        let decision = match policy {
+            PermissionPolicy::Auto => PermissionDecision::AllowAlways,
+            PermissionPolicy::Deny => PermissionDecision::Deny,
+            PermissionPolicy::Ask => self.ask_permission(session_id, msg).await,
+        };
+        info!(
+            "🔐 {} permission request (session {}, policy {}): {}",
+            self.command.label,
+            session_id,
+            policy,
+            decision.as_str()
+        );
+
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": Self::permission_result(msg, decision)
+        });
+        if let Err(e) = self.send_raw(&response).await {
+            warn!("Failed to respond permission request: {}", e);
+        }
+    }
+
+    /// 把請求丟給頻道的 writer 顯示按鈕，並等待回覆或逾時
+    async fn ask_permission(&self, session_id: &str, msg: &Value) -> PermissionDecision {
+        let tx = self.session_senders.read().await.get(session_id).cloned();
+        let Some(tx) = tx else {
+            return PermissionDecision::Deny;
+        };
+
+        let broker = permission::broker();
+        let (token, mut rx) = broker.register();
+        self.session_permissions
+            .lock()
+            .await
+            .entry(session_id.to_string())
+            .or_default()
+            .push(token.clone());
+        let (title, detail) = Self::permission_summary(msg);
+        let event = AgentEvent::PermissionRequest {
+            token: token.clone(),
+            title,
+            detail,
+            choices: Self::permission_choices(msg),
+        };
+        let decision = if tx.send(event).is_err() {
+            // 沒有人在看這個頻道的回覆，直接拒絕
+            broker.resolve(&token, PermissionDecision::Deny);
+            PermissionDecision::Deny
+        } else {
+            permission::wait_decision(&mut rx).await
+        };
+        if let Some(tokens) = self.session_permissions.lock().await.get_mut(session_id) {
+            tokens.retain(|t| t != &token);
+        }
+        decision
+    }
+
+    fn permission_summary(msg: &Value) -> (String, String) {
+        let tool_call = &msg["params"]["toolCall"];
+        let title = tool_call["title"]
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .unwrap_or("Tool Call")
+            .to_string();
+        let detail = if tool_call["rawInput"].is_null() {
+            String::new()
+        } else {
+            Self::value_text(&tool_call["rawInput"])
+        };
+        (title, detail)
+    }
+
+    fn permission_options(msg: &Value) -> Vec<(String, String)> {
+        msg["params"]["options"]
+            .as_array()
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|opt| {
+                        let id = opt.get("optionId")?.as_str()?.to_string();
+                        let kind = opt
+                            .get("kind")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string();
+                        Some((id, kind))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// 依 ACP option kind（或舊版以 optionId 命名的慣例）找出對應選項
+    fn permission_option_for(msg: &Value, decision: PermissionDecision) -> Option<String> {
+        let options = Self::permission_options(msg);
+        decision.option_kinds().iter().find_map(|kind| {
+            options
+                .iter()
+                .find(|(id, k)| k == kind || id.contains(kind))
+                .map(|(id, _)| id.clone())
+        })
+    }
+
+    /// 使用者可選的按鈕：有對應選項的 allow 類別，以及一律可用的 deny
+    fn permission_choices(msg: &Value) -> Vec<PermissionDecision> {
+        let mut choices: Vec<PermissionDecision> = [
+            PermissionDecision::AllowOnce,
+            PermissionDecision::AllowAlways,
+        ]
+        .into_iter()
+        .filter(|d| {
+            Self::permission_options(msg)
+                .iter()
+                .any(|(id, k)| k == d.as_str() || id.contains(d.as_str()))
+        })
+        .collect();
+        if choices.is_empty() && Self::permission_option_id(msg).is_some() {
+            choices.push(PermissionDecision::AllowOnce);
+        }
+        choices.push(PermissionDecision::Deny);
+        choices
+    }
+
+    /// 組出 JSON-RPC result。同時帶上 ACP 規格的 `outcome` 與先前使用的 `optionId` 欄位。
+    fn permission_result(msg: &Value, decision: PermissionDecision) -> Value {
+        let option_id = if decision.is_allowed() {
+            Self::permission_option_for(msg, decision).or_else(|| Self::permission_option_id(msg))
+        } else {
+            Self::permission_option_for(msg, decision)
+        };
+        match option_id {
+            Some(option_id) => json!({
+                "outcome": { "outcome": "selected", "optionId": option_id },
+                "optionId": option_id
+            }),
+            None => json!({ "outcome": { "outcome": "cancelled" } }),
+        }
+    }
+
+    fn permission_option_id(msg: &Value) -> Option<String> {
+        msg["params"]["options"].as_array().and_then(|options| {
+            options
+                .iter()
+                .find_map(|opt| {
+                    let id = opt.get("optionId")?.as_str()?;
+                    if id.contains("allow_always") {
+                        Some(id.to_string())
+                    } else {
+                        None
+                    }
+                })
+                .or_else(|| {
+                    options
+                        .iter()
+                        .find_map(|opt| opt.get("optionId")?.as_str().map(|s| s.to_string()))
+                })
+        })
+    }
+
+    async fn handle_session_update(&self, msg: &Value) {
+        let session_id = match msg["params"]["sessionId"].as_str() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let tx = {
+            let sessions = self.session_senders.read().await;
+            sessions.get(session_id).cloned()
+        };
+        let Some(tx) = tx else {
+            return;
+        };
+
+        let update = &msg["params"]["update"];
+        match Self::parse_session_update(update) {
+            SessionUpdateAction::MessageUpdate {
+                thinking,
+                text,
+                is_delta,
+                id,
+            } => {
+                let _ = tx.send(AgentEvent::MessageUpdate {
+                    thinking,
+                    text,
+                    is_delta,
+                    id,
+                });
+            }
+            SessionUpdateAction::ToolStart { id, name } => {
+                let _ = tx.send(AgentEvent::ToolExecutionStart { id, name });
+            }
+            SessionUpdateAction::ToolUpdate { id, output } => {
+                let _ = tx.send(AgentEvent::ToolExecutionUpdate { id, output });
+            }
+            SessionUpdateAction::Usage { cost } => {
+                let mut info_map = self.session_info.write().await;
+                info_map.entry(session_id.to_string()).or_default().cost = Some(cost);
+            }
+            SessionUpdateAction::Ignore => {}
+        }
+    }
+
+    fn parse_session_update(update: &Value) -> SessionUpdateAction {
+        let update_type = update["sessionUpdate"].as_str().unwrap_or("");
+        match update_type {
+            "agent_thought_chunk" => {
+                if let Some(text) = Self::update_text(update) {
+                    SessionUpdateAction::MessageUpdate {
+                        thinking: text,
+                        text: "".to_string(),
+                        is_delta: true,
+                        id: None,
+                    }
+                } else {
+                    SessionUpdateAction::Ignore
+                }
+            }
+            "agent_message_chunk" => {
+                if let Some(text) = Self::update_text(update) {
+                    SessionUpdateAction::MessageUpdate {
+                        thinking: "".to_string(),
+                        text,
+                        is_delta: true,
+                        id: None,
+                    }
+                } else {
+                    SessionUpdateAction::Ignore
+                }
+            }
+            "tool_call" => {
+                let id = update["toolCallId"].as_str().unwrap_or("tool").to_string();
+                let status = update["status"].as_str().unwrap_or("");
+                let title = update["title"]
+                    .as_str()
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| "Tool Call".to_string());
+                if status == "pending" || status == "running" {
+                    SessionUpdateAction::ToolStart { id, name: title }
+                } else {
+                    SessionUpdateAction::Ignore
+                }
+            }
+            "tool_call_update" => {
+                let id = update["toolCallId"].as_str().unwrap_or("tool").to_string();
+                let status = update["status"].as_str().unwrap_or("");
+                let output = if !update["rawOutput"].is_null() {
+                    Self::value_text(&update["rawOutput"])
+                } else {
+                    status.to_string()
+                };
+                if output.is_empty() {
+                    SessionUpdateAction::Ignore
+                } else {
+                    SessionUpdateAction::ToolUpdate { id, output }
+                }
+            }
+            "usage_update" => match update["cost"]["amount"].as_f64() {
+                Some(cost) => SessionUpdateAction::Usage { cost },
+                None => SessionUpdateAction::Ignore,
+            },
+            _ => SessionUpdateAction::Ignore,
+        }
+    }
+
+    fn update_text(update: &Value) -> Option<String> {
+        update
+            .get("content")
+            .and_then(|c| c.get("text"))
+            .and_then(Value::as_str)
+            .map(|s| s.to_string())
+            .or_else(|| {
+                update
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(|s| s.to_string())
+            })
+    }
+
+    fn value_text(value: &Value) -> String {
+        if let Some(s) = value.as_str() {
+            s.to_string()
+        } else {
+            serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+        }
+    }
+
+    fn error_text(err: &Value) -> String {
+        let message = err
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("Unknown error");
+        match err.get("data") {
+            Some(data) if !data.is_null() => format!("{}: {}", message, data),
+            _ => message.to_string(),
+        }
+    }
+
+    async fn send_raw(&self, payload: &Value) -> anyhow::Result<()> {
+        let line = serde_json::to_string(payload)?;
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn request(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        self.ensure_alive().await?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(id, tx);
+
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params
+        });
+        self.send_raw(&payload).await?;
+
+        match tokio::time::timeout(Duration::from_secs(300), rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => anyhow::bail!("ACP response channel dropped: {}", method),
+            Err(_) => {
+                self.pending.lock().await.remove(&id);
+                anyhow::bail!("ACP request timeout: {}", method);
+            }
+        }
+    }
+
+    fn parse_session_bootstrap(result: Value, provider: &str) -> anyhow::Result<SessionBootstrap> {
+        let session_id = result["sessionId"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing sessionId in ACP response"))?
+            .to_string();
+
+        let models = result["models"]["availableModels"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| {
+                        let id = m.get("modelId")?.as_str()?;
+                        let label = m
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or(id)
+                            .to_string();
+                        Some(ModelInfo {
+                            provider: provider.to_string(),
+                            id: id.to_string(),
+                            label,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let current_model = result["models"]["currentModelId"]
+            .as_str()
+            .map(|s| s.to_string());
+
+        Ok(SessionBootstrap {
+            session_id,
+            info: SessionInfoCache {
+                models,
+                current_model,
+                cost: None,
+            },
+        })
+    }
+
+    async fn create_session(&self, cwd: &str) -> anyhow::Result<SessionBootstrap> {
+        let result = self
+            .request("session/new", json!({ "cwd": cwd, "mcpServers": [] }))
+            .await?;
+        let bootstrap = Self::parse_session_bootstrap(result, &self.command.kind)?;
+        self.session_info
+            .write()
+            .await
+            .insert(bootstrap.session_id.clone(), bootstrap.info.clone());
+        Ok(bootstrap)
+    }
+
+    async fn load_session(&self, session_id: &str, cwd: &str) -> anyhow::Result<SessionBootstrap> {
+        let result = self
+            .request(
+                "session/load",
+                json!({ "sessionId": session_id, "cwd": cwd, "mcpServers": [] }),
+            )
+            .await?;
+        let bootstrap = Self::parse_session_bootstrap(result, &self.command.kind)?;
+        self.session_info
+            .write()
+            .await
+            .insert(bootstrap.session_id.clone(), bootstrap.info.clone());
+        Ok(bootstrap)
+    }
+
+    async fn cached_session_info(&self, session_id: &str) -> Option<SessionInfoCache> {
+        self.session_info.read().await.get(session_id).cloned()
+    }
+
+    async fn register_session_sender(
+        &self,
+        session_id: &str,
+        channel_id: u64,
+        tx: broadcast::Sender<AgentEvent>,
+    ) {
+        self.session_senders
+            .write()
+            .await
+            .insert(session_id.to_string(), tx);
+        self.session_channels
+            .write()
+            .await
+            .insert(session_id.to_string(), channel_id);
+    }
+
+    /// 送出 prompt，回傳 ACP 的 `stopReason`（例如 `end_turn`、`cancelled`）與回應附帶的 token 用量
+    async fn prompt(
+        &self,
+        session_id: &str,
+        message: &str,
+    ) -> anyhow::Result<(Option<String>, TurnUsage)> {
+        let result = self
+            .request(
+                "session/prompt",
+                json!({
+                    "sessionId": session_id,
+                    "prompt": [{ "type": "text", "text": message }]
+                }),
+            )
+            .await?;
+        Ok((
+            result["stopReason"].as_str().map(|s| s.to_string()),
+            Self::prompt_usage(&result["usage"]),
+        ))
+    }
+
+    /// prompt 回應的 `usage`；thought token 也按輸出計費
+    fn prompt_usage(usage: &Value) -> TurnUsage {
+        let count = |key: &str| usage[key].as_u64().unwrap_or(0);
+        TurnUsage {
+            input_tokens: count("inputTokens"),
+            output_tokens: count("outputTokens") + count("thoughtTokens"),
+            cache_read_tokens: count("cachedReadTokens"),
+            cache_write_tokens: count("cachedWriteTokens"),
+            ..Default::default()
+        }
+    }
+
+    async fn session_cost(&self, session_id: &str) -> f64 {
+        self.session_info
+            .read()
+            .await
+            .get(session_id)
+            .and_then(|info| info.cost)
+            .unwrap_or(0.0)
+    }
+
+    /// `session/cancel` 是 notification，進行中的 prompt 會以 `cancelled` 結束
+    async fn cancel(&self, session_id: &str) -> anyhow::Result<()> {
+        self.ensure_alive().await?;
+        // 還在等按鈕的權限請求會卡住 prompt，先全部拒絕
+        let tokens = self
+            .session_permissions
+            .lock()
+            .await
+            .remove(session_id)
+            .unwrap_or_default();
+        let broker = permission::broker();
+        for token in tokens {
+            broker.resolve(&token, PermissionDecision::Deny);
+        }
+        self.send_raw(&json!({
+            "jsonrpc": "2.0",
+            "method": "session/cancel",
+            "params": { "sessionId": session_id }
+        }))
+        .await
+    }
+
+    async fn unregister_session(&self, session_id: &str) {
+        self.session_senders.write().await.remove(session_id);
+        self.session_channels.write().await.remove(session_id);
+        self.session_info.write().await.remove(session_id);
+        self.session_permissions.lock().await.remove(session_id);
+    }
+
+    async fn set_model(&self, session_id: &str, model_id: &str) -> anyhow::Result<()> {
+        self.request(
+            "session/set_model",
+            json!({
+                "sessionId": session_id,
+                "modelId": model_id
+            }),
+        )
+        .await?;
+
+        let mut info_map = self.session_info.write().await;
+        let entry = info_map.entry(session_id.to_string()).or_default();
+        entry.current_model = Some(model_id.to_string());
+        Ok(())
+    }
+}
+
+pub struct AcpAgent {
+    runtime: Arc<AcpRuntime>,
+    channel_id: u64,
+    // clear 會換成新的 ACP session
+    session_id: std::sync::RwLock<String>,
+    cwd: String,
+    prompt_running: watch::Sender<bool>,
+    event_tx: broadcast::Sender<AgentEvent>,
+    message_count: AtomicU64,
+    models: Arc<RwLock<Vec<ModelInfo>>>,
+    current_model: Arc<RwLock<Option<String>>>,
+}
+
+impl AcpAgent {
+    pub async fn new(
+        command: AcpCommand,
+        channel_id: u64,
+        existing_sid: Option<String>,
+        model_opt: Option<(String, String)>,
+        workdir: Option<String>,
+        channels: Arc<ChannelRegistry>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let runtime = AcpRuntime::get(&command, &channels).await?;
+        let cwd = workdir.unwrap_or_else(|| {
+            std::env::current_dir()
+                .unwrap_or_else(|_| std::path::PathBuf::from("."))
+                .to_string_lossy()
+                .to_string()
+        });
+
+        let (bootstrap, loaded_existing) = if let Some(sid) = existing_sid {
+            match runtime.load_session(&sid, &cwd).await {
+                Ok(info) => (info, true),
+                Err(e) if e.to_string().contains("already loaded") => {
+                    let cached = runtime.cached_session_info(&sid).await.unwrap_or_default();
+                    (
+                        SessionBootstrap {
+                            session_id: sid,
+                            info: cached,
+                        },
+                        true,
+                    )
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to load {} session, creating new one: {}",
+                        command.label, e
+                    );
+                    (runtime.create_session(&cwd).await?, false)
+                }
+            }
+        } else {
+            (runtime.create_session(&cwd).await?, false)
+        };
+
+        let (event_tx, _) = broadcast::channel(1000);
+        runtime
+            .register_session_sender(&bootstrap.session_id, channel_id, event_tx.clone())
+            .await;
+
+        let agent = Arc::new(Self {
+            runtime,
+            channel_id,
+            session_id: std::sync::RwLock::new(bootstrap.session_id.clone()),
+            cwd,
+            prompt_running: watch::Sender::new(false),
+            event_tx,
+            message_count: AtomicU64::new(if loaded_existing { 1 } else { 0 }),
+            models: Arc::new(RwLock::new(bootstrap.info.models.clone())),
+            current_model: Arc::new(RwLock::new(bootstrap.info.current_model.clone())),
+        });
+
+        if let Some((provider, model_id)) = model_opt {
+            if provider == command.kind && !model_id.is_empty() {
+                if let Err(e) = agent.set_model(&provider, &model_id).await {
+                    warn!("Failed to restore {} model preference: {}", command.label, e);
+                }
+            }
+        }
+
+        Ok(agent)
+    }
+
+    fn label(&self) -> &str {
+        &self.runtime.command.label
+    }
+
+    pub fn session_id(&self) -> String {
+        self.session_id
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// 依 prompt 的 stopReason 產生回合結束事件
+    fn end_event(stop_reason: Option<&str>) -> AgentEvent {
+        match stop_reason {
+            Some("cancelled") => AgentEvent::AgentEnd {
+                success: false,
+                error: Some("Cancelled".to_string()),
+            },
+            _ => AgentEvent::AgentEnd {
+                success: true,
+                error: None,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl AiAgent for AcpAgent {
+    async fn prompt(&self, message: &str) -> anyhow::Result<()> {
+        self.prompt_running.send_replace(true);
+        let _running = RunningGuard(&self.prompt_running);
+        let session_id = self.session_id();
+        let cost_before = self.runtime.session_cost(&session_id).await;
+        match self.runtime.prompt(&session_id, message).await {
+            Ok((stop_reason, mut usage)) => {
+                self.message_count.fetch_add(1, Ordering::SeqCst);
+                // usage_update 的花費是累計值，取這次 prompt 前後的差
+                let cost = self.runtime.session_cost(&session_id).await - cost_before;
+                usage.cost = cost.max(0.0);
+                if !usage.is_empty() {
+                    usage.model = self
+                        .current_model
+                        .read()
+                        .await
+                        .as_ref()
+                        .map(|m| format!("{}/{}", self.runtime.command.kind, m));
+                    let _ = self.event_tx.send(AgentEvent::Usage { usage });
+                }
+                let _ = self.event_tx.send(Self::end_event(stop_reason.as_deref()));
+                Ok(())
+            }
+            Err(e) => {
+                let err = e.to_string();
+                let _ = self.event_tx.send(AgentEvent::Error {
+                    message: err.clone(),
+                });
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: false,
+                    error: Some(err.clone()),
+                });
+                anyhow::bail!(err);
+            }
+        }
+    }
+
+    async fn set_session_name(&self, _name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_state(&self) -> anyhow::Result<AgentState> {
+        let model = self.current_model.read().await.clone();
+        Ok(AgentState {
+            message_count: self.message_count.load(Ordering::SeqCst),
+            model,
+        })
+    }
+
+    async fn compact(&self) -> anyhow::Result<()> {
+        self.runtime.prompt(&self.session_id(), "/compact").await?;
+        self.message_count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn abort(&self) -> anyhow::Result<()> {
+        self.runtime.cancel(&self.session_id()).await?;
+        let mut running = self.prompt_running.subscribe();
+        if tokio::time::timeout(CANCEL_WAIT, running.wait_for(|r| !*r))
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} prompt in channel {} did not stop within {:?} after cancel",
+                self.label(),
+                self.channel_id,
+                CANCEL_WAIT
+            );
+        }
+        Ok(())
+    }
+
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
+        // 先停掉進行中的回合，避免舊 session 的輸出混進新對話
+        if *self.prompt_running.borrow() {
+            self.abort().await?;
+        }
+
+        let old_sid = self.session_id();
+        let bootstrap = self.runtime.create_session(&self.cwd).await?;
+        let new_sid = bootstrap.session_id.clone();
+        self.runtime
+            .register_session_sender(&new_sid, self.channel_id, self.event_tx.clone())
+            .await;
+        self.runtime.unregister_session(&old_sid).await;
+        *self.session_id.write().unwrap_or_else(|e| e.into_inner()) = new_sid.clone();
+        self.message_count.store(0, Ordering::SeqCst);
+        *self.models.write().await = bootstrap.info.models;
+
+        // 新 session 會回到預設模型，沿用頻道原本選的模型
+        let selected = self.current_model.read().await.clone();
+        match selected {
+            Some(model_id) if bootstrap.info.current_model.as_ref() != Some(&model_id) => {
+                if let Err(e) = self.runtime.set_model(&new_sid, &model_id).await {
+                    warn!("Failed to keep {} model after clear: {}", self.label(), e);
+                    *self.current_model.write().await = bootstrap.info.current_model;
+                }
+            }
+            Some(_) => {}
+            None => *self.current_model.write().await = bootstrap.info.current_model,
+        }
+
+        info!(
+            "🧹 {} channel {} moved from session {} to {}",
+            self.label(),
+            self.channel_id,
+            old_sid,
+            new_sid
+        );
+        Ok(Some(new_sid))
+    }
+
+    async fn set_model(&self, provider: &str, model_id: &str) -> anyhow::Result<()> {
+        self.runtime.set_model(&self.session_id(), model_id).await?;
+        {
+            let mut current = self.current_model.write().await;
+            *current = Some(model_id.to_string());
+        }
+
+        let channel_id = self.channel_id.to_string();
+        let result = self
+            .runtime
+            .channels
+            .update(|config| {
+                if let Some(entry) = config.channels.get_mut(&channel_id) {
+                    entry.model_provider = Some(provider.to_string());
+                    entry.model_id = Some(model_id.to_string());
+                }
+                Ok(())
+            })
+            .await;
+        if let Err(e) = result {
+            error!("❌ Failed to persist {} model selection: {}", self.label(), e);
+        }
+        Ok(())
+    }
+
+    async fn set_thinking_level(&self, _level: &str) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "{} backend does not support thinking level setting",
+            self.label()
+        )
+    }
+
+    async fn get_available_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
+        let mut models = self.models.read().await.clone();
+        if models.is_empty() {
+            if let Some(info) = self.runtime.cached_session_info(&self.session_id()).await {
+                models = info.models;
+                let mut lock = self.models.write().await;
+                *lock = models.clone();
+            }
+        }
+        Ok(models)
+    }
+
+    async fn load_skill(&self, _name: &str) -> anyhow::Result<()> {
+        anyhow::bail!("{} backend does not support loading skills", self.label())
+    }
+
+    fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
+        self.event_tx.subscribe()
+    }
+
+    fn agent_type(&self) -> String {
+        self.runtime.command.kind.clone()
+    }
+
+    fn exit_reason(&self) -> Option<String> {
+        self.runtime.exit_reason()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AcpAgent, AcpCommand, AcpRuntime, PermissionDecision, SessionUpdateAction,
+    };
+    use crate::config::AcpAgentConfig;
+    use crate::agent::AgentEvent;
+    use crate::channels::ChannelRegistry;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_update_text_and_value_text_extract_text() {
+        let update = json!({
+            "content": {"text": "abc"}
+        });
+        assert_eq!(AcpRuntime::update_text(&update), Some("abc".to_string()));
+
+        let v = json!({"text":"hello"});
+        let out = AcpRuntime::value_text(&v);
+        assert!(out.contains("\"text\""));
+    }
+
+    #[test]
+    fn test_error_text_formats_object_and_string() {
+        let err_obj = json!({"message": "boom"});
+        assert_eq!(AcpRuntime::error_text(&err_obj), "boom");
+        let err_str = json!("oops");
+        assert_eq!(AcpRuntime::error_text(&err_str), "Unknown error");
+    }
+
+    #[test]
+    fn test_parse_session_bootstrap_parses_models_and_current_model() {
+        let result = json!({
+            "sessionId": "sid-1",
+            "models": {
+                "availableModels": [
+                    {"modelId":"m1","name":"M1"},
+                    {"modelId":"m2","name":"M2"}
+                ],
+                "currentModelId": "m2"
+            }
+        });
+        let parsed = AcpRuntime::parse_session_bootstrap(result, "copilot").expect("parse");
+        assert_eq!(parsed.session_id, "sid-1");
+        assert_eq!(parsed.info.models.len(), 2);
+        assert_eq!(parsed.info.current_model.as_deref(), Some("m2"));
+    }
+
+    #[test]
+    fn test_permission_option_id_prefers_allow_always() {
+        let msg = json!({
+            "params": {
+                "options": [
+                    {"optionId":"allow_once"},
+                    {"optionId":"allow_always_workspace"}
+                ]
+            }
+        });
+        assert_eq!(
+            AcpRuntime::permission_option_id(&msg).as_deref(),
+            Some("allow_always_workspace")
+        );
+    }
+
+    #[test]
+    fn test_parse_session_update_variants() {
+        let thought = json!({"sessionUpdate":"agent_thought_chunk","content":{"text":"hmm"}});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&thought),
+            SessionUpdateAction::MessageUpdate {
+                thinking: "hmm".to_string(),
+                text: "".to_string(),
+                is_delta: true,
+                id: None
+            }
+        );
+
+        let tool = json!({"sessionUpdate":"tool_call","toolCallId":"t1","status":"running","title":"Shell"});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&tool),
+            SessionUpdateAction::ToolStart {
+                id: "t1".to_string(),
+                name: "Shell".to_string()
+            }
+        );
+
+        let update = json!({"sessionUpdate":"tool_call_update","toolCallId":"t1","status":"done","rawOutput":{"ok":true}});
+        let parsed = AcpRuntime::parse_session_update(&update);
+        match parsed {
+            SessionUpdateAction::ToolUpdate { id, output } => {
+                assert_eq!(id, "t1");
+                assert!(output.contains("\"ok\""));
+            }
+            _ => panic!("expected tool update"),
+        }
+
+        let usage = json!({
+            "sessionUpdate": "usage_update",
+            "used": 10,
+            "size": 100,
+            "cost": { "amount": 0.42, "currency": "USD" }
+        });
+        assert_eq!(
+            AcpRuntime::parse_session_update(&usage),
+            SessionUpdateAction::Usage { cost: 0.42 }
+        );
+        let no_cost = json!({"sessionUpdate":"usage_update","used":10,"size":100});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&no_cost),
+            SessionUpdateAction::Ignore
+        );
+    }
+
+    #[test]
+    fn test_prompt_usage_counts_thought_tokens_as_output() {
+        let usage = AcpRuntime::prompt_usage(&json!({
+            "inputTokens": 120,
+            "outputTokens": 30,
+            "thoughtTokens": 12,
+            "cachedReadTokens": 80,
+            "totalTokens": 242
+        }));
+        assert_eq!((usage.input_tokens, usage.output_tokens), (120, 42));
+        assert_eq!((usage.cache_read_tokens, usage.cache_write_tokens), (80, 0));
+        assert!(AcpRuntime::prompt_usage(&json!(null)).is_empty());
+    }
+
+    #[test]
+    fn test_permission_option_id_fallback_and_none() {
+        let msg = json!({
+            "params": {
+                "options": [
+                    {"optionId":"allow_once"}
+                ]
+            }
+        });
+        assert_eq!(
+            AcpRuntime::permission_option_id(&msg).as_deref(),
+            Some("allow_once")
+        );
+
+        let empty = json!({"params":{"options":[]}});
+        assert!(AcpRuntime::permission_option_id(&empty).is_none());
+    }
+
+    #[test]
+    fn test_parse_session_update_ignore_paths() {
+        let non_running = json!({"sessionUpdate":"tool_call","toolCallId":"t1","status":"done"});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&non_running),
+            SessionUpdateAction::Ignore
+        );
+
+        let empty_update = json!({"sessionUpdate":"tool_call_update","toolCallId":"t1","status":"","rawOutput":null});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&empty_update),
+            SessionUpdateAction::Ignore
+        );
+
+        let unknown = json!({"sessionUpdate":"other"});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&unknown),
+            SessionUpdateAction::Ignore
+        );
+    }
+
+    #[test]
+    fn test_parse_session_update_message_chunk() {
+        let msg = json!({"sessionUpdate":"agent_message_chunk","text":"hello"});
+        assert_eq!(
+            AcpRuntime::parse_session_update(&msg),
+            SessionUpdateAction::MessageUpdate {
+                thinking: "".to_string(),
+                text: "hello".to_string(),
+                is_delta: true,
+                id: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_session_bootstrap_missing_session_id_fails() {
+        let result = json!({
+            "models": {
+                "availableModels": [],
+                "currentModelId": null
+            }
+        });
+        let err = AcpRuntime::parse_session_bootstrap(result, "copilot").expect_err("should fail");
+        assert!(err.to_string().contains("Missing sessionId"));
+    }
+
+    #[test]
+    fn test_value_text_string_passthrough_and_tool_update_status_fallback() {
+        assert_eq!(AcpRuntime::value_text(&json!("raw")), "raw");
+
+        let update = json!({
+            "sessionUpdate":"tool_call_update",
+            "toolCallId":"t2",
+            "status":"running",
+            "rawOutput":null
+        });
+        assert_eq!(
+            AcpRuntime::parse_session_update(&update),
+            SessionUpdateAction::ToolUpdate {
+                id: "t2".to_string(),
+                output: "running".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_permission_option_id_without_options_returns_none() {
+        let msg = json!({"params":{}});
+        assert!(AcpRuntime::permission_option_id(&msg).is_none());
+    }
+
+    fn acp_permission_request() -> serde_json::Value {
+        json!({
+            "params": {
+                "sessionId": "s1",
+                "toolCall": {"title": "Run shell", "rawInput": {"command": "rm -rf build"}},
+                "options": [
+                    {"optionId": "opt-1", "kind": "allow_once", "name": "Allow"},
+                    {"optionId": "opt-2", "kind": "allow_always", "name": "Always"},
+                    {"optionId": "opt-3", "kind": "reject_once", "name": "Reject"}
+                ]
+            }
+        })
+    }
+
+    #[test]
+    fn test_permission_result_maps_decisions_to_option_kinds() {
+        let msg = acp_permission_request();
+        let once = AcpRuntime::permission_result(&msg, PermissionDecision::AllowOnce);
+        assert_eq!(once["outcome"]["optionId"], "opt-1");
+        assert_eq!(once["optionId"], "opt-1");
+        let always = AcpRuntime::permission_result(&msg, PermissionDecision::AllowAlways);
+        assert_eq!(always["outcome"]["optionId"], "opt-2");
+        let denied = AcpRuntime::permission_result(&msg, PermissionDecision::TimedOut);
+        assert_eq!(denied["outcome"]["optionId"], "opt-3");
+    }
+
+    #[test]
+    fn test_permission_result_cancels_when_no_reject_option() {
+        let msg = json!({"params": {"options": [{"optionId": "allow_always"}]}});
+        let denied = AcpRuntime::permission_result(&msg, PermissionDecision::Deny);
+        assert_eq!(denied["outcome"]["outcome"], "cancelled");
+        assert!(denied.get("optionId").is_none());
+        let allowed = AcpRuntime::permission_result(&msg, PermissionDecision::AllowOnce);
+        assert_eq!(allowed["optionId"], "allow_always");
+    }
+
+    #[test]
+    fn test_permission_choices_and_summary() {
+        let msg = acp_permission_request();
+        assert_eq!(
+            AcpRuntime::permission_choices(&msg),
+            vec![
+                PermissionDecision::AllowOnce,
+                PermissionDecision::AllowAlways,
+                PermissionDecision::Deny
+            ]
+        );
+        let (title, detail) = AcpRuntime::permission_summary(&msg);
+        assert_eq!(title, "Run shell");
+        assert!(detail.contains("rm -rf build"));
+    }
+
+    #[test]
+    fn test_end_event_reports_cancelled_turns_as_failed() {
+        match AcpAgent::end_event(Some("cancelled")) {
+            AgentEvent::AgentEnd { success, error } => {
+                assert!(!success);
+                assert_eq!(error.as_deref(), Some("Cancelled"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        for reason in [Some("end_turn"), None] {
+            assert!(matches!(
+                AcpAgent::end_event(reason),
+                AgentEvent::AgentEnd {
+                    success: true,
+                    error: None
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_command_from_config_uses_acp_kind_for_models() {
+        let config = AcpAgentConfig {
+            command: "/nonexistent/gemini".into(),
+            args: vec!["--experimental-acp".into()],
+            env: [("GEMINI_API_KEY".to_string(), "k".to_string())].into(),
+            display_name: Some("Gemini CLI".into()),
+        };
+        let command = AcpCommand::from_config("gemini", &config);
+        assert_eq!(command.kind, "acp:gemini");
+        assert_eq!(command.label, "Gemini CLI");
+        assert_eq!(command.program, "/nonexistent/gemini");
+        assert_eq!(command.args, config.args);
+
+        let result = json!({
+            "sessionId": "sid-1",
+            "models": {"availableModels": [{"modelId": "pro"}]}
+        });
+        let parsed = AcpRuntime::parse_session_bootstrap(result, &command.kind).expect("parse");
+        assert_eq!(parsed.info.models[0].provider, "acp:gemini");
+        assert_eq!(parsed.info.models[0].label, "pro");
+    }
+
+    #[tokio::test]
+    async fn test_starting_agent_does_not_block_other_kinds() {
+        let command = |kind: &str, program: &str| AcpCommand {
+            kind: kind.to_string(),
+            label: kind.to_string(),
+            program: program.to_string(),
+            args: vec!["5".into()],
+            env: HashMap::new(),
+        };
+        let dir = tempfile::tempdir().expect("tempdir");
+        let channels = ChannelRegistry::in_dir(dir.path());
+        // 不回應 initialize 的進程會讓這個 kind 停在啟動中
+        let stuck = command("acp:test-stuck", "sleep");
+        let starting = tokio::spawn({
+            let channels = Arc::clone(&channels);
+            async move { AcpRuntime::get(&stuck, &channels).await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let missing = command("acp:test-missing", "/nonexistent/acp-agent");
+        let get = AcpRuntime::get(&missing, &channels);
+        let result = tokio::time::timeout(Duration::from_secs(2), get)
+            .await
+            .expect("other kinds are not blocked");
+        assert!(result.is_err());
+        starting.abort();
+    }
+}
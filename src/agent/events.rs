@@ -0,0 +1,375 @@
+use async_trait::async_trait;
+use eventsource_client::{Client, ClientBuilder, ReconnectOptions, SSE};
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, RwLock};
+use tracing::{info, warn};
+
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// 接收 server 事件的一方（一個 session 對應一個）
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn handle_event(&self, val: Value);
+    /// 連線中斷後重新接上時呼叫，讓進行中的回合補抓漏掉的內容
+    async fn resync(&self);
+}
+
+/// 重連等待時間：1s 起跳、每次加倍，最多 30s
+pub fn backoff_delay(attempt: u32) -> Duration {
+    BACKOFF_BASE
+        .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+        .unwrap_or(BACKOFF_MAX)
+        .min(BACKOFF_MAX)
+}
+
+/// 沒有 session id 時仍要送給所有 agent 的 server 層級事件；
+/// 其他沒有 session id 的事件（例如錯誤或回合結束）不知道屬於誰，直接丟掉
+const BROADCAST_EVENTS: &[&str] = &["server.connected"];
+
+/// 取出事件所屬的 session；server 層級的事件沒有 session id
+fn event_session_id(val: &Value) -> Option<&str> {
+    let properties = &val["properties"];
+    properties["sessionID"]
+        .as_str()
+        .or(properties["part"]["sessionID"].as_str())
+        .or(properties["info"]["sessionID"].as_str())
+        .or(val["data"]["sessionID"].as_str())
+}
+
+/// 一個 server（含工作目錄）共用的 `/event` SSE 訂閱，依 session id 分派給各 agent
+enum Delivery {
+    Event(Value),
+    Resync,
+}
+
+/// 一個 agent 的投遞佇列：各自依序處理，慢的 agent 不會卡住同一個 server 上的其他頻道
+struct Route {
+    sink: Weak<dyn EventSink>,
+    tx: mpsc::UnboundedSender<Delivery>,
+}
+
+impl Route {
+    fn new(sink: Weak<dyn EventSink>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let weak = sink.clone();
+        tokio::spawn(async move {
+            while let Some(delivery) = rx.recv().await {
+                let Some(sink) = weak.upgrade() else {
+                    return;
+                };
+                match delivery {
+                    Delivery::Event(val) => sink.handle_event(val).await,
+                    Delivery::Resync => sink.resync().await,
+                }
+            }
+        });
+        Self { sink, tx }
+    }
+
+    fn is_dead(&self) -> bool {
+        self.sink.strong_count() == 0 || self.tx.is_closed()
+    }
+}
+
+pub struct EventStream {
+    url: String,
+    auth_header: String,
+    routes: RwLock<HashMap<String, Route>>,
+    /// 後端換 port 後由 BackendManager 取消，背景任務不再重連舊位址
+    cancel: watch::Sender<bool>,
+}
+
+impl EventStream {
+    /// 建立並在背景開始訂閱；最後一個 Arc 被丟掉時背景任務會結束
+    pub fn start(url: String, auth_header: String) -> Arc<Self> {
+        let stream = Arc::new(Self::new(url, auth_header));
+        tokio::spawn(Self::run(Arc::downgrade(&stream)));
+        stream
+    }
+
+    fn new(url: String, auth_header: String) -> Self {
+        Self {
+            url,
+            auth_header,
+            routes: RwLock::new(HashMap::new()),
+            cancel: watch::Sender::new(false),
+        }
+    }
+
+    /// 停止訂閱；agent 仍持有這個 Arc 也不會再連線
+    pub fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancel.borrow()
+    }
+
+    async fn cancelled(&self) {
+        let mut rx = self.cancel.subscribe();
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+
+    pub async fn register(&self, session_id: &str, sink: Weak<dyn EventSink>) {
+        self.routes
+            .write()
+            .await
+            .insert(session_id.to_string(), Route::new(sink));
+    }
+
+    /// session 被換掉（例如 /clear）時，把路由搬到新的 session id
+    pub async fn reroute(&self, old_sid: &str, new_sid: &str) {
+        let mut routes = self.routes.write().await;
+        if let Some(route) = routes.remove(old_sid) {
+            routes.insert(new_sid.to_string(), route);
+        }
+    }
+
+    /// 把事件排進接收者的佇列，順便清掉已釋放的 agent
+    async fn dispatch(&self, val: Value) {
+        let broadcast = val["type"]
+            .as_str()
+            .is_some_and(|t| BROADCAST_EVENTS.contains(&t));
+        let mut dead = Vec::new();
+        {
+            let routes = self.routes.read().await;
+            let mut deliver = |sid: &String, route: &Route| {
+                if route.is_dead() || route.tx.send(Delivery::Event(val.clone())).is_err() {
+                    dead.push(sid.clone());
+                }
+            };
+            match event_session_id(&val) {
+                Some(sid) => {
+                    if let Some((sid, route)) = routes.get_key_value(sid) {
+                        deliver(sid, route);
+                    }
+                }
+                None if broadcast => routes.iter().for_each(|(sid, route)| deliver(sid, route)),
+                None => {}
+            }
+        }
+        self.prune(dead).await;
+    }
+
+    async fn resync_all(&self) {
+        let mut dead = Vec::new();
+        for (sid, route) in self.routes.read().await.iter() {
+            if route.is_dead() || route.tx.send(Delivery::Resync).is_err() {
+                dead.push(sid.clone());
+            }
+        }
+        self.prune(dead).await;
+    }
+
+    async fn prune(&self, dead: Vec<String>) {
+        if dead.is_empty() {
+            return;
+        }
+        let mut routes = self.routes.write().await;
+        for sid in dead {
+            if routes.get(&sid).is_some_and(Route::is_dead) {
+                routes.remove(&sid);
+            }
+        }
+    }
+
+    async fn run(weak: Weak<Self>) {
+        let mut attempt = 0u32;
+        let mut connected_before = false;
+        loop {
+            let Some(stream) = weak.upgrade() else {
+                return;
+            };
+            if stream.is_cancelled() {
+                return;
+            }
+            // 重連由這裡的迴圈負責，才知道何時需要 resync
+            let builder = match ClientBuilder::for_url(&stream.url) {
+                Ok(b) => b.header("Authorization", &stream.auth_header),
+                Err(e) => Err(e),
+            };
+            let client = match builder {
+                Ok(b) => b
+                    .reconnect(ReconnectOptions::reconnect(false).build())
+                    .build(),
+                Err(e) => {
+                    warn!("❌ Invalid event stream {}: {}", stream.url, e);
+                    return;
+                }
+            };
+            let mut events = client.stream();
+            loop {
+                let event = tokio::select! {
+                    event = events.next() => event,
+                    _ = stream.cancelled() => None,
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                match event {
+                    Ok(SSE::Connected(_)) => {
+                        attempt = 0;
+                        if connected_before {
+                            info!("🔌 Event stream {} reconnected", stream.url);
+                            stream.resync_all().await;
+                        }
+                        connected_before = true;
+                    }
+                    Ok(SSE::Event(e)) => {
+                        if let Ok(val) = serde_json::from_str::<Value>(&e.data) {
+                            stream.dispatch(val).await;
+                        }
+                    }
+                    Ok(SSE::Comment(_)) => {}
+                    Err(e) => {
+                        warn!("⚠️ Event stream {} dropped: {:?}", stream.url, e);
+                        break;
+                    }
+                }
+            }
+            if stream.is_cancelled() {
+                info!("🔌 Event stream {} cancelled", stream.url);
+                return;
+            }
+            drop(stream);
+            let delay = backoff_delay(attempt);
+            attempt = attempt.saturating_add(1);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<Value>>,
+        resyncs: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        async fn handle_event(&self, val: Value) {
+            self.events.lock().unwrap().push(val);
+        }
+        async fn resync(&self) {
+            *self.resyncs.lock().unwrap() += 1;
+        }
+    }
+
+    fn sink() -> (Arc<RecordingSink>, Weak<dyn EventSink>) {
+        let sink = Arc::new(RecordingSink::default());
+        let dyn_sink: Arc<dyn EventSink> = sink.clone();
+        (sink, Arc::downgrade(&dyn_sink))
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_up_to_cap() {
+        assert_eq!(backoff_delay(0), Duration::from_secs(1));
+        assert_eq!(backoff_delay(1), Duration::from_secs(2));
+        assert_eq!(backoff_delay(4), Duration::from_secs(16));
+        assert_eq!(backoff_delay(5), BACKOFF_MAX);
+        assert_eq!(backoff_delay(u32::MAX), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_event_session_id_locations() {
+        let idle = json!({"type": "session.idle", "properties": {"sessionID": "a"}});
+        let part = json!({"type": "message.part.updated",
+            "properties": {"part": {"sessionID": "b"}}});
+        let info = json!({"type": "message.updated", "properties": {"info": {"sessionID": "c"}}});
+        let data = json!({"type": "turn.end", "data": {"sessionID": "d"}});
+        assert_eq!(event_session_id(&idle), Some("a"));
+        assert_eq!(event_session_id(&part), Some("b"));
+        assert_eq!(event_session_id(&info), Some("c"));
+        assert_eq!(event_session_id(&data), Some("d"));
+        assert_eq!(event_session_id(&json!({"type": "server.connected"})), None);
+    }
+
+    /// 投遞在各 agent 自己的任務裡進行，等它們處理完
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_routes_by_session_and_broadcasts_server_events() {
+        let stream = EventStream::new("http://127.0.0.1:1/event".into(), "Bearer ".into());
+        let (a, a_weak) = sink();
+        let (b, b_weak) = sink();
+        stream.register("sa", a_weak).await;
+        stream.register("sb", b_weak).await;
+
+        stream
+            .dispatch(json!({"type": "session.idle", "properties": {"sessionID": "sa"}}))
+            .await;
+        stream
+            .dispatch(json!({"type": "session.idle", "properties": {"sessionID": "other"}}))
+            .await;
+        // 沒有 session id 的錯誤與回合結束不會波及其他頻道
+        stream.dispatch(json!({"type": "error", "data": {}})).await;
+        stream.dispatch(json!({"type": "session.idle"})).await;
+        stream.dispatch(json!({"type": "server.connected"})).await;
+        settle().await;
+        assert_eq!(a.events.lock().unwrap().len(), 2);
+        assert_eq!(b.events.lock().unwrap().len(), 1);
+
+        stream.reroute("sa", "sa2").await;
+        stream
+            .dispatch(json!({"type": "session.idle", "properties": {"sessionID": "sa"}}))
+            .await;
+        stream
+            .dispatch(json!({"type": "session.idle", "properties": {"sessionID": "sa2"}}))
+            .await;
+        stream.resync_all().await;
+        settle().await;
+        assert_eq!(a.events.lock().unwrap().len(), 3);
+        assert_eq!(*a.resyncs.lock().unwrap(), 1);
+        assert_eq!(*b.resyncs.lock().unwrap(), 1);
+    }
+
+    struct StuckSink;
+
+    #[async_trait]
+    impl EventSink for StuckSink {
+        async fn handle_event(&self, _val: Value) {
+            std::future::pending::<()>().await;
+        }
+        async fn resync(&self) {}
+    }
+
+    #[tokio::test]
+    async fn test_slow_sink_does_not_block_other_sessions() {
+        let stream = EventStream::new("http://127.0.0.1:1/event".into(), "Bearer ".into());
+        let stuck: Arc<dyn EventSink> = Arc::new(StuckSink);
+        let (a, a_weak) = sink();
+        stream.register("stuck", Arc::downgrade(&stuck)).await;
+        stream.register("sa", a_weak).await;
+
+        for sid in ["stuck", "stuck", "sa"] {
+            stream
+                .dispatch(json!({"type": "session.idle", "properties": {"sessionID": sid}}))
+                .await;
+        }
+        settle().await;
+        assert_eq!(a.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dead_sinks_are_pruned() {
+        let stream = EventStream::new("http://127.0.0.1:1/event".into(), "Bearer ".into());
+        let (a, a_weak) = sink();
+        stream.register("sa", a_weak).await;
+        drop(a);
+        stream.dispatch(json!({"type": "server.connected"})).await;
+        assert!(stream.routes.read().await.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+use super::manager::ServerEndpoint;
 use super::opencode::OpencodeAgent;
 use super::{AgentEvent, AgentState, AiAgent, ModelInfo, UserInput};
+use crate::channels::ChannelRegistry;
 use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::broadcast;
@@ -13,17 +15,20 @@ pub struct KiloAgent {
 impl KiloAgent {
     pub async fn new(
         channel_id: u64,
-        base_url: String,
+        endpoint: ServerEndpoint,
         existing_sid: Option<String>,
         model_opt: Option<(String, String)>,
+        directory: Option<String>,
+        channels: Arc<ChannelRegistry>,
     ) -> anyhow::Result<Arc<Self>> {
         let inner = OpencodeAgent::new(
             channel_id,
-            base_url,
-            "".to_string(),
+            endpoint,
             existing_sid,
             model_opt,
             "kilo",
+            directory,
+            channels,
         )
         .await?;
         Ok(Arc::new(Self { inner }))
@@ -31,7 +36,11 @@ impl KiloAgent {
 
     // 暴露 session_id 以供 SessionManager 使用
     pub fn session_id(&self) -> String {
-        self.inner.session_id.clone()
+        self.inner.session_id()
+    }
+
+    pub fn inner(&self) -> &Arc<OpencodeAgent> {
+        &self.inner
     }
 }
 
@@ -56,7 +65,7 @@ impl AiAgent for KiloAgent {
     async fn abort(&self) -> anyhow::Result<()> {
         self.inner.abort().await
     }
-    async fn clear(&self) -> anyhow::Result<()> {
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
         self.inner.clear().await
     }
     async fn set_model(&self, provider: &str, model_id: &str) -> anyhow::Result<()> {
@@ -74,7 +83,10 @@ impl AiAgent for KiloAgent {
     fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
         self.inner.subscribe_events()
     }
-    fn agent_type(&self) -> &'static str {
-        "kilo"
+    fn agent_type(&self) -> String {
+        "kilo".to_string()
+    }
+    async fn resume_turn(&self) -> anyhow::Result<()> {
+        self.inner.resume_turn().await
     }
 }
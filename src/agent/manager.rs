@@ -1,11 +1,13 @@
 use crate::agent::AgentType;
+use crate::agent::events::{EventSink, EventStream};
 use crate::agent::runtime;
+use crate::agent::OpencodeAgent;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info, warn};
 
 pub struct BackendProcess {
@@ -13,17 +15,228 @@ pub struct BackendProcess {
     pub port: u16,
 }
 
+/// 控制通道回報用的後端快照
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct BackendStatus {
+    pub name: String,
+    pub port: u16,
+    pub pid: Option<u32>,
+    pub alive: bool,
+}
+
+/// supervisor 追蹤的後端健康狀態
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BackendHealth {
+    pub name: String,
+    pub alive: bool,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// session 實際連線的 server 位址
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerEndpoint {
+    pub base_url: String,
+    pub password: String,
+}
+
+/// 一條共用 SSE 與掛在上面的 agent
+struct SharedStream {
+    stream: Arc<EventStream>,
+    agents: Vec<Weak<OpencodeAgent>>,
+}
+
 pub struct BackendManager {
     processes: Arc<Mutex<HashMap<String, Arc<BackendProcess>>>>,
-    config: Arc<crate::config::Config>,
+    config: Arc<RwLock<crate::config::Config>>,
+    // key 為 SSE 網址（含 directory 參數），每個 server 實例只開一條連線
+    event_streams: Mutex<HashMap<String, SharedStream>>,
+    // 後端重啟期間失去訂閱的 agent，依後端名稱等新 server 起來後接回
+    detached: Mutex<HashMap<String, Vec<Weak<OpencodeAgent>>>>,
+    health: std::sync::Mutex<HashMap<String, BackendHealth>>,
 }
 
 impl BackendManager {
-    pub fn new(config: Arc<crate::config::Config>) -> Self {
+    pub fn new(config: Arc<RwLock<crate::config::Config>>) -> Self {
         Self {
             processes: Arc::new(Mutex::new(HashMap::new())),
             config,
+            event_streams: Mutex::new(HashMap::new()),
+            detached: Mutex::new(HashMap::new()),
+            health: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn update_health(&self, name: &str, f: impl FnOnce(&mut BackendHealth)) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = health
+            .entry(name.to_string())
+            .or_insert_with(|| BackendHealth {
+                name: name.to_string(),
+                ..Default::default()
+            });
+        f(entry);
+    }
+
+    /// 後端可用；從停止狀態恢復時重新計算 uptime
+    pub fn mark_running(&self, name: &str) {
+        self.update_health(name, |h| {
+            if !h.alive {
+                h.alive = true;
+                h.started_at = Some(chrono::Utc::now());
+            }
+        });
+    }
+
+    pub fn mark_failed(&self, name: &str, error: &str) {
+        self.update_health(name, |h| {
+            h.alive = false;
+            h.last_error = Some(error.to_string());
+        });
+    }
+
+    pub fn count_restart(&self, name: &str) {
+        self.update_health(name, |h| h.restarts += 1);
+    }
+
+    /// 曾經啟動過的後端健康狀態，依名稱排序
+    pub fn health(&self) -> Vec<BackendHealth> {
+        let mut list: Vec<BackendHealth> = self
+            .health
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// 找出已結束的子進程並移出管理表，回傳 (後端, 原因) 交給 supervisor 重啟
+    pub async fn reap_dead(&self) -> Vec<(AgentType, String)> {
+        let dead = {
+            let mut procs = self.processes.lock().await;
+            let mut dead = Vec::new();
+            for (name, p) in procs.iter() {
+                let reason = match p.child.lock().await.try_wait() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => format!("exited with {}", status),
+                    Err(e) => format!("wait failed: {}", e),
+                };
+                dead.push((name.clone(), p.port, reason));
+            }
+            for (name, _, _) in &dead {
+                procs.remove(name);
+            }
+            dead
+        };
+
+        let mut reaped = Vec::new();
+        for (name, port, reason) in dead {
+            warn!("💥 Backend {} on port {} {}", name, port, reason);
+            self.mark_failed(&name, &reason);
+            self.drop_event_streams(&name, port).await;
+            if let Ok(agent_type) = name.parse::<AgentType>() {
+                reaped.push((agent_type, reason));
+            }
+        }
+        reaped
+    }
+
+    /// 關機時結束自己啟動的 server（external 模式不在管理表內），回傳後端名稱
+    pub async fn shutdown(&self) -> Vec<String> {
+        let procs = std::mem::take(&mut *self.processes.lock().await);
+        let mut stopped = Vec::new();
+        for (name, p) in procs {
+            let mut child = p.child.lock().await;
+            if let Err(e) = child.kill().await {
+                warn!("⚠️ Failed to stop backend {}: {}", name, e);
+                continue;
+            }
+            self.update_health(&name, |h| h.alive = false);
+            stopped.push(name);
+        }
+        for shared in self.event_streams.lock().await.drain().map(|(_, s)| s) {
+            shared.stream.cancel();
+        }
+        stopped.sort();
+        stopped
+    }
+
+    /// 把 agent 的 session 掛到該 server 共用的 SSE 訂閱上
+    pub async fn attach_event_stream(&self, agent: &Arc<OpencodeAgent>) {
+        let url = agent.event_url();
+        let stream = {
+            let mut streams = self.event_streams.lock().await;
+            let shared = streams.entry(url.clone()).or_insert_with(|| SharedStream {
+                stream: EventStream::start(url, agent.auth_header()),
+                agents: Vec::new(),
+            });
+            shared.agents.retain(|a| a.strong_count() > 0);
+            shared.agents.push(Arc::downgrade(agent));
+            shared.stream.clone()
+        };
+        let sink: Arc<dyn EventSink> = agent.clone();
+        stream
+            .register(&agent.session_id(), Arc::downgrade(&sink))
+            .await;
+        agent.set_event_stream(stream);
+    }
+
+    /// 後端重啟會換 port：取消舊位址的訂閱，仍存活的 agent 留待新 server 起來後接回
+    async fn drop_event_streams(&self, name: &str, port: u16) {
+        let prefix = format!("http://127.0.0.1:{}/", port);
+        let mut orphans = Vec::new();
+        self.event_streams.lock().await.retain(|url, shared| {
+            if !url.starts_with(&prefix) {
+                return true;
+            }
+            shared.stream.cancel();
+            orphans.append(&mut shared.agents);
+            false
+        });
+        orphans.retain(|a| a.strong_count() > 0);
+        if !orphans.is_empty() {
+            self.detached
+                .lock()
+                .await
+                .entry(name.to_string())
+                .or_default()
+                .extend(orphans);
+        }
+    }
+
+    /// 把重啟前的 agent 改連新 server 並掛上新的訂閱，補同步斷線期間的回合
+    async fn reattach(&self, name: &str, base_url: &str) {
+        let orphans = self.detached.lock().await.remove(name).unwrap_or_default();
+        for agent in orphans.iter().filter_map(Weak::upgrade) {
+            agent.rebase(base_url.to_string());
+            self.attach_event_stream(&agent).await;
+            agent.resync().await;
+            info!(
+                "🔌 Reattached session {} to {} at {}",
+                agent.session_id(),
+                name,
+                base_url
+            );
+        }
+    }
+
+    pub async fn list_backends(&self) -> Vec<BackendStatus> {
+        let procs = self.processes.lock().await;
+        let mut list = Vec::new();
+        for (name, p) in procs.iter() {
+            let mut child = p.child.lock().await;
+            list.push(BackendStatus {
+                name: name.clone(),
+                port: p.port,
+                pid: child.id(),
+                alive: matches!(child.try_wait(), Ok(None)),
+            });
         }
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
     }
 
     fn spawn_stream_logger<R>(label: String, reader: R)
@@ -46,6 +259,53 @@ impl BackendManager {
         });
     }
 
+    /// 以 /provider 檢查 server 是否可用
+    async fn probe(client: &reqwest::Client, base_url: &str, password: Option<&str>) -> bool {
+        let mut req = client.get(format!("{}/provider", base_url));
+        if let Some(password) = password.filter(|p| !p.is_empty()) {
+            req = req.header("Authorization", format!("Bearer {}", password));
+        }
+        matches!(req.send().await, Ok(resp) if resp.status().is_success())
+    }
+
+    /// 取得頻道要連的 server：external 模式只檢查既有 server 是否可用，
+    /// managed 模式則確保子進程在跑
+    pub async fn resolve_server(
+        &self,
+        agent_type: &AgentType,
+        server: Option<&str>,
+    ) -> anyhow::Result<ServerEndpoint> {
+        let (external, password) = {
+            let config = self.config.read().await;
+            let server_config = config
+                .server_config(agent_type)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported agent type"))?;
+            (
+                server_config.external(server)?,
+                server_config.password.clone(),
+            )
+        };
+
+        let Some(external) = external else {
+            let port = self.ensure_backend(agent_type).await?;
+            return Ok(ServerEndpoint {
+                base_url: format!("http://127.0.0.1:{}", port),
+                password: password.unwrap_or_default(),
+            });
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        if !Self::probe(&client, &external.url, external.password.as_deref()).await {
+            anyhow::bail!("{} server at {} is unreachable", agent_type, external.url);
+        }
+        Ok(ServerEndpoint {
+            base_url: external.url,
+            password: external.password.unwrap_or_default(),
+        })
+    }
+
     fn get_free_port() -> u16 {
         std::net::TcpListener::bind("127.0.0.1:0")
             .and_then(|listener| listener.local_addr())
@@ -57,7 +317,7 @@ impl BackendManager {
         let key = agent_type.to_string();
 
         // 1. 快速檢查是否已有運行的進程 (使用最小鎖定範圍)
-        let mut dead_backend = false;
+        let mut dead_port = None;
         {
             let procs = self.processes.lock().await;
             if let Some(p) = procs.get(&key) {
@@ -65,14 +325,17 @@ impl BackendManager {
                 if let Ok(None) = child.try_wait() {
                     return Ok(p.port);
                 }
-                dead_backend = true;
+                dead_port = Some(p.port);
             }
         }
 
-        if dead_backend {
+        if let Some(port) = dead_port {
             let mut procs = self.processes.lock().await;
             warn!("Backend {} died. Removing from map.", agent_type);
             procs.remove(&key);
+            drop(procs);
+            self.mark_failed(&key, "exited");
+            self.drop_event_streams(&key, port).await;
         }
 
         // 2. 啟動新進程 (重新加鎖)
@@ -82,6 +345,12 @@ impl BackendManager {
             return Ok(p.port);
         }
 
+        let password = self
+            .config
+            .read()
+            .await
+            .server_config(agent_type)
+            .and_then(|c| c.password.clone());
         let port = Self::get_free_port();
         let bin_name = match agent_type {
             AgentType::Kilo => "kilo",
@@ -118,7 +387,7 @@ impl BackendManager {
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
 
-        if let Some(password) = &self.config.opencode.password {
+        if let Some(password) = &password {
             if !password.is_empty() {
                 match agent_type {
                     AgentType::Opencode => {
@@ -152,29 +421,20 @@ impl BackendManager {
 
         let mut attempts = 0;
         let client = reqwest::Client::new();
-        let health_url = format!("http://127.0.0.1:{}/provider", port);
+        let base_url = format!("http://127.0.0.1:{}", port);
 
         loop {
             tokio::time::sleep(Duration::from_millis(500)).await;
-            let mut req = client.get(&health_url);
-            if let Some(password) = &self.config.opencode.password {
-                if !password.is_empty() {
-                    req = req.header("Authorization", format!("Bearer {}", password));
-                }
+            if Self::probe(&client, &base_url, password.as_deref()).await {
+                info!("✅ Backend {} is ready on port {}", agent_type, port);
+                self.mark_running(&agent_type.to_string());
+                self.reattach(&agent_type.to_string(), &base_url).await;
+                return Ok(port);
             }
-
-            match req.send().await {
-                Ok(resp) if resp.status().is_success() => {
-                    info!("✅ Backend {} is ready on port {}", agent_type, port);
-                    return Ok(port);
-                }
-                _ => {
-                    attempts += 1;
-                    if attempts > 60 {
-                        error!("❌ Backend {} failed to start on port {}", agent_type, port);
-                        return Err(anyhow::anyhow!("Backend timeout"));
-                    }
-                }
+            attempts += 1;
+            if attempts > 60 {
+                error!("❌ Backend {} failed to start on port {}", agent_type, port);
+                return Err(anyhow::anyhow!("Backend timeout"));
             }
         }
     }
@@ -182,10 +442,11 @@ impl BackendManager {
 
 #[cfg(test)]
 mod tests {
-    use super::BackendManager;
+    use super::{BackendManager, ServerEndpoint};
     use crate::agent::AgentType;
     use crate::config::Config;
     use std::sync::Arc;
+    use tokio::sync::RwLock;
 
     #[test]
     fn test_get_free_port_returns_non_zero() {
@@ -195,11 +456,132 @@ mod tests {
 
     #[tokio::test]
     async fn test_ensure_backend_rejects_unsupported_agent_type() {
-        let manager = BackendManager::new(Arc::new(Config::default()));
+        let manager = BackendManager::new(Arc::new(RwLock::new(Config::default())));
         let err = manager
             .ensure_backend(&AgentType::Pi)
             .await
             .expect_err("pi should be unsupported in backend manager");
         assert!(err.to_string().contains("Unsupported agent type"));
     }
+
+    #[tokio::test]
+    async fn test_list_backends_reports_pid_and_liveness() -> anyhow::Result<()> {
+        let manager = BackendManager::new(Arc::new(RwLock::new(Config::default())));
+        let child = tokio::process::Command::new("sleep").arg("30").spawn()?;
+        manager.processes.lock().await.insert(
+            "opencode".to_string(),
+            Arc::new(super::BackendProcess {
+                child: tokio::sync::Mutex::new(child),
+                port: 4242,
+            }),
+        );
+
+        let list = manager.list_backends().await;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "opencode");
+        assert_eq!(list[0].port, 4242);
+        assert!(list[0].pid.is_some());
+        assert!(list[0].alive);
+
+        let procs = manager.processes.lock().await;
+        procs["opencode"].child.lock().await.kill().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_kills_managed_servers() -> anyhow::Result<()> {
+        let manager = BackendManager::new(Arc::new(RwLock::new(Config::default())));
+        let child = tokio::process::Command::new("sleep").arg("30").spawn()?;
+        let pid = child.id().expect("pid");
+        manager.mark_running("opencode");
+        manager.processes.lock().await.insert(
+            "opencode".to_string(),
+            Arc::new(super::BackendProcess {
+                child: tokio::sync::Mutex::new(child),
+                port: 4244,
+            }),
+        );
+
+        assert_eq!(manager.shutdown().await, vec!["opencode".to_string()]);
+        assert!(manager.list_backends().await.is_empty());
+        assert!(!manager.health()[0].alive);
+        // kill().await 已回收子進程，PID 不再存在
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reap_dead_removes_exited_process_and_records_error() -> anyhow::Result<()> {
+        let manager = BackendManager::new(Arc::new(RwLock::new(Config::default())));
+        let mut child = tokio::process::Command::new("true").spawn()?;
+        child.wait().await?;
+        manager.mark_running("kilo");
+        manager.processes.lock().await.insert(
+            "kilo".to_string(),
+            Arc::new(super::BackendProcess {
+                child: tokio::sync::Mutex::new(child),
+                port: 4243,
+            }),
+        );
+
+        let reaped = manager.reap_dead().await;
+        assert_eq!(reaped.len(), 1);
+        assert_eq!(reaped[0].0, AgentType::Kilo);
+        assert!(reaped[0].1.starts_with("exited with"));
+        assert!(manager.processes.lock().await.is_empty());
+        assert!(manager.reap_dead().await.is_empty());
+
+        manager.count_restart("kilo");
+        manager.mark_running("kilo");
+        let health = manager.health();
+        assert_eq!(health.len(), 1);
+        assert!(health[0].alive);
+        assert_eq!(health[0].restarts, 1);
+        assert_eq!(health[0].last_error.as_deref(), Some(reaped[0].1.as_str()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_agents_on_same_server_share_one_event_stream() -> anyhow::Result<()> {
+        let manager = BackendManager::new(Arc::new(RwLock::new(Config::default())));
+        let state_dir = tempfile::tempdir()?;
+        let channels = crate::channels::ChannelRegistry::in_dir(state_dir.path());
+        let new_agent = |sid: &str, dir: Option<&str>| {
+            crate::agent::OpencodeAgent::new(
+                1,
+                ServerEndpoint {
+                    base_url: "http://127.0.0.1:9".to_string(),
+                    password: String::new(),
+                },
+                Some(sid.to_string()),
+                None,
+                "opencode",
+                dir.map(str::to_string),
+                Arc::clone(&channels),
+            )
+        };
+        let a = new_agent("sa", None).await?;
+        let b = new_agent("sb", None).await?;
+        let c = new_agent("sc", Some("/tmp")).await?;
+        for agent in [&a, &b, &c] {
+            manager.attach_event_stream(agent).await;
+        }
+        assert_eq!(manager.event_streams.lock().await.len(), 2);
+
+        let old_stream = manager.event_streams.lock().await["http://127.0.0.1:9/event"]
+            .stream
+            .clone();
+        manager.drop_event_streams("opencode", 9).await;
+        assert!(manager.event_streams.lock().await.is_empty());
+        assert!(old_stream.is_cancelled());
+
+        // 新 server 起來後，存活的 agent 改連新 port 並重新共用訂閱
+        drop(b);
+        manager.reattach("opencode", "http://127.0.0.1:10").await;
+        assert_eq!(a.event_url(), "http://127.0.0.1:10/event");
+        assert!(c.event_url().starts_with("http://127.0.0.1:10/event?directory="));
+        assert_eq!(manager.event_streams.lock().await.len(), 2);
+        assert!(manager.detached.lock().await.is_empty());
+        Ok(())
+    }
 }
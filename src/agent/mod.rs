@@ -1,7 +1,8 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
-use tokio::sync::broadcast;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
 
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
@@ -64,6 +65,8 @@ impl UploadedFile {
 pub struct UserInput {
     pub text: String,
     pub files: Vec<UploadedFile>,
+    /// 送出訊息的使用者（排程為建立者），用量依此歸屬
+    pub user_id: Option<u64>,
 }
 
 impl UserInput {
@@ -71,6 +74,7 @@ impl UserInput {
         Self {
             text,
             files: Vec::new(),
+            user_id: None,
         }
     }
 
@@ -99,6 +103,41 @@ impl UserInput {
     }
 }
 
+/// 一個回合的 token 用量與花費；後端沒有提供的欄位為 0
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TurnUsage {
+    /// 實際使用的模型（`provider/model`），後端沒回報時為 None
+    pub model: Option<String>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    /// 後端回報的花費（USD）
+    pub cost: f64,
+}
+
+impl TurnUsage {
+    pub fn is_empty(&self) -> bool {
+        self.input_tokens == 0
+            && self.output_tokens == 0
+            && self.cache_read_tokens == 0
+            && self.cache_write_tokens == 0
+            && self.cost == 0.0
+    }
+
+    /// 同一回合有多則 assistant 訊息時累加；模型以最後一則為準
+    pub fn add(&mut self, other: TurnUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cost += other.cost;
+        if other.model.is_some() {
+            self.model = other.model;
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum AgentEvent {
     MessageUpdate {
@@ -139,6 +178,29 @@ pub enum AgentEvent {
         id: String,
         data: serde_json::Value,
     },
+    /// 回合的用量，在 AgentEnd 之前送出
+    Usage {
+        usage: TurnUsage,
+    },
+    /// 工具權限請求，等待頻道中的使用者以按鈕回應
+    PermissionRequest {
+        token: String,
+        title: String,
+        detail: String,
+        choices: Vec<permission::PermissionDecision>,
+    },
+}
+
+/// abort 後等待進行中的 prompt 結束的上限
+const CANCEL_WAIT: Duration = Duration::from_secs(30);
+
+/// prompt 進行中的旗標；prompt 的 future 被中途丟棄時也會復原
+struct RunningGuard<'a>(&'a watch::Sender<bool>);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(false);
+    }
 }
 
 #[async_trait]
@@ -152,26 +214,43 @@ pub trait AiAgent: Send + Sync {
     async fn get_state(&self) -> anyhow::Result<AgentState>;
     async fn compact(&self) -> anyhow::Result<()>;
     async fn abort(&self) -> anyhow::Result<()>;
-    async fn clear(&self) -> anyhow::Result<()>;
+    /// 清除對話；後端若直接開了新的 session 取代舊的，回傳新的 session ID 供持久化
+    async fn clear(&self) -> anyhow::Result<Option<String>>;
     async fn set_model(&self, provider: &str, model_id: &str) -> anyhow::Result<()>;
     async fn set_thinking_level(&self, level: &str) -> anyhow::Result<()>;
     async fn get_available_models(&self) -> anyhow::Result<Vec<ModelInfo>>;
     async fn load_skill(&self, name: &str) -> anyhow::Result<()>;
     fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent>;
-    fn agent_type(&self) -> &'static str;
+    /// 與 AgentType 的字串形式相同
+    fn agent_type(&self) -> String;
+    /// session 專屬的後端進程已結束時回傳原因，供 supervisor 重啟；
+    /// 共用 server 的後端（OpenCode/Kilo）由 BackendManager 監看
+    fn exit_reason(&self) -> Option<String> {
+        None
+    }
+    /// 結束 session 專屬的後端進程（閒置回收時用）；共用進程的後端不需實作
+    fn terminate(&self) {}
+    /// terminate 後等子進程真正結束，關機時讓它有時間寫完 session
+    async fn wait_exit(&self) {}
+    /// bot 重啟後接回仍在後端進行的回合：之後的事件照常送出，直到 AgentEnd
+    async fn resume_turn(&self) -> anyhow::Result<()> {
+        anyhow::bail!("{} cannot resume a turn after restart", self.agent_type())
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+/// state.json 仍以純字串保存（`kilo`、`acp:gemini`）
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(try_from = "String", into = "String")]
 pub enum AgentType {
-    #[serde(rename = "pi")]
     Pi,
-    #[serde(rename = "opencode")]
     Opencode,
-    #[serde(rename = "copilot")]
     Copilot,
-    #[serde(rename = "kilo")]
     #[default]
     Kilo,
+    /// config.toml `[acp.<name>]` 設定的 ACP agent
+    Acp(String),
+    /// `/v1/chat/completions` 相容的 HTTP server
+    OpenAiCompat,
 }
 
 impl std::fmt::Display for AgentType {
@@ -181,6 +260,8 @@ impl std::fmt::Display for AgentType {
             AgentType::Opencode => write!(f, "opencode"),
             AgentType::Copilot => write!(f, "copilot"),
             AgentType::Kilo => write!(f, "kilo"),
+            AgentType::Acp(name) => write!(f, "acp:{}", name),
+            AgentType::OpenAiCompat => write!(f, "openai"),
         }
     }
 }
@@ -188,37 +269,77 @@ impl std::fmt::Display for AgentType {
 impl std::str::FromStr for AgentType {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // ACP agent 名稱保留大小寫，與 config.toml 的 key 一致
+        if let Some(name) = s.strip_prefix("acp:") {
+            if name.is_empty() {
+                anyhow::bail!("Missing ACP agent name: {}", s);
+            }
+            return Ok(AgentType::Acp(name.to_string()));
+        }
         match s.to_lowercase().as_str() {
             "pi" => Ok(AgentType::Pi),
             "opencode" => Ok(AgentType::Opencode),
             "copilot" => Ok(AgentType::Copilot),
             "kilo" => Ok(AgentType::Kilo),
+            "openai" => Ok(AgentType::OpenAiCompat),
             _ => anyhow::bail!("Unknown agent type: {}", s),
         }
     }
 }
 
+impl TryFrom<String> for AgentType {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<AgentType> for String {
+    fn from(agent_type: AgentType) -> Self {
+        agent_type.to_string()
+    }
+}
+
+pub mod acp;
 pub mod copilot;
+pub mod events;
 pub mod kilo;
 pub mod manager;
+pub mod openai;
 pub mod opencode;
+pub mod permission;
 pub mod pi;
 pub mod runtime;
-pub use copilot::CopilotAgent;
+pub use acp::AcpAgent;
 pub use kilo::KiloAgent;
+pub use openai::OpenAiAgent;
 pub use opencode::OpencodeAgent;
 pub use pi::PiAgent;
 
 #[cfg(test)]
 pub struct MockAgent {
     pub tx: tokio::sync::broadcast::Sender<AgentEvent>,
+    pub kind: &'static str,
+    pub exit_reason: std::sync::Mutex<Option<String>>,
+    /// 共享旗標，測試不必握著 agent 也能確認是否被 terminate
+    pub terminated: std::sync::Arc<std::sync::atomic::AtomicBool>,
 }
 
 #[cfg(test)]
 impl MockAgent {
     pub fn new() -> Self {
+        Self::of_kind("mock")
+    }
+
+    /// 偽裝成指定後端（例如 supervisor 測試需要可解析的 agent_type）
+    pub fn of_kind(kind: &'static str) -> Self {
         let (tx, _) = tokio::sync::broadcast::channel(100);
-        Self { tx }
+        Self {
+            tx,
+            kind,
+            exit_reason: std::sync::Mutex::new(None),
+            terminated: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)),
+        }
     }
 }
 
@@ -256,8 +377,8 @@ impl AiAgent for MockAgent {
     async fn abort(&self) -> anyhow::Result<()> {
         Ok(())
     }
-    async fn clear(&self) -> anyhow::Result<()> {
-        Ok(())
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
+        Ok(None)
     }
     async fn set_model(&self, _p: &str, _m: &str) -> anyhow::Result<()> {
         Ok(())
@@ -274,14 +395,21 @@ impl AiAgent for MockAgent {
     fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
         self.tx.subscribe()
     }
-    fn agent_type(&self) -> &'static str {
-        "mock"
+    fn agent_type(&self) -> String {
+        self.kind.to_string()
+    }
+    fn exit_reason(&self) -> Option<String> {
+        self.exit_reason.lock().unwrap().clone()
+    }
+    fn terminate(&self) {
+        self.terminated
+            .store(true, std::sync::atomic::Ordering::SeqCst);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{UploadedFile, UserInput};
+    use super::{AgentType, RunningGuard, UploadedFile, UserInput};
 
     #[test]
     fn test_uploaded_file_display_name_fallback_to_path() {
@@ -300,6 +428,7 @@ mod tests {
     fn test_user_input_fallback_prompt_includes_files_section() {
         let input = UserInput {
             text: "Please analyze files".to_string(),
+            user_id: None,
             files: vec![UploadedFile {
                 id: "f1".to_string(),
                 name: "image.png".to_string(),
@@ -316,4 +445,38 @@ mod tests {
         assert!(rendered.contains("mime=image/png"));
         assert!(rendered.contains("local_path=/tmp/uploads/image.png"));
     }
+
+    #[test]
+    fn test_agent_type_round_trips_acp_names() {
+        let acp: AgentType = "acp:Gemini".parse().expect("parse");
+        assert_eq!(acp, AgentType::Acp("Gemini".into()));
+        assert_eq!(acp.to_string(), "acp:Gemini");
+        assert_eq!("Copilot".parse::<AgentType>().unwrap(), AgentType::Copilot);
+        assert_eq!("openai".parse::<AgentType>().unwrap(), AgentType::OpenAiCompat);
+        assert!("acp:".parse::<AgentType>().is_err());
+        assert!("gemini".parse::<AgentType>().is_err());
+
+        let json = serde_json::to_string(&vec![AgentType::Kilo, acp]).unwrap();
+        assert_eq!(json, r#"["kilo","acp:Gemini"]"#);
+        let back: Vec<AgentType> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back[1], AgentType::Acp("Gemini".into()));
+        assert!(serde_json::from_str::<AgentType>(r#""unknown""#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_running_guard_clears_flag_when_prompt_is_dropped() {
+        let running = tokio::sync::watch::Sender::new(false);
+        let mut rx = running.subscribe();
+        let task = {
+            let running = running.clone();
+            tokio::spawn(async move {
+                running.send_replace(true);
+                let _guard = RunningGuard(&running);
+                std::future::pending::<()>().await;
+            })
+        };
+        rx.wait_for(|r| *r).await.expect("started");
+        task.abort();
+        rx.wait_for(|r| !*r).await.expect("cleared");
+    }
 }
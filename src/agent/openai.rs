@@ -0,0 +1,1167 @@
+use super::permission::{self, PermissionDecision};
+use super::{AgentEvent, AgentState, AiAgent, ModelInfo, TurnUsage};
+use super::{RunningGuard, CANCEL_WAIT};
+use crate::channels::ChannelRegistry;
+use crate::commands::agent::PermissionPolicy;
+use crate::config::OpenAiConfig;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{broadcast, watch, Mutex};
+use tracing::{error, info, warn};
+
+/// 持久化模型選擇時使用的 provider
+const PROVIDER: &str = "openai";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// 串流超過這段時間沒有任何資料就視為卡住
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// 工具輸出回傳給模型的上限
+const MAX_TOOL_OUTPUT: usize = 16 * 1024;
+const COMPACT_PROMPT: &str = "Summarize the conversation so far for your own future \
+reference. Keep decisions, open questions, file names and facts the user gave. \
+Reply with the summary only.";
+
+/// 串流中逐步拼出的 tool call
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// 一次 chat completion 的結果
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Completion {
+    text: String,
+    thinking: String,
+    tool_calls: Vec<ToolCall>,
+    usage: TurnUsage,
+}
+
+/// completion 回應的 `usage`；cached_tokens 已含在 prompt_tokens 內，要扣掉
+fn parse_usage(usage: &Value) -> TurnUsage {
+    let count = |v: &Value| v.as_u64().unwrap_or(0);
+    let cached = count(&usage["prompt_tokens_details"]["cached_tokens"]);
+    TurnUsage {
+        input_tokens: count(&usage["prompt_tokens"]).saturating_sub(cached),
+        output_tokens: count(&usage["completion_tokens"]),
+        cache_read_tokens: cached,
+        ..Default::default()
+    }
+}
+
+impl Completion {
+    /// 套用一個串流 chunk，回傳要顯示的 (thinking, text) 增量
+    fn apply_chunk(&mut self, chunk: &Value) -> (String, String) {
+        let delta = &chunk["choices"][0]["delta"];
+        // llama.cpp/vLLM 用 reasoning_content，部分 server 用 reasoning
+        let thinking = delta["reasoning_content"]
+            .as_str()
+            .or(delta["reasoning"].as_str())
+            .unwrap_or_default();
+        let text = delta["content"].as_str().unwrap_or_default();
+        self.thinking.push_str(thinking);
+        self.text.push_str(text);
+        // include_usage 時最後一個 chunk 的 choices 是空的，只帶 usage
+        if chunk["usage"].is_object() {
+            self.usage = parse_usage(&chunk["usage"]);
+        }
+
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = call["index"].as_u64().unwrap_or(0) as usize;
+            if self.tool_calls.len() <= index {
+                self.tool_calls.resize_with(index + 1, ToolCall::default);
+            }
+            let slot = &mut self.tool_calls[index];
+            if let Some(id) = call["id"].as_str() {
+                slot.id = id.to_string();
+            }
+            if let Some(name) = call["function"]["name"].as_str() {
+                slot.name.push_str(name);
+            }
+            if let Some(args) = call["function"]["arguments"].as_str() {
+                slot.arguments.push_str(args);
+            }
+        }
+        (thinking.to_string(), text.to_string())
+    }
+
+    /// 不支援串流的 server 直接回傳完整的 message
+    fn from_message(message: &Value) -> Self {
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|call| ToolCall {
+                id: call["id"].as_str().unwrap_or_default().to_string(),
+                name: call["function"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                arguments: match &call["function"]["arguments"] {
+                    Value::String(s) => s.clone(),
+                    Value::Null => String::new(),
+                    other => other.to_string(),
+                },
+            })
+            .collect();
+        Self {
+            text: message["content"].as_str().unwrap_or_default().to_string(),
+            thinking: message["reasoning_content"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            tool_calls,
+            usage: TurnUsage::default(),
+        }
+    }
+
+    /// 有些 server 不給 tool call id，補上才能對應 tool 訊息
+    fn finish(mut self) -> Self {
+        self.tool_calls.retain(|c| !c.name.is_empty());
+        for (i, call) in self.tool_calls.iter_mut().enumerate() {
+            if call.id.is_empty() {
+                call.id = format!("call_{}", i);
+            }
+        }
+        self
+    }
+
+    fn assistant_message(&self) -> Value {
+        if self.tool_calls.is_empty() {
+            return json!({ "role": "assistant", "content": self.text });
+        }
+        let calls: Vec<Value> = self
+            .tool_calls
+            .iter()
+            .map(|c| {
+                json!({
+                    "id": c.id,
+                    "type": "function",
+                    "function": { "name": c.name, "arguments": c.arguments }
+                })
+            })
+            .collect();
+        let content = if self.text.is_empty() {
+            Value::Null
+        } else {
+            json!(self.text)
+        };
+        json!({ "role": "assistant", "content": content, "tool_calls": calls })
+    }
+}
+
+enum SseLine {
+    Data(Value),
+    Done,
+    Skip,
+}
+
+fn parse_sse_line(line: &str) -> SseLine {
+    let Some(data) = line.strip_prefix("data:") else {
+        return SseLine::Skip;
+    };
+    match data.trim() {
+        "[DONE]" => SseLine::Done,
+        data => serde_json::from_str(data)
+            .map(SseLine::Data)
+            .unwrap_or(SseLine::Skip),
+    }
+}
+
+fn tool_specs() -> Value {
+    json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "list_dir",
+                "description": "List a directory in the workspace. Directories end with '/'.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "'.' for the root" }
+                    },
+                    "required": ["path"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "read_file",
+                "description": "Read a text file in the workspace.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Relative path" }
+                    },
+                    "required": ["path"]
+                }
+            }
+        }
+    ])
+}
+
+/// 工具只能碰工作目錄（解析 symlink 後）底下的路徑
+fn resolve_tool_path(root: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let root = root.canonicalize()?;
+    let canonical = root
+        .join(path.trim())
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Cannot access {}: {}", path, e))?;
+    if !canonical.starts_with(&root) {
+        anyhow::bail!("{} is outside the workspace", path);
+    }
+    Ok(canonical)
+}
+
+fn truncate_output(output: String) -> String {
+    let total = output.len() as u64;
+    truncate_output_of(output, total)
+}
+
+/// 超過上限時截斷並註明來源的總位元組數（來源可能只讀了開頭）
+fn truncate_output_of(mut output: String, total: u64) -> String {
+    if output.len() <= MAX_TOOL_OUTPUT && total <= output.len() as u64 {
+        return output;
+    }
+    let mut end = MAX_TOOL_OUTPUT.min(output.len());
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    output.push_str(&format!("\n… (truncated, {} bytes total)", total));
+    output
+}
+
+async fn execute_tool(root: &Path, name: &str, arguments: &str) -> anyhow::Result<String> {
+    let args: Value = if arguments.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(arguments)?
+    };
+    let path = args["path"].as_str().unwrap_or(".");
+    match name {
+        "list_dir" => {
+            let dir = resolve_tool_path(root, path)?;
+            let mut entries = Vec::new();
+            let mut read_dir = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let mut name = entry.file_name().to_string_lossy().to_string();
+                if entry.file_type().await?.is_dir() {
+                    name.push('/');
+                }
+                entries.push(name);
+            }
+            entries.sort();
+            Ok(truncate_output(entries.join("\n")))
+        }
+        "read_file" => {
+            let file = resolve_tool_path(root, path)?;
+            let total = tokio::fs::metadata(&file).await?.len();
+            // 大檔只讀開頭，避免整個載入記憶體
+            let mut bytes = Vec::new();
+            tokio::fs::File::open(&file)
+                .await?
+                .take(MAX_TOOL_OUTPUT as u64)
+                .read_to_end(&mut bytes)
+                .await?;
+            Ok(truncate_output_of(
+                String::from_utf8_lossy(&bytes).into_owned(),
+                total,
+            ))
+        }
+        other => anyhow::bail!("Unknown tool: {}", other),
+    }
+}
+
+async fn load_history(path: &Path) -> anyhow::Result<Vec<Value>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect())
+}
+
+/// 直接對 `/v1/chat/completions` 說話的後端；對話紀錄存在 sessions 目錄
+pub struct OpenAiAgent {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    channel_id: u64,
+    history_path: PathBuf,
+    history: Mutex<Vec<Value>>,
+    // 同一時間只跑一個回合或 compact
+    turn_lock: Mutex<()>,
+    model: Mutex<Option<String>>,
+    reasoning_effort: Mutex<Option<String>>,
+    /// 沒綁定工作目錄時不提供工具
+    workdir: Option<PathBuf>,
+    tools_enabled: bool,
+    max_tool_rounds: u32,
+    // 使用者選了「一律允許」後，本 session 不再詢問
+    tools_always: AtomicBool,
+    pending_permission: std::sync::Mutex<Option<String>>,
+    event_tx: broadcast::Sender<AgentEvent>,
+    cancel: watch::Sender<bool>,
+    prompt_running: watch::Sender<bool>,
+    // 工具權限策略與模型選擇所在的頻道設定
+    channels: Arc<ChannelRegistry>,
+}
+
+impl OpenAiAgent {
+    pub async fn new(
+        channel_id: u64,
+        config: OpenAiConfig,
+        session_dir: &Path,
+        model_opt: Option<(String, String)>,
+        workdir: Option<PathBuf>,
+        channels: Arc<ChannelRegistry>,
+    ) -> anyhow::Result<Arc<Self>> {
+        tokio::fs::create_dir_all(session_dir).await?;
+        let history_path = session_dir.join(format!("discord-rs-{}.jsonl", channel_id));
+        let history = load_history(&history_path).await?;
+        // 頻道記錄的可能是其他後端的模型
+        let model = model_opt
+            .filter(|(provider, id)| provider == PROVIDER && !id.is_empty())
+            .map(|(_, id)| id)
+            .or(config.model.clone());
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()?;
+        let (event_tx, _) = broadcast::channel(1000);
+
+        Ok(Arc::new(Self {
+            client,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            api_key: config.api_key.filter(|k| !k.is_empty()),
+            channel_id,
+            history_path,
+            history: Mutex::new(history),
+            turn_lock: Mutex::new(()),
+            model: Mutex::new(model),
+            reasoning_effort: Mutex::new(None),
+            workdir,
+            tools_enabled: config.tools,
+            max_tool_rounds: config.max_tool_rounds,
+            tools_always: AtomicBool::new(false),
+            pending_permission: std::sync::Mutex::new(None),
+            event_tx,
+            cancel: watch::Sender::new(false),
+            prompt_running: watch::Sender::new(false),
+            channels,
+        }))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn authorized(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => req.bearer_auth(key),
+            None => req,
+        }
+    }
+
+    async fn save_history(&self) -> anyhow::Result<()> {
+        let mut content = String::new();
+        for message in self.history.lock().await.iter() {
+            content.push_str(&serde_json::to_string(message)?);
+            content.push('\n');
+        }
+        // 先寫暫存檔再改名，寫到一半中斷也不會留下殘缺的紀錄
+        let tmp = self.history_path.with_extension("jsonl.tmp");
+        tokio::fs::write(&tmp, content).await?;
+        tokio::fs::rename(&tmp, &self.history_path).await?;
+        Ok(())
+    }
+
+    async fn list_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
+        let resp = self
+            .authorized(self.client.get(self.url("/models")))
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("GET /models failed: {}", resp.status());
+        }
+        let val: Value = resp.json().await?;
+        Ok(val["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["id"].as_str())
+            .map(|id| ModelInfo {
+                provider: PROVIDER.to_string(),
+                id: id.to_string(),
+                label: id.to_string(),
+            })
+            .collect())
+    }
+
+    /// 沒有指定模型時採用 server 列出的第一個
+    async fn resolve_model(&self) -> anyhow::Result<String> {
+        if let Some(model) = self.model.lock().await.clone() {
+            return Ok(model);
+        }
+        let first = self
+            .list_models()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                anyhow::anyhow!("No model configured and {} lists none", self.url("/models"))
+            })?;
+        *self.model.lock().await = Some(first.id.clone());
+        Ok(first.id)
+    }
+
+    fn emit(&self, thinking: String, text: String) {
+        if thinking.is_empty() && text.is_empty() {
+            return;
+        }
+        let _ = self.event_tx.send(AgentEvent::MessageUpdate {
+            thinking,
+            text,
+            is_delta: true,
+            id: None,
+        });
+    }
+
+    async fn request_body(
+        &self,
+        model: &str,
+        messages: &[Value],
+        stream: bool,
+        tools: bool,
+    ) -> Value {
+        let mut body = json!({
+            "model": model,
+            "messages": messages,
+            "stream": stream,
+        });
+        if stream {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if tools {
+            body["tools"] = tool_specs();
+        }
+        if let Some(effort) = self.reasoning_effort.lock().await.clone() {
+            body["reasoning_effort"] = json!(effort);
+        }
+        body
+    }
+
+    async fn send_completion(&self, body: &Value) -> anyhow::Result<reqwest::Response> {
+        let resp = self
+            .authorized(self.client.post(self.url("/chat/completions")))
+            .json(body)
+            .send()
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            let detail: String = text.chars().take(500).collect();
+            anyhow::bail!("POST /chat/completions failed: {} {}", status, detail);
+        }
+        Ok(resp)
+    }
+
+    /// 送出一次 completion 並把增量轉成事件；被 abort 時回傳 None
+    async fn complete(
+        &self,
+        messages: &[Value],
+        tools: bool,
+        cancel: &mut watch::Receiver<bool>,
+    ) -> anyhow::Result<Option<Completion>> {
+        let model = self.resolve_model().await?;
+        let body = self.request_body(&model, messages, true, tools).await;
+        let mut resp = tokio::select! {
+            resp = self.send_completion(&body) => resp?,
+            _ = cancel.wait_for(|c| *c) => return Ok(None),
+        };
+
+        let streaming = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("event-stream"));
+        if !streaming {
+            let val: Value = resp.json().await?;
+            let mut completion = Completion::from_message(&val["choices"][0]["message"]);
+            completion.usage = parse_usage(&val["usage"]);
+            self.emit(completion.thinking.clone(), completion.text.clone());
+            return Ok(Some(completion.finish()));
+        }
+
+        let mut completion = Completion::default();
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            let chunk = tokio::select! {
+                chunk = tokio::time::timeout(STREAM_IDLE_TIMEOUT, resp.chunk()) => chunk
+                    .map_err(|_| anyhow::anyhow!("Completion stream stalled"))??,
+                _ = cancel.wait_for(|c| *c) => return Ok(None),
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            buf.extend_from_slice(&chunk);
+            // 一行可能被切在兩個 chunk 之間，只處理完整的行
+            while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                match parse_sse_line(String::from_utf8_lossy(&line).trim()) {
+                    SseLine::Done => return Ok(Some(completion.finish())),
+                    SseLine::Data(val) if val.get("error").is_some() => {
+                        anyhow::bail!("Completion stream error: {}", val["error"]);
+                    }
+                    SseLine::Data(val) => {
+                        let (thinking, text) = completion.apply_chunk(&val);
+                        self.emit(thinking, text);
+                    }
+                    SseLine::Skip => {}
+                }
+            }
+        }
+        Ok(Some(completion.finish()))
+    }
+
+    /// 工具可用時回傳頻道的權限策略；策略為 deny 或沒有工作目錄時不提供工具
+    async fn tool_policy(&self) -> Option<PermissionPolicy> {
+        if !self.tools_enabled || self.workdir.is_none() {
+            return None;
+        }
+        let policy = self
+            .channels
+            .snapshot()
+            .get_permission_policy(&self.channel_id.to_string());
+        (policy != PermissionPolicy::Deny).then_some(policy)
+    }
+
+    async fn ask_permission(&self, call: &ToolCall) -> PermissionDecision {
+        if self.tools_always.load(Ordering::SeqCst) {
+            return PermissionDecision::AllowAlways;
+        }
+        let broker = permission::broker();
+        let (token, mut rx) = broker.register();
+        *self
+            .pending_permission
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(token.clone());
+        let event = AgentEvent::PermissionRequest {
+            token: token.clone(),
+            title: call.name.clone(),
+            detail: call.arguments.clone(),
+            choices: vec![
+                PermissionDecision::AllowOnce,
+                PermissionDecision::AllowAlways,
+                PermissionDecision::Deny,
+            ],
+        };
+        let decision = if self.event_tx.send(event).is_err() {
+            broker.resolve(&token, PermissionDecision::Deny);
+            PermissionDecision::Deny
+        } else {
+            permission::wait_decision(&mut rx).await
+        };
+        self.pending_permission
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if decision == PermissionDecision::AllowAlways {
+            self.tools_always.store(true, Ordering::SeqCst);
+        }
+        decision
+    }
+
+    async fn run_tool(&self, call: &ToolCall, policy: PermissionPolicy) -> String {
+        let _ = self.event_tx.send(AgentEvent::ToolExecutionStart {
+            id: call.id.clone(),
+            name: call.name.clone(),
+        });
+        let allowed = match policy {
+            PermissionPolicy::Auto => true,
+            PermissionPolicy::Ask => self.ask_permission(call).await.is_allowed(),
+            PermissionPolicy::Deny => false,
+        };
+        let output = match (&self.workdir, allowed) {
+            (Some(root), true) => execute_tool(root, &call.name, &call.arguments)
+                .await
+                .unwrap_or_else(|e| format!("Error: {}", e)),
+            _ => "Error: permission denied".to_string(),
+        };
+        let _ = self.event_tx.send(AgentEvent::ToolExecutionUpdate {
+            id: call.id.clone(),
+            output: output.clone(),
+        });
+        output
+    }
+
+    /// 跑完一個回合（含工具迴圈），各輪用量累加到 usage；被 abort 時回傳 false
+    async fn run_turn(
+        &self,
+        cancel: &mut watch::Receiver<bool>,
+        usage: &mut TurnUsage,
+    ) -> anyhow::Result<bool> {
+        let policy = self.tool_policy().await;
+        let mut round = 0;
+        loop {
+            if *cancel.borrow() {
+                return Ok(false);
+            }
+            let messages = self.history.lock().await.clone();
+            let Some(completion) = self.complete(&messages, policy.is_some(), cancel).await? else {
+                return Ok(false);
+            };
+            usage.add(completion.usage.clone());
+            self.history
+                .lock()
+                .await
+                .push(completion.assistant_message());
+            let Some(policy) = policy.filter(|_| !completion.tool_calls.is_empty()) else {
+                return Ok(true);
+            };
+            if round >= self.max_tool_rounds {
+                anyhow::bail!("Stopped after {} tool rounds", self.max_tool_rounds);
+            }
+            round += 1;
+            for call in &completion.tool_calls {
+                if *cancel.borrow() {
+                    return Ok(false);
+                }
+                let output = self.run_tool(call, policy).await;
+                self.history.lock().await.push(json!({
+                    "role": "tool",
+                    "tool_call_id": call.id,
+                    "content": output,
+                }));
+            }
+        }
+    }
+
+    fn resolve_pending_permission(&self) {
+        let token = self
+            .pending_permission
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(token) = token {
+            permission::broker().resolve(&token, PermissionDecision::Deny);
+        }
+    }
+}
+
+#[async_trait]
+impl AiAgent for OpenAiAgent {
+    async fn prompt(&self, message: &str) -> anyhow::Result<()> {
+        // 排隊前就重設，等待 turn_lock（例如 compact 中）期間收到的 abort 不會被蓋掉
+        self.cancel.send_replace(false);
+        let mut cancel = self.cancel.subscribe();
+        let _turn = self.turn_lock.lock().await;
+        self.prompt_running.send_replace(true);
+        let _running = RunningGuard(&self.prompt_running);
+
+        let rollback = {
+            let mut history = self.history.lock().await;
+            history.push(json!({ "role": "user", "content": message }));
+            history.len() - 1
+        };
+        let mut usage = TurnUsage::default();
+        let result = self.run_turn(&mut cancel, &mut usage).await;
+        // 失敗或中斷的回合不留在紀錄裡，下一次從乾淨的狀態開始
+        if !matches!(result, Ok(true)) {
+            self.history.lock().await.truncate(rollback);
+        }
+        if let Err(e) = self.save_history().await {
+            error!("❌ Failed to save OpenAI history: {}", e);
+        }
+        // 失敗或中斷的回合也已經消耗 token
+        if !usage.is_empty() {
+            usage.model = self
+                .model
+                .lock()
+                .await
+                .as_ref()
+                .map(|m| format!("{}/{}", PROVIDER, m));
+            let _ = self.event_tx.send(AgentEvent::Usage { usage });
+        }
+
+        match result {
+            Ok(true) => {
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: true,
+                    error: None,
+                });
+                Ok(())
+            }
+            Ok(false) => {
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: false,
+                    error: Some("Cancelled".to_string()),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                let err = e.to_string();
+                let _ = self.event_tx.send(AgentEvent::Error {
+                    message: err.clone(),
+                });
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: false,
+                    error: Some(err.clone()),
+                });
+                anyhow::bail!(err);
+            }
+        }
+    }
+
+    async fn set_session_name(&self, _name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_state(&self) -> anyhow::Result<AgentState> {
+        let message_count = self
+            .history
+            .lock()
+            .await
+            .iter()
+            .filter(|m| m["role"] == "user")
+            .count() as u64;
+        Ok(AgentState {
+            message_count,
+            model: self.model.lock().await.clone(),
+        })
+    }
+
+    /// 請模型摘要目前的對話，並以摘要取代整段紀錄
+    async fn compact(&self) -> anyhow::Result<()> {
+        let _turn = self.turn_lock.lock().await;
+        let mut messages = self.history.lock().await.clone();
+        if messages.is_empty() {
+            return Ok(());
+        }
+        messages.push(json!({ "role": "user", "content": COMPACT_PROMPT }));
+        let model = self.resolve_model().await?;
+        let body = self.request_body(&model, &messages, false, false).await;
+        let val: Value = self.send_completion(&body).await?.json().await?;
+        let summary = Completion::from_message(&val["choices"][0]["message"]).text;
+        if summary.trim().is_empty() {
+            anyhow::bail!("Summarization returned an empty reply");
+        }
+
+        let before = messages.len() - 1;
+        *self.history.lock().await = vec![json!({
+            "role": "system",
+            "content": format!("Summary of the earlier conversation:\n{}", summary.trim()),
+        })];
+        self.save_history().await?;
+        info!(
+            "🗜️ OpenAI channel {} compacted {} messages",
+            self.channel_id, before
+        );
+        Ok(())
+    }
+
+    async fn abort(&self) -> anyhow::Result<()> {
+        self.cancel.send_replace(true);
+        self.resolve_pending_permission();
+        let mut running = self.prompt_running.subscribe();
+        if tokio::time::timeout(CANCEL_WAIT, running.wait_for(|r| !*r))
+            .await
+            .is_err()
+        {
+            warn!(
+                "OpenAI prompt in channel {} did not stop within {:?} after abort",
+                self.channel_id, CANCEL_WAIT
+            );
+        }
+        Ok(())
+    }
+
+    /// 只清空記憶體中的紀錄；檔案由 /clear 一併刪除
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
+        if *self.prompt_running.borrow() {
+            self.abort().await?;
+        }
+        self.history.lock().await.clear();
+        self.tools_always.store(false, Ordering::SeqCst);
+        Ok(None)
+    }
+
+    async fn set_model(&self, provider: &str, model_id: &str) -> anyhow::Result<()> {
+        *self.model.lock().await = Some(model_id.to_string());
+
+        let channel_id = self.channel_id.to_string();
+        let result = self
+            .channels
+            .update(|config| {
+                if let Some(entry) = config.channels.get_mut(&channel_id) {
+                    entry.model_provider = Some(provider.to_string());
+                    entry.model_id = Some(model_id.to_string());
+                }
+                Ok(())
+            })
+            .await;
+        if let Err(e) = result {
+            error!("❌ Failed to persist OpenAI model selection: {}", e);
+        }
+        Ok(())
+    }
+
+    /// 對應 OpenAI 的 `reasoning_effort`；off 表示不送這個參數
+    async fn set_thinking_level(&self, level: &str) -> anyhow::Result<()> {
+        let effort = match level {
+            "off" => None,
+            "minimal" | "low" | "medium" | "high" => Some(level.to_string()),
+            "xhigh" => Some("high".to_string()),
+            other => anyhow::bail!("Unsupported thinking level: {}", other),
+        };
+        *self.reasoning_effort.lock().await = effort;
+        Ok(())
+    }
+
+    async fn get_available_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
+        self.list_models().await
+    }
+
+    async fn load_skill(&self, _name: &str) -> anyhow::Result<()> {
+        anyhow::bail!("OpenAI-compatible backend does not support loading skills")
+    }
+
+    fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
+        self.event_tx.subscribe()
+    }
+
+    fn agent_type(&self) -> String {
+        PROVIDER.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn sse(chunks: &[Value]) -> String {
+        let mut body: String = chunks.iter().map(|c| format!("data: {}\n\n", c)).collect();
+        body.push_str("data: [DONE]\n\n");
+        body
+    }
+
+    fn delta(delta: Value) -> Value {
+        json!({ "choices": [{ "index": 0, "delta": delta }] })
+    }
+
+    async fn build_agent(
+        server: &MockServer,
+        session_dir: &Path,
+        workdir: Option<PathBuf>,
+    ) -> Arc<OpenAiAgent> {
+        let config = OpenAiConfig {
+            base_url: format!("{}/v1/", server.uri()),
+            api_key: Some("sk-test".into()),
+            model: Some("m1".into()),
+            tools: true,
+            ..Default::default()
+        };
+        let channels = ChannelRegistry::in_dir(session_dir);
+        OpenAiAgent::new(7, config, session_dir, None, workdir, channels)
+            .await
+            .expect("agent")
+    }
+
+    fn drain(rx: &mut broadcast::Receiver<AgentEvent>) -> Vec<AgentEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_apply_chunk_accumulates_text_and_tool_calls() {
+        let mut completion = Completion::default();
+        let (thinking, text) = completion.apply_chunk(&delta(json!({"reasoning_content": "hm"})));
+        assert_eq!((thinking.as_str(), text.as_str()), ("hm", ""));
+        completion.apply_chunk(&delta(json!({"content": "Hi"})));
+        completion.apply_chunk(&delta(json!({"tool_calls": [
+            {"index": 0, "id": "c1", "function": {"name": "read_file", "arguments": "{\"pa"}}
+        ]})));
+        completion.apply_chunk(&delta(json!({"tool_calls": [
+            {"index": 0, "function": {"arguments": "th\":\"a\"}"}},
+            {"index": 1, "function": {"name": "list_dir", "arguments": "{}"}}
+        ]})));
+        let completion = completion.finish();
+        assert_eq!(completion.text, "Hi");
+        assert_eq!(completion.thinking, "hm");
+        assert_eq!(
+            completion.tool_calls,
+            vec![
+                ToolCall {
+                    id: "c1".into(),
+                    name: "read_file".into(),
+                    arguments: "{\"path\":\"a\"}".into(),
+                },
+                ToolCall {
+                    id: "call_1".into(),
+                    name: "list_dir".into(),
+                    arguments: "{}".into(),
+                },
+            ]
+        );
+        let message = completion.assistant_message();
+        assert_eq!(message["content"], "Hi");
+        assert_eq!(message["tool_calls"][1]["function"]["name"], "list_dir");
+    }
+
+    #[tokio::test]
+    async fn test_tools_stay_inside_the_workspace() {
+        let root = tempdir().expect("root");
+        std::fs::create_dir(root.path().join("src")).expect("mkdir");
+        std::fs::write(root.path().join("a.txt"), "hello").expect("write");
+        let outside = tempdir().expect("outside");
+        std::fs::write(outside.path().join("secret"), "x").expect("write");
+
+        let listing = execute_tool(root.path(), "list_dir", r#"{"path":"."}"#)
+            .await
+            .expect("list");
+        assert_eq!(listing, "a.txt\nsrc/");
+        let content = execute_tool(root.path(), "read_file", r#"{"path":"a.txt"}"#)
+            .await
+            .expect("read");
+        assert_eq!(content, "hello");
+
+        let escape = format!(
+            r#"{{"path":"{}"}}"#,
+            outside.path().join("secret").display()
+        );
+        assert!(execute_tool(root.path(), "read_file", &escape)
+            .await
+            .is_err());
+        assert!(execute_tool(root.path(), "read_file", r#"{"path":"../x"}"#)
+            .await
+            .is_err());
+        assert!(execute_tool(root.path(), "rm", "{}").await.is_err());
+
+        let long = truncate_output("é".repeat(MAX_TOOL_OUTPUT));
+        assert!(long.contains("truncated"));
+
+        std::fs::write(root.path().join("big.txt"), "x".repeat(MAX_TOOL_OUTPUT * 4))
+            .expect("write");
+        let big = execute_tool(root.path(), "read_file", r#"{"path":"big.txt"}"#)
+            .await
+            .expect("read");
+        assert!(big.starts_with(&"x".repeat(MAX_TOOL_OUTPUT)));
+        assert!(big.ends_with(&format!("{} bytes total)", MAX_TOOL_OUTPUT * 4)));
+    }
+
+    #[tokio::test]
+    async fn test_prompt_streams_deltas_and_persists_history() {
+        let server = MockServer::start().await;
+        let body = sse(&[
+            delta(json!({"role": "assistant", "reasoning_content": "think"})),
+            delta(json!({"content": "Hel"})),
+            delta(json!({"content": "lo"})),
+            json!({"choices": [], "usage": {
+                "prompt_tokens": 30,
+                "completion_tokens": 5,
+                "prompt_tokens_details": {"cached_tokens": 20}
+            }}),
+        ]);
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({
+                "model": "m1",
+                "stream": true,
+                "stream_options": {"include_usage": true}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempdir().expect("dir");
+        let agent = build_agent(&server, dir.path(), None).await;
+        let mut rx = agent.subscribe_events();
+        agent.prompt("Hi").await.expect("prompt");
+
+        let events = drain(&mut rx);
+        let text: String = events
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::MessageUpdate { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "Hello");
+        let usage = events.iter().find_map(|e| match e {
+            AgentEvent::Usage { usage } => Some(usage.clone()),
+            _ => None,
+        });
+        let usage = usage.expect("usage event");
+        assert_eq!(usage.model.as_deref(), Some("openai/m1"));
+        assert_eq!((usage.input_tokens, usage.cache_read_tokens), (10, 20));
+        assert_eq!(usage.output_tokens, 5);
+        assert!(matches!(
+            events.last(),
+            Some(AgentEvent::AgentEnd { success: true, .. })
+        ));
+
+        // 新建的 agent 從 sessions 目錄接回紀錄
+        let reloaded = build_agent(&server, dir.path(), None).await;
+        let history = reloaded.history.lock().await.clone();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1]["content"], "Hello");
+        assert_eq!(reloaded.get_state().await.expect("state").message_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_feeds_results_back_to_the_model() {
+        let server = MockServer::start().await;
+        let first = sse(&[delta(json!({"tool_calls": [{
+            "index": 0, "id": "c1",
+            "function": {"name": "read_file", "arguments": "{\"path\":\"notes.txt\"}"}
+        }]}))]);
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(first, "text/event-stream"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{"message": {"role": "assistant", "content": "It says hi"}}]
+            })))
+            .mount(&server)
+            .await;
+
+        let dir = tempdir().expect("dir");
+        let workdir = tempdir().expect("workdir");
+        std::fs::write(workdir.path().join("notes.txt"), "hi").expect("write");
+        let agent = build_agent(&server, dir.path(), Some(workdir.path().to_path_buf())).await;
+        let mut rx = agent.subscribe_events();
+        agent.prompt("What is in notes.txt?").await.expect("prompt");
+
+        let requests = server.received_requests().await.expect("requests");
+        assert_eq!(requests.len(), 2);
+        let first_body: Value = serde_json::from_slice(&requests[0].body).expect("json");
+        assert_eq!(first_body["tools"][1]["function"]["name"], "read_file");
+        let second: Value = serde_json::from_slice(&requests[1].body).expect("json");
+        let tool_message = &second["messages"][2];
+        assert_eq!(tool_message["role"], "tool");
+        assert_eq!(tool_message["tool_call_id"], "c1");
+        assert_eq!(tool_message["content"], "hi");
+
+        let events = drain(&mut rx);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            AgentEvent::ToolExecutionUpdate { output, .. } if output == "hi"
+        )));
+        assert_eq!(agent.history.lock().await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_turn_is_rolled_back() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("boom"))
+            .mount(&server)
+            .await;
+
+        let dir = tempdir().expect("dir");
+        let agent = build_agent(&server, dir.path(), None).await;
+        let mut rx = agent.subscribe_events();
+        let err = agent.prompt("Hi").await.expect_err("should fail");
+        assert!(err.to_string().contains("500"));
+        assert!(agent.history.lock().await.is_empty());
+        assert!(drain(&mut rx)
+            .iter()
+            .any(|e| matches!(e, AgentEvent::AgentEnd { success: false, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_abort_while_prompt_waits_for_turn_cancels_it() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let dir = tempdir().expect("dir");
+        let agent = build_agent(&server, dir.path(), None).await;
+        let mut rx = agent.subscribe_events();
+        // 模擬 compact 佔住回合時送來的訊息與 abort
+        let turn = agent.turn_lock.lock().await;
+        let queued = tokio::spawn({
+            let agent = Arc::clone(&agent);
+            async move { agent.prompt("Hi").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        agent.abort().await.expect("abort");
+        drop(turn);
+
+        queued.await.expect("join").expect("prompt");
+        assert!(agent.history.lock().await.is_empty());
+        assert!(drain(&mut rx).iter().any(|e| matches!(
+            e,
+            AgentEvent::AgentEnd { success: false, error: Some(err) } if err == "Cancelled"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_compact_replaces_history_with_summary_and_lists_models() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({"stream": false})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{"message": {"role": "assistant", "content": " User likes Rust. "}}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "data": [{"id": "m1", "object": "model"}, {"id": "m2", "object": "model"}]
+            })))
+            .mount(&server)
+            .await;
+
+        let dir = tempdir().expect("dir");
+        let agent = build_agent(&server, dir.path(), None).await;
+        agent.history.lock().await.extend([
+            json!({"role": "user", "content": "I like Rust"}),
+            json!({"role": "assistant", "content": "Nice"}),
+        ]);
+        agent.compact().await.expect("compact");
+        let history = agent.history.lock().await.clone();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["role"], "system");
+        assert!(history[0]["content"]
+            .as_str()
+            .unwrap()
+            .ends_with("User likes Rust."));
+
+        let models = agent.get_available_models().await.expect("models");
+        let ids: Vec<&str> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["m1", "m2"]);
+        assert_eq!(models[0].provider, "openai");
+    }
+}
@@ -1,8 +1,11 @@
-use super::{AgentEvent, AgentState, AiAgent, ContentItem, ContentType, ModelInfo, UserInput};
+use super::events::{EventSink, EventStream};
+use super::manager::ServerEndpoint;
+use super::{
+    AgentEvent, AgentState, AiAgent, ContentItem, ContentType, ModelInfo, TurnUsage, UserInput,
+};
+use crate::channels::ChannelRegistry;
 use async_trait::async_trait;
 use base64::Engine;
-use eventsource_client::{Client, ClientBuilder, SSE};
-use futures::StreamExt;
 use serde_json::{json, Value};
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -10,7 +13,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[derive(Debug, Clone, PartialEq)]
 enum RealtimeEventAction {
@@ -27,6 +30,8 @@ enum RealtimeEventAction {
         id: String,
         output: String,
     },
+    /// 多步驟回合中的一則 assistant 訊息完成，回合還沒結束
+    StepCompleted,
     TurnCompleted,
     Error(String),
     Ignore,
@@ -35,108 +40,210 @@ enum RealtimeEventAction {
 pub struct OpencodeAgent {
     client: reqwest::Client,
     api_key: String,
-    base_url: String,
-    pub session_id: String,
+    // 受管的 server 重啟後會換 port，由 BackendManager 改寫
+    base_url: std::sync::RwLock<String>,
+    // clear 會換成新的 server session
+    session_id: std::sync::RwLock<String>,
     channel_id: u64,
     event_tx: broadcast::Sender<AgentEvent>,
     current_model: Arc<Mutex<Option<(String, String)>>>,
     turn_failed: Arc<AtomicBool>,
     agent_type_name: &'static str,
+    // 頻道綁定的工作目錄，透過 `directory` query 參數傳給 server
+    directory: Option<String>,
+    prompt_options: Mutex<PromptOptions>,
+    // 送出 prompt 後到 turn 結束前為 true；SSE 重連時據此補同步
+    turn_active: AtomicBool,
+    // 由 BackendManager 掛上的共用 SSE 訂閱
+    event_stream: std::sync::Mutex<Option<Arc<EventStream>>>,
+    // 模型、session 與 prompt 選項寫回頻道設定
+    channels: Arc<ChannelRegistry>,
+}
+
+/// 每次送出訊息時附帶的選項
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PromptOptions {
+    /// 模型的 reasoning variant（/thinking 設定），None 表示使用模型預設
+    variant: Option<String>,
+    /// 以 /skill 選定的 server agent
+    agent: Option<String>,
+}
+
+/// 組出 API 網址；有工作目錄時附上 `?directory=`
+fn build_url(base_url: &str, path: &str, directory: Option<&str>) -> String {
+    let url = format!("{}{}", base_url, path);
+    match directory {
+        Some(dir) => reqwest::Url::parse_with_params(&url, &[("directory", dir)])
+            .map(|u| u.to_string())
+            .unwrap_or(url),
+        None => url,
+    }
 }
 
 impl OpencodeAgent {
     const MAX_INLINE_FILE_BYTES: u64 = 4 * 1024 * 1024;
 
+    fn url(&self, path: &str) -> String {
+        let base_url = self.base_url.read().unwrap_or_else(|e| e.into_inner());
+        build_url(&base_url, path, self.directory.as_deref())
+    }
+
+    pub fn session_id(&self) -> String {
+        self.session_id
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    async fn create_session(
+        client: &reqwest::Client,
+        url: &str,
+        api_key: &str,
+        channel_id: u64,
+    ) -> anyhow::Result<String> {
+        let resp = client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&json!({ "title": format!("Discord #{}", channel_id) }))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Create session failed: {}", resp.status());
+        }
+        let info: Value = resp.json().await?;
+        Ok(info["id"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Create failed"))?
+            .to_string())
+    }
+
+    /// GET 一個列表型 API（/command、/agent），回傳每個項目的 name；404 代表 server 不支援
+    async fn list_names(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        let resp = self
+            .client
+            .get(self.url(path))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        if !resp.status().is_success() {
+            anyhow::bail!("GET {} failed: {}", path, resp.status());
+        }
+        let val: Value = resp.json().await?;
+        Ok(val
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|i| i["name"].as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
     pub async fn new(
         channel_id: u64,
-        base_url: String,
-        api_key: String,
+        endpoint: ServerEndpoint,
         existing_sid: Option<String>,
         model_opt: Option<(String, String)>,
         agent_type_name: &'static str,
+        directory: Option<String>,
+        channels: Arc<ChannelRegistry>,
     ) -> anyhow::Result<Arc<Self>> {
+        let ServerEndpoint {
+            base_url,
+            password: api_key,
+        } = endpoint;
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(120))
             .build()?;
-        let mut session_id = existing_sid;
-
-        if session_id.is_none() {
-            info!(
-                "Creating NEW {} session for channel {}",
-                agent_type_name, channel_id
-            );
-            let resp = client
-                .post(format!("{}/session", base_url))
-                .header("Authorization", format!("Bearer {}", api_key))
-                .json(&json!({ "title": format!("Discord #{}", channel_id) }))
-                .send()
-                .await?;
-            let info: Value = resp.json().await?;
-            session_id = Some(
-                info["id"]
-                    .as_str()
-                    .ok_or_else(|| anyhow::anyhow!("Create failed"))?
-                    .to_string(),
-            );
-        }
-
-        let session_id = session_id.unwrap();
+        let session_id = match existing_sid {
+            Some(sid) => sid,
+            None => {
+                info!(
+                    "Creating NEW {} session for channel {}",
+                    agent_type_name, channel_id
+                );
+                Self::create_session(
+                    &client,
+                    &build_url(&base_url, "/session", directory.as_deref()),
+                    &api_key,
+                    channel_id,
+                )
+                .await?
+            }
+        };
         let (event_tx, _) = broadcast::channel(1000);
         let current_model = Arc::new(Mutex::new(model_opt));
         let turn_failed = Arc::new(AtomicBool::new(false));
 
-        let agent = Arc::new(Self {
+        Ok(Arc::new(Self {
             client,
-            api_key: api_key.clone(),
-            base_url: base_url.clone(),
-            session_id: session_id.clone(),
+            api_key,
+            base_url: std::sync::RwLock::new(base_url),
+            session_id: std::sync::RwLock::new(session_id),
             channel_id,
-            event_tx: event_tx.clone(),
+            event_tx,
             current_model,
             turn_failed,
             agent_type_name,
-        });
+            directory,
+            prompt_options: Mutex::new(PromptOptions::default()),
+            turn_active: AtomicBool::new(false),
+            event_stream: std::sync::Mutex::new(None),
+            channels,
+        }))
+    }
 
-        let sse_url = format!("{}/event", base_url);
-        let agent_weak = Arc::downgrade(&agent);
-        let auth_header = format!("Bearer {}", api_key);
+    /// 同一個 server 與工作目錄的 agent 共用這條 SSE
+    pub fn event_url(&self) -> String {
+        self.url("/event")
+    }
 
-        tokio::spawn(async move {
-            let mut retry = 0;
-            loop {
-                let sse_client = match ClientBuilder::for_url(&sse_url) {
-                    Ok(b) => match b.header("Authorization", &auth_header) {
-                        Ok(b) => b.build(),
-                        Err(_) => break,
-                    },
-                    Err(_) => break,
-                };
-                let mut stream = sse_client.stream();
-                while let Some(event) = stream.next().await {
-                    retry = 0;
-                    if let Ok(val) = serde_json::from_str::<Value>(&match event {
-                        Ok(SSE::Event(e)) => e.data,
-                        _ => continue,
-                    }) {
-                        if let Some(agent) = agent_weak.upgrade() {
-                            agent.handle_event(val).await;
-                        } else {
-                            return;
-                        }
-                    }
-                }
-                if agent_weak.strong_count() == 0 || retry > 10 {
-                    break;
-                }
-                retry += 1;
-                tokio::time::sleep(Duration::from_secs(2)).await;
-            }
-        });
+    pub fn auth_header(&self) -> String {
+        format!("Bearer {}", self.api_key)
+    }
+
+    pub fn set_event_stream(&self, stream: Arc<EventStream>) {
+        *self.event_stream.lock().unwrap_or_else(|e| e.into_inner()) = Some(stream);
+    }
+
+    /// 受管的 server 重啟到新 port 後，讓既有 session 改連新位址
+    pub fn rebase(&self, base_url: String) {
+        *self.base_url.write().unwrap_or_else(|e| e.into_inner()) = base_url;
+    }
+
+    /// 還原頻道設定中保存的 variant 與 agent 選擇
+    pub async fn restore_prompt_options(&self, variant: Option<String>, agent: Option<String>) {
+        *self.prompt_options.lock().await = PromptOptions { variant, agent };
+    }
 
-        Ok(agent)
+    /// 把目前的 variant 與 agent 選擇寫回頻道設定，回收或重啟後沿用
+    async fn persist_prompt_options(&self) {
+        let options = self.prompt_options.lock().await.clone();
+        let channel_id = self.channel_id.to_string();
+        let result = self
+            .channels
+            .update(|config| {
+                if let Some(entry) = config.channels.get_mut(&channel_id) {
+                    entry.variant = options.variant;
+                    entry.server_agent = options.agent;
+                }
+                Ok(())
+            })
+            .await;
+        if let Err(e) = result {
+            error!("❌ Failed to persist prompt options: {}", e);
+        }
     }
 
-    async fn construct_message_body(input: &UserInput, model_opt: &Option<(String, String)>) -> Value {
+    async fn construct_message_body(
+        input: &UserInput,
+        model_opt: &Option<(String, String)>,
+        options: &PromptOptions,
+    ) -> Value {
         let (text, extra_parts) = Self::build_parts_from_input(input).await;
         let mut parts = vec![json!({ "type": "text", "text": text })];
         parts.extend(extra_parts);
@@ -145,9 +252,31 @@ impl OpencodeAgent {
         if let Some((provider, model)) = model_opt {
             body["model"] = json!({ "providerID": provider, "modelID": model });
         }
+        if let Some(variant) = &options.variant {
+            body["variant"] = json!(variant);
+        }
+        if let Some(agent) = &options.agent {
+            body["agent"] = json!(agent);
+        }
         body
     }
 
+    /// 從 /provider 回應中取出模型支援的 reasoning variants；找不到模型時回傳 None
+    fn model_variants(providers: &Value, provider: &str, model: &str) -> Option<Vec<String>> {
+        let model = providers["all"]
+            .as_array()?
+            .iter()
+            .find(|p| p["id"] == provider)?
+            .get("models")?
+            .get(model)?;
+        Some(
+            model["variants"]
+                .as_object()
+                .map(|v| v.keys().cloned().collect())
+                .unwrap_or_default(),
+        )
+    }
+
     async fn build_parts_from_input(input: &UserInput) -> (String, Vec<Value>) {
         if input.files.is_empty() {
             return (input.text.clone(), Vec::new());
@@ -202,47 +331,6 @@ impl OpencodeAgent {
         Duration::from_secs(2)
     }
 
-    async fn handle_event(&self, val: Value) {
-        let type_ = val["type"].as_str().unwrap_or("");
-        // 只記錄關鍵事件，避免日誌過多
-        if !type_.contains("delta") {
-            info!("📡 SSE Event: type={}", type_);
-        }
-
-        match Self::parse_realtime_event(&val) {
-            RealtimeEventAction::MessageUpdate { thinking, text, id } => {
-                let _ = self.event_tx.send(AgentEvent::MessageUpdate {
-                    thinking,
-                    text,
-                    is_delta: true,
-                    id,
-                });
-            }
-            RealtimeEventAction::ToolStart { id, name } => {
-                let _ = self.event_tx.send(AgentEvent::ToolExecutionStart { id, name });
-            }
-            RealtimeEventAction::ToolUpdate { id, output } => {
-                let _ = self.event_tx.send(AgentEvent::ToolExecutionUpdate { id, output });
-            }
-            RealtimeEventAction::TurnCompleted => {
-                info!("🏁 Turn completed signal received: {}", type_);
-                if !self.turn_failed.load(Ordering::SeqCst) {
-                    self.trigger_sync().await;
-                }
-            }
-            RealtimeEventAction::Error(msg) => {
-                error!("❌ FULL ERROR JSON: {}", val);
-                error!("❌ Backend Error Summary: {}", msg);
-                self.turn_failed.store(true, Ordering::SeqCst);
-                let _ = self.event_tx.send(AgentEvent::AgentEnd {
-                    success: false,
-                    error: Some(msg),
-                });
-            }
-            RealtimeEventAction::Ignore => {}
-        }
-    }
-
     fn parse_realtime_event(val: &Value) -> RealtimeEventAction {
         let type_ = val["type"].as_str().unwrap_or("");
         let properties = &val["properties"];
@@ -252,12 +340,13 @@ impl OpencodeAgent {
             "message.part.updated" | "message.part.delta" | "session.message.part.delta" => {
                 Self::parse_delta_event(properties, data)
             }
-            "session.turn.close"
-            | "session.message.completed"
-            | "turn.close"
-            | "message.completed"
-            | "turn.end"
-            | "session.idle" => RealtimeEventAction::TurnCompleted,
+            "session.message.completed" | "message.completed" => RealtimeEventAction::StepCompleted,
+            "session.turn.close" | "turn.close" | "turn.end" | "session.idle" => {
+                RealtimeEventAction::TurnCompleted
+            }
+            "session.status" if properties["status"]["type"] == "idle" => {
+                RealtimeEventAction::TurnCompleted
+            }
             "session.error" | "error" => {
                 let msg = Self::extract_error_message(properties, data);
                 RealtimeEventAction::Error(msg)
@@ -348,10 +437,77 @@ impl OpencodeAgent {
             .to_string()
     }
 
+    /// `/session/status` 只列出忙碌中（busy/retry）的 session，沒列出就是閒置
+    fn status_busy(status: &Value, session_id: &str) -> bool {
+        status[session_id]["type"]
+            .as_str()
+            .is_some_and(|t| t != "idle")
+    }
+
+    /// 加總最後一則使用者訊息之後各 assistant 訊息的 tokens 與 cost
+    fn turn_usage(msgs: &Value) -> TurnUsage {
+        let msgs = msgs.as_array().map(Vec::as_slice).unwrap_or(&[]);
+        let info = |m: &'_ Value| -> Value {
+            if m["info"].is_object() {
+                m["info"].clone()
+            } else {
+                m.clone()
+            }
+        };
+        let start = msgs
+            .iter()
+            .rposition(|m| info(m)["role"] == "user")
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let mut total = TurnUsage::default();
+        for info in msgs[start..].iter().map(info) {
+            if info["role"] != "assistant" {
+                continue;
+            }
+            let tokens = &info["tokens"];
+            let count = |v: &Value| v.as_u64().unwrap_or(0);
+            let model = match (info["providerID"].as_str(), info["modelID"].as_str()) {
+                (Some(provider), Some(model)) => Some(format!("{}/{}", provider, model)),
+                _ => None,
+            };
+            total.add(TurnUsage {
+                model,
+                input_tokens: count(&tokens["input"]),
+                // reasoning token 也按輸出計費
+                output_tokens: count(&tokens["output"]) + count(&tokens["reasoning"]),
+                cache_read_tokens: count(&tokens["cache"]["read"]),
+                cache_write_tokens: count(&tokens["cache"]["write"]),
+                cost: info["cost"].as_f64().unwrap_or(0.0),
+            });
+        }
+        total
+    }
+
+    /// 向 server 查詢目前 session 是否仍在產生回覆
+    async fn session_busy(&self) -> anyhow::Result<bool> {
+        let resp = self
+            .client
+            .get(self.url("/session/status"))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("GET session status failed: {}", resp.status());
+        }
+        let status: Value = resp.json().await?;
+        Ok(Self::status_busy(&status, &self.session_id()))
+    }
+
+    /// 同步最終內容與用量，並結束回合
     async fn trigger_sync(&self) {
+        self.sync_content(true).await;
+    }
+
+    /// 拉取最後一則 assistant 訊息覆蓋畫面；`finish` 時一併回報用量並送出 AgentEnd
+    async fn sync_content(&self, finish: bool) {
         let client = self.client.clone();
         let api_key = self.api_key.clone();
-        let url = format!("{}/session/{}/message", self.base_url, self.session_id);
+        let url = self.url(&format!("/session/{}/message", self.session_id()));
         let tx = self.event_tx.clone();
         let turn_failed = Arc::clone(&self.turn_failed); // 克隆 Arc 以進入 spawn
         tokio::spawn(async move {
@@ -393,10 +549,13 @@ impl OpencodeAgent {
                             let _ = tx.send(AgentEvent::ContentSync { items });
                         }
                     }
+                    let usage = Self::turn_usage(&msgs);
+                    if finish && !usage.is_empty() {
+                        let _ = tx.send(AgentEvent::Usage { usage });
+                    }
                 }
             }
-            let failed = turn_failed.load(Ordering::SeqCst);
-            if !failed {
+            if finish && !turn_failed.load(Ordering::SeqCst) {
                 let _ = tx.send(AgentEvent::AgentEnd {
                     success: true,
                     error: None,
@@ -406,6 +565,83 @@ impl OpencodeAgent {
     }
 }
 
+#[async_trait]
+impl EventSink for OpencodeAgent {
+    async fn handle_event(&self, val: Value) {
+        let type_ = val["type"].as_str().unwrap_or("");
+        // 只記錄關鍵事件，避免日誌過多
+        if !type_.contains("delta") {
+            info!("📡 SSE Event: type={}", type_);
+        }
+
+        match Self::parse_realtime_event(&val) {
+            RealtimeEventAction::MessageUpdate { thinking, text, id } => {
+                let _ = self.event_tx.send(AgentEvent::MessageUpdate {
+                    thinking,
+                    text,
+                    is_delta: true,
+                    id,
+                });
+            }
+            RealtimeEventAction::ToolStart { id, name } => {
+                let _ = self.event_tx.send(AgentEvent::ToolExecutionStart { id, name });
+            }
+            RealtimeEventAction::ToolUpdate { id, output } => {
+                let _ = self.event_tx.send(AgentEvent::ToolExecutionUpdate { id, output });
+            }
+            // 只補畫面；用量與 AgentEnd 等整個回合閒置後才送，後面步驟的用量才不會漏掉
+            RealtimeEventAction::StepCompleted => {
+                if self.turn_active.load(Ordering::SeqCst)
+                    && !self.turn_failed.load(Ordering::SeqCst)
+                {
+                    self.sync_content(false).await;
+                }
+            }
+            RealtimeEventAction::TurnCompleted => {
+                info!("🏁 Turn completed signal received: {}", type_);
+                // session.idle 與 session.status 可能都會送來，只結束一次
+                if self.turn_active.swap(false, Ordering::SeqCst)
+                    && !self.turn_failed.load(Ordering::SeqCst)
+                {
+                    self.trigger_sync().await;
+                }
+            }
+            RealtimeEventAction::Error(msg) => {
+                error!("❌ FULL ERROR JSON: {}", val);
+                error!("❌ Backend Error Summary: {}", msg);
+                self.turn_failed.store(true, Ordering::SeqCst);
+                self.turn_active.store(false, Ordering::SeqCst);
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: false,
+                    error: Some(msg),
+                });
+            }
+            RealtimeEventAction::Ignore => {}
+        }
+    }
+
+    async fn resync(&self) {
+        // 斷線期間可能錯過 turn 結束的事件，直接向 server 拿最新內容
+        if !self.turn_active.load(Ordering::SeqCst) {
+            return;
+        }
+        info!("🔄 Resyncing session {} after reconnect", self.session_id());
+        match self.session_busy().await {
+            // 還在產生中：只補畫面，等真正的 session.idle 或錯誤再結束
+            Ok(true) => self.sync_content(false).await,
+            Ok(false) => {
+                if self.turn_active.swap(false, Ordering::SeqCst) {
+                    self.trigger_sync().await;
+                }
+            }
+            Err(e) => {
+                warn!("⚠️ Session {} status unavailable: {}", self.session_id(), e);
+                self.sync_content(false).await;
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl AiAgent for OpencodeAgent {
     async fn prompt(&self, message: &str) -> anyhow::Result<()> {
@@ -414,10 +650,12 @@ impl AiAgent for OpencodeAgent {
     }
 
     async fn prompt_with_input(&self, input: &UserInput) -> anyhow::Result<()> {
-        let url = format!("{}/session/{}/message", self.base_url, self.session_id);
+        let url = self.url(&format!("/session/{}/message", self.session_id()));
         self.turn_failed.store(false, Ordering::SeqCst);
+        self.turn_active.store(true, Ordering::SeqCst);
         let model_opt = self.current_model.lock().await.clone();
-        let body = Self::construct_message_body(input, &model_opt).await;
+        let options = self.prompt_options.lock().await.clone();
+        let body = Self::construct_message_body(input, &model_opt, &options).await;
 
         let max_retries = 3;
         let retry_delay = Self::retry_delay();
@@ -443,12 +681,19 @@ impl AiAgent for OpencodeAgent {
 
                     let status = resp.status();
                     if status == 404 {
-                        let mut config = crate::commands::agent::ChannelConfig::load().await?;
-                        if let Some(entry) = config.channels.get_mut(&self.channel_id.to_string()) {
-                            entry.session_id = None;
-                            if let Err(e) = config.save().await {
-                                error!("❌ Failed to clear expired session id: {}", e);
-                            }
+                        self.turn_active.store(false, Ordering::SeqCst);
+                        let channel_id = self.channel_id.to_string();
+                        let result = self
+                            .channels
+                            .update(|config| {
+                                if let Some(entry) = config.channels.get_mut(&channel_id) {
+                                    entry.session_id = None;
+                                }
+                                Ok(())
+                            })
+                            .await;
+                        if let Err(e) = result {
+                            error!("❌ Failed to clear expired session id: {}", e);
                         }
                         let _ = self.event_tx.send(AgentEvent::AgentEnd {
                             success: false,
@@ -481,6 +726,7 @@ impl AiAgent for OpencodeAgent {
             }
         }
 
+        self.turn_active.store(false, Ordering::SeqCst);
         if let Some(err_msg) = last_error_message {
             let _ = self.event_tx.send(AgentEvent::Error {
                 message: err_msg.clone(),
@@ -490,7 +736,7 @@ impl AiAgent for OpencodeAgent {
         anyhow::bail!("Prompt failed after all retries")
     }
     async fn get_state(&self) -> anyhow::Result<AgentState> {
-        let url = format!("{}/session/{}", self.base_url, self.session_id);
+        let url = self.url(&format!("/session/{}", self.session_id()));
         let resp = self
             .client
             .get(url)
@@ -505,12 +751,18 @@ impl AiAgent for OpencodeAgent {
             });
         }
         if resp.status() == 404 {
-            let mut config = crate::commands::agent::ChannelConfig::load().await?;
-            if let Some(entry) = config.channels.get_mut(&self.channel_id.to_string()) {
-                entry.session_id = None;
-                if let Err(e) = config.save().await {
-                    error!("❌ Failed to clear missing session id: {}", e);
-                }
+            let channel_id = self.channel_id.to_string();
+            let result = self
+                .channels
+                .update(|config| {
+                    if let Some(entry) = config.channels.get_mut(&channel_id) {
+                        entry.session_id = None;
+                    }
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = result {
+                error!("❌ Failed to clear missing session id: {}", e);
             }
         }
         Ok(AgentState {
@@ -521,33 +773,86 @@ impl AiAgent for OpencodeAgent {
     async fn set_model(&self, provider: &str, mid: &str) -> anyhow::Result<()> {
         let mut m = self.current_model.lock().await;
         *m = Some((provider.into(), mid.into()));
-        let mut config = crate::commands::agent::ChannelConfig::load().await?;
-        if let Some(entry) = config.channels.get_mut(&self.channel_id.to_string()) {
-            entry.model_provider = Some(provider.into());
-            entry.model_id = Some(mid.into());
-            if let Err(e) = config.save().await {
-                error!("❌ Failed to persist model selection: {}", e);
-            }
+        let channel_id = self.channel_id.to_string();
+        let result = self
+            .channels
+            .update(|config| {
+                if let Some(entry) = config.channels.get_mut(&channel_id) {
+                    entry.model_provider = Some(provider.into());
+                    entry.model_id = Some(mid.into());
+                }
+                Ok(())
+            })
+            .await;
+        if let Err(e) = result {
+            error!("❌ Failed to persist model selection: {}", e);
         }
         Ok(())
     }
+    async fn resume_turn(&self) -> anyhow::Result<()> {
+        self.turn_failed.store(false, Ordering::SeqCst);
+        self.turn_active.store(true, Ordering::SeqCst);
+        match self.session_busy().await {
+            // 重啟期間已經結束：直接同步最終內容
+            Ok(false) => {
+                info!("🔄 Turn of session {} finished while offline", self.session_id());
+                self.turn_active.store(false, Ordering::SeqCst);
+                self.trigger_sync().await;
+                Ok(())
+            }
+            // 仍在產生中：先補上離線期間的內容，再等共用 SSE 送來結束事件
+            Ok(true) => {
+                self.sync_content(false).await;
+                Ok(())
+            }
+            Err(e) => {
+                self.turn_active.store(false, Ordering::SeqCst);
+                let _ = self.event_tx.send(AgentEvent::AgentEnd {
+                    success: false,
+                    error: Some(e.to_string()),
+                });
+                Err(e)
+            }
+        }
+    }
     async fn abort(&self) -> anyhow::Result<()> {
         let _ = self
             .client
-            .post(format!(
-                "{}/session/{}/abort",
-                self.base_url, self.session_id
-            ))
+            .post(self.url(&format!("/session/{}/abort", self.session_id())))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .send()
             .await;
         Ok(())
     }
-    async fn clear(&self) -> anyhow::Result<()> {
-        Ok(())
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
+        // 先中斷舊 session 上進行中的回合，免得它在背景繼續跑
+        if self.turn_active.load(Ordering::SeqCst) {
+            self.abort().await?;
+        }
+        let new_sid =
+            Self::create_session(&self.client, &self.url("/session"), &self.api_key, self.channel_id)
+                .await?;
+        let old_sid = std::mem::replace(
+            &mut *self.session_id.write().unwrap_or_else(|e| e.into_inner()),
+            new_sid.clone(),
+        );
+        let stream = self
+            .event_stream
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(stream) = stream {
+            stream.reroute(&old_sid, &new_sid).await;
+        }
+        self.turn_active.store(false, Ordering::SeqCst);
+        info!(
+            "🧹 {} channel {} moved from session {} to {}",
+            self.agent_type_name, self.channel_id, old_sid, new_sid
+        );
+        Ok(Some(new_sid))
     }
     async fn compact(&self) -> anyhow::Result<()> {
-        let url = format!("{}/session/{}/message", self.base_url, self.session_id);
+        let url = self.url(&format!("/session/{}/message", self.session_id()));
         let body = json!({
             "parts": [{"type": "text", "text": "/compact"}]
         });
@@ -566,13 +871,58 @@ impl AiAgent for OpencodeAgent {
     async fn set_session_name(&self, _n: &str) -> anyhow::Result<()> {
         Ok(())
     }
-    async fn set_thinking_level(&self, _l: &str) -> anyhow::Result<()> {
+    async fn set_thinking_level(&self, level: &str) -> anyhow::Result<()> {
+        if level == "off" {
+            self.prompt_options.lock().await.variant = None;
+            self.persist_prompt_options().await;
+            return Ok(());
+        }
+        // variant 依模型而定，先確認 server 回報目前模型支援這個 variant
+        let Some((provider, model)) = self.current_model.lock().await.clone() else {
+            anyhow::bail!("Unsupported: select a model with /model before setting thinking");
+        };
+        let resp = self
+            .client
+            .get(self.url("/provider"))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Provider list failed: {}", resp.status());
+        }
+        let providers: Value = resp.json().await?;
+        let Some(variants) = Self::model_variants(&providers, &provider, &model) else {
+            anyhow::bail!(
+                "Unsupported: {}/{} is not available on this {} server",
+                provider,
+                model,
+                self.agent_type_name
+            );
+        };
+        if variants.is_empty() {
+            anyhow::bail!(
+                "Unsupported: {}/{} has no reasoning variants on this {} server",
+                provider,
+                model,
+                self.agent_type_name
+            );
+        }
+        if !variants.iter().any(|v| v == level) {
+            anyhow::bail!(
+                "Unsupported: {}/{} supports {}",
+                provider,
+                model,
+                variants.join(", ")
+            );
+        }
+        self.prompt_options.lock().await.variant = Some(level.to_string());
+        self.persist_prompt_options().await;
         Ok(())
     }
     async fn get_available_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
         let resp = self
             .client
-            .get(format!("{}/provider", self.base_url))
+            .get(self.url("/provider"))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .send()
             .await?;
@@ -605,14 +955,38 @@ impl AiAgent for OpencodeAgent {
         }
         Ok(models)
     }
-    async fn load_skill(&self, _n: &str) -> anyhow::Result<()> {
-        Ok(())
+    async fn load_skill(&self, name: &str) -> anyhow::Result<()> {
+        // server 端的 slash command：在目前 session 執行，等指令跑完再回報結果
+        if self.list_names("/command").await?.iter().any(|c| c == name) {
+            let resp = self
+                .client
+                .post(self.url(&format!("/session/{}/command", self.session_id())))
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&json!({ "command": name, "arguments": "" }))
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                anyhow::bail!("Command {} failed: {}", name, resp.status());
+            }
+            return Ok(());
+        }
+        // server 端的 agent：之後的訊息都交給它處理
+        if self.list_names("/agent").await?.iter().any(|a| a == name) {
+            self.prompt_options.lock().await.agent = Some(name.to_string());
+            self.persist_prompt_options().await;
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Unsupported: {} is neither a command nor an agent on this {} server",
+            name,
+            self.agent_type_name
+        )
     }
     fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
         self.event_tx.subscribe()
     }
-    fn agent_type(&self) -> &'static str {
-        self.agent_type_name
+    fn agent_type(&self) -> String {
+        self.agent_type_name.to_string()
     }
 }
 
@@ -620,41 +994,273 @@ impl AiAgent for OpencodeAgent {
 mod tests {
     use super::*;
     use crate::agent::{UploadedFile, UserInput};
-    use crate::migrate::BASE_DIR_ENV;
     use serde_json::json;
-    use std::sync::{Mutex as StdMutex, OnceLock};
     use tempfile::tempdir;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    fn env_lock() -> &'static StdMutex<()> {
-        static LOCK: OnceLock<StdMutex<()>> = OnceLock::new();
-        LOCK.get_or_init(|| StdMutex::new(()))
-    }
-
     fn build_test_agent(
         mock_server: &MockServer,
         api_key: &str,
         session_id: &str,
+        channels: &Arc<ChannelRegistry>,
     ) -> (OpencodeAgent, broadcast::Receiver<AgentEvent>) {
         let (event_tx, _) = broadcast::channel(100);
         let rx = event_tx.subscribe();
         let agent = OpencodeAgent {
             client: reqwest::Client::new(),
             api_key: api_key.to_string(),
-            base_url: mock_server.uri(),
-            session_id: session_id.to_string(),
+            base_url: std::sync::RwLock::new(mock_server.uri()),
+            session_id: std::sync::RwLock::new(session_id.to_string()),
             channel_id: 1,
             event_tx,
             current_model: Arc::new(Mutex::new(None)),
             turn_failed: Arc::new(AtomicBool::new(false)),
             agent_type_name: "opencode",
+            directory: None,
+            prompt_options: Mutex::new(PromptOptions::default()),
+            turn_active: AtomicBool::new(false),
+            event_stream: std::sync::Mutex::new(None),
+            channels: Arc::clone(channels),
         };
         (agent, rx)
     }
 
+    #[test]
+    fn test_status_busy_reads_session_status() {
+        let status = json!({
+            "busy": { "type": "busy" },
+            "retry": { "type": "retry", "attempt": 2 },
+            "idle": { "type": "idle" }
+        });
+        assert!(OpencodeAgent::status_busy(&status, "busy"));
+        assert!(OpencodeAgent::status_busy(&status, "retry"));
+        assert!(!OpencodeAgent::status_busy(&status, "idle"));
+        assert!(!OpencodeAgent::status_busy(&status, "missing"));
+        assert!(!OpencodeAgent::status_busy(&json!({}), "busy"));
+    }
+
+    #[test]
+    fn test_turn_usage_sums_assistant_messages_of_last_turn() {
+        let msgs = json!([
+            { "info": { "role": "assistant", "cost": 9.0, "tokens": { "input": 999 } } },
+            { "info": { "role": "user" } },
+            {
+                "info": {
+                    "role": "assistant",
+                    "providerID": "anthropic",
+                    "modelID": "claude",
+                    "cost": 0.25,
+                    "tokens": {
+                        "input": 100,
+                        "output": 20,
+                        "reasoning": 5,
+                        "cache": { "read": 40, "write": 10 }
+                    }
+                }
+            },
+            { "role": "assistant", "cost": 0.5, "tokens": { "input": 1, "output": 2 } }
+        ]);
+        let usage = OpencodeAgent::turn_usage(&msgs);
+        assert_eq!(usage.model.as_deref(), Some("anthropic/claude"));
+        assert_eq!((usage.input_tokens, usage.output_tokens), (101, 27));
+        assert_eq!((usage.cache_read_tokens, usage.cache_write_tokens), (40, 10));
+        assert_eq!(usage.cost, 0.75);
+        assert!(OpencodeAgent::turn_usage(&json!([])).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_turn_syncs_finished_turn() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/session/sid-r/message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "role": "user", "time": { "created": 1 } },
+                {
+                    "role": "assistant",
+                    "time": { "created": 2, "completed": 3 },
+                    "parts": [{ "type": "text", "text": "final answer", "id": "p1" }]
+                }
+            ])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/session/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&mock_server)
+            .await;
+        let (agent, mut rx) = build_test_agent(&mock_server, "k", "sid-r", &channels);
+
+        agent.resume_turn().await?;
+        match rx.recv().await? {
+            AgentEvent::ContentSync { items } => assert_eq!(items[0].content, "final answer"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            rx.recv().await?,
+            AgentEvent::AgentEnd { success: true, .. }
+        ));
+        assert!(!agent.turn_active.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_turn_waits_for_running_turn() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/session/sid-r/message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "role": "user", "time": { "created": 1 } },
+                {
+                    "role": "assistant",
+                    "time": { "created": 2, "completed": 3 },
+                    "parts": [{ "type": "text", "text": "step one", "id": "p1" }]
+                }
+            ])))
+            .mount(&mock_server)
+            .await;
+        // 上一步已完成，但 server 仍回報 busy：以 status 為準
+        Mock::given(method("GET"))
+            .and(path("/session/status"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "sid-r": { "type": "busy" } })),
+            )
+            .mount(&mock_server)
+            .await;
+        let (agent, mut rx) = build_test_agent(&mock_server, "k", "sid-r", &channels);
+
+        agent.resume_turn().await?;
+        match rx.recv().await? {
+            AgentEvent::ContentSync { items } => assert_eq!(items[0].content, "step one"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+        assert!(agent.turn_active.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resync_keeps_busy_turn_open_until_idle() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/session/sid-r/message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "role": "user", "time": { "created": 1 } },
+                {
+                    "role": "assistant",
+                    "time": { "created": 2 },
+                    "parts": [{ "type": "text", "text": "partial", "id": "p1" }]
+                }
+            ])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/session/status"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "sid-r": { "type": "busy" } })),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/session/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&mock_server)
+            .await;
+        let (agent, mut rx) = build_test_agent(&mock_server, "k", "sid-r", &channels);
+        agent.turn_active.store(true, Ordering::SeqCst);
+
+        // 重連時仍在產生：只同步內容，不結束回合
+        agent.resync().await;
+        match rx.recv().await? {
+            AgentEvent::ContentSync { items } => assert_eq!(items[0].content, "partial"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+        assert!(agent.turn_active.load(Ordering::SeqCst));
+
+        // 之後重連時已閒置：同步並結束
+        agent.resync().await;
+        assert!(matches!(rx.recv().await?, AgentEvent::ContentSync { .. }));
+        assert!(matches!(
+            rx.recv().await?,
+            AgentEvent::AgentEnd { success: true, .. }
+        ));
+        assert!(!agent.turn_active.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_turn_ends_on_idle_with_usage_of_every_step() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/session/sid-m/message"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "role": "user" },
+                {
+                    "role": "assistant",
+                    "tokens": { "input": 10, "output": 1 },
+                    "parts": [{ "type": "text", "text": "step one", "id": "p1" }]
+                },
+                {
+                    "role": "assistant",
+                    "tokens": { "input": 20, "output": 2 },
+                    "parts": [{ "type": "text", "text": "step two", "id": "p2" }]
+                }
+            ])))
+            .mount(&mock_server)
+            .await;
+        let (agent, mut rx) = build_test_agent(&mock_server, "k", "sid-m", &channels);
+        agent.turn_active.store(true, Ordering::SeqCst);
+
+        // 一則訊息完成不代表回合結束：只同步內容，不回報用量
+        agent
+            .handle_event(json!({ "type": "message.completed" }))
+            .await;
+        assert!(matches!(rx.recv().await?, AgentEvent::ContentSync { .. }));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+        assert!(agent.turn_active.load(Ordering::SeqCst));
+
+        agent.handle_event(json!({ "type": "session.idle" })).await;
+        assert!(matches!(rx.recv().await?, AgentEvent::ContentSync { .. }));
+        match rx.recv().await? {
+            AgentEvent::Usage { usage } => {
+                assert_eq!((usage.input_tokens, usage.output_tokens), (30, 3))
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            rx.recv().await?,
+            AgentEvent::AgentEnd { success: true, .. }
+        ));
+
+        // 接著到的 session.status idle 不會再結束一次
+        agent
+            .handle_event(json!({
+                "type": "session.status",
+                "properties": { "status": { "type": "idle" } }
+            }))
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_opencode_retry_logic() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let mock_server = MockServer::start().await;
         let api_key = "test_key".to_string();
         let session_id = "test_session".to_string();
@@ -668,7 +1274,7 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let (agent, mut rx) = build_test_agent(&mock_server, &api_key, &session_id);
+        let (agent, mut rx) = build_test_agent(&mock_server, &api_key, &session_id, &channels);
 
         let result = agent.prompt("Hello").await;
 
@@ -682,6 +1288,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_opencode_retry_success_on_second_attempt() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let mock_server = MockServer::start().await;
         let api_key = "test_key".to_string();
         let session_id = "test_session".to_string();
@@ -702,7 +1310,7 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let (agent, mut rx) = build_test_agent(&mock_server, &api_key, &session_id);
+        let (agent, mut rx) = build_test_agent(&mock_server, &api_key, &session_id, &channels);
 
         let result = agent.prompt("Hello").await;
         assert!(result.is_ok());
@@ -807,6 +1415,21 @@ mod tests {
             OpencodeAgent::parse_realtime_event(&done),
             RealtimeEventAction::TurnCompleted
         );
+        let idle = json!({"type":"session.status","properties":{"status":{"type":"idle"}}});
+        assert_eq!(
+            OpencodeAgent::parse_realtime_event(&idle),
+            RealtimeEventAction::TurnCompleted
+        );
+        let busy = json!({"type":"session.status","properties":{"status":{"type":"busy"}}});
+        assert_eq!(
+            OpencodeAgent::parse_realtime_event(&busy),
+            RealtimeEventAction::Ignore
+        );
+        let step = json!({"type":"message.completed"});
+        assert_eq!(
+            OpencodeAgent::parse_realtime_event(&step),
+            RealtimeEventAction::StepCompleted
+        );
 
         let err = json!({
             "type":"error",
@@ -898,6 +1521,7 @@ mod tests {
 
         let input = UserInput {
             text: "prompt".to_string(),
+            user_id: None,
             files: vec![UploadedFile {
                 id: "1".to_string(),
                 name: "a.txt".to_string(),
@@ -915,6 +1539,7 @@ mod tests {
 
         let input_large = UserInput {
             text: "prompt2".to_string(),
+            user_id: None,
             files: vec![UploadedFile {
                 id: "2".to_string(),
                 name: "big.bin".to_string(),
@@ -937,6 +1562,7 @@ mod tests {
         tokio::fs::write(&img_path, b"png-bytes").await?;
         let input = UserInput {
             text: "img".to_string(),
+            user_id: None,
             files: vec![UploadedFile {
                 id: "i1".to_string(),
                 name: "a.png".to_string(),
@@ -956,6 +1582,7 @@ mod tests {
     async fn test_build_parts_from_input_missing_file_falls_back() -> anyhow::Result<()> {
         let input = UserInput {
             text: "missing".to_string(),
+            user_id: None,
             files: vec![UploadedFile {
                 id: "m1".to_string(),
                 name: "missing.txt".to_string(),
@@ -977,6 +1604,7 @@ mod tests {
         let body = OpencodeAgent::construct_message_body(
             &input,
             &Some(("openai".to_string(), "gpt-4.1".to_string())),
+            &PromptOptions::default(),
         )
         .await;
         assert_eq!(body["model"]["providerID"], "openai");
@@ -988,7 +1616,7 @@ mod tests {
     #[tokio::test]
     async fn test_construct_message_body_without_model() -> anyhow::Result<()> {
         let input = UserInput::new_text("hello".to_string());
-        let body = OpencodeAgent::construct_message_body(&input, &None).await;
+        let body = OpencodeAgent::construct_message_body(&input, &None, &PromptOptions::default()).await;
         assert!(body.get("model").is_none());
         assert_eq!(body["parts"][0]["text"], "hello");
         Ok(())
@@ -996,6 +1624,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_available_models_filters_connected_providers() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let mock_server = MockServer::start().await;
         Mock::given(method("GET"))
             .and(path("/provider"))
@@ -1010,7 +1640,7 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let (agent, _) = build_test_agent(&mock_server, "k", "sid");
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
         let models = agent.get_available_models().await?;
         assert_eq!(models.len(), 2);
         assert!(models.iter().all(|m| m.provider == "openai"));
@@ -1019,6 +1649,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_available_models_empty_when_disconnected() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let mock_server = MockServer::start().await;
         Mock::given(method("GET"))
             .and(path("/provider"))
@@ -1030,7 +1662,7 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        let (agent, _) = build_test_agent(&mock_server, "k", "sid");
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
         let models = agent.get_available_models().await?;
         assert!(models.is_empty());
         Ok(())
@@ -1038,10 +1670,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_state_404_clears_sid() -> anyhow::Result<()> {
-        let _guard = env_lock().lock().unwrap_or_else(|e| e.into_inner());
         let dir = tempdir()?;
-        // SAFETY: serialized by env lock
-        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+        let channels = ChannelRegistry::in_dir(dir.path());
 
         let mock_server_404 = MockServer::start().await;
         Mock::given(method("GET"))
@@ -1050,36 +1680,29 @@ mod tests {
             .expect(1)
             .mount(&mock_server_404)
             .await;
-        let (agent_404, _) = build_test_agent(&mock_server_404, "k", "sid");
+        let (agent_404, _) = build_test_agent(&mock_server_404, "k", "sid", &channels);
         let state_404 = agent_404.get_state().await?;
         assert_eq!(state_404.message_count, 0);
-        // SAFETY: serialized by env lock
-        unsafe { std::env::remove_var(BASE_DIR_ENV) };
         Ok(())
     }
 
     #[tokio::test]
     async fn test_set_model_persists_to_channel_config() -> anyhow::Result<()> {
-        let _guard = env_lock().lock().unwrap_or_else(|e| e.into_inner());
         let dir = tempdir()?;
-        // SAFETY: serialized by env lock
-        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+        let channels = ChannelRegistry::in_dir(dir.path());
 
         let mock_server = MockServer::start().await;
-        let (agent, _) = build_test_agent(&mock_server, "k", "sid");
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
         agent.set_model("openai", "gpt-4.1").await?;
         let model = agent.current_model.lock().await.clone();
-        assert_eq!(
-            model,
-            Some(("openai".to_string(), "gpt-4.1".to_string()))
-        );
-        // SAFETY: serialized by env lock
-        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+        assert_eq!(model, Some(("openai".to_string(), "gpt-4.1".to_string())));
         Ok(())
     }
 
     #[tokio::test]
     async fn test_compact_success_and_failure() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let ok_server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/session/sid/message"))
@@ -1087,7 +1710,7 @@ mod tests {
             .expect(1)
             .mount(&ok_server)
             .await;
-        let (ok_agent, _) = build_test_agent(&ok_server, "k", "sid");
+        let (ok_agent, _) = build_test_agent(&ok_server, "k", "sid", &channels);
         ok_agent.compact().await?;
 
         let fail_server = MockServer::start().await;
@@ -1097,7 +1720,7 @@ mod tests {
             .expect(1)
             .mount(&fail_server)
             .await;
-        let (fail_agent, _) = build_test_agent(&fail_server, "k", "sid");
+        let (fail_agent, _) = build_test_agent(&fail_server, "k", "sid", &channels);
         let err = fail_agent.compact().await.expect_err("compact must fail");
         assert!(err.to_string().contains("Compact failed"));
         Ok(())
@@ -1105,6 +1728,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_abort_hits_endpoint() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
         let mock_server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/session/sid/abort"))
@@ -1112,17 +1737,15 @@ mod tests {
             .expect(1)
             .mount(&mock_server)
             .await;
-        let (agent, _) = build_test_agent(&mock_server, "k", "sid");
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
         agent.abort().await?;
         Ok(())
     }
 
     #[tokio::test]
     async fn test_prompt_404_clears_sid_and_returns_err() -> anyhow::Result<()> {
-        let _guard = env_lock().lock().unwrap_or_else(|e| e.into_inner());
         let dir = tempdir()?;
-        // SAFETY: serialized by env lock
-        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+        let channels = ChannelRegistry::in_dir(dir.path());
 
         let mock_server = MockServer::start().await;
         Mock::given(method("POST"))
@@ -1131,11 +1754,9 @@ mod tests {
             .expect(1)
             .mount(&mock_server)
             .await;
-        let (agent, _) = build_test_agent(&mock_server, "k", "sid");
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
         let err = agent.prompt("x").await.expect_err("expected 404 error");
         assert!(err.to_string().contains("Session expired"));
-        // SAFETY: serialized by env lock
-        unsafe { std::env::remove_var(BASE_DIR_ENV) };
         Ok(())
     }
 
@@ -1157,4 +1778,246 @@ mod tests {
             RealtimeEventAction::Ignore
         );
     }
+
+    #[test]
+    fn test_build_url_appends_encoded_directory() {
+        assert_eq!(
+            build_url("http://127.0.0.1:4096", "/provider", None),
+            "http://127.0.0.1:4096/provider"
+        );
+        assert_eq!(
+            build_url("http://127.0.0.1:4096", "/session/a b", Some("/src/my app")),
+            "http://127.0.0.1:4096/session/a%20b?directory=%2Fsrc%2Fmy+app"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_construct_message_body_carries_variant_and_agent() {
+        let input = UserInput::new_text("hello".to_string());
+        let options = PromptOptions {
+            variant: Some("high".to_string()),
+            agent: Some("plan".to_string()),
+        };
+        let body = OpencodeAgent::construct_message_body(&input, &None, &options).await;
+        assert_eq!(body["variant"], "high");
+        assert_eq!(body["agent"], "plan");
+    }
+
+    #[test]
+    fn test_model_variants_lookup() {
+        let providers = json!({
+            "all": [{"id": "openai", "models": {
+                "gpt-5": {"variants": {"low": {}, "high": {}}},
+                "gpt-4.1": {}
+            }}]
+        });
+        let mut variants =
+            OpencodeAgent::model_variants(&providers, "openai", "gpt-5").expect("model");
+        variants.sort();
+        assert_eq!(variants, vec!["high", "low"]);
+        assert_eq!(
+            OpencodeAgent::model_variants(&providers, "openai", "gpt-4.1"),
+            Some(vec![])
+        );
+        assert!(OpencodeAgent::model_variants(&providers, "anthropic", "x").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_set_thinking_level_rejects_unsupported_variant() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/provider"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "all": [{"id": "openai", "models": {"gpt-5": {"variants": {"high": {}}}}}]
+            })))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
+        let err = agent.set_thinking_level("high").await.expect_err("no model");
+        assert!(err.to_string().contains("/model"));
+        *agent.current_model.lock().await = Some(("openai".into(), "gpt-4.1".into()));
+        let err = agent.set_thinking_level("high").await.expect_err("missing model");
+        assert!(err.to_string().contains("not available"));
+        *agent.current_model.lock().await = Some(("openai".into(), "gpt-5".into()));
+
+        let err = agent.set_thinking_level("xhigh").await.expect_err("unsupported");
+        assert!(err.to_string().contains("Unsupported"));
+        agent.set_thinking_level("high").await?;
+        assert_eq!(agent.prompt_options.lock().await.variant.as_deref(), Some("high"));
+        agent.set_thinking_level("off").await?;
+        assert!(agent.prompt_options.lock().await.variant.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear_switches_to_new_server_session() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/session"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "sid-new"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid-old", &channels);
+        assert_eq!(agent.clear().await?.as_deref(), Some("sid-new"));
+        assert_eq!(agent.session_id(), "sid-new");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_skill_uses_commands_then_agents() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/command"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"name": "review"}])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/agent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"name": "plan"}])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/session/sid/command"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
+
+        agent.load_skill("review").await?;
+        assert!(agent.prompt_options.lock().await.agent.is_none());
+        agent.load_skill("plan").await?;
+        assert_eq!(agent.prompt_options.lock().await.agent.as_deref(), Some("plan"));
+        let err = agent.load_skill("missing").await.expect_err("unsupported");
+        assert!(err.to_string().contains("Unsupported"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prompt_options_persist_to_channel_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        channels
+            .update(|config| {
+                config.ensure_entry("1");
+                Ok(())
+            })
+            .await?;
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/command"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/agent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"name": "plan"}])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/provider"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "all": [{"id": "openai", "models": {"gpt-5": {"variants": {"high": {}}}}}]
+            })))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
+        *agent.current_model.lock().await = Some(("openai".into(), "gpt-5".into()));
+        agent.set_thinking_level("high").await?;
+        agent.load_skill("plan").await?;
+
+        let config = channels.snapshot();
+        let entry = config.channels.get("1").expect("entry");
+        assert_eq!(entry.variant.as_deref(), Some("high"));
+        assert_eq!(entry.server_agent.as_deref(), Some("plan"));
+
+        // 新建的 agent 從頻道設定還原選擇
+        let (restored, _) = build_test_agent(&mock_server, "k", "sid", &channels);
+        restored
+            .restore_prompt_options(entry.variant.clone(), entry.server_agent.clone())
+            .await;
+        assert_eq!(
+            *restored.prompt_options.lock().await,
+            *agent.prompt_options.lock().await
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear_fails_when_session_creation_is_rejected() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/session"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(json!({"id": "bogus"})))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid-old", &channels);
+        let err = agent.clear().await.expect_err("rejected");
+        assert!(err.to_string().contains("500"));
+        assert_eq!(agent.session_id(), "sid-old");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clear_aborts_running_turn_first() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/session/sid-old/abort"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!(true)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/session"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "sid-new"})))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid-old", &channels);
+        agent.turn_active.store(true, Ordering::SeqCst);
+        assert_eq!(agent.clear().await?.as_deref(), Some("sid-new"));
+        assert!(!agent.turn_active.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_thinking_level_and_load_skill_report_server_errors() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let channels = ChannelRegistry::in_dir(dir.path());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/provider"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/command"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"name": "review"}])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/session/sid/command"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let (agent, _) = build_test_agent(&mock_server, "k", "sid", &channels);
+        *agent.current_model.lock().await = Some(("openai".into(), "gpt-5".into()));
+
+        let err = agent.set_thinking_level("high").await.expect_err("provider down");
+        assert!(err.to_string().contains("503"));
+        assert!(agent.prompt_options.lock().await.variant.is_none());
+        let err = agent.load_skill("review").await.expect_err("command failed");
+        assert!(err.to_string().contains("500"));
+        Ok(())
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::info;
+
+/// 預設等待使用者回應的秒數
+pub const DEFAULT_PERMISSION_TIMEOUT_SECS: u64 = 120;
+
+static BROKER: OnceLock<Arc<PermissionBroker>> = OnceLock::new();
+
+/// 全域的權限請求中介，ACP runtime 與 Discord 按鈕透過它交換決定
+pub fn broker() -> Arc<PermissionBroker> {
+    BROKER
+        .get_or_init(|| {
+            Arc::new(PermissionBroker::new(Duration::from_secs(
+                DEFAULT_PERMISSION_TIMEOUT_SECS,
+            )))
+        })
+        .clone()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermissionDecision {
+    AllowOnce,
+    AllowAlways,
+    Deny,
+    TimedOut,
+}
+
+impl PermissionDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PermissionDecision::AllowOnce => "allow_once",
+            PermissionDecision::AllowAlways => "allow_always",
+            PermissionDecision::Deny => "deny",
+            PermissionDecision::TimedOut => "timeout",
+        }
+    }
+
+    /// 解析按鈕上的選項（逾時不是使用者可選的選項）
+    pub fn parse_choice(s: &str) -> Option<Self> {
+        match s {
+            "allow_once" => Some(PermissionDecision::AllowOnce),
+            "allow_always" => Some(PermissionDecision::AllowAlways),
+            "deny" => Some(PermissionDecision::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        matches!(
+            self,
+            PermissionDecision::AllowOnce | PermissionDecision::AllowAlways
+        )
+    }
+
+    /// 對應 ACP `PermissionOption.kind`，依偏好排序
+    pub fn option_kinds(&self) -> &'static [&'static str] {
+        match self {
+            PermissionDecision::AllowOnce => &["allow_once", "allow_always"],
+            PermissionDecision::AllowAlways => &["allow_always", "allow_once"],
+            PermissionDecision::Deny | PermissionDecision::TimedOut => {
+                &["reject_once", "reject_always"]
+            }
+        }
+    }
+}
+
+/// 按鈕 custom_id：`perm:<token>:<choice>`
+pub fn build_custom_id(token: &str, decision: PermissionDecision) -> String {
+    format!("perm:{}:{}", token, decision.as_str())
+}
+
+pub fn parse_custom_id(custom_id: &str) -> Option<(String, PermissionDecision)> {
+    let rest = custom_id.strip_prefix("perm:")?;
+    let (token, choice) = rest.rsplit_once(':')?;
+    if token.is_empty() {
+        return None;
+    }
+    Some((token.to_string(), PermissionDecision::parse_choice(choice)?))
+}
+
+type DecisionSender = watch::Sender<Option<PermissionDecision>>;
+pub type DecisionReceiver = watch::Receiver<Option<PermissionDecision>>;
+
+pub struct PermissionBroker {
+    pending: Mutex<HashMap<String, DecisionSender>>,
+    timeout: Mutex<Duration>,
+}
+
+impl PermissionBroker {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            timeout: Mutex::new(timeout),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        *self.timeout.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.lock().unwrap_or_else(|e| e.into_inner()) = timeout;
+    }
+
+    /// 登記一個待確認的請求；逾時後自動以 `TimedOut` 結束
+    pub fn register(self: &Arc<Self>) -> (String, DecisionReceiver) {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let (tx, rx) = watch::channel(None);
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(token.clone(), tx);
+
+        let broker = Arc::clone(self);
+        let timer_token = token.clone();
+        let timeout = self.timeout();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if broker.resolve(&timer_token, PermissionDecision::TimedOut) {
+                info!("⌛ Permission request {} timed out", timer_token);
+            }
+        });
+
+        (token, rx)
+    }
+
+    pub fn subscribe(&self, token: &str) -> Option<DecisionReceiver> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(token)
+            .map(|tx| tx.subscribe())
+    }
+
+    /// 回覆請求；若請求已結束（逾時或已被回覆）則回傳 false
+    pub fn resolve(&self, token: &str, decision: PermissionDecision) -> bool {
+        let tx = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(token);
+        match tx {
+            Some(tx) => {
+                let _ = tx.send(Some(decision));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 等待決定；通道被關閉視同逾時
+pub async fn wait_decision(rx: &mut DecisionReceiver) -> PermissionDecision {
+    match rx.wait_for(|d| d.is_some()).await {
+        Ok(d) => d.unwrap_or(PermissionDecision::TimedOut),
+        Err(_) => PermissionDecision::TimedOut,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_id_round_trip() {
+        let id = build_custom_id("abc", PermissionDecision::AllowAlways);
+        assert_eq!(id, "perm:abc:allow_always");
+        assert_eq!(
+            parse_custom_id(&id),
+            Some(("abc".to_string(), PermissionDecision::AllowAlways))
+        );
+        assert_eq!(parse_custom_id("perm::deny"), None);
+        assert_eq!(parse_custom_id("perm:abc:timeout"), None);
+        assert_eq!(parse_custom_id("other"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_delivers_decision_once() {
+        let broker = Arc::new(PermissionBroker::new(Duration::from_secs(60)));
+        let (token, mut rx) = broker.register();
+        let mut ui_rx = broker.subscribe(&token).expect("pending");
+
+        assert!(broker.resolve(&token, PermissionDecision::AllowOnce));
+        assert!(!broker.resolve(&token, PermissionDecision::Deny));
+        assert_eq!(wait_decision(&mut rx).await, PermissionDecision::AllowOnce);
+        assert_eq!(
+            wait_decision(&mut ui_rx).await,
+            PermissionDecision::AllowOnce
+        );
+        assert!(broker.subscribe(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_request_times_out() {
+        let broker = Arc::new(PermissionBroker::new(Duration::from_millis(20)));
+        let (token, mut rx) = broker.register();
+        assert_eq!(wait_decision(&mut rx).await, PermissionDecision::TimedOut);
+        assert!(!broker.resolve(&token, PermissionDecision::AllowOnce));
+    }
+
+    #[test]
+    fn test_decision_kinds() {
+        assert!(PermissionDecision::AllowOnce.is_allowed());
+        assert!(!PermissionDecision::TimedOut.is_allowed());
+        assert_eq!(
+            PermissionDecision::TimedOut.option_kinds(),
+            PermissionDecision::Deny.option_kinds()
+        );
+    }
+}
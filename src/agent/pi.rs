@@ -1,25 +1,36 @@
-use super::{AgentEvent, AgentState, AiAgent, ContentItem, ContentType, ModelInfo};
+use super::{AgentEvent, AgentState, AiAgent, ContentItem, ContentType, ModelInfo, TurnUsage};
 use crate::agent::runtime;
 use async_trait::async_trait;
 use serde_json::{json, Value};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{ChildStdin, Command};
-use tokio::sync::broadcast;
+use tokio::process::{Child, ChildStdin, Command};
 use tokio::sync::Mutex;
+use tokio::sync::{broadcast, oneshot, watch};
 use tracing::{info, warn};
 
+// 收到 SIGTERM 後等 Pi 寫完 session jsonl 的時間，逾時才 SIGKILL
+const TERM_GRACE: Duration = Duration::from_secs(3);
+
 pub struct PiAgent {
     stdin: Arc<Mutex<ChildStdin>>,
     event_tx: broadcast::Sender<AgentEvent>,
-    child_pid: u32,
+    // 通知 wait 任務結束子進程；Child 由該任務持有，回收前 PID 不會被重複使用
+    kill_tx: std::sync::Mutex<Option<oneshot::Sender<()>>>,
+    // 子進程結束後由 wait 任務填入
+    exit_reason: watch::Receiver<Option<String>>,
     _pending_trace: Arc<Mutex<String>>, // 修改為非 Option，方便狀態機追加
 }
 
 impl PiAgent {
-    pub async fn new(channel_id: u64, session_dir: &PathBuf) -> anyhow::Result<(Arc<Self>, u64)> {
+    pub async fn new(
+        channel_id: u64,
+        session_dir: &PathBuf,
+        workdir: Option<&Path>,
+    ) -> anyhow::Result<(Arc<Self>, u64)> {
         std::fs::create_dir_all(session_dir)?;
         let pi_binary = runtime::resolve_binary_with_env("PI_BINARY", "pi");
         let current_path = std::env::var("PATH").unwrap_or_default();
@@ -27,7 +38,12 @@ impl PiAgent {
 
         info!("🚀 Spawning Pi binary: {}", pi_binary);
         let session_file = session_dir.join(format!("discord-rs-{}.jsonl", channel_id));
-        let mut child = Command::new(&pi_binary)
+        let mut cmd = Command::new(&pi_binary);
+        // 頻道綁定工作目錄時，pi 的工具都在該目錄下執行
+        if let Some(dir) = workdir {
+            cmd.current_dir(dir);
+        }
+        let mut child = cmd
             .arg("--mode")
             .arg("rpc")
             .arg("--session")
@@ -38,9 +54,9 @@ impl PiAgent {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?;
 
-        let child_pid = child.id().unwrap_or(0);
         let stdin = Arc::new(Mutex::new(child.stdin.take().unwrap()));
         let (event_tx, _) = broadcast::channel(1000);
         let tx = event_tx.clone();
@@ -79,15 +95,18 @@ impl PiAgent {
             }
         });
 
+        let (exit_tx, exit_reason) = watch::channel(None);
+        let (kill_tx, kill_rx) = oneshot::channel();
         tokio::spawn(async move {
-            let status = child.wait().await;
-            info!("Pi process (PID {}) exited with {:?}", child_pid, status);
+            let reason = Self::supervise(child, kill_rx, TERM_GRACE).await;
+            exit_tx.send_replace(Some(reason));
         });
 
         let agent = Arc::new(PiAgent {
             stdin,
             event_tx: tx,
-            child_pid,
+            kill_tx: std::sync::Mutex::new(Some(kill_tx)),
+            exit_reason,
             _pending_trace: pending_trace,
         });
         agent
@@ -98,6 +117,29 @@ impl PiAgent {
         Ok((agent, 0))
     }
 
+    /// 加總本回合 assistant 訊息的 `usage`（含 `cost.total`）
+    fn turn_usage(messages: &[Value]) -> TurnUsage {
+        let mut total = TurnUsage::default();
+        for msg in messages.iter().filter(|m| m["role"] == "assistant") {
+            let usage = &msg["usage"];
+            let tokens = |key: &str| usage[key].as_u64().unwrap_or(0);
+            let model = match (msg["provider"].as_str(), msg["model"].as_str()) {
+                (Some(provider), Some(model)) => Some(format!("{}/{}", provider, model)),
+                (None, Some(model)) => Some(model.to_string()),
+                _ => None,
+            };
+            total.add(TurnUsage {
+                model,
+                input_tokens: tokens("input"),
+                output_tokens: tokens("output"),
+                cache_read_tokens: tokens("cacheRead"),
+                cache_write_tokens: tokens("cacheWrite"),
+                cost: usage["cost"]["total"].as_f64().unwrap_or(0.0),
+            });
+        }
+        total
+    }
+
     async fn parse_event(
         tx: &broadcast::Sender<AgentEvent>,
         val: Value,
@@ -371,6 +413,10 @@ impl PiAgent {
                     if !items.is_empty() {
                         let _ = tx.send(AgentEvent::ContentSync { items });
                     }
+                    let usage = Self::turn_usage(current_turn);
+                    if !usage.is_empty() {
+                        let _ = tx.send(AgentEvent::Usage { usage });
+                    }
                 }
                 let _ = tx.send(AgentEvent::AgentEnd {
                     success: final_err.is_none(),
@@ -411,11 +457,47 @@ impl PiAgent {
         Ok(id)
     }
 
-    fn kill_child(&self) {
-        if self.child_pid > 0 {
-            unsafe {
-                libc::kill(self.child_pid as libc::pid_t, libc::SIGKILL);
+    /// 等待子進程結束；收到終止通知時先送 SIGTERM，寬限期過後才強制 kill
+    async fn supervise(
+        mut child: Child,
+        kill_rx: oneshot::Receiver<()>,
+        grace: Duration,
+    ) -> String {
+        let pid = child.id().unwrap_or(0);
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = kill_rx => {
+                // 尚未 wait 回收，id() 仍指向自己的子進程
+                if let Some(id) = child.id() {
+                    unsafe {
+                        libc::kill(id as libc::pid_t, libc::SIGTERM);
+                    }
+                }
+                match tokio::time::timeout(grace, child.wait()).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        warn!("Pi process (PID {}) ignored SIGTERM, killing", pid);
+                        let _ = child.kill().await;
+                        child.wait().await
+                    }
+                }
             }
+        };
+        info!("Pi process (PID {}) exited with {:?}", pid, status);
+        match status {
+            Ok(status) => format!("exited with {}", status),
+            Err(e) => format!("wait failed: {}", e),
+        }
+    }
+
+    fn kill_child(&self) {
+        let tx = self
+            .kill_tx
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(tx) = tx {
+            let _ = tx.send(());
         }
     }
 }
@@ -445,8 +527,8 @@ impl AiAgent for PiAgent {
         self.raw_call(json!({ "type": "abort" })).await?;
         Ok(())
     }
-    async fn clear(&self) -> anyhow::Result<()> {
-        Ok(())
+    async fn clear(&self) -> anyhow::Result<Option<String>> {
+        Ok(None)
     }
     async fn set_model(&self, p: &str, mid: &str) -> anyhow::Result<()> {
         self.raw_call(json!({ "type": "set_model", "provider": p, "modelId": mid }))
@@ -500,8 +582,19 @@ impl AiAgent for PiAgent {
     fn subscribe_events(&self) -> broadcast::Receiver<AgentEvent> {
         self.event_tx.subscribe()
     }
-    fn agent_type(&self) -> &'static str {
-        "pi"
+    fn agent_type(&self) -> String {
+        "pi".to_string()
+    }
+    fn exit_reason(&self) -> Option<String> {
+        self.exit_reason.borrow().clone()
+    }
+    fn terminate(&self) {
+        // 對話都已寫進 --session 檔，下次建立時會自動接續
+        self.kill_child();
+    }
+    async fn wait_exit(&self) {
+        let mut exit = self.exit_reason.clone();
+        let _ = exit.wait_for(|reason| reason.is_some()).await;
     }
 }
 
@@ -730,7 +823,8 @@ mod tests {
                 {"role":"assistant","content":[{"type":"text","text":"old"}]},
                 {"role":"user","content":[{"type":"text","text":"question"}]},
                 {"role":"tool","content":[{"type":"text","text":"tool output"}]},
-                {"role":"assistant","content":[{"type":"thinking","thinking":"plan"},{"type":"text","text":"answer"}], "errorMessage":"rate limited"}
+                {"role":"assistant","content":[{"type":"thinking","thinking":"plan"},{"type":"text","text":"answer"}], "errorMessage":"rate limited",
+                 "provider":"anthropic","model":"claude","usage":{"input":120,"output":30,"cacheRead":40,"cacheWrite":0,"cost":{"total":0.0125}}}
             ]
         });
         PiAgent::parse_event(&tx, val, &pending).await;
@@ -745,6 +839,16 @@ mod tests {
             _ => panic!("expected content sync"),
         }
 
+        match rx.recv().await.unwrap() {
+            AgentEvent::Usage { usage } => {
+                assert_eq!(usage.model.as_deref(), Some("anthropic/claude"));
+                assert_eq!((usage.input_tokens, usage.output_tokens), (120, 30));
+                assert_eq!(usage.cache_read_tokens, 40);
+                assert_eq!(usage.cost, 0.0125);
+            }
+            _ => panic!("expected usage"),
+        }
+
         match rx.recv().await.unwrap() {
             AgentEvent::AgentEnd { success, error } => {
                 assert!(!success);
@@ -753,4 +857,54 @@ mod tests {
             _ => panic!("expected agent end"),
         }
     }
+
+    async fn spawn_ready(script: &str, arg: &std::path::Path) -> Child {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .arg(arg)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // 等 trap 裝好再送訊號
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        assert_eq!(line.trim(), "ready");
+        child
+    }
+
+    #[tokio::test]
+    async fn test_supervise_sends_sigterm_and_waits_for_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("flushed");
+        let child = spawn_ready(
+            r#"trap 'echo flushed > "$0"; exit 0' TERM; echo ready; while :; do sleep 0.05; done"#,
+            &marker,
+        )
+        .await;
+        let (tx, rx) = oneshot::channel();
+        let task = tokio::spawn(PiAgent::supervise(child, rx, Duration::from_secs(5)));
+        tx.send(()).unwrap();
+        let reason = task.await.unwrap();
+        assert!(reason.contains("exit status: 0"), "{}", reason);
+        assert!(marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_supervise_kills_after_grace_when_sigterm_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let child = spawn_ready(
+            "trap '' TERM; echo ready; while :; do sleep 0.05; done",
+            dir.path(),
+        )
+        .await;
+        let (tx, rx) = oneshot::channel();
+        let task = tokio::spawn(PiAgent::supervise(child, rx, Duration::from_millis(200)));
+        drop(tx);
+        let reason = task.await.unwrap();
+        assert!(reason.contains("SIGKILL"), "{}", reason);
+    }
 }
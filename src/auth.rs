@@ -1,14 +1,12 @@
-use crate::migrate;
+use crate::state::StateStore;
 use anyhow::Result;
 use chrono::{DateTime, Duration, Utc};
-use fs2::FileExt;
 use rand::distr::Alphanumeric;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::{self, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tracing::error;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AuthEntry {
@@ -38,91 +36,64 @@ pub struct PendingStore {
     pub tokens: HashMap<String, PendingToken>, // token -> data
 }
 
+/// 授權名單快取在記憶體，每則訊息檢查時不讀檔；
+/// 寫入在 blocking 執行緒完成後更新快取，CLI 兌換 token 後透過 reload 同步
 pub struct AuthManager {
-    auth_path: PathBuf,
-    pending_path: PathBuf,
+    store: StateStore,
+    cache: RwLock<Arc<Registry>>,
 }
 
 impl AuthManager {
     pub fn new() -> Self {
-        let base_dir = migrate::get_base_dir();
-        fs::create_dir_all(&base_dir).unwrap();
-        Self::with_paths(
-            base_dir.join("auth.json"),
-            base_dir.join("pending_tokens.json"),
-        )
+        Self::with_store(StateStore::open())
     }
 
-    pub fn with_paths(auth_path: PathBuf, pending_path: PathBuf) -> Self {
+    pub fn with_store(store: StateStore) -> Self {
+        let registry = store.read().map(|state| state.auth).unwrap_or_else(|e| {
+            error!("❌ Failed to load auth registry: {}", e);
+            Registry::default()
+        });
         Self {
-            auth_path,
-            pending_path,
+            store,
+            cache: RwLock::new(Arc::new(registry)),
         }
     }
 
-    fn with_lock<T, F>(&self, path: PathBuf, default: T, f: F) -> Result<T>
-    where
-        T: serde::de::DeserializeOwned + serde::Serialize + Default,
-        F: FnOnce(&mut T) -> Result<()>,
-    {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(&path)?;
-
-        file.lock_exclusive()?;
-
-        // Read
-        let mut content = String::new();
-        let mut reader = std::io::BufReader::new(&file);
-        reader.read_to_string(&mut content)?;
-
-        let mut data: T = if content.trim().is_empty() {
-            default
-        } else {
-            serde_json::from_str(&content).unwrap_or(default)
-        };
-
-        // Modify
-        f(&mut data)?;
+    fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.cache.read().unwrap_or_else(|e| e.into_inner()))
+    }
 
-        // Write
-        let json = serde_json::to_string_pretty(&data)?;
-        let mut file = file; // Rebind as mutable for writing
-        file.set_len(0)?;
-        file.seek(SeekFrom::Start(0))?;
-        file.write_all(json.as_bytes())?;
+    fn replace(&self, registry: Registry) {
+        *self.cache.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(registry);
+    }
 
-        file.unlock()?;
-        Ok(data)
+    /// 重新讀取 state.json（其他行程兌換 token 後由 reload 呼叫）
+    pub async fn reload(&self) -> Result<()> {
+        let store = self.store.clone();
+        let registry = tokio::task::spawn_blocking(move || store.read()).await??.auth;
+        self.replace(registry);
+        Ok(())
     }
 
     pub fn is_authorized(&self, user_id: &str, channel_id: &str) -> (bool, bool) {
         // (authorized, mention_only)
-        if let Ok(content) = fs::read_to_string(&self.auth_path) {
-            if let Ok(reg) = serde_json::from_str::<Registry>(&content) {
-                // Check User
-                if reg.users.contains_key(user_id) {
-                    return (true, false); // User auth overrides channel mention_only setting
-                }
-                // Check Channel
-                if let Some(entry) = reg.channels.get(channel_id) {
-                    return (true, entry.mention_only);
-                }
-            }
+        let reg = self.registry();
+        // Check User
+        if reg.users.contains_key(user_id) {
+            return (true, false); // User auth overrides channel mention_only setting
+        }
+        // Check Channel
+        if let Some(entry) = reg.channels.get(channel_id) {
+            return (true, entry.mention_only);
         }
         (false, false)
     }
 
     pub fn get_channel_mention_only(&self, channel_id: &str) -> Option<bool> {
-        if let Ok(content) = fs::read_to_string(&self.auth_path) {
-            if let Ok(reg) = serde_json::from_str::<Registry>(&content) {
-                return reg.channels.get(channel_id).map(|entry| entry.mention_only);
-            }
-        }
-        None
+        self.registry()
+            .channels
+            .get(channel_id)
+            .map(|entry| entry.mention_only)
     }
 
     pub async fn is_authorized_with_thread(
@@ -149,7 +120,7 @@ impl AuthManager {
         (false, false)
     }
 
-    pub fn create_token(&self, type_: &str, id: &str) -> Result<String> {
+    pub async fn create_token(&self, type_: &str, id: &str) -> Result<String> {
         let token: String = rand::rng()
             .sample_iter(&Alphanumeric)
             .take(6)
@@ -163,75 +134,74 @@ impl AuthManager {
             expires_at: Utc::now() + Duration::minutes(5),
         };
 
-        self.with_lock(
-            self.pending_path.clone(),
-            PendingStore::default(),
-            |store| {
+        let key = token.clone();
+        self.store
+            .update_async(move |state| {
                 // Cleanup expired tokens
                 let now = Utc::now();
-                store.tokens.retain(|_, v| v.expires_at > now);
+                state.pending.tokens.retain(|_, v| v.expires_at > now);
                 // Add new token
-                store.tokens.insert(token.clone(), entry);
+                state.pending.tokens.insert(key, entry);
                 Ok(())
-            },
-        )?;
+            })
+            .await?;
 
         Ok(token)
     }
 
-    pub fn redeem_token(&self, token: &str) -> Result<(String, String)> {
+    pub async fn redeem_token(&self, token: &str) -> Result<(String, String)> {
         // (type, id)
-        let mut found_entry: Option<PendingToken> = None;
-
-        // 1. Validate and Remove Token
-        self.with_lock(
-            self.pending_path.clone(),
-            PendingStore::default(),
-            |store| {
+        // 兌換 token 與寫入授權在同一次更新內完成
+        let token = token.to_string();
+        let (entry, registry) = self
+            .store
+            .update_async(move |state| {
                 let now = Utc::now();
-                store.tokens.retain(|_, v| v.expires_at > now);
-
-                if let Some(entry) = store.tokens.remove(token) {
-                    found_entry = Some(entry);
+                state.pending.tokens.retain(|_, v| v.expires_at > now);
+
+                let entry = state
+                    .pending
+                    .tokens
+                    .remove(&token)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid or expired token"))?;
+
+                let auth_entry = AuthEntry {
+                    authorized_at: now,
+                    mention_only: entry.type_ == "channel", // Default true for channels
+                };
+                match entry.type_.as_str() {
+                    "user" => {
+                        state.auth.users.insert(entry.id.clone(), auth_entry);
+                    }
+                    "channel" => {
+                        state.auth.channels.insert(entry.id.clone(), auth_entry);
+                    }
+                    _ => {}
                 }
-                Ok(())
-            },
-        )?;
-
-        let entry = found_entry.ok_or_else(|| anyhow::anyhow!("Invalid or expired token"))?;
-
-        // 2. Add to Registry
-        self.with_lock(self.auth_path.clone(), Registry::default(), |reg| {
-            let auth_entry = AuthEntry {
-                authorized_at: Utc::now(),
-                mention_only: entry.type_ == "channel", // Default true for channels
-            };
-            match entry.type_.as_str() {
-                "user" => {
-                    reg.users.insert(entry.id.clone(), auth_entry);
-                }
-                "channel" => {
-                    reg.channels.insert(entry.id.clone(), auth_entry);
-                }
-                _ => {}
-            }
-            Ok(())
-        })?;
+                Ok((entry, state.auth.clone()))
+            })
+            .await?;
+        self.replace(registry);
 
         Ok((entry.type_, entry.id))
     }
 
     // New method: Toggle mention_only
-    pub fn set_mention_only(&self, channel_id: &str, enable: bool) -> Result<()> {
-        self.with_lock(self.auth_path.clone(), Registry::default(), |reg| {
-            if let Some(entry) = reg.channels.get_mut(channel_id) {
-                entry.mention_only = enable;
-            } else {
-                // If not authorized yet, maybe auto-authorize? No, fail.
-                anyhow::bail!("Channel not authorized yet.");
-            }
-            Ok(())
-        })?;
+    pub async fn set_mention_only(&self, channel_id: &str, enable: bool) -> Result<()> {
+        let channel_id = channel_id.to_string();
+        let registry = self
+            .store
+            .update_async(move |state| {
+                if let Some(entry) = state.auth.channels.get_mut(&channel_id) {
+                    entry.mention_only = enable;
+                } else {
+                    // If not authorized yet, maybe auto-authorize? No, fail.
+                    anyhow::bail!("Channel not authorized yet.");
+                }
+                Ok(state.auth.clone())
+            })
+            .await?;
+        self.replace(registry);
         Ok(())
     }
 }
@@ -243,23 +213,20 @@ mod tests {
 
     fn create_test_manager() -> anyhow::Result<(TempDir, AuthManager)> {
         let dir = tempdir()?;
-        let manager = AuthManager::with_paths(
-            dir.path().join("auth.json"),
-            dir.path().join("pending_tokens.json"),
-        );
+        let manager = AuthManager::with_store(StateStore::with_path(dir.path().join("state.json")));
         Ok((dir, manager))
     }
 
-    #[test]
-    fn test_auth_token_flow() -> anyhow::Result<()> {
+    #[tokio::test]
+    async fn test_auth_token_flow() -> anyhow::Result<()> {
         let (_dir, manager) = create_test_manager()?;
 
         // 1. Create Token
-        let token = manager.create_token("channel", "12345")?;
+        let token = manager.create_token("channel", "12345").await?;
         assert_eq!(token.len(), 6);
 
         // 2. Redeem Token
-        let (type_, id) = manager.redeem_token(&token)?;
+        let (type_, id) = manager.redeem_token(&token).await?;
         assert_eq!(type_, "channel");
         assert_eq!(id, "12345");
 
@@ -271,17 +238,32 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_auth_user_override() -> anyhow::Result<()> {
+    #[tokio::test]
+    async fn test_redeem_is_single_use() -> anyhow::Result<()> {
+        let (_dir, manager) = create_test_manager()?;
+        let token = manager.create_token("user", "u1").await?;
+        manager.redeem_token(&token).await?;
+
+        // 同一個 token 不能兌換第二次，失敗的兌換不會寫入任何東西
+        assert!(manager.redeem_token(&token).await.is_err());
+        assert!(manager.redeem_token("nope").await.is_err());
+        let state = manager.store.read()?;
+        assert!(state.pending.tokens.is_empty());
+        assert_eq!(state.auth.users.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auth_user_override() -> anyhow::Result<()> {
         let (_dir, manager) = create_test_manager()?;
 
         // 1. Authorize a channel with mention_only = true
-        let token = manager.create_token("channel", "chan_1")?;
-        let _ = manager.redeem_token(&token)?;
+        let token = manager.create_token("channel", "chan_1").await?;
+        let _ = manager.redeem_token(&token).await?;
 
         // 2. Authorize a user globally
-        let u_token = manager.create_token("user", "user_god")?;
-        let _ = manager.redeem_token(&u_token)?;
+        let u_token = manager.create_token("user", "user_god").await?;
+        let _ = manager.redeem_token(&u_token).await?;
 
         // 3. Check: User god should NOT be restricted by mention_only
         let (auth, mention) = manager.is_authorized("user_god", "chan_1");
@@ -290,4 +272,18 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_cached_registry_follows_other_writers_after_reload() -> anyhow::Result<()> {
+        let (dir, manager) = create_test_manager()?;
+        // 另一個行程（CLI）兌換 token
+        let cli = AuthManager::with_store(StateStore::with_path(dir.path().join("state.json")));
+        let token = manager.create_token("user", "u2").await?;
+        cli.redeem_token(&token).await?;
+
+        assert!(!manager.is_authorized("u2", "c").0);
+        manager.reload().await?;
+        assert!(manager.is_authorized("u2", "c").0);
+        Ok(())
+    }
 }
@@ -0,0 +1,726 @@
+use crate::commands::agent::ChannelConfig;
+use crate::config::{BudgetConfig, BudgetLimits};
+use crate::i18n::I18n;
+use crate::state::StateStore;
+use crate::usage::{
+    format_cost, format_tokens, Reservation, UsageBucket, UsageLedger, UsageStore, UsageTotals,
+};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::warn;
+
+/// 管理員以 /budget 暫時解除限制，到期後自動失效
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BudgetOverrides {
+    #[serde(default)]
+    pub channels: HashMap<u64, DateTime<Utc>>,
+    #[serde(default)]
+    pub users: HashMap<u64, DateTime<Utc>>,
+}
+
+impl BudgetOverrides {
+    fn map(&self, scope: Scope) -> &HashMap<u64, DateTime<Utc>> {
+        match scope {
+            Scope::Channel => &self.channels,
+            Scope::User => &self.users,
+        }
+    }
+
+    fn map_mut(&mut self, scope: Scope) -> &mut HashMap<u64, DateTime<Utc>> {
+        match scope {
+            Scope::Channel => &mut self.channels,
+            Scope::User => &mut self.users,
+        }
+    }
+
+    pub fn active_until(&self, scope: Scope, id: u64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.map(scope)
+            .get(&id)
+            .copied()
+            .filter(|until| *until > now)
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.channels.retain(|_, until| *until > now);
+        self.users.retain(|_, until| *until > now);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Channel,
+    User,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Prompts,
+    Tokens,
+    Cost,
+}
+
+/// 一項設定了上限的用量
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetHit {
+    pub scope: Scope,
+    /// 頻道為父頻道 ID，使用者為 user ID
+    pub id: u64,
+    pub period: Period,
+    /// 這個期間的第一天
+    pub since: NaiveDate,
+    pub metric: Metric,
+    pub used: f64,
+    pub limit: f64,
+}
+
+impl BudgetHit {
+    pub fn exceeded(&self) -> bool {
+        self.used >= self.limit
+    }
+
+    pub fn describe(&self, i18n: &I18n) -> String {
+        let scope = match self.scope {
+            Scope::Channel => i18n.get("budget_scope_channel"),
+            Scope::User => format!("<@{}>", self.id),
+        };
+        let period = match self.period {
+            Period::Daily => i18n.get("budget_period_daily"),
+            Period::Monthly => i18n.get("budget_period_monthly"),
+        };
+        let (metric, used, limit) = match self.metric {
+            Metric::Prompts => (
+                i18n.get("budget_metric_prompts"),
+                self.used.to_string(),
+                self.limit.to_string(),
+            ),
+            Metric::Tokens => (
+                i18n.get("budget_metric_tokens"),
+                format_tokens(self.used as u64),
+                format_tokens(self.limit as u64),
+            ),
+            Metric::Cost => (
+                i18n.get("budget_metric_cost"),
+                format_cost(self.used),
+                format_cost(self.limit),
+            ),
+        };
+        i18n.get_args("budget_hit", &[scope, period, metric, used, limit])
+    }
+}
+
+/// 送出 prompt 前的判定
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Warn(Vec<BudgetHit>),
+    Refuse(BudgetHit),
+}
+
+impl Verdict {
+    /// 要貼到頻道的提示，Allow 時沒有
+    pub fn notice(&self, i18n: &I18n) -> Option<String> {
+        let lines = |hits: &[BudgetHit]| {
+            hits.iter()
+                .map(|h| format!("- {}", h.describe(i18n)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        match self {
+            Verdict::Allow => None,
+            Verdict::Warn(hits) => Some(i18n.get_args("budget_warning", &[lines(hits)])),
+            Verdict::Refuse(hit) => {
+                Some(i18n.get_args("budget_refused", &[lines(std::slice::from_ref(hit))]))
+            }
+        }
+    }
+}
+
+fn limit_of(limits: &BudgetLimits, period: Period, metric: Metric) -> f64 {
+    match (period, metric) {
+        (Period::Daily, Metric::Prompts) => limits.daily_prompts as f64,
+        (Period::Daily, Metric::Tokens) => limits.daily_tokens as f64,
+        (Period::Daily, Metric::Cost) => limits.daily_cost,
+        (Period::Monthly, Metric::Prompts) => limits.monthly_prompts as f64,
+        (Period::Monthly, Metric::Tokens) => limits.monthly_tokens as f64,
+        (Period::Monthly, Metric::Cost) => limits.monthly_cost,
+    }
+}
+
+/// 頻道與 thread mode 開出的討論串共用額度：回傳父頻道與其下所有討論串
+pub fn channel_family(channels: &ChannelConfig, channel_id: u64) -> (u64, Vec<u64>) {
+    let root = channels
+        .channels
+        .get(&channel_id.to_string())
+        .and_then(|e| e.parent_id.as_deref())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or(channel_id);
+    let root_str = root.to_string();
+    let ids = std::iter::once(root)
+        .chain(
+            channels
+                .channels
+                .iter()
+                .filter(|(_, e)| e.parent_id.as_deref() == Some(root_str.as_str()))
+                .filter_map(|(id, _)| id.parse().ok()),
+        )
+        .collect();
+    (root, ids)
+}
+
+/// 預算判定看的用量：已記帳的帳本，加上已放行、還沒記帳的 prompt
+#[derive(Clone, Copy)]
+pub struct BudgetUsage<'a> {
+    pub recorded: &'a UsageLedger,
+    pub in_flight: &'a UsageLedger,
+}
+
+impl BudgetUsage<'_> {
+    fn totals(&self, since: NaiveDate, filter: impl Fn(&UsageBucket) -> bool) -> UsageTotals {
+        let mut totals = self.recorded.totals(since, &filter);
+        totals.add(&self.in_flight.totals(since, &filter));
+        totals
+    }
+}
+
+/// 列出一個 scope 中用量達到上限 min_ratio 倍的項目，沒設定的上限略過
+fn scope_hits(
+    limits: &BudgetLimits,
+    scope: Scope,
+    id: u64,
+    usage: BudgetUsage,
+    today: NaiveDate,
+    min_ratio: f64,
+    filter: impl Fn(&UsageBucket) -> bool,
+) -> Vec<BudgetHit> {
+    let mut hits = Vec::new();
+    for period in [Period::Daily, Period::Monthly] {
+        let since = match period {
+            Period::Daily => today,
+            Period::Monthly => today.with_day(1).unwrap_or(today),
+        };
+        let totals = usage.totals(since, &filter);
+        for metric in [Metric::Prompts, Metric::Tokens, Metric::Cost] {
+            let limit = limit_of(limits, period, metric);
+            if limit <= 0.0 {
+                continue;
+            }
+            let used = match metric {
+                Metric::Prompts => totals.turns as f64,
+                Metric::Tokens => totals.tokens() as f64,
+                Metric::Cost => totals.cost,
+            };
+            if used >= limit * min_ratio {
+                hits.push(BudgetHit {
+                    scope,
+                    id,
+                    period,
+                    since,
+                    metric,
+                    used,
+                    limit,
+                });
+            }
+        }
+    }
+    hits
+}
+
+/// 頻道與使用者所有設定了上限的項目（/budget 顯示用）
+pub fn budget_status(
+    config: &BudgetConfig,
+    usage: BudgetUsage,
+    channels: &ChannelConfig,
+    channel_id: u64,
+    user_id: Option<u64>,
+    today: NaiveDate,
+) -> Vec<BudgetHit> {
+    collect_hits(config, usage, channels, channel_id, user_id, today, 0.0)
+}
+
+fn collect_hits(
+    config: &BudgetConfig,
+    usage: BudgetUsage,
+    channels: &ChannelConfig,
+    channel_id: u64,
+    user_id: Option<u64>,
+    today: NaiveDate,
+    min_ratio: f64,
+) -> Vec<BudgetHit> {
+    let (root, ids) = channel_family(channels, channel_id);
+    let mut hits = scope_hits(
+        &config.channel,
+        Scope::Channel,
+        root,
+        usage,
+        today,
+        min_ratio,
+        |b| ids.contains(&b.channel_id),
+    );
+    if let Some(user) = user_id {
+        hits.extend(scope_hits(
+            &config.user,
+            Scope::User,
+            user,
+            usage,
+            today,
+            min_ratio,
+            |b| b.user_id == Some(user),
+        ));
+    }
+    hits
+}
+
+/// 依目前用量判定：任何一項到達上限就拒絕，到達 warn_percent 就警告；解除限制的 scope 不檢查
+pub fn evaluate(
+    config: &BudgetConfig,
+    usage: BudgetUsage,
+    overrides: &BudgetOverrides,
+    channels: &ChannelConfig,
+    channel_id: u64,
+    user_id: Option<u64>,
+    now: DateTime<Utc>,
+) -> Verdict {
+    let min_ratio = match config.warn_percent {
+        0 => 1.0,
+        p => p.min(100) as f64 / 100.0,
+    };
+    let today = now.date_naive();
+    let hits: Vec<BudgetHit> = collect_hits(
+        config, usage, channels, channel_id, user_id, today, min_ratio,
+    )
+    .into_iter()
+    .filter(|h| overrides.active_until(h.scope, h.id, now).is_none())
+    .collect();
+    if let Some(hit) = hits.iter().find(|h| h.exceeded()) {
+        return Verdict::Refuse(hit.clone());
+    }
+    if hits.is_empty() {
+        Verdict::Allow
+    } else {
+        Verdict::Warn(hits)
+    }
+}
+
+type WarnKey = (Scope, u64, Period, Metric, NaiveDate);
+
+/// 預算檢查與解除限制；同一期間的同一個警告只發一次。
+/// 解除限制快取在記憶體，寫入在 blocking 執行緒落到 state.json
+pub struct BudgetGuard {
+    store: StateStore,
+    overrides: RwLock<BudgetOverrides>,
+    warned: Mutex<HashSet<WarnKey>>,
+    /// 判定與佔位依序進行，同時送出的 prompt 不會一起通過最後一個名額
+    admitting: Mutex<()>,
+}
+
+impl BudgetGuard {
+    pub fn new() -> Result<Self> {
+        Self::with_store(StateStore::open())
+    }
+
+    pub fn with_store(store: StateStore) -> Result<Self> {
+        let overrides = store.read()?.budget_overrides;
+        Ok(Self {
+            store,
+            overrides: RwLock::new(overrides),
+            warned: Mutex::new(HashSet::new()),
+            admitting: Mutex::new(()),
+        })
+    }
+
+    /// 放行時（含警告）在 usage 佔一個 prompt，回合開始記帳前由呼叫端持有
+    pub fn check(
+        &self,
+        config: &BudgetConfig,
+        usage: &Arc<UsageStore>,
+        channels: &ChannelConfig,
+        channel_id: u64,
+        user_id: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> (Verdict, Option<Reservation>) {
+        // 沒有設定任何上限時不必計算用量
+        if config.channel == BudgetLimits::default() && config.user == BudgetLimits::default() {
+            return (Verdict::Allow, None);
+        }
+        let _admitting = self.admitting.lock().unwrap_or_else(|e| e.into_inner());
+        // 先取進行中的再取帳本：回合記帳後才釋放佔位，中間最多多算一次，不會少算
+        let in_flight = usage.in_flight();
+        let recorded = usage.ledger();
+        let verdict = evaluate(
+            config,
+            BudgetUsage {
+                recorded: &recorded,
+                in_flight: &in_flight,
+            },
+            &self.overrides(),
+            channels,
+            channel_id,
+            user_id,
+            now,
+        );
+        if matches!(verdict, Verdict::Refuse(_)) {
+            return (verdict, None);
+        }
+        let reservation = Some(usage.reserve(channel_id, user_id));
+        let Verdict::Warn(hits) = verdict else {
+            return (verdict, reservation);
+        };
+        let mut warned = self.warned.lock().unwrap_or_else(|e| e.into_inner());
+        let fresh: Vec<BudgetHit> = hits
+            .into_iter()
+            .filter(|h| warned.insert((h.scope, h.id, h.period, h.metric, h.since)))
+            .collect();
+        let verdict = if fresh.is_empty() {
+            Verdict::Allow
+        } else {
+            Verdict::Warn(fresh)
+        };
+        (verdict, reservation)
+    }
+
+    pub fn overrides(&self) -> BudgetOverrides {
+        self.overrides
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub async fn set_override(&self, scope: Scope, id: u64, until: DateTime<Utc>) -> Result<()> {
+        let overrides = self
+            .store
+            .update_async(move |state| {
+                state.budget_overrides.prune(Utc::now());
+                state.budget_overrides.map_mut(scope).insert(id, until);
+                Ok(state.budget_overrides.clone())
+            })
+            .await?;
+        self.replace(overrides);
+        Ok(())
+    }
+
+    /// 回傳是否真的有解除中的限制被移除
+    pub async fn clear_override(&self, scope: Scope, id: u64) -> Result<bool> {
+        let (active, overrides) = self
+            .store
+            .update_async(move |state| {
+                let now = Utc::now();
+                let active = state
+                    .budget_overrides
+                    .active_until(scope, id, now)
+                    .is_some();
+                state.budget_overrides.map_mut(scope).remove(&id);
+                state.budget_overrides.prune(now);
+                Ok((active, state.budget_overrides.clone()))
+            })
+            .await?;
+        self.replace(overrides);
+        Ok(active)
+    }
+
+    /// 重新讀取 state.json（手動編輯後 reload 用）
+    pub async fn reload(&self) -> Result<()> {
+        let store = self.store.clone();
+        let overrides = tokio::task::spawn_blocking(move || store.read())
+            .await??
+            .budget_overrides;
+        self.replace(overrides);
+        Ok(())
+    }
+
+    fn replace(&self, overrides: BudgetOverrides) {
+        *self.overrides.write().unwrap_or_else(|e| e.into_inner()) = overrides;
+    }
+}
+
+/// 送出 prompt 前的檢查結果；notice 由呼叫端貼到頻道
+pub struct Admission {
+    pub allowed: bool,
+    pub notice: Option<String>,
+    /// 放行的 prompt 先佔的額度，交給回合的 TurnRecorder 接手
+    pub reservation: Option<Reservation>,
+}
+
+/// 回合開始前呼叫（Handler::gate_input 與佇列送出時），排進佇列的輸入輪到時才檢查
+pub async fn admit(state: &crate::AppState, channel_id: u64, user_id: Option<u64>) -> Admission {
+    let config = state.config.read().await.budget.clone();
+    let channels = state.channels.snapshot();
+    let (verdict, reservation) = state.budget.check(
+        &config,
+        &state.usage,
+        &channels,
+        channel_id,
+        user_id,
+        Utc::now(),
+    );
+    if let Verdict::Refuse(hit) = &verdict {
+        warn!(
+            "🚫 Prompt in channel {} refused: {:?} {:?} {:?} budget reached ({} / {})",
+            channel_id, hit.scope, hit.period, hit.metric, hit.used, hit.limit
+        );
+    }
+    let notice = verdict.notice(&*state.i18n.read().await);
+    Admission {
+        allowed: !matches!(verdict, Verdict::Refuse(_)),
+        notice,
+        reservation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::TurnUsage;
+    use crate::commands::agent::ChannelEntry;
+    use crate::usage::TurnRecorder;
+    use tempfile::tempdir;
+
+    fn now() -> DateTime<Utc> {
+        "2026-03-15T12:00:00Z".parse().expect("time")
+    }
+
+    fn usage(tokens: u64, cost: f64) -> TurnUsage {
+        TurnUsage {
+            input_tokens: tokens,
+            cost,
+            ..Default::default()
+        }
+    }
+
+    /// 沒有進行中的 prompt
+    fn recorded(ledger: &UsageLedger) -> BudgetUsage<'_> {
+        static NONE: UsageLedger = UsageLedger {
+            buckets: Vec::new(),
+        };
+        BudgetUsage {
+            recorded: ledger,
+            in_flight: &NONE,
+        }
+    }
+
+    fn config(channel: BudgetLimits, user: BudgetLimits) -> BudgetConfig {
+        BudgetConfig {
+            warn_percent: 80,
+            channel,
+            user,
+        }
+    }
+
+    #[test]
+    fn test_channel_family_groups_threads_under_parent() {
+        let mut channels = ChannelConfig::default();
+        channels
+            .channels
+            .insert("1".into(), ChannelEntry::default());
+        for thread in ["2", "3"] {
+            channels.channels.insert(
+                thread.into(),
+                ChannelEntry {
+                    parent_id: Some("1".into()),
+                    ..Default::default()
+                },
+            );
+        }
+        let (root, mut ids) = channel_family(&channels, 3);
+        ids.sort();
+        assert_eq!((root, ids), (1, vec![1, 2, 3]));
+        assert_eq!(channel_family(&channels, 9), (9, vec![9]));
+    }
+
+    #[test]
+    fn test_evaluate_warns_then_refuses_and_honours_overrides() {
+        let channels = ChannelConfig::default();
+        let today = now().date_naive();
+        let limits = BudgetLimits {
+            daily_prompts: 5,
+            monthly_cost: 10.0,
+            ..Default::default()
+        };
+        let config = config(limits, BudgetLimits::default());
+        let mut ledger = UsageLedger::default();
+        let mut overrides = BudgetOverrides::default();
+        let verdict = |ledger: &UsageLedger, overrides: &BudgetOverrides| {
+            evaluate(
+                &config,
+                recorded(ledger),
+                overrides,
+                &channels,
+                7,
+                Some(1),
+                now(),
+            )
+        };
+
+        for _ in 0..3 {
+            ledger.record(today, 7, Some(1), "m", &usage(10, 0.0));
+        }
+        assert_eq!(verdict(&ledger, &overrides), Verdict::Allow);
+        ledger.record(today, 7, Some(1), "m", &usage(10, 0.0));
+        match verdict(&ledger, &overrides) {
+            Verdict::Warn(hits) => {
+                assert_eq!(hits.len(), 1);
+                assert_eq!((hits[0].metric, hits[0].used), (Metric::Prompts, 4.0));
+            }
+            other => panic!("unexpected verdict: {:?}", other),
+        }
+
+        // 月初的花費算進本月，上個月的不算
+        ledger.record(
+            today.with_day(1).expect("day"),
+            7,
+            None,
+            "m",
+            &usage(0, 9.0),
+        );
+        ledger.record(
+            today - chrono::Duration::days(20),
+            7,
+            None,
+            "m",
+            &usage(0, 100.0),
+        );
+        ledger.record(today, 7, Some(1), "m", &usage(10, 1.0));
+        match verdict(&ledger, &overrides) {
+            Verdict::Refuse(hit) => assert_eq!(hit.metric, Metric::Prompts),
+            other => panic!("unexpected verdict: {:?}", other),
+        }
+
+        overrides
+            .channels
+            .insert(7, now() + chrono::Duration::hours(1));
+        assert_eq!(verdict(&ledger, &overrides), Verdict::Allow);
+        overrides
+            .channels
+            .insert(7, now() - chrono::Duration::hours(1));
+        assert!(matches!(verdict(&ledger, &overrides), Verdict::Refuse(_)));
+    }
+
+    #[test]
+    fn test_user_limits_count_every_channel() {
+        let channels = ChannelConfig::default();
+        let today = now().date_naive();
+        let limits = BudgetLimits {
+            daily_tokens: 100,
+            ..Default::default()
+        };
+        let config = config(BudgetLimits::default(), limits);
+        let mut ledger = UsageLedger::default();
+        ledger.record(today, 7, Some(1), "m", &usage(60, 0.0));
+        ledger.record(today, 8, Some(1), "m", &usage(60, 0.0));
+        ledger.record(today, 8, Some(2), "m", &usage(500, 0.0));
+        let overrides = BudgetOverrides::default();
+
+        let usage = recorded(&ledger);
+        let refused = evaluate(&config, usage, &overrides, &channels, 9, Some(1), now());
+        match refused {
+            Verdict::Refuse(hit) => {
+                assert_eq!((hit.scope, hit.id, hit.used), (Scope::User, 1, 120.0));
+                let i18n = I18n::new("en");
+                let notice = Verdict::Refuse(hit).notice(&i18n).expect("notice");
+                assert!(notice.contains("<@1>") && notice.contains("120 / 100"));
+            }
+            other => panic!("unexpected verdict: {:?}", other),
+        }
+        // 排程重啟後接回的回合沒有使用者，只看頻道額度
+        let verdict = evaluate(&config, usage, &overrides, &channels, 9, None, now());
+        assert_eq!(verdict, Verdict::Allow);
+    }
+
+    #[tokio::test]
+    async fn test_guard_warns_once_and_persists_overrides() {
+        let dir = tempdir().expect("tempdir");
+        let state_path = dir.path().join("state.json");
+        let guard =
+            BudgetGuard::with_store(StateStore::with_path(state_path.clone())).expect("guard");
+        let store = Arc::new(UsageStore::load(dir.path().join("usage.jsonl")).expect("usage"));
+        let channels = ChannelConfig::default();
+        let limits = BudgetLimits {
+            daily_prompts: 2,
+            ..Default::default()
+        };
+        let config = config(limits, BudgetLimits::default());
+        store.record(7, Some(1), "m", &usage(1, 0.0));
+        store.record(7, Some(1), "m", &usage(1, 0.0));
+        let check = || {
+            guard
+                .check(&config, &store, &channels, 7, Some(1), Utc::now())
+                .0
+        };
+
+        assert!(matches!(check(), Verdict::Refuse(_)));
+        guard
+            .set_override(Scope::Channel, 7, Utc::now() + chrono::Duration::hours(1))
+            .await
+            .expect("override");
+        assert_eq!(check(), Verdict::Allow);
+        let reloaded = BudgetGuard::with_store(StateStore::with_path(state_path)).expect("reload");
+        assert!(reloaded.overrides().channels.contains_key(&7));
+        assert!(guard
+            .clear_override(Scope::Channel, 7)
+            .await
+            .expect("clear"));
+        assert!(!guard
+            .clear_override(Scope::Channel, 7)
+            .await
+            .expect("clear again"));
+
+        // 達到警告門檻但未超過：只警告一次
+        let config = BudgetConfig {
+            warn_percent: 50,
+            channel: BudgetLimits {
+                daily_prompts: 4,
+                ..Default::default()
+            },
+            user: BudgetLimits::default(),
+        };
+        let check = || {
+            guard
+                .check(&config, &store, &channels, 7, Some(1), Utc::now())
+                .0
+        };
+        assert!(matches!(check(), Verdict::Warn(_)));
+        assert_eq!(check(), Verdict::Allow);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_prompts_count_against_limits() {
+        let dir = tempdir().expect("tempdir");
+        let guard = BudgetGuard::with_store(StateStore::with_path(dir.path().join("state.json")))
+            .expect("guard");
+        let store = Arc::new(UsageStore::load(dir.path().join("usage.jsonl")).expect("usage"));
+        let channels = ChannelConfig::default();
+        let limits = BudgetLimits {
+            daily_prompts: 2,
+            ..Default::default()
+        };
+        let config = config(limits, BudgetLimits::default());
+        let check = || guard.check(&config, &store, &channels, 7, Some(1), Utc::now());
+
+        // 兩個還沒結束的 prompt 就佔滿額度，第三個同時送出的被拒絕
+        let (first, held) = check();
+        assert_eq!(first, Verdict::Allow);
+        let (_, held_too) = check();
+        let (third, none) = check();
+        assert!(matches!(third, Verdict::Refuse(_)) && none.is_none());
+
+        // 回合開始時 TurnRecorder 接手先佔的額度，不會算兩次；結束時記成一次 prompt
+        let recorder = TurnRecorder::new(Arc::clone(&store), 7, Some(1), "pi".into(), held);
+        assert!(matches!(check().0, Verdict::Refuse(_)));
+        drop(held_too);
+        assert_eq!(check().0, Verdict::Allow);
+        drop(recorder);
+        let today = Utc::now().date_naive();
+        assert_eq!(store.ledger().totals(today, |_| true).turns, 1);
+        let (_, held) = check();
+        assert!(held.is_some());
+        assert!(matches!(check().0, Verdict::Refuse(_)));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::commands::agent::{ChannelConfig, ChannelEntry};
+use crate::session::SessionManager;
+use crate::state::StateStore;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+const EVENT_CAPACITY: usize = 64;
+
+/// 一個頻道設定的變更；before/after 為 None 代表新增或移除
+#[derive(Clone, Debug)]
+pub struct ChannelChange {
+    pub channel_id: String,
+    pub before: Option<ChannelEntry>,
+    pub after: Option<ChannelEntry>,
+}
+
+impl ChannelChange {
+    pub fn backend_changed(&self) -> bool {
+        self.before.as_ref().map(|e| &e.agent_type) != self.after.as_ref().map(|e| &e.agent_type)
+    }
+}
+
+/// 比較兩份設定，依頻道 ID 排序回傳有變動的頻道
+pub fn diff(before: &ChannelConfig, after: &ChannelConfig) -> Vec<ChannelChange> {
+    let ids: BTreeSet<&String> = before
+        .channels
+        .keys()
+        .chain(after.channels.keys())
+        .collect();
+    ids.into_iter()
+        .filter_map(|id| {
+            let (old, new) = (before.channels.get(id), after.channels.get(id));
+            (old != new).then(|| ChannelChange {
+                channel_id: id.clone(),
+                before: old.cloned(),
+                after: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// 頻道設定的記憶體快取：啟動時讀一次，讀取不碰磁碟，
+/// 寫入在 blocking 執行緒落到 state.json 後再更新快取並廣播變更
+pub struct ChannelRegistry {
+    store: StateStore,
+    cache: RwLock<Arc<ChannelConfig>>,
+    /// 寫入與廣播依序進行，訂閱者看到的順序和磁碟一致
+    write_lock: tokio::sync::Mutex<()>,
+    events: broadcast::Sender<ChannelChange>,
+}
+
+impl ChannelRegistry {
+    pub fn load(store: StateStore) -> Result<Self> {
+        let channels = store.read()?.channels;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Ok(Self {
+            store,
+            cache: RwLock::new(Arc::new(channels)),
+            write_lock: tokio::sync::Mutex::new(()),
+            events,
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<ChannelConfig> {
+        Arc::clone(&self.cache.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn get(&self, channel_id: &str) -> Option<ChannelEntry> {
+        self.snapshot().channels.get(channel_id).cloned()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChannelChange> {
+        self.events.subscribe()
+    }
+
+    /// 在快取的複本上修改，寫入 state.json 成功後才更新快取；f 回傳錯誤時什麼都不改。
+    /// 行程內只有 registry 會寫頻道設定，外部手動編輯要先 reload
+    pub async fn update<R>(&self, f: impl FnOnce(&mut ChannelConfig) -> Result<R>) -> Result<R> {
+        let _guard = self.write_lock.lock().await;
+        let mut channels = (*self.snapshot()).clone();
+        let out = f(&mut channels)?;
+        let written = channels.clone();
+        self.store
+            .update_async(move |state| {
+                state.channels = written;
+                Ok(())
+            })
+            .await?;
+        self.replace(channels);
+        Ok(out)
+    }
+
+    /// 重新讀取 state.json（手動編輯後 reload 用）
+    pub async fn reload(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let store = self.store.clone();
+        let channels = tokio::task::spawn_blocking(move || store.read())
+            .await??
+            .channels;
+        self.replace(channels);
+        Ok(())
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        self.store.flush_async().await
+    }
+
+    fn replace(&self, channels: ChannelConfig) {
+        let after = Arc::new(channels);
+        let before = {
+            let mut cache = self.cache.write().unwrap_or_else(|e| e.into_inner());
+            std::mem::replace(&mut *cache, Arc::clone(&after))
+        };
+        for change in diff(&before, &after) {
+            // 沒有訂閱者時送出會失敗，可以忽略
+            let _ = self.events.send(change);
+        }
+    }
+}
+
+#[cfg(test)]
+impl ChannelRegistry {
+    /// 以 dir 下的 state.json 建立 registry，給需要頻道設定的後端測試使用
+    pub fn in_dir(dir: &std::path::Path) -> Arc<Self> {
+        let store = StateStore::with_path(dir.join("state.json"));
+        Arc::new(Self::load(store).expect("load channel registry"))
+    }
+}
+
+/// 頻道換了後端時立刻丟掉舊的 session，下一則訊息以新的後端建立
+pub async fn watch_sessions(
+    mut rx: broadcast::Receiver<ChannelChange>,
+    sessions: Arc<SessionManager>,
+) {
+    loop {
+        let change = match rx.recv().await {
+            Ok(change) => change,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("⚠️ Channel change watcher skipped {} events", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let (Some(after), Ok(channel_id)) = (&change.after, change.channel_id.parse::<u64>())
+        else {
+            continue;
+        };
+        if change.backend_changed()
+            && sessions
+                .remove_if_backend_differs(channel_id, &after.agent_type)
+                .await
+        {
+            info!(
+                "🔀 Dropped {} session of channel {} after backend switch",
+                change
+                    .before
+                    .as_ref()
+                    .map(|e| e.agent_type.to_string())
+                    .unwrap_or_default(),
+                channel_id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentType;
+    use tempfile::tempdir;
+
+    fn entry(agent_type: AgentType) -> ChannelEntry {
+        ChannelEntry {
+            agent_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_added_changed_and_removed_channels() {
+        let mut before = ChannelConfig::default();
+        before.channels.insert("1".into(), entry(AgentType::Pi));
+        before.channels.insert("2".into(), entry(AgentType::Pi));
+        before.channels.insert("3".into(), entry(AgentType::Pi));
+        let mut after = before.clone();
+        after.channels.remove("1");
+        after.channels.get_mut("2").expect("2").model_id = Some("m".into());
+        after.channels.insert("4".into(), entry(AgentType::Kilo));
+
+        let changes = diff(&before, &after);
+        let ids: Vec<&str> = changes.iter().map(|c| c.channel_id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "4"]);
+        assert!(changes[0].after.is_none() && changes[0].backend_changed());
+        let model = changes[1].after.as_ref().and_then(|e| e.model_id.as_deref());
+        assert!(model == Some("m") && !changes[1].backend_changed());
+        assert!(changes[2].before.is_none() && changes[2].backend_changed());
+    }
+
+    #[tokio::test]
+    async fn test_update_writes_through_and_publishes_changes() {
+        let dir = tempdir().expect("tempdir");
+        let store = StateStore::with_path(dir.path().join("state.json"));
+        let registry = ChannelRegistry::load(store.clone()).expect("load");
+        let mut rx = registry.subscribe();
+
+        registry
+            .update(|c| {
+                c.set_agent_type("7", AgentType::Opencode);
+                Ok(())
+            })
+            .await
+            .expect("update");
+        assert_eq!(
+            registry.get("7").map(|e| e.agent_type),
+            Some(AgentType::Opencode)
+        );
+        let on_disk = store.read().expect("read").channels;
+        assert_eq!(on_disk.channels["7"].agent_type, AgentType::Opencode);
+        let change = rx.try_recv().expect("change");
+        assert_eq!(change.channel_id, "7");
+        assert!(change.before.is_none() && change.backend_changed());
+
+        // 沒有變動就不廣播，失敗的更新不動快取
+        registry.update(|_| Ok(())).await.expect("noop");
+        let err = registry
+            .update(|c| {
+                c.set_agent_type("7", AgentType::Kilo);
+                anyhow::bail!("rejected")
+            })
+            .await;
+        assert!(err.map(|_: ()| ()).is_err());
+        assert_eq!(
+            registry.get("7").map(|e| e.agent_type),
+            Some(AgentType::Opencode)
+        );
+        assert!(rx.try_recv().is_err());
+
+        // 其他寫入者改了磁碟，reload 後快取跟上並廣播
+        store
+            .update(|s| {
+                s.channels.set_agent_type("7", AgentType::Pi);
+                Ok(())
+            })
+            .expect("external write");
+        assert_eq!(
+            registry.get("7").map(|e| e.agent_type),
+            Some(AgentType::Opencode)
+        );
+        registry.reload().await.expect("reload");
+        assert_eq!(registry.get("7").map(|e| e.agent_type), Some(AgentType::Pi));
+        assert!(rx.try_recv().expect("reload change").backend_changed());
+    }
+}
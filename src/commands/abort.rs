@@ -1,9 +1,63 @@
 use super::SlashCommand;
 use async_trait::async_trait;
-use serenity::all::{CommandInteraction, Context, EditInteractionResponse};
+use serenity::all::{ChannelId, CommandInteraction, Context, EditInteractionResponse, Http};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::agent::AiAgent;
 
 pub struct AbortCommand;
 
+/// 中斷頻道的回合：停止 agent、把進行中的回覆標成已中止，再放行排隊的訊息。
+/// 回傳是否有進行中的回覆被結束
+pub async fn abort_channel(
+    state: &crate::AppState,
+    http: &Http,
+    channel_id_u64: u64,
+    agent: Option<Arc<dyn AiAgent>>,
+) -> anyhow::Result<bool> {
+    // 先停掉 render 任務，免得它在中斷後又蓋掉訊息
+    let render = state
+        .active_renders
+        .lock()
+        .await
+        .remove(&channel_id_u64)
+        .map(|render| {
+            for h in render.handles {
+                h.abort();
+            }
+            render.message_id
+        });
+
+    let aborted = match agent {
+        Some(agent) => agent.abort().await,
+        None => Ok(()),
+    };
+
+    // 還在準備中的回合（沒有訊息）會在開始前發現位置已被釋放
+    if let Some(Some(msg_id)) = render {
+        let (title, hint) = {
+            let i18n = state.i18n.read().await;
+            (i18n.get("turn_aborted"), i18n.get("turn_aborted_hint"))
+        };
+        let channel_id = ChannelId::new(channel_id_u64);
+        crate::shutdown::mark_interrupted(http, channel_id, msg_id, &title, &hint).await;
+        if let Err(e) = state.inflight.finish(channel_id_u64, msg_id.get()).await {
+            error!("❌ Failed to clear in-flight turn: {}", e);
+        }
+        info!("🛑 Ended render {} in channel {}", msg_id, channel_id_u64);
+    }
+    if render.is_some() {
+        state.threads.refresh(channel_id_u64);
+        state.input_queue.notify_idle(channel_id_u64);
+    }
+
+    if let Err(e) = &aborted {
+        warn!("⚠️ Agent abort failed in channel {}: {}", channel_id_u64, e);
+    }
+    aborted.map(|_| render.is_some())
+}
+
 #[async_trait]
 impl SlashCommand for AbortCommand {
     fn name(&self) -> &'static str {
@@ -23,9 +77,7 @@ impl SlashCommand for AbortCommand {
         command.defer_ephemeral(&ctx.http).await?;
 
         let channel_id_str = command.channel_id.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
         let agent_type = channel_config.get_agent_type(&channel_id_str);
 
         let (agent, _) = state
@@ -33,7 +85,7 @@ impl SlashCommand for AbortCommand {
             .get_or_create_session(command.channel_id.get(), agent_type, &state.backend_manager)
             .await?;
 
-        agent.abort().await?;
+        abort_channel(state, &ctx.http, command.channel_id.get(), Some(agent)).await?;
 
         let i18n = state.i18n.read().await;
         let msg = i18n.get("abort_success");
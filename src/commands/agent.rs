@@ -31,11 +31,20 @@ pub fn build_backend_error_message(
     );
 
     if is_binary_not_found(error_text) {
-        let install_cmd = match agent_type {
+        let install_cmd = match &agent_type {
             AgentType::Pi => "npm i -g @mariozechner/pi-coding-agent",
             AgentType::Opencode => "npm i -g opencode-ai@latest",
             AgentType::Kilo => "npm i -g @kilocode/cli",
             AgentType::Copilot => "npm i -g @github/copilot",
+            // 不需要安裝 CLI，只要 server 連得到
+            AgentType::OpenAiCompat => {
+                return format!("{}\n\n{}", base, i18n.get("openai_runtime_hint"));
+            }
+            // 命令由 config.toml 設定，沒有固定的安裝方式
+            AgentType::Acp(name) => {
+                let hint = i18n.get_args("acp_command_hint", std::slice::from_ref(name));
+                return format!("{}\n\n{}", base, hint);
+            }
         };
         return format!(
             "{}\n\n{}:\n```bash\n{}\n```",
@@ -77,6 +86,33 @@ pub fn build_backend_error_message(
             )
         }
         AgentType::Pi => format!("{}\n\n{}", base, i18n.get("pi_runtime_hint")),
+        AgentType::OpenAiCompat => format!("{}\n\n{}", base, i18n.get("openai_runtime_hint")),
+        AgentType::Acp(name) => format!(
+            "{}\n\n{}",
+            base,
+            i18n.get_args("acp_runtime_hint", std::slice::from_ref(&name))
+        ),
+    }
+}
+
+/// `/agent backend:acp` 沒給名稱或名稱不存在時，列出 config.toml 中可用的 ACP agent
+fn format_acp_agents(
+    i18n: &crate::i18n::I18n,
+    name: &str,
+    agents: &[(String, String)],
+) -> String {
+    if agents.is_empty() {
+        return i18n.get("agent_acp_none");
+    }
+    let list = agents
+        .iter()
+        .map(|(name, label)| format!("- `{}` ({})", name, label))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if name.is_empty() {
+        i18n.get_args("agent_acp_missing_name", &[list])
+    } else {
+        i18n.get_args("agent_acp_unknown", &[name.to_string(), list])
     }
 }
 
@@ -86,7 +122,81 @@ pub struct ChannelConfig {
     pub channels: HashMap<String, ChannelEntry>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+/// ACP 後端（Copilot 與 config.toml 的 ACP agent）工具權限請求的處理方式
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionPolicy {
+    /// 自動允許（舊行為）
+    #[default]
+    Auto,
+    /// 在 Discord 發出確認按鈕
+    Ask,
+    /// 一律拒絕
+    Deny,
+}
+
+impl std::fmt::Display for PermissionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            PermissionPolicy::Auto => "auto",
+            PermissionPolicy::Ask => "ask",
+            PermissionPolicy::Deny => "deny",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::str::FromStr for PermissionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(PermissionPolicy::Auto),
+            "ask" => Ok(PermissionPolicy::Ask),
+            "deny" => Ok(PermissionPolicy::Deny),
+            other => anyhow::bail!("Unknown permission policy: {}", other),
+        }
+    }
+}
+
+/// 回覆進行中又收到新訊息時的處理方式
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePolicy {
+    /// 中斷目前的回覆，立即處理新訊息（舊行為）
+    #[default]
+    Preempt,
+    /// 排隊，等目前回覆結束後依序處理
+    Queue,
+    /// 排隊期間的訊息合併成一則，在目前回覆結束後送出
+    Merge,
+}
+
+impl std::fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            QueuePolicy::Preempt => "preempt",
+            QueuePolicy::Queue => "queue",
+            QueuePolicy::Merge => "merge",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::str::FromStr for QueuePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preempt" => Ok(QueuePolicy::Preempt),
+            "queue" => Ok(QueuePolicy::Queue),
+            "merge" => Ok(QueuePolicy::Merge),
+            other => anyhow::bail!("Unknown queue policy: {}", other),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct ChannelEntry {
     #[serde(default)]
     pub agent_type: AgentType,
@@ -100,24 +210,159 @@ pub struct ChannelEntry {
     pub model_provider: Option<String>,
     pub model_id: Option<String>,
     pub assistant_name: Option<String>,
+    #[serde(default)]
+    pub permission_policy: PermissionPolicy,
+    #[serde(default)]
+    pub queue_policy: QueuePolicy,
+    /// 綁定的工作目錄（已正規化的絕對路徑），未設定時沿用 bot 的啟動目錄
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// 開啟時，每個頂層 mention 都會開一個獨立 session 的討論串
+    #[serde(default)]
+    pub thread_mode: bool,
+    /// 由 thread mode 開出的討論串會記錄父頻道
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// 指定連線的外部 server（config 中 `servers` 的名稱），未設定時依後端的 mode
+    #[serde(default)]
+    pub server: Option<String>,
+    /// 對話討論串被封存的時間；保留一段時間讓用量仍算進父頻道，之後移除
+    #[serde(default)]
+    pub archived_at: Option<String>,
+    /// OpenCode/Kilo 以 /thinking 選定的 reasoning variant
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// OpenCode/Kilo 以 /skill 選定的 server agent
+    #[serde(default)]
+    pub server_agent: Option<String>,
+}
+
+impl ChannelEntry {
+    /// 新頻道的預設值：立即授權時間、mention_only 開啟
+    pub fn new(agent_type: AgentType) -> Self {
+        Self {
+            agent_type,
+            authorized_at: chrono::Utc::now().to_rfc3339(),
+            mention_only: true,
+            ..Default::default()
+        }
+    }
 }
 
 impl ChannelConfig {
-    pub async fn load() -> anyhow::Result<Self> {
-        let path = super::super::migrate::get_channel_config_path();
-        if !path.exists() {
-            return Ok(Self::default());
+    /// 取得頻道設定，不存在時以目前（預設）後端建立
+    pub fn ensure_entry(&mut self, channel_id: &str) -> &mut ChannelEntry {
+        let agent_type = self.get_agent_type(channel_id);
+        self.channels
+            .entry(channel_id.to_string())
+            .or_insert_with(|| ChannelEntry::new(agent_type))
+    }
+
+    pub fn get_permission_policy(&self, channel_id: &str) -> PermissionPolicy {
+        self.channels
+            .get(channel_id)
+            .map(|e| e.permission_policy)
+            .unwrap_or_default()
+    }
+
+    pub fn get_queue_policy(&self, channel_id: &str) -> QueuePolicy {
+        self.channels
+            .get(channel_id)
+            .map(|e| e.queue_policy)
+            .unwrap_or_default()
+    }
+
+    pub fn get_workdir(&self, channel_id: &str) -> Option<String> {
+        self.channels.get(channel_id).and_then(|e| e.workdir.clone())
+    }
+
+    pub fn is_thread_mode(&self, channel_id: &str) -> bool {
+        self.channels.get(channel_id).is_some_and(|e| e.thread_mode)
+    }
+
+    /// 是否為 thread mode 開出的對話討論串
+    pub fn is_conversation_thread(&self, channel_id: &str) -> bool {
+        self.channels
+            .get(channel_id)
+            .is_some_and(|e| e.parent_id.is_some())
+    }
+
+    /// 為新討論串建立設定：沿用父頻道的後端、模型與各項策略，但從新的 session 開始
+    pub fn spawn_thread_entry(&mut self, parent_id: &str, thread_id: &str) {
+        let parent = self
+            .channels
+            .get(parent_id)
+            .cloned()
+            .unwrap_or_else(|| ChannelEntry::new(AgentType::default()));
+        let entry = ChannelEntry {
+            authorized_at: chrono::Utc::now().to_rfc3339(),
+            mention_only: false,
+            session_id: None,
+            thread_mode: false,
+            parent_id: Some(parent_id.to_string()),
+            ..parent
+        };
+        self.channels.insert(thread_id.to_string(), entry);
+    }
+
+    /// 尚未封存的對話討論串
+    pub fn open_conversation_threads(&self) -> Vec<u64> {
+        self.channels
+            .iter()
+            .filter(|(_, e)| e.parent_id.is_some() && e.archived_at.is_none())
+            .filter_map(|(id, _)| id.parse().ok())
+            .collect()
+    }
+
+    /// 標記或取消標記討論串的封存；回傳是否有變動
+    pub fn set_thread_archived(
+        &mut self,
+        thread_id: &str,
+        archived_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> bool {
+        let Some(entry) = self
+            .channels
+            .get_mut(thread_id)
+            .filter(|e| e.parent_id.is_some())
+        else {
+            return false;
+        };
+        if entry.archived_at.is_some() == archived_at.is_some() {
+            return false;
         }
-        let content = tokio::fs::read_to_string(&path).await?;
-        let config: Self = serde_json::from_str(&content)?;
-        Ok(config)
+        entry.archived_at = archived_at.map(|t| t.to_rfc3339());
+        true
     }
 
-    pub async fn save(&self) -> anyhow::Result<()> {
-        let path = super::super::migrate::get_channel_config_path();
-        let content = serde_json::to_string_pretty(self)?;
-        tokio::fs::write(&path, content).await?;
-        Ok(())
+    /// 封存超過 `retention` 的討論串
+    pub fn expired_threads(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        retention: chrono::Duration,
+    ) -> Vec<String> {
+        self.channels
+            .iter()
+            .filter(|(_, e)| {
+                e.archived_at
+                    .as_deref()
+                    .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                    .is_some_and(|t| now - t.with_timezone(&chrono::Utc) >= retention)
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// 移除封存超過 `retention` 的討論串設定，回傳移除的 ID
+    pub fn prune_archived_threads(
+        &mut self,
+        now: chrono::DateTime<chrono::Utc>,
+        retention: chrono::Duration,
+    ) -> Vec<String> {
+        let expired = self.expired_threads(now, retention);
+        for id in &expired {
+            self.channels.remove(id);
+        }
+        expired
     }
 
     pub fn get_agent_type(&self, channel_id: &str) -> AgentType {
@@ -131,15 +376,7 @@ impl ChannelConfig {
         let entry = self
             .channels
             .entry(channel_id.to_string())
-            .or_insert_with(|| ChannelEntry {
-                agent_type: agent_type.clone(),
-                authorized_at: chrono::Utc::now().to_rfc3339(),
-                mention_only: true,
-                session_id: None,
-                model_provider: None,
-                model_id: None,
-                assistant_name: None,
-            });
+            .or_insert_with(|| ChannelEntry::new(agent_type.clone()));
         entry.agent_type = agent_type;
     }
 }
@@ -150,21 +387,34 @@ impl SlashCommand for AgentCommand {
         "agent"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
     fn description(&self, i18n: &crate::i18n::I18n) -> String {
         i18n.get("cmd_agent_desc")
     }
 
     fn options(&self, i18n: &crate::i18n::I18n) -> Vec<CreateCommandOption> {
-        vec![CreateCommandOption::new(
-            CommandOptionType::String,
-            "backend",
-            i18n.get("cmd_agent_opt_backend"),
-        )
-        .required(true)
-        .add_string_choice(i18n.get("agent_choice_kilo"), "kilo")
-        .add_string_choice(i18n.get("agent_choice_copilot"), "copilot")
-        .add_string_choice(i18n.get("agent_choice_pi"), "pi")
-        .add_string_choice(i18n.get("agent_choice_opencode"), "opencode")]
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "backend",
+                i18n.get("cmd_agent_opt_backend"),
+            )
+            .required(true)
+            .add_string_choice(i18n.get("agent_choice_kilo"), "kilo")
+            .add_string_choice(i18n.get("agent_choice_copilot"), "copilot")
+            .add_string_choice(i18n.get("agent_choice_pi"), "pi")
+            .add_string_choice(i18n.get("agent_choice_opencode"), "opencode")
+            .add_string_choice(i18n.get("agent_choice_openai"), "openai")
+            .add_string_choice(i18n.get("agent_choice_acp"), "acp"),
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "name",
+                i18n.get("cmd_agent_opt_name"),
+            ),
+        ]
     }
 
     async fn execute(
@@ -176,23 +426,40 @@ impl SlashCommand for AgentCommand {
         // 先 defer，避免 3 秒超時
         command.defer_ephemeral(&ctx.http).await?;
 
-        let new_agent_type_str = command
-            .data
-            .options
-            .iter()
-            .find(|o| o.name == "backend")
-            .and_then(|o| o.value.as_str())
-            .unwrap_or("pi");
+        let option = |name: &str| {
+            command
+                .data
+                .options
+                .iter()
+                .find(|o| o.name == name)
+                .and_then(|o| o.value.as_str())
+        };
+        let new_agent_type_str = option("backend").unwrap_or("pi");
+        let i18n = state.i18n.read().await;
 
-        let new_agent_type: AgentType = new_agent_type_str.parse()?;
+        let new_agent_type: AgentType = if new_agent_type_str == "acp" {
+            let agents = state.config.read().await.acp_agents();
+            match option("name").map(str::trim) {
+                Some(name) if agents.iter().any(|(n, _)| n == name) => {
+                    AgentType::Acp(name.to_string())
+                }
+                name => {
+                    let msg = format_acp_agents(&i18n, name.unwrap_or_default(), &agents);
+                    command
+                        .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        } else {
+            new_agent_type_str.parse()?
+        };
         let channel_id = command.channel_id.to_string();
 
         // 檢查當前 agent 類型
-        let config = ChannelConfig::load().await?;
+        let config = state.channels.snapshot();
         let current_agent = config.get_agent_type(&channel_id);
 
-        let i18n = state.i18n.read().await;
-
         if current_agent == new_agent_type {
             let msg = i18n.get_args("agent_already", &[new_agent_type.to_string()]);
             command
@@ -251,10 +518,6 @@ pub async fn handle_button(
         let channel_id = interaction.channel_id.to_string();
         let channel_id_u64 = interaction.channel_id.get();
 
-        // 先更新配置
-        let mut channel_config = ChannelConfig::load().await?;
-        channel_config.set_agent_type(&channel_id, agent_type.clone());
-
         // 移除舊 session
         state.session_manager.remove_session(channel_id_u64).await;
 
@@ -266,7 +529,10 @@ pub async fn handle_button(
         {
             Ok(_) => {
                 // 連接成功，保存配置
-                channel_config.save().await?;
+                state.channels.update(|channel_config| {
+                    channel_config.set_agent_type(&channel_id, agent_type.clone());
+                    Ok(())
+                }).await?;
                 info!("Channel {} switched to {} backend", channel_id, agent_type);
 
                 interaction
@@ -285,7 +551,7 @@ pub async fn handle_button(
                     &i18n,
                     agent_type,
                     &error_text,
-                    state.config.opencode.port,
+                    state.config.read().await.opencode.port,
                 );
 
                 interaction
@@ -305,7 +571,10 @@ pub async fn handle_button(
 
 #[cfg(test)]
 mod tests {
-    use super::{build_backend_error_message, is_binary_not_found, ChannelConfig, ChannelEntry};
+    use super::{
+        build_backend_error_message, format_acp_agents, is_binary_not_found, ChannelConfig,
+        ChannelEntry, PermissionPolicy, QueuePolicy,
+    };
     use crate::agent::AgentType;
     use crate::i18n::I18n;
 
@@ -326,7 +595,7 @@ mod tests {
             4096,
         );
         assert!(msg.contains("Install the backend first"));
-        assert!(msg.contains("npm install -g @opencode-ai/cli"));
+        assert!(msg.contains("npm i -g opencode-ai@latest"));
     }
 
     #[test]
@@ -349,12 +618,70 @@ mod tests {
         }"#;
         let entry: ChannelEntry = serde_json::from_str(legacy).expect("legacy json should parse");
         assert_eq!(entry.session_id.as_deref(), Some("sid-legacy"));
+        assert_eq!(entry.permission_policy, PermissionPolicy::Auto);
+        assert_eq!(entry.queue_policy, QueuePolicy::Preempt);
 
         let serialized = serde_json::to_string(&entry).expect("serialize");
         assert!(serialized.contains("\"session_id\""));
         assert!(!serialized.contains("\"kilo_session_id\""));
     }
 
+    #[test]
+    fn test_channel_policies_parse_and_default_per_channel() {
+        assert_eq!("merge".parse::<QueuePolicy>().expect("parse"), QueuePolicy::Merge);
+        assert_eq!("ask".parse::<PermissionPolicy>().expect("parse"), PermissionPolicy::Ask);
+        assert!("later".parse::<QueuePolicy>().is_err());
+
+        let mut cfg = ChannelConfig::default();
+        cfg.ensure_entry("1").queue_policy = QueuePolicy::Queue;
+        assert_eq!(cfg.get_queue_policy("1"), QueuePolicy::Queue);
+        assert_eq!(cfg.get_queue_policy("2"), QueuePolicy::Preempt);
+        assert_eq!(cfg.get_permission_policy("1"), PermissionPolicy::Auto);
+        assert!(cfg.channels["1"].mention_only);
+    }
+
+    #[test]
+    fn test_spawn_thread_entry_inherits_parent_with_fresh_session() {
+        let mut cfg = ChannelConfig::default();
+        let parent = cfg.ensure_entry("10");
+        parent.agent_type = AgentType::Copilot;
+        parent.session_id = Some("parent-sid".to_string());
+        parent.queue_policy = QueuePolicy::Merge;
+        parent.workdir = Some("/src/app".to_string());
+        parent.thread_mode = true;
+
+        cfg.spawn_thread_entry("10", "11");
+        let thread = &cfg.channels["11"];
+        assert_eq!(thread.agent_type, AgentType::Copilot);
+        assert_eq!(thread.queue_policy, QueuePolicy::Merge);
+        assert_eq!(thread.workdir.as_deref(), Some("/src/app"));
+        assert!(thread.session_id.is_none());
+        assert!(!thread.thread_mode && !thread.mention_only);
+        assert!(cfg.is_conversation_thread("11"));
+        assert!(!cfg.is_conversation_thread("10"));
+        assert!(cfg.is_thread_mode("10"));
+    }
+
+    #[test]
+    fn test_archived_threads_are_kept_then_pruned() {
+        let now = chrono::Utc::now();
+        let mut cfg = ChannelConfig::default();
+        cfg.ensure_entry("10").thread_mode = true;
+        cfg.spawn_thread_entry("10", "11");
+        cfg.spawn_thread_entry("10", "12");
+        assert!(!cfg.set_thread_archived("10", Some(now)));
+        assert!(cfg.set_thread_archived("11", Some(now - chrono::Duration::days(40))));
+        assert!(!cfg.set_thread_archived("11", Some(now)));
+        assert_eq!(cfg.open_conversation_threads(), vec![12]);
+
+        assert!(cfg.set_thread_archived("12", Some(now)));
+        assert!(cfg.set_thread_archived("12", None));
+        assert!(cfg.set_thread_archived("12", Some(now)));
+        let pruned = cfg.prune_archived_threads(now, chrono::Duration::days(35));
+        assert_eq!(pruned, vec!["11".to_string()]);
+        assert!(cfg.channels.contains_key("10") && cfg.channels.contains_key("12"));
+    }
+
     #[test]
     fn test_backend_error_message_for_pi_runtime_hint() {
         let i18n = I18n::new("en");
@@ -410,4 +737,29 @@ mod tests {
         assert!(copilot.contains("@github/copilot"));
         assert!(kilo.contains("@kilocode/cli"));
     }
+
+    #[test]
+    fn test_acp_agent_listing_and_error_hints() {
+        let i18n = I18n::new("en");
+        assert_eq!(format_acp_agents(&i18n, "", &[]), i18n.get("agent_acp_none"));
+        let agents = vec![("gemini".to_string(), "Gemini CLI".to_string())];
+        let missing = format_acp_agents(&i18n, "", &agents);
+        assert!(missing.contains("- `gemini` (Gemini CLI)"));
+        let unknown = format_acp_agents(&i18n, "nope", &agents);
+        assert!(unknown.contains("nope"));
+        assert!(unknown.contains("`gemini`"));
+
+        let gemini = AgentType::Acp("gemini".into());
+        let not_found = build_backend_error_message(
+            &i18n,
+            gemini.clone(),
+            "No such file or directory",
+            0,
+        );
+        assert!(not_found.contains("acp:gemini"));
+        assert!(not_found.contains("[acp.gemini]"));
+        assert!(!not_found.contains("npm i -g"));
+        let runtime = build_backend_error_message(&i18n, gemini, "initialize failed", 0);
+        assert!(runtime.contains("[acp.gemini]"));
+    }
 }
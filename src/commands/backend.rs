@@ -0,0 +1,169 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+
+use crate::agent::manager::BackendHealth;
+use crate::agent::AgentType;
+use crate::i18n::I18n;
+
+pub struct BackendCommand;
+
+/// 所有後端都列出來，尚未啟動過的也顯示
+const BACKENDS: [&str; 5] = ["kilo", "opencode", "copilot", "pi", "openai"];
+
+/// 內建後端加上 config.toml 設定的 ACP agent
+fn backend_names(config: &crate::config::Config) -> Vec<String> {
+    BACKENDS
+        .iter()
+        .map(|name| name.to_string())
+        .chain(
+            config
+                .acp_agents()
+                .into_iter()
+                .map(|(name, _)| AgentType::Acp(name).to_string()),
+        )
+        .collect()
+}
+
+fn format_status(
+    i18n: &I18n,
+    names: &[String],
+    health: &[BackendHealth],
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let mut lines = vec![format!("### {}", i18n.get("backend_status_title"))];
+    for name in names {
+        let Some(h) = health.iter().find(|h| &h.name == name) else {
+            lines.push(i18n.get_args("backend_status_never", std::slice::from_ref(name)));
+            continue;
+        };
+        let mut line = if h.alive {
+            let uptime = h
+                .started_at
+                .map(|t| (now - t).num_seconds().max(0) as u64)
+                .unwrap_or(0);
+            i18n.get_args(
+                "backend_status_up",
+                &[
+                    name.to_string(),
+                    crate::control::format_uptime(uptime),
+                    h.restarts.to_string(),
+                ],
+            )
+        } else {
+            i18n.get_args(
+                "backend_status_down",
+                &[name.to_string(), h.restarts.to_string()],
+            )
+        };
+        if let Some(err) = &h.last_error {
+            line.push('\n');
+            line.push_str(&i18n.get_args("backend_status_last_error", std::slice::from_ref(err)));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl SlashCommand for BackendCommand {
+    fn name(&self) -> &'static str {
+        "backend"
+    }
+
+    fn description(&self, i18n: &I18n) -> String {
+        i18n.get("cmd_backend_desc")
+    }
+
+    fn options(&self, i18n: &I18n) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "status",
+            i18n.get("cmd_backend_status_desc"),
+        )]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let health = state.backend_manager.health();
+        let names = backend_names(&*state.config.read().await);
+        let msg = {
+            let i18n = state.i18n.read().await;
+            format_status(&i18n, &names, &health, chrono::Utc::now())
+        };
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_status_lists_every_backend() {
+        let i18n = I18n::new("en");
+        let now = chrono::Utc::now();
+        let health = vec![
+            BackendHealth {
+                name: "opencode".into(),
+                alive: true,
+                started_at: Some(now - chrono::Duration::seconds(3_661)),
+                restarts: 2,
+                last_error: Some("exited with exit status: 1".into()),
+            },
+            BackendHealth {
+                name: "pi".into(),
+                alive: false,
+                started_at: None,
+                restarts: 0,
+                last_error: Some("spawn failed".into()),
+            },
+        ];
+        let names = backend_names(&crate::config::Config::default());
+        let out = format_status(&i18n, &names, &health, now);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            lines[1],
+            i18n.get_args("backend_status_never", &["kilo".into()])
+        );
+        assert!(lines[2].contains("1h 01m 01s"));
+        assert!(lines[3].contains("exited with exit status: 1"));
+        assert_eq!(
+            lines[5],
+            i18n.get_args("backend_status_down", &["pi".into(), "0".into()])
+        );
+    }
+
+    #[test]
+    fn test_backend_names_include_configured_acp_agents() {
+        let mut config = crate::config::Config::default();
+        config.acp.insert(
+            "gemini".into(),
+            crate::config::AcpAgentConfig {
+                command: "gemini".into(),
+                ..Default::default()
+            },
+        );
+        let names = backend_names(&config);
+        assert_eq!(names.len(), 6);
+        assert_eq!(names[5], "acp:gemini");
+
+        let i18n = I18n::new("en");
+        let out = format_status(&i18n, &names, &[], chrono::Utc::now());
+        assert!(out.ends_with(&i18n.get_args("backend_status_never", &["acp:gemini".into()])));
+    }
+}
@@ -0,0 +1,199 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+use tracing::info;
+
+use crate::budget::{self, BudgetHit, BudgetOverrides, Scope};
+use crate::i18n::I18n;
+
+pub struct BudgetCommand;
+
+/// 一次最多解除一個月
+const MAX_OVERRIDE_HOURS: u64 = 24 * 31;
+
+fn scope_label(i18n: &I18n, scope: Scope, id: u64) -> String {
+    match scope {
+        Scope::Channel => i18n.get("budget_target_channel"),
+        Scope::User => format!("<@{}>", id),
+    }
+}
+
+/// 各項上限的目前用量，以及解除中的限制
+fn format_status(
+    i18n: &I18n,
+    hits: &[BudgetHit],
+    overrides: &BudgetOverrides,
+    targets: &[(Scope, u64)],
+    now: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![format!("### {}", i18n.get("budget_title"))];
+    if hits.is_empty() {
+        lines.push(i18n.get("budget_unlimited"));
+    }
+    for hit in hits {
+        let mark = if hit.exceeded() { "🔴" } else { "🟢" };
+        lines.push(format!("{} {}", mark, hit.describe(i18n)));
+    }
+    for (scope, id) in targets {
+        if let Some(until) = overrides.active_until(*scope, *id, now) {
+            lines.push(i18n.get_args(
+                "budget_override_active",
+                &[
+                    scope_label(i18n, *scope, *id),
+                    until.timestamp().to_string(),
+                ],
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl SlashCommand for BudgetCommand {
+    fn name(&self) -> &'static str {
+        "budget"
+    }
+
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Admin
+    }
+
+    fn description(&self, i18n: &I18n) -> String {
+        i18n.get("cmd_budget_desc")
+    }
+
+    fn options(&self, i18n: &I18n) -> Vec<CreateCommandOption> {
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                i18n.get("cmd_budget_opt_user"),
+            ),
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "override_hours",
+                i18n.get("cmd_budget_opt_override_hours"),
+            )
+            .min_int_value(1)
+            .max_int_value(MAX_OVERRIDE_HOURS),
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "reset",
+                i18n.get("cmd_budget_opt_reset"),
+            ),
+        ]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let option = |name: &str| command.data.options.iter().find(|o| o.name == name);
+        let user = option("user")
+            .and_then(|o| o.value.as_user_id())
+            .map(|id| id.get());
+        let hours = option("override_hours").and_then(|o| o.value.as_i64());
+        let reset = option("reset")
+            .and_then(|o| o.value.as_bool())
+            .unwrap_or(false);
+        let channels = state.channels.snapshot();
+        let (root, _) = budget::channel_family(&channels, command.channel_id.get());
+        // 指定使用者時只動該使用者的限制，否則是整個頻道（含討論串）
+        let (scope, id) = match user {
+            Some(user) => (Scope::User, user),
+            None => (Scope::Channel, root),
+        };
+        let now = Utc::now();
+
+        let i18n = state.i18n.read().await;
+        let msg = if reset {
+            if state.budget.clear_override(scope, id).await? {
+                info!("💰 Budget override cleared for {:?} {}", scope, id);
+                i18n.get_args("budget_override_cleared", &[scope_label(&i18n, scope, id)])
+            } else {
+                i18n.get_args("budget_override_none", &[scope_label(&i18n, scope, id)])
+            }
+        } else if let Some(hours) = hours {
+            let hours = hours.clamp(1, MAX_OVERRIDE_HOURS as i64);
+            let until = now + chrono::Duration::hours(hours);
+            state.budget.set_override(scope, id, until).await?;
+            info!(
+                "💰 Budget override for {:?} {} until {} by {}",
+                scope, id, until, command.user.id
+            );
+            i18n.get_args(
+                "budget_override_set",
+                &[scope_label(&i18n, scope, id), until.timestamp().to_string()],
+            )
+        } else {
+            let config = state.config.read().await.budget.clone();
+            // 和送出 prompt 前的判定一樣，進行中的 prompt 也算進去
+            let in_flight = state.usage.in_flight();
+            let recorded = state.usage.ledger();
+            let usage = budget::BudgetUsage {
+                recorded: &recorded,
+                in_flight: &in_flight,
+            };
+            let hits = budget::budget_status(
+                &config,
+                usage,
+                &channels,
+                command.channel_id.get(),
+                user,
+                now.date_naive(),
+            );
+            let mut targets = vec![(Scope::Channel, root)];
+            targets.extend(user.map(|u| (Scope::User, u)));
+            format_status(&i18n, &hits, &state.budget.overrides(), &targets, now)
+        };
+        drop(i18n);
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::{Metric, Period};
+
+    #[test]
+    fn test_format_status_marks_exceeded_limits_and_overrides() {
+        let i18n = I18n::new("en");
+        let now = Utc::now();
+        let hit = |metric, used| BudgetHit {
+            scope: Scope::User,
+            id: 5,
+            period: Period::Daily,
+            since: now.date_naive(),
+            metric,
+            used,
+            limit: 10.0,
+        };
+        let hits = [hit(Metric::Prompts, 10.0), hit(Metric::Cost, 2.5)];
+        let mut overrides = BudgetOverrides::default();
+        overrides.users.insert(5, now + chrono::Duration::hours(2));
+        let targets = [(Scope::Channel, 1), (Scope::User, 5)];
+
+        let out = format_status(&i18n, &hits, &overrides, &targets, now);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("🔴") && lines[1].contains("10 / 10"));
+        assert!(lines[2].starts_with("🟢") && lines[2].contains("$2.50 / $10.00"));
+        assert!(lines[3].contains("<@5>"));
+
+        let empty = format_status(&i18n, &[], &BudgetOverrides::default(), &targets, now);
+        assert!(empty.ends_with(&i18n.get("budget_unlimited")));
+    }
+}
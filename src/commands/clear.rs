@@ -2,17 +2,67 @@ use super::SlashCommand;
 use async_trait::async_trait;
 use serenity::all::{CommandInteraction, Context, EditInteractionResponse};
 
-use super::agent::ChannelConfig;
 use crate::migrate;
 
 pub struct ClearCommand;
 
+/// 清除頻道的對話：後端 session、記憶體快取、本地檔案與持久化的 session ID
+pub async fn clear_channel(state: &crate::AppState, channel_id_u64: u64) -> anyhow::Result<()> {
+    let channel_id_str = channel_id_u64.to_string();
+    let channel_config = state.channels.snapshot();
+    let agent_type = channel_config.get_agent_type(&channel_id_str);
+
+    let (agent, _) = state
+        .session_manager
+        .get_or_create_session(channel_id_u64, agent_type.clone(), &state.backend_manager)
+        .await?;
+
+    // 1. 清除後端 session；後端若已原地換成新的 session，只需更新持久化的 ID
+    if let Some(new_sid) = agent.clear().await? {
+        state
+            .session_manager
+            .persist_sid(channel_id_u64, agent_type, new_sid)
+            .await?;
+        state.input_queue.clear(channel_id_u64);
+        return Ok(());
+    }
+
+    // 2. 移除記憶體快取
+    state.session_manager.remove_session(channel_id_u64).await;
+
+    // 3. 刪除本地 session 檔案
+    let agent_type = agent.agent_type();
+    let session_file =
+        migrate::get_sessions_dir(&agent_type).join(format!("discord-rs-{}.jsonl", channel_id_u64));
+
+    if session_file.exists() {
+        tokio::fs::remove_file(&session_file).await.ok();
+    }
+
+    // 4. 清除持久化配置中的 ID
+    let _ = state.channels.update(|config| {
+        if let Some(entry) = config.channels.get_mut(&channel_id_str) {
+            entry.session_id = None;
+        }
+        Ok(())
+    }).await;
+
+    // 5. 捨棄尚未送出的排隊訊息
+    state.input_queue.clear(channel_id_u64);
+
+    Ok(())
+}
+
 #[async_trait]
 impl SlashCommand for ClearCommand {
     fn name(&self) -> &'static str {
         "clear"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
     fn description(&self, i18n: &crate::i18n::I18n) -> String {
         i18n.get("cmd_clear_desc")
     }
@@ -25,40 +75,7 @@ impl SlashCommand for ClearCommand {
     ) -> anyhow::Result<()> {
         command.defer_ephemeral(&ctx.http).await?;
 
-        let channel_id_u64 = command.channel_id.get();
-        let channel_id_str = channel_id_u64.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
-        let agent_type = channel_config.get_agent_type(&channel_id_str);
-
-        let (agent, _) = state
-            .session_manager
-            .get_or_create_session(channel_id_u64, agent_type, &state.backend_manager)
-            .await?;
-
-        // 1. 清除後端 session
-        agent.clear().await?;
-
-        // 2. 移除記憶體快取
-        state.session_manager.remove_session(channel_id_u64).await;
-
-        // 3. 刪除本地 session 檔案
-        let agent_type = agent.agent_type();
-        let session_file = migrate::get_sessions_dir(agent_type)
-            .join(format!("discord-rs-{}.jsonl", channel_id_u64));
-
-        if session_file.exists() {
-            tokio::fs::remove_file(&session_file).await.ok();
-        }
-
-        // 4. 清除持久化配置中的 ID
-        if let Ok(mut config) = ChannelConfig::load().await {
-            if let Some(entry) = config.channels.get_mut(&channel_id_str) {
-                entry.session_id = None;
-                let _ = config.save().await;
-            }
-        }
+        clear_channel(state, command.channel_id.get()).await?;
 
         let i18n = state.i18n.read().await;
         let msg = i18n.get("clear_success");
@@ -24,9 +24,7 @@ impl SlashCommand for CompactCommand {
 
         let channel_id_u64 = command.channel_id.get();
         let channel_id_str = channel_id_u64.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
         let agent_type = channel_config.get_agent_type(&channel_id_str);
 
         let (agent, _) = state
@@ -7,8 +7,10 @@ use serenity::all::{
 };
 
 use crate::agent::AgentType;
+use crate::commands::agent::{PermissionPolicy, QueuePolicy};
 
 const ASSISTANT_NAME_MAX_CHARS: usize = 48;
+const MAX_SELECT_OPTIONS: usize = 25;
 
 #[derive(Debug, Clone, PartialEq)]
 enum ConfigSelectAction {
@@ -16,6 +18,8 @@ enum ConfigSelectAction {
     Mention(bool),
     AssistantDefault,
     AssistantCustom,
+    Permission(PermissionPolicy),
+    Queue(QueuePolicy),
     Ignore,
 }
 
@@ -40,20 +44,26 @@ impl SlashCommand for ConfigCommand {
         command.defer_ephemeral(&ctx.http).await?;
 
         let channel_id_str = command.channel_id.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
         let backend = channel_config.get_agent_type(&channel_id_str);
+        let (default_name, acp_agents) = {
+            let config = state.config.read().await;
+            (config.assistant_name.clone(), config.acp_agents())
+        };
         let assistant_name = channel_config
             .channels
             .get(&channel_id_str)
             .and_then(|e| e.assistant_name.clone())
             .filter(|s| !s.trim().is_empty())
-            .unwrap_or_else(|| state.config.assistant_name.clone());
+            .unwrap_or(default_name);
         let mention_only = state
             .auth
             .get_channel_mention_only(&channel_id_str)
             .unwrap_or(true);
+        let permission_policy = channel_config.get_permission_policy(&channel_id_str);
+        let queue_policy = channel_config.get_queue_policy(&channel_id_str);
+        let workdir = channel_config.get_workdir(&channel_id_str);
+        let thread_mode = channel_config.is_thread_mode(&channel_id_str);
 
         let i18n = state.i18n.read().await;
         let status = i18n.get_args(
@@ -66,18 +76,33 @@ impl SlashCommand for ConfigCommand {
                     i18n.get("config_mention_off")
                 },
                 assistant_name,
+                i18n.get(&format!("config_permission_{}", permission_policy)),
+                i18n.get(&format!("config_queue_{}", queue_policy)),
+                workdir.unwrap_or_else(|| i18n.get("workspace_default")),
+                i18n.get(if thread_mode {
+                    "config_thread_on"
+                } else {
+                    "config_thread_off"
+                }),
             ],
         );
 
+        let mut backend_options = vec![
+            CreateSelectMenuOption::new(i18n.get("agent_choice_kilo"), "kilo"),
+            CreateSelectMenuOption::new(i18n.get("agent_choice_copilot"), "copilot"),
+            CreateSelectMenuOption::new(i18n.get("agent_choice_pi"), "pi"),
+            CreateSelectMenuOption::new(i18n.get("agent_choice_opencode"), "opencode"),
+            CreateSelectMenuOption::new(i18n.get("agent_choice_openai"), "openai"),
+        ];
+        // Discord 選單最多 25 個選項，放不下的 ACP agent 仍可用 /agent 指定
+        let room = MAX_SELECT_OPTIONS - backend_options.len();
+        backend_options.extend(acp_agents.into_iter().take(room).map(|(name, label)| {
+            CreateSelectMenuOption::new(format!("{} (ACP)", label), AgentType::Acp(name).to_string())
+        }));
         let backend_menu = CreateSelectMenu::new(
             "config_backend_select",
             CreateSelectMenuKind::String {
-                options: vec![
-                    CreateSelectMenuOption::new(i18n.get("agent_choice_kilo"), "kilo"),
-                    CreateSelectMenuOption::new(i18n.get("agent_choice_copilot"), "copilot"),
-                    CreateSelectMenuOption::new(i18n.get("agent_choice_pi"), "pi"),
-                    CreateSelectMenuOption::new(i18n.get("agent_choice_opencode"), "opencode"),
-                ],
+                options: backend_options,
             },
         )
         .placeholder(i18n.get("config_backend_placeholder"))
@@ -110,6 +135,34 @@ impl SlashCommand for ConfigCommand {
         .min_values(1)
         .max_values(1);
 
+        let permission_menu = CreateSelectMenu::new(
+            "config_permission_select",
+            CreateSelectMenuKind::String {
+                options: vec![
+                    CreateSelectMenuOption::new(i18n.get("config_permission_auto"), "auto"),
+                    CreateSelectMenuOption::new(i18n.get("config_permission_ask"), "ask"),
+                    CreateSelectMenuOption::new(i18n.get("config_permission_deny"), "deny"),
+                ],
+            },
+        )
+        .placeholder(i18n.get("config_permission_placeholder"))
+        .min_values(1)
+        .max_values(1);
+
+        let queue_menu = CreateSelectMenu::new(
+            "config_queue_select",
+            CreateSelectMenuKind::String {
+                options: vec![
+                    CreateSelectMenuOption::new(i18n.get("config_queue_preempt"), "preempt"),
+                    CreateSelectMenuOption::new(i18n.get("config_queue_queue"), "queue"),
+                    CreateSelectMenuOption::new(i18n.get("config_queue_merge"), "merge"),
+                ],
+            },
+        )
+        .placeholder(i18n.get("config_queue_placeholder"))
+        .min_values(1)
+        .max_values(1);
+
         command
             .edit_response(
                 &ctx.http,
@@ -119,6 +172,8 @@ impl SlashCommand for ConfigCommand {
                         CreateActionRow::SelectMenu(backend_menu),
                         CreateActionRow::SelectMenu(mention_menu),
                         CreateActionRow::SelectMenu(assistant_menu),
+                        CreateActionRow::SelectMenu(permission_menu),
+                        CreateActionRow::SelectMenu(queue_menu),
                     ]),
             )
             .await?;
@@ -174,6 +229,14 @@ fn parse_config_select_action(custom_id: &str, value: &str) -> ConfigSelectActio
         "config_mention_select" => ConfigSelectAction::Mention(value == "on"),
         "config_assistant_select" if value == "default" => ConfigSelectAction::AssistantDefault,
         "config_assistant_select" if value == "custom" => ConfigSelectAction::AssistantCustom,
+        "config_permission_select" => value
+            .parse::<PermissionPolicy>()
+            .map(ConfigSelectAction::Permission)
+            .unwrap_or(ConfigSelectAction::Ignore),
+        "config_queue_select" => value
+            .parse::<QueuePolicy>()
+            .map(ConfigSelectAction::Queue)
+            .unwrap_or(ConfigSelectAction::Ignore),
         _ => ConfigSelectAction::Ignore,
     }
 }
@@ -193,14 +256,13 @@ pub async fn handle_config_select(
     let channel_id_str = interaction.channel_id.to_string();
 
     if parse_config_select_action(custom_id, &value) == ConfigSelectAction::AssistantCustom {
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
+        let default_name = state.config.read().await.assistant_name.clone();
         let current = channel_config
             .channels
             .get(&channel_id_str)
             .and_then(|e| e.assistant_name.clone())
-            .unwrap_or_else(|| state.config.assistant_name.clone());
+            .unwrap_or(default_name);
 
         let i18n = state.i18n.read().await;
         let modal = CreateModal::new(
@@ -229,16 +291,13 @@ pub async fn handle_config_select(
 
     match parse_config_select_action(custom_id, &value) {
         ConfigSelectAction::Backend(selected) => {
-            let mut channel_config = crate::commands::agent::ChannelConfig::load()
-                .await
-                .unwrap_or_default();
+            let channel_config = state.channels.snapshot();
             let current = channel_config.get_agent_type(&channel_id_str);
 
             let msg = if current == selected {
                 let i18n = state.i18n.read().await;
                 i18n.get_args("agent_already", &[selected.to_string()])
             } else {
-                channel_config.set_agent_type(&channel_id_str, selected.clone());
                 state.session_manager.remove_session(channel_id_u64).await;
 
                 match state
@@ -247,7 +306,10 @@ pub async fn handle_config_select(
                     .await
                 {
                     Ok(_) => {
-                        channel_config.save().await?;
+                        state.channels.update(|channel_config| {
+                            channel_config.set_agent_type(&channel_id_str, selected.clone());
+                            Ok(())
+                        }).await?;
                         let i18n = state.i18n.read().await;
                         i18n.get_args("config_backend_set", &[selected.to_string()])
                     }
@@ -257,7 +319,7 @@ pub async fn handle_config_select(
                             &i18n,
                             selected,
                             &e.to_string(),
-                            state.config.opencode.port,
+                            state.config.read().await.opencode.port,
                         )
                     }
                 }
@@ -270,7 +332,7 @@ pub async fn handle_config_select(
         ConfigSelectAction::Mention(enable) => {
             let msg = {
                 let i18n = state.i18n.read().await;
-                match state.auth.set_mention_only(&channel_id_str, enable) {
+                match state.auth.set_mention_only(&channel_id_str, enable).await {
                     Ok(_) => i18n.get(if enable { "mention_on" } else { "mention_off" }),
                     Err(_) => i18n.get("mention_not_auth"),
                 }
@@ -281,21 +343,51 @@ pub async fn handle_config_select(
                 .await?;
         }
         ConfigSelectAction::AssistantDefault => {
-            let mut channel_config = crate::commands::agent::ChannelConfig::load()
-                .await
-                .unwrap_or_default();
-            channel_config.set_agent_type(
-                &channel_id_str,
-                channel_config.get_agent_type(&channel_id_str),
-            );
-            if let Some(entry) = channel_config.channels.get_mut(&channel_id_str) {
-                entry.assistant_name = None;
-            }
-            channel_config.save().await?;
+            state.channels.update(|channel_config| {
+                channel_config.ensure_entry(&channel_id_str).assistant_name = None;
+                Ok(())
+            }).await?;
+
+            let default_name = state.config.read().await.assistant_name.clone();
+            let msg = {
+                let i18n = state.i18n.read().await;
+                i18n.get_args("config_assistant_set", &[default_name])
+            };
+
+            interaction
+                .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+                .await?;
+        }
+        ConfigSelectAction::Permission(policy) => {
+            state.channels.update(|channel_config| {
+                channel_config.ensure_entry(&channel_id_str).permission_policy = policy;
+                Ok(())
+            }).await?;
+
+            let msg = {
+                let i18n = state.i18n.read().await;
+                i18n.get_args(
+                    "config_permission_set",
+                    &[i18n.get(&format!("config_permission_{}", policy))],
+                )
+            };
+
+            interaction
+                .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+                .await?;
+        }
+        ConfigSelectAction::Queue(policy) => {
+            state.channels.update(|channel_config| {
+                channel_config.ensure_entry(&channel_id_str).queue_policy = policy;
+                Ok(())
+            }).await?;
 
             let msg = {
                 let i18n = state.i18n.read().await;
-                i18n.get_args("config_assistant_set", &[state.config.assistant_name.clone()])
+                i18n.get_args(
+                    "config_queue_set",
+                    &[i18n.get(&format!("config_queue_{}", policy))],
+                )
             };
 
             interaction
@@ -338,14 +430,10 @@ pub async fn handle_assistant_modal_submit(
     };
 
     let channel_id = interaction.channel_id.to_string();
-    let mut channel_config = crate::commands::agent::ChannelConfig::load()
-        .await
-        .unwrap_or_default();
-    channel_config.set_agent_type(&channel_id, channel_config.get_agent_type(&channel_id));
-    if let Some(entry) = channel_config.channels.get_mut(&channel_id) {
-        entry.assistant_name = Some(safe_name.clone());
-    }
-    channel_config.save().await?;
+    state.channels.update(|channel_config| {
+        channel_config.ensure_entry(&channel_id).assistant_name = Some(safe_name.clone());
+        Ok(())
+    }).await?;
 
     let msg = {
         let i18n = state.i18n.read().await;
@@ -363,7 +451,7 @@ pub async fn handle_assistant_modal_submit(
 mod tests {
     use super::{
         extract_selected_value, parse_config_select_action, sanitize_assistant_name,
-        ConfigSelectAction,
+        ConfigSelectAction, PermissionPolicy, QueuePolicy,
     };
     use serenity::all::ComponentInteractionDataKind;
     use crate::agent::AgentType;
@@ -426,9 +514,25 @@ mod tests {
             parse_config_select_action("config_mention_select", "off"),
             ConfigSelectAction::Mention(false)
         );
+        assert_eq!(
+            parse_config_select_action("config_backend_select", "acp:gemini"),
+            ConfigSelectAction::Backend(AgentType::Acp("gemini".into()))
+        );
         assert_eq!(
             parse_config_select_action("config_backend_select", "invalid-backend"),
             ConfigSelectAction::Ignore
         );
+        assert_eq!(
+            parse_config_select_action("config_permission_select", "ask"),
+            ConfigSelectAction::Permission(PermissionPolicy::Ask)
+        );
+        assert_eq!(
+            parse_config_select_action("config_permission_select", "maybe"),
+            ConfigSelectAction::Ignore
+        );
+        assert_eq!(
+            parse_config_select_action("config_queue_select", "merge"),
+            ConfigSelectAction::Queue(QueuePolicy::Merge)
+        );
     }
 }
@@ -149,6 +149,10 @@ impl SlashCommand for CronCommand {
         "cron"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
     fn description(&self, i18n: &I18n) -> String {
         i18n.get("cmd_cron_desc")
     }
@@ -219,6 +223,10 @@ impl SlashCommand for CronListCommand {
         "cron_list"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::ReadOnly
+    }
+
     fn description(&self, i18n: &I18n) -> String {
         i18n.get("cmd_cron_list_desc")
     }
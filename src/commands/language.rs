@@ -15,6 +15,10 @@ impl SlashCommand for LanguageCommand {
         "language"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Admin
+    }
+
     fn description(&self, i18n: &I18n) -> String {
         i18n.get("cmd_lang_desc")
     }
@@ -75,12 +79,7 @@ impl SlashCommand for LanguageCommand {
 
         // 3. 關鍵：重新註冊所有 Slash Commands 以更新說明文字
         let i18n = state.i18n.read().await;
-        let commands = super::get_all_commands()
-            .into_iter()
-            .map(|cmd| cmd.create_command(&i18n))
-            .collect::<Vec<_>>();
-
-        match serenity::all::Command::set_global_commands(&ctx.http, commands).await {
+        match super::register_global_commands(&ctx.http, &i18n).await {
             Ok(_) => {
                 info!("✅ Re-registered global commands for language: {}", lang);
                 let final_msg = i18n.get_args("lang_updated", &[lang.to_string()]);
@@ -12,6 +12,10 @@ impl SlashCommand for MentionOnlyCommand {
         "mention_only"
     }
 
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
     fn description(&self, i18n: &crate::i18n::I18n) -> String {
         i18n.get("cmd_mention_desc")
     }
@@ -45,7 +49,7 @@ impl SlashCommand for MentionOnlyCommand {
         let auth = state.auth.clone();
 
         let i18n = state.i18n.read().await;
-        let msg = match auth.set_mention_only(&ch_id, enable) {
+        let msg = match auth.set_mention_only(&ch_id, enable).await {
             Ok(_) => i18n.get(if enable { "mention_on" } else { "mention_off" }),
             Err(_) => i18n.get("mention_not_auth"),
         };
@@ -2,9 +2,12 @@ use async_trait::async_trait;
 use serenity::all::{CommandInteraction, Context, CreateCommand, CreateCommandOption};
 
 use crate::i18n::I18n;
+use crate::roles::Role;
 
 pub mod abort;
 pub mod agent;
+pub mod backend;
+pub mod budget;
 pub mod clear;
 pub mod compact;
 pub mod config;
@@ -12,8 +15,14 @@ pub mod cron;
 pub mod language;
 pub mod mention_only;
 pub mod model;
+pub mod permission;
+pub mod role;
+pub mod server;
 pub mod skill;
 pub mod thinking;
+pub mod thread_mode;
+pub mod usage;
+pub mod workspace;
 
 #[async_trait]
 pub trait SlashCommand: Send + Sync {
@@ -23,6 +32,11 @@ pub trait SlashCommand: Send + Sync {
         vec![]
     }
 
+    /// 執行此指令所需的最低角色
+    fn required_role(&self) -> Role {
+        Role::User
+    }
+
     fn create_command(&self, i18n: &I18n) -> CreateCommand {
         let mut cmd = CreateCommand::new(self.name()).description(self.description(i18n));
         for opt in self.options(i18n) {
@@ -50,12 +64,32 @@ pub fn get_all_commands() -> Vec<Box<dyn SlashCommand>> {
         Box::new(abort::AbortCommand),
         Box::new(skill::SkillCommand),
         Box::new(mention_only::MentionOnlyCommand),
+        Box::new(thread_mode::ThreadModeCommand),
         Box::new(language::LanguageCommand),
         Box::new(cron::CronCommand),
         Box::new(cron::CronListCommand),
+        Box::new(role::RoleCommand),
+        Box::new(workspace::WorkspaceCommand),
+        Box::new(server::ServerCommand),
+        Box::new(backend::BackendCommand),
+        Box::new(usage::UsageCommand),
+        Box::new(budget::BudgetCommand),
     ]
 }
 
+/// 依目前語系重新註冊所有全域 Slash Commands
+pub async fn register_global_commands(
+    http: &serenity::http::Http,
+    i18n: &I18n,
+) -> serenity::Result<()> {
+    let commands = get_all_commands()
+        .into_iter()
+        .map(|cmd| cmd.create_command(i18n))
+        .collect::<Vec<_>>();
+    serenity::all::Command::set_global_commands(http, commands).await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +106,29 @@ mod tests {
             let _create = cmd.create_command(&i18n);
         }
     }
+
+    #[test]
+    fn test_destructive_commands_require_elevated_roles() {
+        let role_of = |name: &str| {
+            get_all_commands()
+                .into_iter()
+                .find(|c| c.name() == name)
+                .map(|c| c.required_role())
+                .expect("command exists")
+        };
+        assert_eq!(role_of("agent"), Role::Operator);
+        assert_eq!(role_of("clear"), Role::Operator);
+        assert_eq!(role_of("cron"), Role::Operator);
+        assert_eq!(role_of("mention_only"), Role::Operator);
+        assert_eq!(role_of("workspace"), Role::Operator);
+        assert_eq!(role_of("server"), Role::Operator);
+        assert_eq!(role_of("thread_mode"), Role::Operator);
+        assert_eq!(role_of("language"), Role::Admin);
+        assert_eq!(role_of("role"), Role::Admin);
+        assert_eq!(role_of("budget"), Role::Admin);
+        assert_eq!(role_of("cron_list"), Role::ReadOnly);
+        assert_eq!(role_of("usage"), Role::ReadOnly);
+        assert_eq!(role_of("abort"), Role::User);
+        assert_eq!(role_of("backend"), Role::User);
+    }
 }
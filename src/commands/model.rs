@@ -50,9 +50,7 @@ impl SlashCommand for ModelCommand {
         command.defer_ephemeral(&ctx.http).await?;
 
         let channel_id_str = command.channel_id.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
         let agent_type = channel_config.get_agent_type(&channel_id_str);
 
         let (agent, _) = state
@@ -105,7 +103,7 @@ impl SlashCommand for ModelCommand {
                     // 使用 | 作為定界符，避免與 ID 內部的 / 衝突
                     let value = build_model_value(&m.provider, &m.id);
                     CreateSelectMenuOption::new(&m.label, value)
-                        .description(i18n.get_args("model_provider_desc", &[m.provider.clone()]))
+                        .description(i18n.get_args("model_provider_desc", std::slice::from_ref(&m.provider)))
                 })
                 .collect();
 
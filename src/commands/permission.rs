@@ -0,0 +1,229 @@
+use serenity::all::{
+    ButtonStyle, ChannelId, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditMessage, Http,
+};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::agent::permission::{self, PermissionDecision};
+use crate::i18n::I18n;
+
+/// 工具參數在 embed 中最多顯示的字元數
+const DETAIL_MAX_CHARS: usize = 1500;
+
+fn choice_label_key(decision: PermissionDecision) -> &'static str {
+    match decision {
+        PermissionDecision::AllowOnce => "perm_allow_once",
+        PermissionDecision::AllowAlways => "perm_allow_always",
+        PermissionDecision::Deny | PermissionDecision::TimedOut => "perm_deny",
+    }
+}
+
+fn result_key(decision: PermissionDecision) -> &'static str {
+    match decision {
+        PermissionDecision::AllowOnce => "perm_allowed_once",
+        PermissionDecision::AllowAlways => "perm_allowed_always",
+        PermissionDecision::Deny => "perm_denied",
+        PermissionDecision::TimedOut => "perm_timed_out",
+    }
+}
+
+fn format_detail(detail: &str) -> String {
+    if detail.trim().is_empty() {
+        return String::new();
+    }
+    let mut shown: String = detail.chars().take(DETAIL_MAX_CHARS).collect();
+    if shown.len() < detail.len() {
+        shown.push('…');
+    }
+    format!("```\n{}\n```", shown.replace("```", "'''"))
+}
+
+fn build_buttons(i18n: &I18n, token: &str, choices: &[PermissionDecision]) -> CreateActionRow {
+    let buttons = choices
+        .iter()
+        .map(|&decision| {
+            let style = if decision.is_allowed() {
+                ButtonStyle::Success
+            } else {
+                ButtonStyle::Danger
+            };
+            CreateButton::new(permission::build_custom_id(token, decision))
+                .label(i18n.get(choice_label_key(decision)))
+                .style(style)
+        })
+        .collect();
+    CreateActionRow::Buttons(buttons)
+}
+
+/// 請求結束後取代原本的 embed：保留標題，說明換成結果，不再顯示逾時倒數
+fn resolved_embed(title: Option<String>, outcome: String) -> CreateEmbed {
+    let embed = CreateEmbed::new().description(outcome).color(0x808080);
+    match title {
+        Some(title) => embed.title(title),
+        None => embed,
+    }
+}
+
+/// 在頻道貼出確認按鈕；若最後逾時，把訊息改成逾時並移除按鈕
+pub async fn post_request(
+    http: Arc<Http>,
+    i18n: Arc<RwLock<I18n>>,
+    channel_id: ChannelId,
+    token: String,
+    title: String,
+    detail: String,
+    choices: Vec<PermissionDecision>,
+) {
+    let broker = permission::broker();
+    // 先訂閱，避免在貼訊息期間就被回覆或逾時而漏掉結果
+    let Some(mut rx) = broker.subscribe(&token) else {
+        return;
+    };
+
+    let (embed, row, request_title, timed_out_text) = {
+        let i18n = i18n.read().await;
+        let request_title = i18n.get_args("perm_request_title", &[title]);
+        let embed = CreateEmbed::new()
+            .title(&request_title)
+            .description(i18n.get_args(
+                "perm_request_desc",
+                &[
+                    format_detail(&detail),
+                    broker.timeout().as_secs().to_string(),
+                ],
+            ))
+            .color(0xFFA500);
+        (
+            embed,
+            build_buttons(&i18n, &token, &choices),
+            request_title,
+            i18n.get(result_key(PermissionDecision::TimedOut)),
+        )
+    };
+
+    let mut msg = match channel_id
+        .send_message(
+            &http,
+            CreateMessage::new().embed(embed).components(vec![row]),
+        )
+        .await
+    {
+        Ok(msg) => msg,
+        Err(e) => {
+            error!("❌ Failed to post permission request: {}", e);
+            broker.resolve(&token, PermissionDecision::Deny);
+            return;
+        }
+    };
+
+    if permission::wait_decision(&mut rx).await == PermissionDecision::TimedOut {
+        info!(
+            "⌛ Permission request {} expired in channel {}",
+            token, channel_id
+        );
+        let _ = msg
+            .edit(
+                &http,
+                EditMessage::new()
+                    .embed(resolved_embed(Some(request_title), timed_out_text))
+                    .components(vec![]),
+            )
+            .await;
+    }
+}
+
+/// 處理 `perm:<token>:<choice>` 按鈕
+pub async fn handle_button(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &crate::AppState,
+) -> anyhow::Result<()> {
+    let parsed = permission::parse_custom_id(&interaction.data.custom_id);
+    let resolved = parsed
+        .as_ref()
+        .is_some_and(|(token, decision)| permission::broker().resolve(token, *decision));
+
+    let i18n = state.i18n.read().await;
+    let response = match parsed {
+        Some((_, decision)) if resolved => {
+            let title = interaction
+                .message
+                .embeds
+                .first()
+                .and_then(|e| e.title.clone());
+            let outcome = i18n.get_args(
+                "perm_resolved_by",
+                &[
+                    i18n.get(result_key(decision)),
+                    format!("<@{}>", interaction.user.id),
+                ],
+            );
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(resolved_embed(title, outcome))
+                    .components(vec![]),
+            )
+        }
+        _ => CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(i18n.get("perm_expired"))
+                .ephemeral(true),
+        ),
+    };
+    drop(i18n);
+
+    interaction.create_response(&ctx.http, response).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_detail_truncates_and_escapes_fences() {
+        assert_eq!(format_detail("  "), "");
+        let short = format_detail("ls ```x```");
+        assert!(short.starts_with("```\n"));
+        assert!(!short.trim_matches('`').contains("```"));
+
+        let long = "a".repeat(DETAIL_MAX_CHARS + 10);
+        let shown = format_detail(&long);
+        assert!(shown.contains('…'));
+        assert!(shown.len() < long.len() + 10);
+    }
+
+    #[test]
+    fn test_every_decision_has_localized_text() {
+        let i18n = I18n::new("en");
+        for decision in [
+            PermissionDecision::AllowOnce,
+            PermissionDecision::AllowAlways,
+            PermissionDecision::Deny,
+            PermissionDecision::TimedOut,
+        ] {
+            assert_ne!(
+                i18n.get(choice_label_key(decision)),
+                choice_label_key(decision)
+            );
+            assert_ne!(i18n.get(result_key(decision)), result_key(decision));
+        }
+    }
+
+    #[test]
+    fn test_resolved_embed_replaces_countdown_with_outcome() {
+        let embed = serde_json::to_value(resolved_embed(
+            Some("🔐 Tool permission request: bash".to_string()),
+            "⌛ Timed out, denied".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(embed["title"], "🔐 Tool permission request: bash");
+        assert_eq!(embed["description"], "⌛ Timed out, denied");
+
+        let untitled = serde_json::to_value(resolved_embed(None, "⛔ Denied".to_string())).unwrap();
+        assert!(untitled.get("title").is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+
+use crate::roles::{Role, RoleTarget};
+
+pub struct RoleCommand;
+
+/// `level` 選項的值：指定角色或撤銷
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoleChange {
+    Set(Role),
+    Revoke,
+}
+
+pub fn parse_role_change(value: &str) -> Option<RoleChange> {
+    if value == "revoke" {
+        return Some(RoleChange::Revoke);
+    }
+    value.parse::<Role>().ok().map(RoleChange::Set)
+}
+
+fn mention(target: &RoleTarget) -> String {
+    match target {
+        RoleTarget::User(id) => format!("<@{}>", id),
+        RoleTarget::GuildRole(id) => format!("<@&{}>", id),
+    }
+}
+
+#[async_trait]
+impl SlashCommand for RoleCommand {
+    fn name(&self) -> &'static str {
+        "role"
+    }
+
+    fn description(&self, i18n: &crate::i18n::I18n) -> String {
+        i18n.get("cmd_role_desc")
+    }
+
+    fn required_role(&self) -> Role {
+        Role::Admin
+    }
+
+    fn options(&self, i18n: &crate::i18n::I18n) -> Vec<CreateCommandOption> {
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "level",
+                i18n.get("cmd_role_opt_level"),
+            )
+            .add_string_choice(i18n.get("role_choice_admin"), "admin")
+            .add_string_choice(i18n.get("role_choice_operator"), "operator")
+            .add_string_choice(i18n.get("role_choice_user"), "user")
+            .add_string_choice(i18n.get("role_choice_read_only"), "read_only")
+            .add_string_choice(i18n.get("role_choice_revoke"), "revoke"),
+            CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                i18n.get("cmd_role_opt_user"),
+            ),
+            CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                i18n.get("cmd_role_opt_role"),
+            ),
+        ]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let options = &command.data.options;
+        let change = options
+            .iter()
+            .find(|o| o.name == "level")
+            .and_then(|o| o.value.as_str())
+            .and_then(parse_role_change);
+        let target = options
+            .iter()
+            .find(|o| o.name == "user")
+            .and_then(|o| o.value.as_user_id())
+            .map(|id| RoleTarget::User(id.to_string()))
+            .or_else(|| {
+                options
+                    .iter()
+                    .find(|o| o.name == "role")
+                    .and_then(|o| o.value.as_role_id())
+                    .map(|id| RoleTarget::GuildRole(id.to_string()))
+            });
+
+        let i18n = state.i18n.read().await;
+        let msg = match (target, change) {
+            (None, _) => format!(
+                "**{}**\n```\n{}\n```",
+                i18n.get("role_grants_title"),
+                state.roles.format_grants()
+            ),
+            (Some(target), None) => {
+                let reg = state.roles.load();
+                let role = match &target {
+                    RoleTarget::User(id) => reg.users.get(id).copied(),
+                    RoleTarget::GuildRole(id) => reg.guild_roles.get(id).copied(),
+                }
+                .unwrap_or(reg.default_role);
+                i18n.get_args("role_current", &[mention(&target), role.to_string()])
+            }
+            (Some(target), Some(change)) => {
+                let is_self = target == RoleTarget::User(command.user.id.to_string());
+                if is_self && change != RoleChange::Set(Role::Admin) {
+                    i18n.get("role_self_demote")
+                } else {
+                    match change {
+                        RoleChange::Set(role) => {
+                            state.roles.set(&target, Some(role)).await?;
+                            i18n.get_args("role_set", &[mention(&target), role.to_string()])
+                        }
+                        RoleChange::Revoke => {
+                            state.roles.set(&target, None).await?;
+                            i18n.get_args("role_revoked", &[mention(&target)])
+                        }
+                    }
+                }
+            }
+        };
+        drop(i18n);
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_role_change() {
+        assert_eq!(parse_role_change("revoke"), Some(RoleChange::Revoke));
+        assert_eq!(
+            parse_role_change("operator"),
+            Some(RoleChange::Set(Role::Operator))
+        );
+        assert_eq!(parse_role_change("bogus"), None);
+    }
+
+    #[test]
+    fn test_mention_formats_user_and_role() {
+        assert_eq!(mention(&RoleTarget::User("1".into())), "<@1>");
+        assert_eq!(mention(&RoleTarget::GuildRole("2".into())), "<@&2>");
+    }
+}
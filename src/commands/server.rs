@@ -0,0 +1,183 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::config::{BackendMode, ExternalServer, OpencodeConfig};
+
+pub struct ServerCommand;
+
+fn format_servers(i18n: &crate::i18n::I18n, servers: &HashMap<String, ExternalServer>) -> String {
+    if servers.is_empty() {
+        return i18n.get("server_no_servers");
+    }
+    let mut names: Vec<&String> = servers.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| format!("- `{}` → {}", name, servers[name].url))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 頻道沒有指定 server 時實際使用的來源
+fn default_label(i18n: &crate::i18n::I18n, server_config: &OpencodeConfig) -> String {
+    match server_config.mode {
+        BackendMode::Managed => i18n.get("server_default_managed"),
+        BackendMode::External => i18n.get_args(
+            "server_default_external",
+            &[server_config.url.clone().unwrap_or_default()],
+        ),
+    }
+}
+
+/// 更新頻道指定的 server；舊 session 屬於舊 server，因此一併丟棄
+async fn apply_server(
+    state: &crate::AppState,
+    channel_id: u64,
+    server: Option<String>,
+) -> anyhow::Result<()> {
+    state.channels.update(|channel_config| {
+        let entry = channel_config.ensure_entry(&channel_id.to_string());
+        entry.server = server;
+        entry.session_id = None;
+        Ok(())
+    }).await?;
+    state.session_manager.remove_session(channel_id).await;
+    Ok(())
+}
+
+#[async_trait]
+impl SlashCommand for ServerCommand {
+    fn name(&self) -> &'static str {
+        "server"
+    }
+
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
+    fn description(&self, i18n: &crate::i18n::I18n) -> String {
+        i18n.get("cmd_server_desc")
+    }
+
+    fn options(&self, i18n: &crate::i18n::I18n) -> Vec<CreateCommandOption> {
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "name",
+                i18n.get("cmd_server_opt_name"),
+            ),
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "reset",
+                i18n.get("cmd_server_opt_reset"),
+            ),
+        ]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let option = |name: &str| command.data.options.iter().find(|o| o.name == name);
+        let name = option("name").and_then(|o| o.value.as_str());
+        let reset = option("reset")
+            .and_then(|o| o.value.as_bool())
+            .unwrap_or(false);
+        let channel_id = command.channel_id.get();
+        let channel_config = state.channels.snapshot();
+        let agent_type = channel_config.get_agent_type(&channel_id.to_string());
+        let server_config = state
+            .config
+            .read()
+            .await
+            .server_config(&agent_type)
+            .cloned();
+
+        let i18n = state.i18n.read().await;
+        let msg = match server_config {
+            None => i18n.get_args("server_unsupported", &[agent_type.to_string()]),
+            Some(server_config) if reset => {
+                apply_server(state, channel_id, None).await?;
+                info!("Channel {} server binding removed", channel_id);
+                i18n.get_args("server_reset", &[default_label(&i18n, &server_config)])
+            }
+            Some(server_config) => match name {
+                Some(name) if server_config.servers.contains_key(name) => {
+                    apply_server(state, channel_id, Some(name.to_string())).await?;
+                    info!("Channel {} bound to server {}", channel_id, name);
+                    i18n.get_args("server_set", &[name.to_string()])
+                }
+                Some(name) => i18n.get_args(
+                    "server_unknown",
+                    &[
+                        name.to_string(),
+                        format_servers(&i18n, &server_config.servers),
+                    ],
+                ),
+                None => {
+                    let current = channel_config
+                        .channels
+                        .get(&channel_id.to_string())
+                        .and_then(|e| e.server.clone())
+                        .map(|s| format!("`{}`", s))
+                        .unwrap_or_else(|| default_label(&i18n, &server_config));
+                    i18n.get_args(
+                        "server_current",
+                        &[current, format_servers(&i18n, &server_config.servers)],
+                    )
+                }
+            },
+        };
+        drop(i18n);
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::I18n;
+
+    #[test]
+    fn test_format_servers_sorted_or_hint() {
+        let i18n = I18n::new("en");
+        assert_eq!(
+            format_servers(&i18n, &HashMap::new()),
+            i18n.get("server_no_servers")
+        );
+        let servers = HashMap::from([
+            (
+                "ci".to_string(),
+                ExternalServer {
+                    url: "http://ci:4096".into(),
+                    password: None,
+                },
+            ),
+            (
+                "a".to_string(),
+                ExternalServer {
+                    url: "http://a:1".into(),
+                    password: Some("secret".into()),
+                },
+            ),
+        ]);
+        assert_eq!(
+            format_servers(&i18n, &servers),
+            "- `a` → http://a:1\n- `ci` → http://ci:4096"
+        );
+    }
+}
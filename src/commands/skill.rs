@@ -43,9 +43,7 @@ impl SlashCommand for SkillCommand {
 
         let channel_id_u64 = command.channel_id.get();
         let channel_id_str = channel_id_u64.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
+        let channel_config = state.channels.snapshot();
         let agent_type = channel_config.get_agent_type(&channel_id_str);
 
         let (agent, _) = state
@@ -53,8 +51,9 @@ impl SlashCommand for SkillCommand {
             .get_or_create_session(channel_id_u64, agent_type, &state.backend_manager)
             .await?;
 
+        let result = agent.load_skill(name).await;
         let i18n = state.i18n.read().await;
-        match agent.load_skill(name).await {
+        match result {
             Ok(_) => {
                 let msg = i18n.get_args("skill_loading", &[name.to_string()]);
                 command
@@ -0,0 +1,78 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+
+
+pub struct ThreadModeCommand;
+
+#[async_trait]
+impl SlashCommand for ThreadModeCommand {
+    fn name(&self) -> &'static str {
+        "thread_mode"
+    }
+
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
+    fn description(&self, i18n: &crate::i18n::I18n) -> String {
+        i18n.get("cmd_thread_mode_desc")
+    }
+
+    fn options(&self, i18n: &crate::i18n::I18n) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "enable",
+            i18n.get("cmd_thread_mode_opt_enable"),
+        )
+        .required(true)]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let enable = command
+            .data
+            .options
+            .iter()
+            .find(|o| o.name == "enable")
+            .and_then(|o| o.value.as_bool())
+            .unwrap_or(true);
+
+        // 討論串裡不能再開討論串
+        let in_thread = match command.channel_id.to_channel(&ctx.http).await {
+            Ok(channel) => channel.guild().is_some_and(|c| c.thread_metadata.is_some()),
+            Err(_) => false,
+        };
+
+        let key = if in_thread {
+            "thread_mode_in_thread"
+        } else {
+            state.channels.update(|channel_config| {
+                channel_config
+                    .ensure_entry(&command.channel_id.to_string())
+                    .thread_mode = enable;
+                Ok(())
+            }).await?;
+            if enable {
+                "thread_mode_on"
+            } else {
+                "thread_mode_off"
+            }
+        };
+        let msg = state.i18n.read().await.get(key);
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+
+use crate::i18n::I18n;
+use crate::usage::{format_cost, format_tokens, UsageBucket, UsageLedger, UsageTotals};
+
+pub struct UsageCommand;
+
+/// 分組明細只看最近 30 天，列出前幾名
+const BREAKDOWN_DAYS: i64 = 30;
+const BREAKDOWN_ROWS: usize = 10;
+
+fn format_totals(i18n: &I18n, totals: &UsageTotals) -> String {
+    i18n.get_args(
+        "usage_line",
+        &[
+            totals.turns.to_string(),
+            format_tokens(totals.input_tokens + totals.cache_read_tokens),
+            format_tokens(totals.output_tokens),
+            format_tokens(totals.cache_read_tokens),
+            format_cost(totals.cost),
+        ],
+    )
+}
+
+/// 頻道（含 thread mode 開出的討論串）在今天、7 天、30 天內的用量與分組明細
+fn format_usage(
+    i18n: &I18n,
+    ledger: &UsageLedger,
+    channel_ids: &[u64],
+    user: Option<u64>,
+    today: NaiveDate,
+) -> String {
+    let filter = |b: &UsageBucket| {
+        channel_ids.contains(&b.channel_id) && user.is_none_or(|u| b.user_id == Some(u))
+    };
+    let since = |days: i64| today - chrono::Duration::days(days - 1);
+    let month = ledger.totals(since(BREAKDOWN_DAYS), filter);
+    let mut lines = vec![match user {
+        Some(id) => format!(
+            "### {}",
+            i18n.get_args("usage_title_user", &[id.to_string()])
+        ),
+        None => format!("### {}", i18n.get("usage_title")),
+    }];
+    if month.turns == 0 {
+        lines.push(i18n.get("usage_none"));
+        return lines.join("\n");
+    }
+
+    for (key, days) in [
+        ("usage_period_today", 1),
+        ("usage_period_week", 7),
+        ("usage_period_month", BREAKDOWN_DAYS),
+    ] {
+        let totals = ledger.totals(since(days), filter);
+        lines.push(format!(
+            "- **{}**: {}",
+            i18n.get(key),
+            format_totals(i18n, &totals)
+        ));
+    }
+
+    lines.push(format!("**{}**", i18n.get("usage_by_model")));
+    let by_model = ledger.breakdown(since(BREAKDOWN_DAYS), filter, |b| b.model.clone());
+    for (model, totals) in by_model.iter().take(BREAKDOWN_ROWS) {
+        lines.push(format!("- `{}`: {}", model, format_totals(i18n, totals)));
+    }
+
+    if user.is_none() {
+        lines.push(format!("**{}**", i18n.get("usage_by_user")));
+        let by_user = ledger.breakdown(since(BREAKDOWN_DAYS), filter, |b| b.user_id);
+        for (user_id, totals) in by_user.iter().take(BREAKDOWN_ROWS) {
+            let who = match user_id {
+                Some(id) => format!("<@{}>", id),
+                None => i18n.get("usage_unknown_user"),
+            };
+            lines.push(format!("- {}: {}", who, format_totals(i18n, totals)));
+        }
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl SlashCommand for UsageCommand {
+    fn name(&self) -> &'static str {
+        "usage"
+    }
+
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::ReadOnly
+    }
+
+    fn description(&self, i18n: &I18n) -> String {
+        i18n.get("cmd_usage_desc")
+    }
+
+    fn options(&self, i18n: &I18n) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            i18n.get("cmd_usage_opt_user"),
+        )]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let user = command
+            .data
+            .options
+            .iter()
+            .find(|o| o.name == "user")
+            .and_then(|o| o.value.as_user_id())
+            .map(|id| id.get());
+        let channel_id = command.channel_id.to_string();
+        // thread mode 的對話在各自的討論串裡，一併算進父頻道
+        let channel_ids: Vec<u64> = state
+            .channels
+            .snapshot()
+            .channels
+            .iter()
+            .filter(|(_, entry)| entry.parent_id.as_deref() == Some(channel_id.as_str()))
+            .filter_map(|(id, _)| id.parse().ok())
+            .chain(std::iter::once(command.channel_id.get()))
+            .collect();
+        let ledger = state.usage.ledger();
+        let msg = {
+            let i18n = state.i18n.read().await;
+            format_usage(
+                &i18n,
+                &ledger,
+                &channel_ids,
+                user,
+                chrono::Utc::now().date_naive(),
+            )
+        };
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::TurnUsage;
+
+    #[test]
+    fn test_format_usage_periods_and_breakdowns() {
+        let i18n = I18n::new("en");
+        let today = NaiveDate::from_ymd_opt(2026, 3, 31).expect("date");
+        let usage = |input: u64, cost: f64| TurnUsage {
+            input_tokens: input,
+            output_tokens: 10,
+            cost,
+            ..Default::default()
+        };
+        let mut ledger = UsageLedger::default();
+        ledger.record(today, 7, Some(1), "anthropic/claude", &usage(1_500, 0.5));
+        ledger.record(
+            today - chrono::Duration::days(3),
+            8,
+            None,
+            "pi",
+            &usage(100, 0.0),
+        );
+        ledger.record(
+            today - chrono::Duration::days(10),
+            7,
+            Some(2),
+            "pi",
+            &usage(100, 0.0),
+        );
+        ledger.record(today, 9, Some(1), "other", &usage(100, 9.0));
+
+        let out = format_usage(&i18n, &ledger, &[7, 8], None, today);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], format!("### {}", i18n.get("usage_title")));
+        assert!(lines[1].contains("1.5k") && lines[1].contains("$0.50"));
+        assert!(lines[2].starts_with(&format!("- **{}**: 2", i18n.get("usage_period_week"))));
+        assert!(lines[3].starts_with(&format!("- **{}**: 3", i18n.get("usage_period_month"))));
+        // 花費高的模型在前，其他頻道的用量不算進來
+        assert!(lines[5].starts_with("- `anthropic/claude`"));
+        assert!(lines[6].starts_with("- `pi`: 2"));
+        assert!(!out.contains("other"));
+        assert!(out.contains("- <@1>: 1") && out.contains("- <@2>: 1"));
+        assert!(out.contains(&i18n.get("usage_unknown_user")));
+
+        let mine = format_usage(&i18n, &ledger, &[7, 8], Some(2), today);
+        assert!(mine.starts_with(&format!(
+            "### {}",
+            i18n.get_args("usage_title_user", &["2".into()])
+        )));
+        assert!(!mine.contains(&i18n.get("usage_by_user")));
+        let none = format_usage(&i18n, &ledger, &[42], None, today);
+        assert!(none.ends_with(&i18n.get("usage_none")));
+    }
+}
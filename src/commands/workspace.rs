@@ -0,0 +1,131 @@
+use super::SlashCommand;
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommandOption, EditInteractionResponse,
+};
+use tracing::info;
+
+
+pub struct WorkspaceCommand;
+
+fn format_roots(i18n: &crate::i18n::I18n, roots: &[String]) -> String {
+    if roots.is_empty() {
+        return i18n.get("workspace_no_roots");
+    }
+    roots
+        .iter()
+        .map(|r| format!("- `{}`", r))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 更新頻道的工作目錄；舊 session 綁在舊目錄上，因此一併丟棄
+async fn apply_workdir(
+    state: &crate::AppState,
+    channel_id: u64,
+    workdir: Option<String>,
+) -> anyhow::Result<()> {
+    state.channels.update(|channel_config| {
+        let entry = channel_config.ensure_entry(&channel_id.to_string());
+        entry.workdir = workdir;
+        entry.session_id = None;
+        Ok(())
+    }).await?;
+    state.session_manager.remove_session(channel_id).await;
+    Ok(())
+}
+
+#[async_trait]
+impl SlashCommand for WorkspaceCommand {
+    fn name(&self) -> &'static str {
+        "workspace"
+    }
+
+    fn required_role(&self) -> crate::roles::Role {
+        crate::roles::Role::Operator
+    }
+
+    fn description(&self, i18n: &crate::i18n::I18n) -> String {
+        i18n.get("cmd_workspace_desc")
+    }
+
+    fn options(&self, i18n: &crate::i18n::I18n) -> Vec<CreateCommandOption> {
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "path",
+                i18n.get("cmd_workspace_opt_path"),
+            ),
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "reset",
+                i18n.get("cmd_workspace_opt_reset"),
+            ),
+        ]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        command: &CommandInteraction,
+        state: &crate::AppState,
+    ) -> anyhow::Result<()> {
+        command.defer_ephemeral(&ctx.http).await?;
+
+        let option = |name: &str| command.data.options.iter().find(|o| o.name == name);
+        let path = option("path").and_then(|o| o.value.as_str());
+        let reset = option("reset")
+            .and_then(|o| o.value.as_bool())
+            .unwrap_or(false);
+        let channel_id = command.channel_id.get();
+        let workspace = state.config.read().await.workspace.clone();
+
+        let i18n = state.i18n.read().await;
+        let msg = if reset {
+            apply_workdir(state, channel_id, None).await?;
+            info!("Channel {} workdir unbound", channel_id);
+            i18n.get("workspace_reset")
+        } else if let Some(path) = path {
+            match workspace.resolve(path) {
+                Ok(dir) => {
+                    let dir = dir.to_string_lossy().to_string();
+                    apply_workdir(state, channel_id, Some(dir.clone())).await?;
+                    info!("Channel {} workdir set to {}", channel_id, dir);
+                    i18n.get_args("workspace_set", &[dir])
+                }
+                Err(e) => i18n.get_args("workspace_invalid", &[e.to_string()]),
+            }
+        } else {
+            let current = state.channels.snapshot()
+                .get_workdir(&channel_id.to_string())
+                .unwrap_or_else(|| i18n.get("workspace_default"));
+            i18n.get_args(
+                "workspace_current",
+                &[current, format_roots(&i18n, &workspace.roots)],
+            )
+        };
+        drop(i18n);
+
+        command
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(msg))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_roots;
+    use crate::i18n::I18n;
+
+    #[test]
+    fn test_format_roots_lists_each_root_or_hint() {
+        let i18n = I18n::new("en");
+        assert_eq!(format_roots(&i18n, &[]), i18n.get("workspace_no_roots"));
+        assert_eq!(
+            format_roots(&i18n, &["/a".to_string(), "/b".to_string()]),
+            "- `/a`\n- `/b`"
+        );
+    }
+}
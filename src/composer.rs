@@ -1,5 +1,8 @@
 use std::collections::VecDeque;
 
+/// Discord embed description 上限為 4096 字元，留一些空間給錯誤訊息
+pub const EMBED_PAGE_CHARS: usize = 3900;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BlockType {
     Thinking,
@@ -80,10 +83,54 @@ impl Block {
         .trim_end()
         .to_string()
     }
+
+    /// 完整輸出用：正文保留原文，思考只留第一行摘要，工具只留呼叫標籤
+    pub fn render_folded(&self) -> String {
+        match &self.block_type {
+            BlockType::Thinking => {
+                let first_line = self.content.lines().find(|l| !l.trim().is_empty());
+                let Some(first_line) = first_line else {
+                    return String::new();
+                };
+                let mut summary: String = first_line.trim().chars().take(80).collect();
+                if summary.len() < self.content.trim().len() {
+                    summary.push('…');
+                }
+                format!("> 💭 {}", summary)
+            }
+            BlockType::Text => self.content.trim_end().to_string(),
+            BlockType::ToolCall => self.render(),
+            BlockType::ToolOutput => String::new(),
+        }
+    }
+
+    /// 逐字紀錄用：所有內容都不截斷
+    pub fn render_transcript(&self) -> String {
+        match &self.block_type {
+            BlockType::Thinking if self.content.trim().is_empty() => String::new(),
+            BlockType::Thinking => format!("### 💭 Thinking\n\n{}", self.content.trim_end()),
+            BlockType::Text => self.content.trim_end().to_string(),
+            BlockType::ToolCall => format!("### {}", self.render()),
+            BlockType::ToolOutput if self.content.trim().is_empty() => String::new(),
+            BlockType::ToolOutput => {
+                // fence 要比內容中最長的連續反引號更長，才不會被內容提早關閉
+                let longest = self
+                    .content
+                    .split(|c| c != '`')
+                    .map(str::len)
+                    .max()
+                    .unwrap_or(0);
+                let fence = "`".repeat(longest.max(2) + 1);
+                format!("{}\n{}\n{}", fence, self.content.trim_end(), fence)
+            }
+        }
+    }
 }
 
 pub struct EmbedComposer {
     pub blocks: VecDeque<Block>,
+    /// 被 prune 移出即時畫面的 Block，回合結束時用來輸出完整內容
+    archived: Vec<Block>,
     max_len: usize,
     pub has_truncated: bool,
 }
@@ -92,6 +139,7 @@ impl EmbedComposer {
     pub fn new(max_len: usize) -> Self {
         Self {
             blocks: VecDeque::new(),
+            archived: Vec::new(),
             max_len,
             has_truncated: false,
         }
@@ -101,11 +149,20 @@ impl EmbedComposer {
     fn prune(&mut self) {
         // 硬性限制：只保留最後 10 個 Block
         while self.blocks.len() > 10 {
-            self.blocks.pop_front();
+            if let Some(block) = self.blocks.pop_front() {
+                self.archived.push(block);
+            }
             self.has_truncated = true;
         }
     }
 
+    /// 已被移出即時畫面的 Block，後續事件仍更新到封存區，讓逐字紀錄完整
+    fn find_archived_mut(&mut self, id: &str, block_type: &BlockType) -> Option<&mut Block> {
+        self.archived
+            .iter_mut()
+            .find(|b| b.id.as_deref() == Some(id) && &b.block_type == block_type)
+    }
+
     pub fn update_block_by_id(&mut self, id: &str, block_type: BlockType, content: String) {
         for block in self.blocks.iter_mut() {
             if block.id.as_deref() == Some(id) && block.block_type == block_type {
@@ -115,6 +172,12 @@ impl EmbedComposer {
                 return;
             }
         }
+        if let Some(block) = self.find_archived_mut(id, &block_type) {
+            if content.len() >= block.content.len() {
+                block.content = content;
+            }
+            return;
+        }
 
         // [核心過濾]: 如果是工具相關事件且 ID 目前不在結構內，視為已被物理截斷的舊事件，直接丟棄。
         if block_type == BlockType::ToolCall || block_type == BlockType::ToolOutput {
@@ -137,6 +200,10 @@ impl EmbedComposer {
                     return;
                 }
             }
+            if let Some(block) = self.find_archived_mut(id_str, &block_type) {
+                block.content.push_str(delta);
+                return;
+            }
 
             // [精確過濾]: 如果是工具相關的舊 ID，且目前結構裡找不到，則不予重建
             if block_type == BlockType::ToolCall || block_type == BlockType::ToolOutput {
@@ -175,6 +242,10 @@ impl EmbedComposer {
                 return;
             }
         }
+        if let Some(block) = self.find_archived_mut(&id, &BlockType::ToolCall) {
+            block.label = Some(label);
+            return;
+        }
         self.blocks
             .push_back(Block::with_label(BlockType::ToolCall, label, Some(id)));
         self.prune();
@@ -184,10 +255,19 @@ impl EmbedComposer {
         if items.is_empty() {
             return;
         }
+        // 同步內容是完整清單：封存區裡重複出現的 Block 改由新清單接手，避免重複
+        let mut revived = Vec::new();
+        self.archived.retain(|b| {
+            let keep = b.id.is_none() || !items.iter().any(|i| i.id == b.id);
+            if !keep {
+                revived.push(b.clone());
+            }
+            keep
+        });
         let mut new_list = VecDeque::new();
         for item in items {
             let mut merged = item.clone();
-            if let Some(local) = self.blocks.iter().find(|b| match (&b.id, &item.id) {
+            if let Some(local) = self.blocks.iter().chain(revived.iter()).find(|b| match (&b.id, &item.id) {
                 (Some(id1), Some(id2)) => id1 == id2,
                 _ => b.block_type == item.block_type && b.id.is_none() && item.id.is_none(),
             }) {
@@ -246,6 +326,125 @@ impl EmbedComposer {
 
         res.trim().to_string()
     }
+
+    /// 回合結束後的完整內容（含已移出即時畫面的部分），不做截斷
+    pub fn render_full(&self) -> String {
+        self.archived
+            .iter()
+            .chain(self.blocks.iter())
+            .map(Block::render_folded)
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+/// 以空行切段落；程式碼區塊（含其中的空行）視為同一段，未閉合的區塊會補上結尾
+fn split_segments(text: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if !in_fence && line.trim().is_empty() {
+            if !current.is_empty() {
+                segments.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+        current.push(line);
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+    }
+    if in_fence {
+        current.push("```");
+    }
+    if !current.is_empty() {
+        segments.push(current.join("\n"));
+    }
+    segments
+}
+
+/// 單一段落超過上限時逐行切；切在程式碼區塊中間時，前段補上結尾、後段重開同一個 fence
+fn split_long_segment(segment: &str, limit: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut fence: Option<String> = None;
+    for line in segment.lines() {
+        let reserve = fence.as_ref().map_or(0, |f| char_len(f)) + 8;
+        let width = limit.saturating_sub(reserve).max(1);
+        let chars: Vec<char> = line.chars().collect();
+        let pieces: Vec<String> = if chars.is_empty() {
+            vec![String::new()]
+        } else {
+            chars.chunks(width).map(|c| c.iter().collect()).collect()
+        };
+
+        for (idx, piece) in pieces.into_iter().enumerate() {
+            let closing = if fence.is_some() { 4 } else { 0 };
+            // 被硬切的長行，後續片段一定要換到下一則，才不會多出換行
+            let overflow = char_len(&current) + 1 + char_len(&piece) + closing > limit;
+            if !current.is_empty() && (idx > 0 || overflow) {
+                if fence.is_some() {
+                    current.push_str("\n```");
+                }
+                parts.push(std::mem::take(&mut current));
+                if let Some(header) = &fence {
+                    current = header.clone();
+                }
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(&piece);
+        }
+
+        if is_fence(line) {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(line.trim().to_string()),
+            };
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// 把完整回覆切成多則 Discord 訊息：優先在段落與程式碼區塊邊界切開，每段不超過 `limit` 字元
+pub fn split_for_discord(text: &str, limit: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut current = String::new();
+    for segment in split_segments(text) {
+        if char_len(&segment) > limit {
+            // 過長的段落自成數則，不和前後段落合併
+            if !current.is_empty() {
+                pages.push(std::mem::take(&mut current));
+            }
+            pages.extend(split_long_segment(&segment, limit));
+        } else if current.is_empty() {
+            current = segment;
+        } else if char_len(&current) + 2 + char_len(&segment) <= limit {
+            current.push_str("\n\n");
+            current.push_str(&segment);
+        } else {
+            pages.push(std::mem::replace(&mut current, segment));
+        }
+    }
+    if !current.is_empty() {
+        pages.push(current);
+    }
+    pages
 }
 
 #[cfg(test)]
@@ -313,4 +512,94 @@ mod tests {
         // 如果 sync 的內容較短，應保留本地較長的內容（防止網路延遲導致抖動）
         assert_eq!(composer.blocks[0].content, "longer_old_data");
     }
+
+    #[test]
+    fn test_render_full_keeps_pruned_text_and_folds_tools() {
+        let mut composer = EmbedComposer::new(1000);
+        composer.push_delta(Some("t".into()), BlockType::Thinking, "plan\nmore plan");
+        composer.set_tool_call("c1".into(), "🛠️ ls".into());
+        composer.update_block_by_id("c1", BlockType::ToolOutput, "secret output".into());
+        for i in 0..12 {
+            composer.push_delta(Some(format!("x{}", i)), BlockType::Text, &format!("para {}", i));
+        }
+
+        let full = composer.render_full();
+        assert!(full.starts_with("> 💭 plan…"));
+        assert!(full.contains("🛠️ ls"));
+        assert!(!full.contains("secret output"));
+        assert!(full.contains("para 0"));
+        assert!(full.contains("para 11"));
+        assert!(!composer.render().contains("para 0"));
+    }
+
+    #[test]
+    fn test_split_for_discord_packs_paragraphs_under_limit() {
+        let text = "aaaa\n\nbbbb\n\ncccc";
+        assert_eq!(split_for_discord(text, 100), vec![text.to_string()]);
+        assert_eq!(
+            split_for_discord(text, 10),
+            vec!["aaaa\n\nbbbb".to_string(), "cccc".to_string()]
+        );
+        assert!(split_for_discord("", 10).is_empty());
+    }
+
+    #[test]
+    fn test_split_for_discord_reopens_code_fences() {
+        let code: Vec<String> = (0..40).map(|i| format!("let v{} = {};", i, i)).collect();
+        let text = format!("intro\n\n```rust\n{}\n```\n\noutro", code.join("\n"));
+        let pages = split_for_discord(&text, 200);
+        assert!(pages.len() > 2);
+        for page in &pages {
+            assert!(page.chars().count() <= 200, "page too long: {}", page.len());
+            assert_eq!(page.matches("```").count() % 2, 0, "unbalanced: {}", page);
+        }
+        assert!(pages[1].starts_with("```rust"));
+        assert_eq!(pages.last().map(String::as_str), Some("outro"));
+        let rejoined = pages.join("\n");
+        assert!(rejoined.contains("let v0 = 0;") && rejoined.contains("let v39 = 39;"));
+    }
+
+    #[test]
+    fn test_split_for_discord_hard_splits_long_lines_and_closes_fence() {
+        let pages = split_for_discord(&"字".repeat(50), 20);
+        assert!(pages.iter().all(|p| p.chars().count() <= 20));
+        assert_eq!(pages.concat(), "字".repeat(50));
+
+        let pages = split_for_discord("```\nunfinished", 100);
+        assert_eq!(pages, vec!["```\nunfinished\n```".to_string()]);
+    }
+
+    #[test]
+    fn test_late_updates_reach_archived_blocks() {
+        let mut composer = EmbedComposer::new(1000);
+        composer.push_delta(Some("first".into()), BlockType::Text, "hello");
+        for i in 0..12 {
+            composer.push_delta(Some(format!("x{}", i)), BlockType::Text, "filler");
+        }
+        composer.push_delta(Some("first".into()), BlockType::Text, " world");
+
+        assert!(composer.render_full().starts_with("hello world"));
+        assert!(!composer.render().contains("hello"));
+    }
+
+    #[test]
+    fn test_block_transcript_uses_longer_fence_than_content() {
+        let output = format!("{}```{}", "A".repeat(600), "B");
+        let block = Block::with_id(BlockType::ToolOutput, output.clone(), "c1".into());
+        let rendered = block.render_transcript();
+        assert!(rendered.starts_with("````\n"));
+        assert!(rendered.contains(&output));
+    }
+
+    #[test]
+    fn test_sync_content_does_not_duplicate_archived_blocks() {
+        let mut composer = EmbedComposer::new(1000);
+        let items: Vec<Block> = (0..12)
+            .map(|i| Block::with_id(BlockType::Text, format!("p{}", i), format!("id{}", i)))
+            .collect();
+        composer.sync_content(items.clone());
+        composer.sync_content(items);
+        assert_eq!(composer.render_full().matches("p0").count(), 1);
+        assert_eq!(composer.blocks.len(), 10);
+    }
 }
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::agent::AgentType;
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Config {
@@ -10,23 +14,329 @@ pub struct Config {
     pub assistant_name: String,
     #[serde(default)]
     pub opencode: OpencodeConfig,
+    /// Kilo 是 OpenCode 的 fork，server 設定格式相同
+    #[serde(default)]
+    pub kilo: OpencodeConfig,
+    #[serde(default)]
+    pub pi: PiConfig,
+    #[serde(default)]
+    pub copilot: CopilotConfig,
+    /// 以名稱區分的 ACP agent（`[acp.<name>]`），頻道以 `acp:<name>` 選用
+    #[serde(default)]
+    pub acp: HashMap<String, AcpAgentConfig>,
+    #[serde(default)]
+    pub openai: OpenAiConfig,
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
+    #[serde(default)]
+    pub threads: ThreadsConfig,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+}
+
+/// Prometheus 抓取用的 HTTP 端點，預設關閉且只綁本機
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_metrics_listen(),
+        }
+    }
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9464".to_string()
+}
+
+/// 每個頻道與每個使用者的用量上限；0 表示不限制
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BudgetConfig {
+    /// 用量達到上限的百分之幾時先發出警告，0 表示不警告
+    #[serde(default = "default_budget_warn_percent")]
+    pub warn_percent: u64,
+    /// 頻道（含 thread mode 開出的討論串）合計
+    #[serde(default)]
+    pub channel: BudgetLimits,
+    /// 同一個使用者在所有頻道的合計，排程以建立者計算
+    #[serde(default)]
+    pub user: BudgetLimits,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            warn_percent: default_budget_warn_percent(),
+            channel: BudgetLimits::default(),
+            user: BudgetLimits::default(),
+        }
+    }
+}
+
+fn default_budget_warn_percent() -> u64 {
+    80
+}
+
+/// 每日以 UTC 日期、每月以 UTC 月份計算
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetLimits {
+    #[serde(default)]
+    pub daily_prompts: u64,
+    #[serde(default)]
+    pub daily_tokens: u64,
+    #[serde(default)]
+    pub daily_cost: f64,
+    #[serde(default)]
+    pub monthly_prompts: u64,
+    #[serde(default)]
+    pub monthly_tokens: u64,
+    #[serde(default)]
+    pub monthly_cost: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ThreadsConfig {
+    /// thread mode 開出的討論串閒置多久（分鐘）後封存並釋放 session，0 表示不封存
+    #[serde(default = "default_idle_archive_mins")]
+    pub idle_archive_mins: u64,
+}
+
+impl Default for ThreadsConfig {
+    fn default() -> Self {
+        Self {
+            idle_archive_mins: default_idle_archive_mins(),
+        }
+    }
+}
+
+fn default_idle_archive_mins() -> u64 {
+    60
+}
+
+/// 頻道可綁定的工作目錄；只允許位於 `roots` 之下的目錄，未設定時停用綁定
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct WorkspaceConfig {
+    #[serde(default)]
+    pub roots: Vec<String>,
+}
+
+impl WorkspaceConfig {
+    /// 檢查並正規化工作目錄，確保它（解析 symlink 後）仍在允許的根目錄內
+    pub fn resolve(&self, path: &str) -> anyhow::Result<PathBuf> {
+        if self.roots.is_empty() {
+            anyhow::bail!("No workspace roots configured in config.toml");
+        }
+        let requested = Path::new(path.trim());
+        if !requested.is_absolute() {
+            anyhow::bail!("Workdir must be an absolute path: {}", path);
+        }
+        let canonical = requested
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot access {}: {}", path, e))?;
+        if !canonical.is_dir() {
+            anyhow::bail!("Not a directory: {}", canonical.display());
+        }
+        let allowed = self
+            .roots
+            .iter()
+            .filter_map(|root| Path::new(root).canonicalize().ok())
+            .any(|root| canonical.starts_with(root));
+        if !allowed {
+            anyhow::bail!(
+                "{} is outside the allowed workspace roots",
+                canonical.display()
+            );
+        }
+        Ok(canonical)
+    }
+}
+
+/// Pi 每個頻道各有一個 `pi --mode rpc` 子進程，閒置或超過上限時回收
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PiConfig {
+    /// 閒置多久（分鐘）後結束子進程，0 表示不回收
+    #[serde(default = "default_pi_idle_timeout_mins")]
+    pub idle_timeout_mins: u64,
+    /// 同時存活的子進程上限，超過時回收最久沒用的，0 表示不限
+    #[serde(default = "default_pi_max_live_sessions")]
+    pub max_live_sessions: usize,
+}
+
+impl Default for PiConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_mins: default_pi_idle_timeout_mins(),
+            max_live_sessions: default_pi_max_live_sessions(),
+        }
+    }
+}
+
+fn default_pi_idle_timeout_mins() -> u64 {
+    30
+}
+
+fn default_pi_max_live_sessions() -> usize {
+    8
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CopilotConfig {
+    /// 頻道設為 ask 時，等待使用者核准工具權限的秒數，逾時視為拒絕
+    #[serde(default = "default_permission_timeout_secs")]
+    pub permission_timeout_secs: u64,
+}
+
+impl Default for CopilotConfig {
+    fn default() -> Self {
+        Self {
+            permission_timeout_secs: default_permission_timeout_secs(),
+        }
+    }
+}
+
+fn default_permission_timeout_secs() -> u64 {
+    crate::agent::permission::DEFAULT_PERMISSION_TIMEOUT_SECS
+}
+
+/// 任何透過 stdio 說 ACP（Agent Client Protocol）的 CLI
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct AcpAgentConfig {
+    /// 執行檔；不含路徑時會在 PATH 與常見安裝目錄中尋找
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// 顯示在選單與訊息中的名稱，未設定時使用 key
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+/// 任何 `/v1/chat/completions` 相容的 HTTP server（OpenAI、llama.cpp、vLLM、Ollama…）
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct OpenAiConfig {
+    /// 含 `/v1` 的 API 根網址
+    #[serde(default = "default_openai_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// 預設模型；未設定時使用 `/models` 列出的第一個
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 頻道綁定工作目錄時，提供唯讀的檔案工具給模型；預設關閉
+    #[serde(default)]
+    pub tools: bool,
+    /// 一個回合內最多幾輪工具呼叫
+    #[serde(default = "default_max_tool_rounds")]
+    pub max_tool_rounds: u32,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_openai_base_url(),
+            api_key: None,
+            model: None,
+            tools: false,
+            max_tool_rounds: default_max_tool_rounds(),
+        }
+    }
+}
+
+fn default_openai_base_url() -> String {
+    "http://127.0.0.1:8080/v1".to_string()
+}
+
+fn default_max_tool_rounds() -> u32 {
+    8
+}
+
+impl AcpAgentConfig {
+    pub fn label(&self, name: &str) -> String {
+        self.display_name
+            .clone()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| name.to_string())
+    }
+}
+
+/// OpenCode/Kilo server 的來源
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendMode {
+    /// 由 bot 在本機隨機 port 啟動並管理子進程
+    #[default]
+    Managed,
+    /// 連到既有的 server（例如與 IDE、CI 共用），不啟動子進程
+    External,
+}
+
+/// 一個外部 server 的位址與密碼
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ExternalServer {
+    pub url: String,
+    #[serde(default)]
+    pub password: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct OpencodeConfig {
+    #[serde(default)]
+    pub mode: BackendMode,
+    /// external 模式連線的 server 網址
+    #[serde(default)]
+    pub url: Option<String>,
     #[serde(default = "default_host")]
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
     pub password: Option<String>,
+    /// 具名的外部 server，頻道可用 /server 個別指定
+    #[serde(default)]
+    pub servers: HashMap<String, ExternalServer>,
 }
 
 impl Default for OpencodeConfig {
     fn default() -> Self {
         Self {
+            mode: BackendMode::default(),
+            url: None,
             host: "127.0.0.1".to_string(),
             port: 4096,
             password: None,
+            servers: HashMap::new(),
+        }
+    }
+}
+
+impl OpencodeConfig {
+    /// 決定要連的外部 server：頻道指定的優先，其次是 external 模式的預設 server；
+    /// 回傳 None 表示交給 BackendManager 啟動
+    pub fn external(&self, server: Option<&str>) -> anyhow::Result<Option<ExternalServer>> {
+        if let Some(name) = server {
+            return match self.servers.get(name) {
+                Some(s) => Ok(Some(s.clone())),
+                None => anyhow::bail!("Unknown server: {}", name),
+            };
+        }
+        match self.mode {
+            BackendMode::Managed => Ok(None),
+            BackendMode::External => match self.url.as_deref().map(str::trim) {
+                Some(url) if !url.is_empty() => Ok(Some(ExternalServer {
+                    url: url.trim_end_matches('/').to_string(),
+                    password: self.password.clone(),
+                })),
+                _ => anyhow::bail!("mode = \"external\" requires a server url"),
+            },
         }
     }
 }
@@ -48,6 +358,34 @@ fn default_port() -> u16 {
 }
 
 impl Config {
+    /// OpenCode 相容後端的 server 設定；其他後端回傳 None
+    pub fn server_config(&self, agent_type: &AgentType) -> Option<&OpencodeConfig> {
+        match agent_type {
+            AgentType::Opencode => Some(&self.opencode),
+            AgentType::Kilo => Some(&self.kilo),
+            _ => None,
+        }
+    }
+
+    pub fn acp_agent(&self, name: &str) -> anyhow::Result<&AcpAgentConfig> {
+        match self.acp.get(name) {
+            Some(agent) if !agent.command.trim().is_empty() => Ok(agent),
+            Some(_) => anyhow::bail!("ACP agent {} has no command configured", name),
+            None => anyhow::bail!("Unknown ACP agent: {}", name),
+        }
+    }
+
+    /// 設定好的 ACP agent：(名稱, 顯示名稱)，依名稱排序
+    pub fn acp_agents(&self) -> Vec<(String, String)> {
+        let mut agents: Vec<(String, String)> = self
+            .acp
+            .iter()
+            .map(|(name, agent)| (name.clone(), agent.label(name)))
+            .collect();
+        agents.sort();
+        agents
+    }
+
     pub async fn load() -> anyhow::Result<Self> {
         let config_path = super::migrate::get_config_path();
 
@@ -62,6 +400,67 @@ assistant_name = "Agent"
 host = "127.0.0.1"
 port = 4096
 # password = "your-password"  # Uncomment if using OPENCODE_SERVER_PASSWORD
+# mode = "external"            # Attach to an existing server instead of spawning one
+# url = "http://127.0.0.1:4096"
+
+# Named external servers that /server can bind a channel to
+# [opencode.servers.shared]
+# url = "http://10.0.0.5:4096"
+# password = "your-password"
+
+[kilo]
+# Same options as [opencode]
+# password = "your-password"  # Uncomment if using KILO_SERVER_PASSWORD
+
+[pi]
+# Stop a channel's idle pi process after this many minutes (0 disables); it resumes on next message
+idle_timeout_mins = 30
+# Keep at most this many pi processes alive, evicting the least recently used (0 = unlimited)
+max_live_sessions = 8
+
+[copilot]
+permission_timeout_secs = 120
+
+# Any ACP (Agent Client Protocol) CLI; select it in a channel with /agent backend:acp name:gemini
+# [acp.gemini]
+# command = "gemini"
+# args = ["--experimental-acp"]
+# display_name = "Gemini CLI"
+# [acp.gemini.env]
+# GEMINI_API_KEY = "your-key"
+
+[openai]
+# Any /v1/chat/completions server (OpenAI, llama.cpp, vLLM, Ollama)
+base_url = "http://127.0.0.1:8080/v1"
+# api_key = "sk-..."
+# model = "gpt-4o-mini"              # Defaults to the first model listed by /models
+# tools = false                      # Read-only file tools inside the channel's /workspace
+# max_tool_rounds = 8
+
+[workspace]
+# Project roots that /workspace may bind a channel to
+roots = []
+
+[threads]
+# Archive idle conversation threads (thread mode) after this many minutes; 0 disables
+idle_archive_mins = 60
+
+[budget]
+# Warn when a limit is this % used; prompts over a limit are refused (0 = no limit)
+warn_percent = 80
+# Per channel, threads included; days and months are UTC
+# [budget.channel]
+# daily_prompts = 200
+# monthly_cost = 50.0
+# Per user across all channels; cron jobs count for their creator
+# [budget.user]
+# daily_tokens = 2000000
+# daily_cost = 5.0
+
+[metrics]
+# Serve Prometheus metrics at http://<listen>/metrics
+enabled = false
+listen = "127.0.0.1:9464"
 "#;
             tokio::fs::write(&config_path, default_config).await?;
             anyhow::bail!(
@@ -71,14 +470,34 @@ port = 4096
         }
 
         let content = tokio::fs::read_to_string(&config_path).await?;
-        let config: Config = toml::from_str(&content)?;
+        let mut config: Config = toml::from_str(&content)?;
+        let has_kilo = toml::from_str::<toml::Table>(&content)?.contains_key("kilo");
+        if config.inherit_kilo_password(has_kilo) {
+            tracing::warn!(
+                "⚠️ No [kilo] section in config.toml: Kilo now reads its own password, \
+                 falling back to [opencode] password. Add a [kilo] section to silence this."
+            );
+        }
         Ok(config)
     }
+
+    /// 舊版 Kilo 共用 `[opencode]` 的 password；沒有 `[kilo]` 區段時沿用，回傳是否套用
+    fn inherit_kilo_password(&mut self, has_kilo_section: bool) -> bool {
+        if has_kilo_section || self.opencode.password.is_none() {
+            return false;
+        }
+        self.kilo.password = self.opencode.password.clone();
+        true
+    }
 }
 
 #[cfg(test)]
+// env lock 需跨 await 持有，才能序列化 BASE_DIR_ENV 的設定
+#[allow(clippy::await_holding_lock)]
 mod tests {
-    use super::Config;
+    use super::{
+        AcpAgentConfig, BackendMode, Config, ExternalServer, OpencodeConfig, WorkspaceConfig,
+    };
     use crate::migrate::BASE_DIR_ENV;
     use std::sync::{Mutex, OnceLock};
     use tempfile::tempdir;
@@ -125,7 +544,145 @@ port = 4096
         assert_eq!(cfg.discord_token, "abc");
         assert_eq!(cfg.language, "en");
         assert_eq!(cfg.assistant_name, "AgentX");
+        assert_eq!(cfg.copilot.permission_timeout_secs, 120);
+        assert_eq!(cfg.threads.idle_archive_mins, 60);
+        assert_eq!(cfg.pi.idle_timeout_mins, 30);
+        assert_eq!(cfg.pi.max_live_sessions, 8);
+        assert!(!cfg.metrics.enabled);
+        assert_eq!(cfg.metrics.listen, "127.0.0.1:9464");
+        // SAFETY: serialized by env lock
+        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+    }
+
+    #[tokio::test]
+    async fn test_load_falls_back_to_opencode_password_without_kilo_section() {
+        let _guard = env_lock().lock().expect("lock");
+        let dir = tempdir().expect("tempdir");
+        // SAFETY: serialized by env lock
+        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+        let path = dir.path().join("config.toml");
+        let legacy = "discord_token = \"abc\"\n[opencode]\npassword = \"shared\"\n";
+        tokio::fs::write(&path, legacy).await.expect("write config");
+        let cfg = Config::load().await.expect("load");
+        assert_eq!(cfg.kilo.password.as_deref(), Some("shared"));
+
+        tokio::fs::write(&path, format!("{}[kilo]\nport = 4097\n", legacy))
+            .await
+            .expect("write config");
+        let cfg = Config::load().await.expect("load");
+        assert_eq!(cfg.kilo.password, None);
         // SAFETY: serialized by env lock
         unsafe { std::env::remove_var(BASE_DIR_ENV) };
     }
+
+    #[test]
+    fn test_workspace_resolve_only_allows_dirs_under_roots() {
+        let root = tempdir().expect("root");
+        let outside = tempdir().expect("outside");
+        let project = root.path().join("proj");
+        std::fs::create_dir(&project).expect("mkdir");
+        std::fs::write(root.path().join("file.txt"), "x").expect("write");
+
+        let ws = WorkspaceConfig {
+            roots: vec![root.path().to_string_lossy().to_string()],
+        };
+        let resolved = ws.resolve(&project.to_string_lossy()).expect("allowed");
+        assert_eq!(resolved, project.canonicalize().expect("canonical"));
+
+        let escape = project.join("..").join("..");
+        assert!(ws.resolve(&escape.to_string_lossy()).is_err());
+        assert!(ws.resolve(&outside.path().to_string_lossy()).is_err());
+        assert!(ws
+            .resolve(&root.path().join("file.txt").to_string_lossy())
+            .is_err());
+        assert!(ws.resolve("relative/dir").is_err());
+        assert!(WorkspaceConfig::default()
+            .resolve(&project.to_string_lossy())
+            .is_err());
+    }
+
+    #[test]
+    fn test_external_server_selection() {
+        let cfg: Config = toml::from_str(
+            r#"discord_token = "abc"
+
+[opencode]
+mode = "external"
+url = "http://10.0.0.5:4096/"
+password = "pw"
+
+[opencode.servers.ci]
+url = "http://ci:4096"
+"#,
+        )
+        .expect("parse");
+        assert_eq!(cfg.opencode.mode, BackendMode::External);
+        assert_eq!(cfg.kilo.mode, BackendMode::Managed);
+        assert_eq!(
+            cfg.opencode.external(None).expect("default"),
+            Some(ExternalServer {
+                url: "http://10.0.0.5:4096".into(),
+                password: Some("pw".into()),
+            })
+        );
+        assert_eq!(
+            cfg.opencode.external(Some("ci")).expect("named").map(|s| s.url),
+            Some("http://ci:4096".to_string())
+        );
+        assert!(cfg.opencode.external(Some("nope")).is_err());
+        assert_eq!(cfg.kilo.external(None).expect("managed"), None);
+
+        let missing_url = OpencodeConfig {
+            mode: BackendMode::External,
+            ..Default::default()
+        };
+        assert!(missing_url.external(None).is_err());
+    }
+
+    #[test]
+    fn test_acp_agents_parse_and_lookup() {
+        let cfg: Config = toml::from_str(
+            r#"discord_token = "abc"
+
+[acp.gemini]
+command = "gemini"
+args = ["--experimental-acp"]
+display_name = "Gemini CLI"
+
+[acp.gemini.env]
+GEMINI_API_KEY = "k"
+
+[acp.local]
+command = "/opt/agent/bin/agent"
+
+[acp.broken]
+command = " "
+"#,
+        )
+        .expect("parse");
+        let gemini = cfg.acp_agent("gemini").expect("gemini");
+        assert_eq!(gemini.args, vec!["--experimental-acp".to_string()]);
+        assert_eq!(gemini.env["GEMINI_API_KEY"], "k");
+        assert_eq!(
+            cfg.acp_agent("local").expect("local"),
+            &AcpAgentConfig {
+                command: "/opt/agent/bin/agent".into(),
+                ..Default::default()
+            }
+        );
+        assert!(cfg.acp_agent("broken").is_err());
+        assert!(cfg.acp_agent("nope").is_err());
+        assert_eq!(
+            cfg.acp_agents(),
+            vec![
+                ("broken".to_string(), "broken".to_string()),
+                ("gemini".to_string(), "Gemini CLI".to_string()),
+                ("local".to_string(), "local".to_string()),
+            ]
+        );
+        assert!(Config::default().acp.is_empty());
+        assert_eq!(cfg.openai, super::OpenAiConfig::default());
+        assert_eq!(cfg.openai.base_url, "http://127.0.0.1:8080/v1");
+        assert!(!cfg.openai.tools);
+    }
 }
@@ -0,0 +1,557 @@
+use crate::agent::manager::BackendStatus;
+use crate::config::Config;
+use crate::cron::manager::CronJobInfo;
+use crate::i18n::I18n;
+use crate::AppState;
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{error, info, warn};
+
+/// 單一請求行的長度上限，避免異常客戶端塞爆記憶體
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// CLI → daemon 的控制請求，以一行 JSON 傳送
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Reload,
+    Status,
+    Sessions,
+    Abort { channel_id: u64 },
+    Clear { channel_id: u64 },
+    Backends,
+    CronList,
+}
+
+/// daemon → CLI 的回應，同樣是一行 JSON
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+}
+
+impl ControlResponse {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+            data: Value::Null,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+            data: Value::Null,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = data;
+        self
+    }
+}
+
+/// `sessions` 回報的單一頻道狀態
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionSummary {
+    pub channel_id: u64,
+    pub agent_type: String,
+    /// 正在渲染中的回覆訊息 ID（沒有則代表閒置）
+    pub render_message_id: Option<u64>,
+}
+
+/// 綁定控制 socket。若舊 socket 仍有 daemon 在聽則拒絕，否則視為殘留檔案並移除。
+/// 所在目錄會設為 0700，socket 建立的瞬間就只有自己能連線。
+pub async fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!("Another daemon is already listening on {}", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)?;
+        // 目錄早已存在時 mode 不會套用
+        std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// 接受連線並把每個請求交給 handler 處理（每條連線一個請求）
+pub async fn serve<F, Fut>(listener: UnixListener, handler: F)
+where
+    F: Fn(ControlRequest) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ControlResponse> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, handler).await {
+                        warn!("⚠️ Control connection error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("❌ Control socket accept failed: {}", e);
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection<F, Fut>(stream: UnixStream, handler: F) -> anyhow::Result<()>
+where
+    F: Fn(ControlRequest) -> Fut,
+    Fut: Future<Output = ControlResponse>,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader)
+        .take(MAX_REQUEST_BYTES)
+        .read_line(&mut line)
+        .await?;
+
+    let response = match serde_json::from_str::<ControlRequest>(line.trim()) {
+        Ok(req) => handler(req).await,
+        Err(e) => ControlResponse::error(format!("Invalid request: {}", e)),
+    };
+
+    let mut out = serde_json::to_string(&response)?;
+    out.push('\n');
+    writer.write_all(out.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// CLI 端：送出一個請求並等待回應
+pub async fn send_request(
+    path: &Path,
+    request: &ControlRequest,
+) -> anyhow::Result<ControlResponse> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!(
+            "Cannot reach the running daemon at {} (is `agent-discord run` active?)",
+            path.display()
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut out = serde_json::to_string(request)?;
+    out.push('\n');
+    writer.write_all(out.as_bytes()).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    if line.trim().is_empty() {
+        anyhow::bail!("Daemon closed the control connection without a response");
+    }
+    Ok(serde_json::from_str(line.trim())?)
+}
+
+/// daemon 端的請求分派
+pub async fn dispatch(
+    state: &AppState,
+    http: &serenity::http::Http,
+    request: ControlRequest,
+) -> ControlResponse {
+    let label = format!("{:?}", request);
+    let result = match request {
+        ControlRequest::Reload => reload(state, http).await,
+        ControlRequest::Status => status(state).await,
+        ControlRequest::Sessions => sessions(state).await,
+        ControlRequest::Abort { channel_id } => abort(state, http, channel_id).await,
+        ControlRequest::Clear { channel_id } => clear(state, channel_id).await,
+        ControlRequest::Backends => backends(state).await,
+        ControlRequest::CronList => cron_list(state).await,
+    };
+    match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("❌ Control request {} failed: {}", label, e);
+            ControlResponse::error(format!("{} failed: {}", label, e))
+        }
+    }
+}
+
+async fn collect_sessions(state: &AppState) -> Vec<SessionSummary> {
+    let renders: std::collections::HashMap<u64, u64> = {
+        let active = state.active_renders.lock().await;
+        active
+            .iter()
+            .filter_map(|(channel_id, render)| {
+                render.message_id.map(|msg_id| (*channel_id, msg_id.get()))
+            })
+            .collect()
+    };
+    state
+        .session_manager
+        .list_sessions()
+        .await
+        .into_iter()
+        .map(|(channel_id, agent_type)| SessionSummary {
+            channel_id,
+            agent_type,
+            render_message_id: renders.get(&channel_id).copied(),
+        })
+        .collect()
+}
+
+async fn status(state: &AppState) -> anyhow::Result<ControlResponse> {
+    let uptime = (chrono::Utc::now() - state.started_at).num_seconds().max(0) as u64;
+    let language = state.config.read().await.language.clone();
+    let sessions = collect_sessions(state).await;
+    let rendering = sessions
+        .iter()
+        .filter(|s| s.render_message_id.is_some())
+        .count();
+    let backends = state.backend_manager.list_backends().await;
+    let alive = backends.iter().filter(|b| b.alive).count();
+    let cron_jobs = state.cron_manager.list_jobs().await.len();
+
+    let message = format!(
+        "agent-discord v{} — up {}\nlanguage:  {}\nsessions:  {} ({} rendering)\nbackends:  {} ({} alive)\ncron jobs: {}",
+        env!("CARGO_PKG_VERSION"),
+        format_uptime(uptime),
+        language,
+        sessions.len(),
+        rendering,
+        backends.len(),
+        alive,
+        cron_jobs
+    );
+    Ok(ControlResponse::ok(message).with_data(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": uptime,
+        "language": language,
+        "sessions": sessions.len(),
+        "rendering": rendering,
+        "backends": backends.len(),
+        "backends_alive": alive,
+        "cron_jobs": cron_jobs,
+    })))
+}
+
+async fn sessions(state: &AppState) -> anyhow::Result<ControlResponse> {
+    let list = collect_sessions(state).await;
+    Ok(ControlResponse::ok(format_sessions(&list)).with_data(serde_json::to_value(&list)?))
+}
+
+async fn abort(
+    state: &AppState,
+    http: &serenity::http::Http,
+    channel_id: u64,
+) -> anyhow::Result<ControlResponse> {
+    let agent = state.session_manager.get_session(channel_id).await;
+    let rendering = state.active_renders.lock().await.contains_key(&channel_id);
+    if agent.is_none() && !rendering {
+        anyhow::bail!("No live session for channel {}", channel_id);
+    }
+    let ended = crate::commands::abort::abort_channel(state, http, channel_id, agent).await?;
+    info!("🛑 Aborted channel {} via control socket", channel_id);
+    Ok(ControlResponse::ok(format!(
+        "Abort sent to channel {}{}",
+        channel_id,
+        if ended { " (reply ended)" } else { "" }
+    )))
+}
+
+async fn clear(state: &AppState, channel_id: u64) -> anyhow::Result<ControlResponse> {
+    crate::commands::clear::clear_channel(state, channel_id).await?;
+    info!("🧹 Cleared channel {} via control socket", channel_id);
+    Ok(ControlResponse::ok(format!(
+        "Cleared session for channel {}",
+        channel_id
+    )))
+}
+
+async fn backends(state: &AppState) -> anyhow::Result<ControlResponse> {
+    let list = state.backend_manager.list_backends().await;
+    Ok(ControlResponse::ok(format_backends(&list)).with_data(serde_json::to_value(&list)?))
+}
+
+async fn cron_list(state: &AppState) -> anyhow::Result<ControlResponse> {
+    let list = state.cron_manager.list_jobs().await;
+    Ok(ControlResponse::ok(format_cron_jobs(&list)).with_data(serde_json::to_value(&list)?))
+}
+
+pub fn format_uptime(secs: u64) -> String {
+    let (d, h, m, s) = (
+        secs / 86_400,
+        secs % 86_400 / 3_600,
+        secs % 3_600 / 60,
+        secs % 60,
+    );
+    if d > 0 {
+        format!("{}d {:02}h {:02}m {:02}s", d, h, m, s)
+    } else if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else {
+        format!("{}m {:02}s", m, s)
+    }
+}
+
+pub fn format_sessions(list: &[SessionSummary]) -> String {
+    if list.is_empty() {
+        return "No live sessions".to_string();
+    }
+    let mut out = format!("{:<20} {:<10} {}", "CHANNEL", "BACKEND", "STATE");
+    for s in list {
+        let state = match s.render_message_id {
+            Some(msg_id) => format!("rendering (message {})", msg_id),
+            None => "idle".to_string(),
+        };
+        out.push_str(&format!(
+            "\n{:<20} {:<10} {}",
+            s.channel_id, s.agent_type, state
+        ));
+    }
+    out
+}
+
+pub fn format_backends(list: &[BackendStatus]) -> String {
+    if list.is_empty() {
+        return "No managed backend processes".to_string();
+    }
+    let mut out = format!("{:<10} {:<6} {:<8} {}", "BACKEND", "PORT", "PID", "STATE");
+    for b in list {
+        let pid = b.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".into());
+        let state = if b.alive { "alive" } else { "exited" };
+        out.push_str(&format!(
+            "\n{:<10} {:<6} {:<8} {}",
+            b.name, b.port, pid, state
+        ));
+    }
+    out
+}
+
+pub fn format_cron_jobs(list: &[CronJobInfo]) -> String {
+    if list.is_empty() {
+        return "No cron jobs".to_string();
+    }
+    let mut out = format!(
+        "{:<36} {:<20} {:<16} {}",
+        "ID", "CHANNEL", "CRON", "DESCRIPTION"
+    );
+    for j in list {
+        out.push_str(&format!(
+            "\n{:<36} {:<20} {:<16} {}",
+            j.id, j.channel_id, j.cron_expr, j.description
+        ));
+    }
+    out
+}
+
+/// 重新讀取 config.toml、state.json、prompts 與語系，不需重啟 daemon
+async fn reload(state: &AppState, http: &serenity::http::Http) -> anyhow::Result<ControlResponse> {
+    let new_config = Config::load().await?;
+    // 手動編輯或 CLI 改過的 state.json 重新載入頻道、授權與預算解除快取；prompts 本來就是每次使用時讀取
+    state.channels.reload().await?;
+    state.auth.reload().await?;
+    state.budget.reload().await?;
+    state.roles.reload().await?;
+    let channel_config = state.channels.snapshot();
+    let prompts = crate::load_all_prompts();
+
+    let (language_changed, token_changed) = {
+        let mut config = state.config.write().await;
+        let language_changed = config.language != new_config.language;
+        let token_changed = config.discord_token != new_config.discord_token;
+        *config = new_config.clone();
+        (language_changed, token_changed)
+    };
+
+    {
+        let mut i18n = state.i18n.write().await;
+        *i18n = I18n::new(&new_config.language);
+    }
+    crate::apply_runtime_config(&new_config);
+
+    if language_changed {
+        let i18n = state.i18n.read().await;
+        if let Err(e) = crate::commands::register_global_commands(http, &i18n).await {
+            error!("❌ Failed to re-register commands after reload: {}", e);
+        }
+    }
+
+    let mut message = format!(
+        "Reloaded config (language={}, {} channel(s), {} bytes of prompts)",
+        new_config.language,
+        channel_config.channels.len(),
+        prompts.len()
+    );
+    if token_changed {
+        warn!("⚠️ discord_token changed; it only takes effect after a restart");
+        message.push_str("; discord_token changed and requires a restart");
+    }
+    info!("🔄 {}", message);
+
+    Ok(ControlResponse::ok(message).with_data(json!({
+        "language": new_config.language,
+        "language_changed": language_changed,
+        "channels": channel_config.channels.len(),
+        "token_changed": token_changed,
+        "permission_timeout_secs": crate::agent::permission::broker().timeout().as_secs(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_request_serializes_with_cmd_tag() {
+        let s = serde_json::to_string(&ControlRequest::Reload).expect("serialize");
+        assert_eq!(s, r#"{"cmd":"reload"}"#);
+        let parsed: ControlRequest = serde_json::from_str(r#"{"cmd":"reload"}"#).expect("parse");
+        assert_eq!(parsed, ControlRequest::Reload);
+    }
+
+    #[test]
+    fn test_channel_requests_carry_channel_id() {
+        let s =
+            serde_json::to_string(&ControlRequest::Abort { channel_id: 42 }).expect("serialize");
+        assert_eq!(s, r#"{"cmd":"abort","channel_id":42}"#);
+        let parsed: ControlRequest = serde_json::from_str(r#"{"cmd":"cron_list"}"#).expect("parse");
+        assert_eq!(parsed, ControlRequest::CronList);
+    }
+
+    #[test]
+    fn test_format_uptime_units() {
+        assert_eq!(format_uptime(5), "0m 05s");
+        assert_eq!(format_uptime(3_661), "1h 01m 01s");
+        assert_eq!(format_uptime(90_061), "1d 01h 01m 01s");
+    }
+
+    #[test]
+    fn test_format_sessions_marks_rendering_channels() {
+        assert_eq!(format_sessions(&[]), "No live sessions");
+        let out = format_sessions(&[
+            SessionSummary {
+                channel_id: 1,
+                agent_type: "kilo".into(),
+                render_message_id: Some(99),
+            },
+            SessionSummary {
+                channel_id: 2,
+                agent_type: "pi".into(),
+                render_message_id: None,
+            },
+        ]);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("rendering (message 99)"));
+        assert!(lines[2].ends_with("idle"));
+    }
+
+    #[test]
+    fn test_format_backends_shows_exited_process() {
+        let out = format_backends(&[BackendStatus {
+            name: "opencode".into(),
+            port: 4096,
+            pid: None,
+            alive: false,
+        }]);
+        assert!(out.contains("opencode"));
+        assert!(out.contains("exited"));
+    }
+
+    #[test]
+    fn test_response_omits_null_data() {
+        let s = serde_json::to_string(&ControlResponse::ok("done")).expect("serialize");
+        assert_eq!(s, r#"{"ok":true,"message":"done"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_over_socket() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("control.sock");
+        let listener = bind(&path).await?;
+        tokio::spawn(serve(listener, |req| async move {
+            ControlResponse::ok(format!("{:?}", req)).with_data(json!({"n": 1}))
+        }));
+
+        let resp = send_request(&path, &ControlRequest::Reload).await?;
+        assert!(resp.ok);
+        assert_eq!(resp.message, "Reload");
+        assert_eq!(resp.data["n"], 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_gets_error_response() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("control.sock");
+        let listener = bind(&path).await?;
+        tokio::spawn(serve(listener, |_| async {
+            ControlResponse::ok("unreachable")
+        }));
+
+        let stream = UnixStream::connect(&path).await?;
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(b"{\"cmd\":\"nope\"}\n").await?;
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        let resp: ControlResponse = serde_json::from_str(line.trim())?;
+        assert!(!resp.ok);
+        assert!(resp.message.contains("Invalid request"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket_but_rejects_live_one() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("control.sock");
+        std::fs::write(&path, b"stale")?;
+        let listener = bind(&path).await?;
+        assert!(bind(&path).await.is_err());
+        drop(listener);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bind_keeps_socket_in_private_directory() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir()?;
+        let run_dir = dir.path().join("run");
+        std::fs::create_dir(&run_dir)?;
+        std::fs::set_permissions(&run_dir, std::fs::Permissions::from_mode(0o755))?;
+        let path = run_dir.join("control.sock");
+        let _listener = bind(&path).await?;
+        let mode = |p: &Path| std::fs::metadata(p).map(|m| m.permissions().mode() & 0o777);
+        assert_eq!(mode(&run_dir)?, 0o700);
+        assert_eq!(mode(&path)?, 0o600);
+
+        let fresh = dir.path().join("fresh").join("control.sock");
+        let _fresh = bind(&fresh).await?;
+        assert_eq!(mode(fresh.parent().unwrap())?, 0o700);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_request_reports_missing_daemon() {
+        let dir = tempdir().expect("tempdir");
+        let err = send_request(&dir.path().join("none.sock"), &ControlRequest::Reload)
+            .await
+            .expect_err("should fail");
+        assert!(err.to_string().contains("Cannot reach the running daemon"));
+    }
+}
@@ -7,6 +7,7 @@ use tokio_cron_scheduler::{Job, JobScheduler};
 use tracing::{error, info};
 use uuid::Uuid;
 
+use crate::state::StateStore;
 use crate::AppState;
 use std::sync::Weak;
 
@@ -25,7 +26,7 @@ pub struct CronJobInfo {
 pub struct CronManager {
     scheduler: JobScheduler,
     jobs: Arc<Mutex<HashMap<Uuid, CronJobInfo>>>,
-    config_dir: PathBuf,
+    store: StateStore,
     http: Arc<Mutex<Option<Arc<serenity::all::Http>>>>,
     state: Arc<Mutex<Option<Weak<AppState>>>>,
 }
@@ -40,12 +41,10 @@ impl CronManager {
         let scheduler = JobScheduler::new().await?;
         scheduler.start().await?;
 
-        let _ = std::fs::create_dir_all(&config_dir);
-
         Ok(Self {
             scheduler,
             jobs: Arc::new(Mutex::new(HashMap::new())),
-            config_dir,
+            store: StateStore::with_path(config_dir.join("state.json")),
             http: Arc::new(Mutex::new(None)),
             state: Arc::new(Mutex::new(None)),
         })
@@ -111,6 +110,7 @@ impl CronManager {
         let cron_expr = info.cron_expr.clone();
         let prompt = info.prompt.clone();
         let channel_id_u64 = info.channel_id;
+        let creator_id = info.creator_id;
 
         let http_ptr = self.http.clone();
         let state_ptr = self.state.clone();
@@ -127,36 +127,62 @@ impl CronManager {
                 if let (Some(http), Some(state_weak)) = (http_opt.as_ref(), state_weak_opt.as_ref())
                 {
                     if let Some(state) = state_weak.upgrade() {
+                        if state.shutting_down.load(std::sync::atomic::Ordering::SeqCst) {
+                            info!("⏰ Cron job for {} skipped: shutting down", channel_id_u64);
+                            crate::metrics::get().cron_run("skipped");
+                            return;
+                        }
                         let channel_id = serenity::model::id::ChannelId::from(channel_id_u64);
-                        let channel_id_str = channel_id.to_string();
-
-                        let channel_config = crate::commands::agent::ChannelConfig::load()
-                            .await
-                            .unwrap_or_default();
-                        let agent_type = channel_config.get_agent_type(&channel_id_str);
-
-                        match state
-                            .session_manager
-                            .get_or_create_session(
-                                channel_id_u64,
-                                agent_type,
-                                &state.backend_manager,
-                            )
-                            .await
+                        // 和一般訊息走同一個閘門：先檢查預算，頻道忙碌時依佇列策略排隊或搶佔
+                        let input = crate::agent::UserInput {
+                            user_id: Some(creator_id),
+                            ..crate::agent::UserInput::new_text(prompt)
+                        };
+                        let (turn, input) = match crate::Handler::gate_input(
+                            &state, http, channel_id, input, None,
+                        )
+                        .await
                         {
-                            Ok((agent, is_new)) => {
-                                crate::Handler::start_agent_loop(
-                                    agent,
-                                    http.clone(),
-                                    channel_id,
-                                    (*state).clone(),
-                                    Some(crate::agent::UserInput::new_text(prompt)),
-                                    is_new,
-                                )
-                                .await;
+                            crate::Gate::Start {
+                                turn,
+                                input,
+                                notice,
+                            } => {
+                                if let Some(notice) = notice {
+                                    let _ = channel_id.say(http, notice).await;
+                                }
+                                (turn, input)
+                            }
+                            crate::Gate::Queued(depth) => {
+                                info!(
+                                    "⏰ Cron job for {} queued behind a running reply (depth {})",
+                                    channel_id_u64, depth
+                                );
+                                crate::metrics::get().cron_run("queued");
+                                return;
+                            }
+                            crate::Gate::Refused(notice) => {
+                                if let Some(notice) = notice {
+                                    let _ = channel_id.say(http, notice).await;
+                                }
+                                info!("⏰ Cron job for {} skipped: budget reached", channel_id_u64);
+                                crate::metrics::get().cron_run("skipped");
+                                return;
                             }
+                        };
+                        match crate::Handler::run_turn(
+                            (*state).clone(),
+                            http.clone(),
+                            channel_id,
+                            turn,
+                            input,
+                        )
+                        .await
+                        {
+                            Ok(()) => crate::metrics::get().cron_run("started"),
                             Err(e) => {
-                                error!("❌ Cron job execution failed to create session: {}", e)
+                                error!("❌ Cron job execution failed to create session: {}", e);
+                                crate::metrics::get().cron_run("failed");
                             }
                         }
                     } else {
@@ -173,21 +199,25 @@ impl CronManager {
     }
 
     async fn save_to_disk(&self) -> anyhow::Result<()> {
+        // 寫入期間持有 jobs 鎖，較舊的快照不會蓋掉較新的
         let jobs = self.jobs.lock().await;
-        let data = serde_json::to_string_pretty(&*jobs)?;
-        let path = self.config_dir.join("cron_jobs.json");
-        tokio::fs::write(path, data).await?;
-        Ok(())
+        let snapshot = jobs.clone();
+        self.store
+            .update_async(move |state| {
+                state.cron_jobs = snapshot;
+                Ok(())
+            })
+            .await
     }
 
-    pub async fn load_from_disk(&self) -> anyhow::Result<()> {
-        let path = self.config_dir.join("cron_jobs.json");
-        if !path.exists() {
-            return Ok(());
-        }
+    /// 關機：停止排程器，不再觸發新任務，並把任務清單寫回磁碟
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        self.scheduler.clone().shutdown().await?;
+        self.save_to_disk().await
+    }
 
-        let data = tokio::fs::read_to_string(path).await?;
-        let loaded_jobs: HashMap<Uuid, CronJobInfo> = serde_json::from_str(&data)?;
+    pub async fn load_from_disk(&self) -> anyhow::Result<()> {
+        let loaded_jobs = self.store.read()?.cron_jobs;
 
         let mut jobs = self.jobs.lock().await;
         *jobs = loaded_jobs;
@@ -204,6 +234,18 @@ impl CronManager {
             .collect()
     }
 
+    /// 列出所有頻道的排程，依頻道與描述排序
+    pub async fn list_jobs(&self) -> Vec<CronJobInfo> {
+        let jobs = self.jobs.lock().await;
+        let mut list: Vec<CronJobInfo> = jobs.values().cloned().collect();
+        list.sort_by(|a, b| {
+            a.channel_id
+                .cmp(&b.channel_id)
+                .then_with(|| a.description.cmp(&b.description))
+        });
+        list
+    }
+
     pub async fn remove_job(&self, id: Uuid) -> anyhow::Result<()> {
         let removed_scheduler_id = {
             let mut jobs = self.jobs.lock().await;
@@ -256,7 +298,7 @@ mod tests {
         manager.add_job(info).await?;
 
         // Check if file exists
-        let path = dir.path().join("cron_jobs.json");
+        let path = dir.path().join("state.json");
         assert!(path.exists());
 
         // Create a new manager instance to load
@@ -272,6 +314,22 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_shutdown_persists_jobs() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let manager = new_test_manager(&dir).await?;
+        let job_id = Uuid::new_v4();
+        manager.add_job(build_job(job_id, 4242, "Nightly")).await?;
+        std::fs::remove_file(dir.path().join("state.json"))?;
+
+        manager.shutdown().await?;
+
+        let manager2 = new_test_manager(&dir).await?;
+        manager2.load_from_disk().await?;
+        assert!(manager2.jobs.lock().await.contains_key(&job_id));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_remove_job_updates_memory_and_disk() -> anyhow::Result<()> {
         let dir = tempdir()?;
@@ -319,4 +377,18 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_list_jobs_returns_all_channels_sorted() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let manager = new_test_manager(&dir).await?;
+        manager.add_job(build_job(Uuid::new_v4(), 2, "B")).await?;
+        manager.add_job(build_job(Uuid::new_v4(), 1, "A")).await?;
+
+        let jobs = manager.list_jobs().await;
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].channel_id, 1);
+        assert_eq!(jobs[1].channel_id, 2);
+        Ok(())
+    }
 }
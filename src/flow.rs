@@ -1,5 +1,6 @@
 use crate::commands::agent::ChannelConfig;
 use crate::i18n::I18n;
+use crate::roles::Role;
 use crate::ExecStatus;
 use serenity::all::MessageType;
 use std::path::PathBuf;
@@ -17,6 +18,9 @@ pub enum ComponentRoute {
     Agent,
     CronDelete,
     ModelSelect,
+    Permission,
+    QueueCancel,
+    Transcript,
     Ignore,
 }
 
@@ -69,11 +73,37 @@ pub fn route_component(custom_id: &str) -> ComponentRoute {
         ComponentRoute::CronDelete
     } else if custom_id.starts_with("model_select") {
         ComponentRoute::ModelSelect
+    } else if custom_id.starts_with("perm:") {
+        ComponentRoute::Permission
+    } else if custom_id == crate::queue::CANCEL_BUTTON_ID {
+        ComponentRoute::QueueCancel
+    } else if custom_id.starts_with(crate::transcript::BUTTON_PREFIX) {
+        ComponentRoute::Transcript
     } else {
         ComponentRoute::Ignore
     }
 }
 
+/// 元件互動需要的最低角色（切換後端、刪除排程、改頻道設定、核准工具權限屬於 operator）
+pub fn required_role_for_component(route: ComponentRoute) -> Role {
+    match route {
+        ComponentRoute::Config
+        | ComponentRoute::Agent
+        | ComponentRoute::CronDelete
+        | ComponentRoute::Permission => Role::Operator,
+        ComponentRoute::ModelSelect | ComponentRoute::QueueCancel => Role::User,
+        ComponentRoute::Transcript => Role::ReadOnly,
+        ComponentRoute::Ignore => Role::ReadOnly,
+    }
+}
+
+pub fn required_role_for_modal(route: ModalRoute) -> Role {
+    match route {
+        ModalRoute::CronSetup | ModalRoute::ConfigAssistant => Role::Operator,
+        ModalRoute::Ignore => Role::ReadOnly,
+    }
+}
+
 pub fn build_render_view(
     i18n: &I18n,
     status: &ExecStatus,
@@ -167,6 +197,7 @@ mod tests {
                 model_provider: None,
                 model_id: None,
                 assistant_name: Some("MyAgent".to_string()),
+                ..Default::default()
             },
         );
 
@@ -207,9 +238,36 @@ mod tests {
         assert_eq!(route_component("agent_confirm:kilo"), ComponentRoute::Agent);
         assert_eq!(route_component("cron_delete_select"), ComponentRoute::CronDelete);
         assert_eq!(route_component("model_select_0"), ComponentRoute::ModelSelect);
+        assert_eq!(route_component("perm:abc:deny"), ComponentRoute::Permission);
+        assert_eq!(route_component("queue_cancel"), ComponentRoute::QueueCancel);
+        assert_eq!(route_component("transcript:42"), ComponentRoute::Transcript);
         assert_eq!(route_component("x"), ComponentRoute::Ignore);
     }
 
+    #[test]
+    fn test_component_and_modal_required_roles() {
+        assert_eq!(
+            required_role_for_component(route_component("agent_confirm:kilo")),
+            Role::Operator
+        );
+        assert_eq!(
+            required_role_for_component(route_component("cron_delete_select")),
+            Role::Operator
+        );
+        assert_eq!(
+            required_role_for_component(route_component("model_select_0")),
+            Role::User
+        );
+        assert_eq!(
+            required_role_for_component(route_component("perm:abc:allow_once")),
+            Role::Operator
+        );
+        assert_eq!(
+            required_role_for_modal(route_modal("cron_setup")),
+            Role::Operator
+        );
+    }
+
     #[test]
     fn test_build_render_view_uses_i18n_values() {
         let i18n = I18n::new("en");
@@ -0,0 +1,241 @@
+use crate::agent::AgentType;
+use crate::commands::agent::ChannelEntry;
+use crate::config::Config;
+use crate::state::StateStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Http, MessageId};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// 進行中的回覆；bot 重啟後依此接回後端 session，補完原本的訊息
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InflightTurn {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub agent_type: AgentType,
+    /// 回合開始時頻道綁定的後端 session；重啟後不同代表已被 /clear 或切換過
+    pub session_id: Option<String>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// state.json 中進行中的回合，每個頻道一筆
+pub struct InflightStore {
+    store: StateStore,
+}
+
+impl InflightStore {
+    pub fn new() -> Self {
+        Self::with_store(StateStore::open())
+    }
+
+    pub fn with_store(store: StateStore) -> Self {
+        Self { store }
+    }
+
+    pub async fn load(&self) -> Result<BTreeMap<u64, InflightTurn>> {
+        let store = self.store.clone();
+        Ok(tokio::task::spawn_blocking(move || store.read())
+            .await??
+            .inflight)
+    }
+
+    /// 同一頻道只會有一個進行中的回合，新的直接取代（例如搶佔）
+    pub async fn record(&self, turn: InflightTurn) -> Result<()> {
+        self.store
+            .update_async(move |state| {
+                state.inflight.insert(turn.channel_id, turn);
+                Ok(())
+            })
+            .await
+    }
+
+    /// 回合結束；頻道已被更新的回合取代時不動
+    pub async fn finish(&self, channel_id: u64, message_id: u64) -> Result<()> {
+        self.store
+            .update_async(move |state| {
+                if state.inflight.get(&channel_id).map(|t| t.message_id) == Some(message_id) {
+                    state.inflight.remove(&channel_id);
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// 啟動時取出上次留下的回合，依頻道排序
+    pub async fn take_all(&self) -> Result<Vec<InflightTurn>> {
+        self.store
+            .update_async(|state| Ok(std::mem::take(&mut state.inflight).into_values().collect()))
+            .await
+    }
+}
+
+/// 後端是否活得比 bot 久：只有外部 OpenCode/Kilo server 會在 bot 重啟期間繼續產生
+pub fn survives_restart(config: &Config, agent_type: &AgentType, server: Option<&str>) -> bool {
+    config
+        .server_config(agent_type)
+        .and_then(|c| c.external(server).ok().flatten())
+        .is_some()
+}
+
+/// 頻道仍綁著同一個後端 session，且該後端沒有隨 bot 一起結束
+pub fn is_resumable(config: &Config, turn: &InflightTurn, entry: Option<&ChannelEntry>) -> bool {
+    let Some(entry) = entry else {
+        return false;
+    };
+    turn.session_id.is_some()
+        && entry.session_id == turn.session_id
+        && entry.agent_type == turn.agent_type
+        && survives_restart(config, &turn.agent_type, entry.server.as_deref())
+}
+
+/// 啟動時處理上次留下的回合：能接回的繼續更新原訊息，其餘標成中斷
+pub async fn resume_all(state: Arc<crate::AppState>, http: Arc<Http>) {
+    let turns = match state.inflight.take_all().await {
+        Ok(turns) => turns,
+        Err(e) => {
+            error!("❌ Failed to read in-flight turns: {}", e);
+            return;
+        }
+    };
+    if turns.is_empty() {
+        return;
+    }
+    info!("🔄 Found {} turn(s) interrupted by restart", turns.len());
+
+    let channel_config = state.channels.snapshot();
+    for turn in turns {
+        let entry = channel_config.channels.get(&turn.channel_id.to_string());
+        let resumable = is_resumable(&*state.config.read().await, &turn, entry);
+        if resumable {
+            match resume(&state, &http, &turn).await {
+                Ok(()) => {
+                    info!("🔄 Resumed turn in channel {}", turn.channel_id);
+                    continue;
+                }
+                Err(e) => warn!(
+                    "⚠️ Cannot resume turn in channel {}: {}",
+                    turn.channel_id, e
+                ),
+            }
+        }
+        let (title, hint) = crate::shutdown::interrupted_texts(&state).await;
+        crate::shutdown::mark_interrupted(
+            &http,
+            ChannelId::new(turn.channel_id),
+            MessageId::new(turn.message_id),
+            &title,
+            &hint,
+        )
+        .await;
+    }
+}
+
+async fn resume(state: &Arc<crate::AppState>, http: &Arc<Http>, turn: &InflightTurn) -> Result<()> {
+    let channel_id = ChannelId::new(turn.channel_id);
+    let msg = channel_id
+        .message(http, MessageId::new(turn.message_id))
+        .await?;
+    let (agent, _) = state
+        .session_manager
+        .get_or_create_session(
+            turn.channel_id,
+            turn.agent_type.clone(),
+            &state.backend_manager,
+        )
+        .await?;
+    if !crate::Handler::reserve_existing(state, turn.channel_id, msg.id).await {
+        anyhow::bail!("channel already started a new turn");
+    }
+    crate::Handler::attach_agent_loop(
+        Arc::clone(&agent),
+        http.clone(),
+        channel_id,
+        (**state).clone(),
+        msg,
+        None,
+        false,
+    )
+    .await;
+    // 失敗時 agent 會送出 AgentEnd，回覆以錯誤狀態收尾
+    if let Err(e) = agent.resume_turn().await {
+        warn!("⚠️ Resume of channel {} failed: {}", turn.channel_id, e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendMode, ExternalServer};
+    use tempfile::tempdir;
+
+    fn turn(channel_id: u64, message_id: u64) -> InflightTurn {
+        InflightTurn {
+            channel_id,
+            message_id,
+            agent_type: AgentType::Opencode,
+            session_id: Some("ses-1".to_string()),
+            started_at: chrono::Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_finish_and_take_all() {
+        let dir = tempdir().expect("tempdir");
+        let store = InflightStore::with_store(StateStore::with_path(dir.path().join("state.json")));
+        store.record(turn(2, 20)).await.expect("record");
+        store.record(turn(1, 10)).await.expect("record");
+        // 搶佔：同頻道的新回合取代舊的，舊回合結束時不該刪掉新的
+        store.record(turn(2, 21)).await.expect("record");
+        store.finish(2, 20).await.expect("finish");
+        assert_eq!(store.load().await.expect("load")[&2].message_id, 21);
+
+        store.finish(1, 10).await.expect("finish");
+        let taken = store.take_all().await.expect("take");
+        assert_eq!(taken.len(), 1);
+        assert_eq!((taken[0].channel_id, taken[0].message_id), (2, 21));
+        assert!(store.load().await.expect("load").is_empty());
+    }
+
+    #[test]
+    fn test_only_external_sessions_are_resumable() {
+        let mut config = Config::default();
+        let t = turn(1, 10);
+        let entry = ChannelEntry {
+            agent_type: AgentType::Opencode,
+            session_id: Some("ses-1".to_string()),
+            ..Default::default()
+        };
+        // 預設 managed：server 隨 bot 結束
+        assert!(!is_resumable(&config, &t, Some(&entry)));
+
+        config.opencode.mode = BackendMode::External;
+        config.opencode.url = Some("http://127.0.0.1:4096".to_string());
+        assert!(is_resumable(&config, &t, Some(&entry)));
+        assert!(!is_resumable(&config, &t, None));
+
+        let cleared = ChannelEntry {
+            session_id: Some("ses-2".to_string()),
+            ..entry.clone()
+        };
+        assert!(!is_resumable(&config, &t, Some(&cleared)));
+
+        // 頻道指定的外部 server 也算
+        config.opencode.mode = BackendMode::Managed;
+        config.opencode.servers.insert(
+            "ci".to_string(),
+            ExternalServer {
+                url: "http://ci:4096".to_string(),
+                password: None,
+            },
+        );
+        let pinned = ChannelEntry {
+            server: Some("ci".to_string()),
+            ..entry
+        };
+        assert!(is_resumable(&config, &t, Some(&pinned)));
+        assert!(!survives_restart(&config, &AgentType::Pi, None));
+    }
+}
@@ -2,43 +2,61 @@ use agent::{AiAgent, UserInput};
 use clap::{Parser, Subcommand};
 use rust_embed::RustEmbed;
 use serenity::all::{
-    Context, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
-    CreateMessage, EditMessage, EventHandler, GatewayIntents, Interaction, Message, Ready,
+    ChannelId, Context, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditMessage, EventHandler, GatewayIntents,
+    Http, Interaction, Message, MessageId, Ready,
 };
 use serenity::async_trait;
 use serenity::Client;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info, Level};
+use tracing::{debug, error, info, warn, Level};
 
 mod cron;
 mod i18n;
+mod inflight;
 
 mod agent;
 mod auth;
+mod budget;
+mod channels;
 mod commands;
 mod composer;
 mod config;
+mod control;
 mod flow;
+mod metrics;
 mod migrate;
+mod queue;
+mod roles;
 mod session;
+mod shutdown;
+mod state;
+mod supervisor;
+mod threads;
+mod transcript;
 mod uploads;
+mod usage;
 mod writer_logic;
 
 use auth::AuthManager;
-use commands::agent::{handle_button, ChannelConfig};
-use composer::EmbedComposer;
+use commands::agent::{handle_button, QueuePolicy};
+use composer::{split_for_discord, EmbedComposer, EMBED_PAGE_CHARS};
 use config::Config;
 use cron::CronManager;
 use flow::{
     build_render_view, build_systemd_service_content, detect_timezone, get_systemd_service_path,
-    resolve_channel_assistant_name, route_component, route_modal, should_process_message,
-    ComponentRoute, ModalRoute,
+    required_role_for_component, required_role_for_modal, resolve_channel_assistant_name,
+    route_component, route_modal, should_process_message, ComponentRoute, ModalRoute,
 };
 use i18n::I18n;
+use queue::InputQueue;
+use roles::{Role, RoleManager, RoleTarget};
 use session::SessionManager;
+use threads::ThreadTracker;
 use uploads::UploadManager;
 use writer_logic::apply_agent_event;
 
@@ -56,13 +74,64 @@ enum Commands {
         #[command(subcommand)]
         action: DaemonAction,
     },
+    /// 通知執行中的 daemon 重新讀取設定、頻道設定、prompts 與語系
     Reload,
+    /// 兌換 bot 發出的授權 token
     Auth {
         token: String,
     },
+    /// 顯示執行中 daemon 的整體狀態
+    Status,
+    /// 列出記憶體中的頻道 session
+    Sessions,
+    /// 中斷指定頻道目前的回覆
+    Abort {
+        channel: u64,
+    },
+    /// 清除指定頻道的對話
+    Clear {
+        channel: u64,
+    },
+    /// 列出由 bot 管理的後端進程
+    Backends,
+    Cron {
+        #[command(subcommand)]
+        action: CronAction,
+    },
+    /// 管理 state.json 中的角色授予
+    Role {
+        #[command(subcommand)]
+        action: RoleAction,
+    },
     Version,
 }
 
+#[derive(Subcommand)]
+enum RoleAction {
+    /// 授予角色 (admin, operator, user, read_only)
+    Grant {
+        /// Discord user ID（搭配 --guild-role 時為 Discord 身分組 ID）
+        target: String,
+        role: Role,
+        #[arg(long)]
+        guild_role: bool,
+    },
+    /// 撤銷明確授予的角色
+    Revoke {
+        target: String,
+        #[arg(long)]
+        guild_role: bool,
+    },
+    /// 列出所有角色授予
+    List,
+}
+
+#[derive(Subcommand)]
+enum CronAction {
+    /// 列出所有頻道的排程
+    List,
+}
+
 #[derive(Subcommand)]
 enum DaemonAction {
     Enable,
@@ -73,18 +142,64 @@ enum DaemonAction {
 #[folder = "prompts/"]
 struct DefaultPrompts;
 
-type ActiveRenderMap = HashMap<u64, (serenity::model::id::MessageId, Vec<JoinHandle<()>>)>;
+/// 頻道上進行中的回覆。開始前就先保留位置，此時還沒有訊息；
+/// `turn` 區分先後保留的位置，被搶佔或中止後晚到的任務據此知道自己已失效
+pub struct ActiveRender {
+    pub turn: u64,
+    pub message_id: Option<MessageId>,
+    pub handles: Vec<JoinHandle<()>>,
+}
+
+type ActiveRenderMap = HashMap<u64, ActiveRender>;
+
+/// 輸入經過頻道閘門的結果；notice 為預算警告或拒絕，由呼叫端貼到頻道
+pub enum Gate {
+    /// 已保留位置並通過預算檢查，可以開始回合
+    Start {
+        turn: u64,
+        input: TurnInput,
+        notice: Option<String>,
+    },
+    /// 頻道忙碌，已排入佇列（回傳深度）；輪到時才檢查預算
+    Queued(usize),
+    /// 預算已用完，沒有開始也沒有排隊
+    Refused(Option<String>),
+}
+
+/// 要開始的回合：送出的 prompt 與預算檢查時先佔的額度，額度交給回合的 TurnRecorder
+pub struct TurnInput {
+    pub input: UserInput,
+    pub reservation: Option<usage::Reservation>,
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    pub config: Arc<Config>,
+    pub config: Arc<RwLock<Config>>,
     pub session_manager: Arc<SessionManager>,
     pub auth: Arc<AuthManager>,
+    pub roles: Arc<RoleManager>,
     pub i18n: Arc<RwLock<I18n>>,
     pub backend_manager: Arc<agent::manager::BackendManager>,
     pub cron_manager: Arc<CronManager>,
     pub active_renders: Arc<Mutex<ActiveRenderMap>>,
     pub upload_manager: Arc<UploadManager>,
+    pub input_queue: Arc<InputQueue>,
+    pub threads: Arc<ThreadTracker>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// 收到停止訊號後設為 true，不再接受新的訊息與排程
+    pub shutting_down: Arc<AtomicBool>,
+    pub inflight: Arc<inflight::InflightStore>,
+    /// 頻道設定的記憶體快取，寫入會落到 state.json 並廣播變更
+    pub channels: Arc<channels::ChannelRegistry>,
+    pub usage: Arc<usage::UsageStore>,
+    pub budget: Arc<budget::BudgetGuard>,
+}
+
+/// 套用不必重建 session 就能生效的設定，啟動與 reload 共用
+fn apply_runtime_config(config: &Config) {
+    agent::permission::broker().set_timeout(std::time::Duration::from_secs(
+        config.copilot.permission_timeout_secs,
+    ));
 }
 
 fn load_all_prompts() -> String {
@@ -128,36 +243,179 @@ pub enum ExecStatus {
 }
 
 impl Handler {
-    pub async fn start_agent_loop(
-        agent: Arc<dyn AiAgent>,
-        http: Arc<serenity::http::Http>,
-        channel_id: serenity::model::id::ChannelId,
-        state: AppState,
-        initial_input: Option<UserInput>,
-        is_brand_new: bool,
-    ) {
+    /// 依個人與 guild 身分組解析有效角色
+    fn resolve_role(
+        &self,
+        user_id: serenity::model::id::UserId,
+        guild_id: Option<serenity::model::id::GuildId>,
+        role_ids: &[serenity::model::id::RoleId],
+    ) -> Role {
+        let role_ids: Vec<String> = role_ids.iter().map(|r| r.to_string()).collect();
+        let guild_id = guild_id.map(|g| g.to_string());
+        self.state
+            .roles
+            .resolve(&user_id.to_string(), guild_id.as_deref(), &role_ids)
+    }
+
+    async fn role_denied_response(&self, required: Role, actual: Role) -> CreateInteractionResponse {
+        let msg = {
+            let i18n = self.state.i18n.read().await;
+            i18n.get_args("role_required", &[required.to_string(), actual.to_string()])
+        };
+        CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(msg)
+                .ephemeral(true),
+        )
+    }
+
+    fn next_turn() -> u64 {
+        static NEXT_TURN: AtomicU64 = AtomicU64::new(1);
+        NEXT_TURN.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 搶佔舊回覆：停掉它的任務並刪除訊息
+    fn preempt(http: &Arc<Http>, channel_id: ChannelId, old: ActiveRender) {
+        for h in old.handles {
+            h.abort();
+        }
+        if let Some(old_msg_id) = old.message_id {
+            let http = http.clone();
+            tokio::spawn(async move {
+                if let Err(e) = channel_id.delete_message(&http, old_msg_id).await {
+                    error!("❌ Failed to delete preempted message: {}", e);
+                }
+            });
+        }
+        info!("🗑️ Preempted unfinished response in channel {}", channel_id);
+    }
+
+    /// 訊息、排程與佇列共用的閘門：在 active_renders 鎖內依頻道的佇列策略
+    /// 決定開始（必要時搶佔）或排隊。開始前檢查預算，同時保留位置，之後到達的輸入一定看得到它
+    pub async fn gate_input(
+        state: &AppState,
+        http: &Arc<Http>,
+        channel_id: ChannelId,
+        input: UserInput,
+        source: Option<MessageId>,
+    ) -> Gate {
         let channel_id_u64 = channel_id.get();
+        let policy = state
+            .channels
+            .snapshot()
+            .get_queue_policy(&channel_id.to_string());
+        let mut active = state.active_renders.lock().await;
+        if policy != QueuePolicy::Preempt
+            && (active.contains_key(&channel_id_u64)
+                || state.input_queue.depth(channel_id_u64) > 0)
+        {
+            // 頻道剛閒下來但佇列還沒送完時也排在後面，維持先後順序
+            let depth = state.input_queue.push(channel_id_u64, input, source);
+            if !active.contains_key(&channel_id_u64) {
+                state.input_queue.notify_idle(channel_id_u64);
+            }
+            return Gate::Queued(depth);
+        }
+        // 先檢查預算再搶佔，被拒絕的 prompt 不會打斷進行中的回覆
+        let admission = budget::admit(state, channel_id_u64, input.user_id).await;
+        if !admission.allowed {
+            return Gate::Refused(admission.notice);
+        }
+        if policy == QueuePolicy::Preempt {
+            state.input_queue.clear(channel_id_u64);
+            if let Some(old) = active.remove(&channel_id_u64) {
+                Self::preempt(http, channel_id, old);
+            }
+        }
+        let turn = Self::next_turn();
+        active.insert(
+            channel_id_u64,
+            ActiveRender {
+                turn,
+                message_id: None,
+                handles: Vec::new(),
+            },
+        );
+        Gate::Start {
+            turn,
+            input: TurnInput {
+                input,
+                reservation: admission.reservation,
+            },
+            notice: admission.notice,
+        }
+    }
+
+    /// 重啟後接回的回合直接佔用位置；頻道已有回合時回傳 false
+    pub async fn reserve_existing(
+        state: &AppState,
+        channel_id_u64: u64,
+        message_id: MessageId,
+    ) -> bool {
+        let mut active = state.active_renders.lock().await;
+        if active.contains_key(&channel_id_u64) {
+            return false;
+        }
+        active.insert(
+            channel_id_u64,
+            ActiveRender {
+                turn: Self::next_turn(),
+                message_id: Some(message_id),
+                handles: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// 回合沒能開始（session 或訊息建立失敗）：釋放自己的位置並讓佇列繼續
+    pub async fn release_turn(state: &AppState, channel_id_u64: u64, turn: u64) {
+        let mut active = state.active_renders.lock().await;
+        if active.get(&channel_id_u64).is_some_and(|r| r.turn == turn) {
+            active.remove(&channel_id_u64);
+            drop(active);
+            state.input_queue.notify_idle(channel_id_u64);
+        }
+    }
 
-        // 1. [搶佔邏輯]: 如果該頻道有正在運行的任務，立即中斷並刪除該訊息
+    /// 在閘門保留的位置上建立 session 並開始回合；失敗時釋放位置並回傳錯誤
+    pub async fn run_turn(
+        state: AppState,
+        http: Arc<Http>,
+        channel_id: ChannelId,
+        turn: u64,
+        input: TurnInput,
+    ) -> anyhow::Result<()> {
+        let channel_id_u64 = channel_id.get();
+        let agent_type = state
+            .channels
+            .snapshot()
+            .get_agent_type(&channel_id.to_string());
+        match state
+            .session_manager
+            .get_or_create_session(channel_id_u64, agent_type, &state.backend_manager)
+            .await
         {
-            let mut active = state.active_renders.lock().await;
-            if let Some((old_msg_id, handles)) = active.remove(&channel_id_u64) {
-                for h in handles {
-                    h.abort();
-                }
-                let http_del = http.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = channel_id.delete_message(&http_del, old_msg_id).await {
-                        error!("❌ Failed to delete preempted message: {}", e);
-                    }
-                });
-                info!(
-                    "🗑️ Preempted unfinished response in channel {}",
-                    channel_id_u64
-                );
+            Ok((agent, is_new)) => {
+                Self::start_agent_loop(agent, http, channel_id, state, turn, input, is_new).await;
+                Ok(())
+            }
+            Err(e) => {
+                Self::release_turn(&state, channel_id_u64, turn).await;
+                Err(e)
             }
         }
+    }
 
+    async fn start_agent_loop(
+        agent: Arc<dyn AiAgent>,
+        http: Arc<Http>,
+        channel_id: ChannelId,
+        state: AppState,
+        turn: u64,
+        input: TurnInput,
+        is_brand_new: bool,
+    ) {
+        let channel_id_u64 = channel_id.get();
         let i18n = state.i18n.read().await;
         let processing_msg = i18n.get("processing");
         drop(i18n);
@@ -173,33 +431,110 @@ impl Handler {
             Ok(m) => m,
             Err(e) => {
                 error!("Failed to send: {}", e);
+                Self::release_turn(&state, channel_id_u64, turn).await;
                 return;
             }
         };
 
-        let composer: Arc<Mutex<EmbedComposer>> = Arc::new(Mutex::new(EmbedComposer::new(3900)));
-        let status: Arc<Mutex<ExecStatus>> = Arc::new(Mutex::new(ExecStatus::Running));
-        let assistant_name = {
-            let channel_cfg = ChannelConfig::load().await.unwrap_or_default();
-            resolve_channel_assistant_name(
-                &channel_cfg,
-                &channel_id.to_string(),
-                &state.config.assistant_name,
-            )
+        // 準備期間被搶佔或中止：位置已不是自己的，收回訊息不送出 prompt
+        let claimed = {
+            let mut active = state.active_renders.lock().await;
+            match active.get_mut(&channel_id_u64) {
+                Some(render) if render.turn == turn => {
+                    render.message_id = Some(discord_msg.id);
+                    true
+                }
+                _ => false,
+            }
         };
+        if !claimed {
+            info!(
+                "🗑️ Turn in channel {} was superseded before it started",
+                channel_id_u64
+            );
+            let _ = discord_msg.delete(&http).await;
+            return;
+        }
+
+        Self::attach_agent_loop(
+            agent,
+            http,
+            channel_id,
+            state,
+            discord_msg,
+            Some(input),
+            is_brand_new,
+        )
+        .await;
+    }
+
+    /// 以既有的訊息呈現回覆；重啟後接回的回合沿用原本的訊息
+    pub async fn attach_agent_loop(
+        agent: Arc<dyn AiAgent>,
+        http: Arc<serenity::http::Http>,
+        channel_id: serenity::model::id::ChannelId,
+        state: AppState,
+        discord_msg: Message,
+        initial: Option<TurnInput>,
+        is_brand_new: bool,
+    ) {
+        let channel_id_u64 = channel_id.get();
+        let composer: Arc<Mutex<EmbedComposer>> =
+            Arc::new(Mutex::new(EmbedComposer::new(EMBED_PAGE_CHARS)));
+        let status: Arc<Mutex<ExecStatus>> = Arc::new(Mutex::new(ExecStatus::Running));
+        let transcript: Arc<Mutex<transcript::Transcript>> =
+            Arc::new(Mutex::new(Default::default()));
+        let channel_cfg = state.channels.snapshot();
+        let assistant_name = resolve_channel_assistant_name(
+            &channel_cfg,
+            &channel_id.to_string(),
+            &state.config.read().await.assistant_name,
+        );
+        // 記下進行中的回合，bot 中途重啟時可以接回或標成中斷
+        if let Ok(agent_type) = agent.agent_type().parse() {
+            let turn = inflight::InflightTurn {
+                channel_id: channel_id_u64,
+                message_id: discord_msg.id.get(),
+                agent_type,
+                session_id: channel_cfg
+                    .channels
+                    .get(&channel_id.to_string())
+                    .and_then(|e| e.session_id.clone()),
+                started_at: chrono::Utc::now(),
+            };
+            if let Err(e) = state.inflight.record(turn).await {
+                error!("❌ Failed to record in-flight turn: {}", e);
+            }
+        }
+
+        // agent 在本回合寫進 outbox 的檔案，結束時附到回覆訊息上
+        let outbox_dir = state.upload_manager.prepare_outbox(channel_id_u64).await;
+        let turn_started = std::time::SystemTime::now();
+        let turn_clock = std::time::Instant::now();
+        let backend = agent.agent_type();
 
         // --- 任務啟動：收集所有 Handles ---
         let mut handles = Vec::new();
+        let (initial_input, reservation) = match initial {
+            Some(TurnInput { input, reservation }) => (Some(input), reservation),
+            None => (None, None),
+        };
+        let turn_user = initial_input.as_ref().and_then(|i| i.user_id);
 
         if let Some(mut input) = initial_input {
             let mut final_msg = input.text;
             if is_brand_new {
                 let prompts = load_all_prompts();
-                if !prompts.is_empty() {
-                    final_msg = format!("{}\n\n{}", prompts, final_msg);
-                }
+                let hint = uploads::outbox_prompt_hint(&outbox_dir);
+                let preamble = if prompts.is_empty() {
+                    hint
+                } else {
+                    format!("{}\n\n{}", prompts, hint)
+                };
+                final_msg = format!("{}\n\n{}", preamble, final_msg);
             }
             input.text = final_msg;
+            metrics::get().prompt_sent(&backend);
             let agent_for_prompt = Arc::clone(&agent);
             let status_for_prompt = Arc::clone(&status);
             let composer_for_prompt = Arc::clone(&composer);
@@ -243,30 +578,105 @@ impl Handler {
         let render_assistant_name = assistant_name.clone();
         let render_channel_id = channel_id;
         let render_msg_id = discord_msg.id;
+        let render_transcript = Arc::clone(&transcript);
 
         let render_task = tokio::spawn(async move {
             let mut last_content = String::new();
             let mut last_status = ExecStatus::Running;
+            let mut last_depth = 0;
+            let mut final_embed = None;
             loop {
                 tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
 
-                let (current_status, desc) = {
+                let (current_status, desc, more_pages, full_text) = {
                     let c = render_composer.lock().await;
                     let s = render_status.lock().await;
-                    (s.clone(), c.render())
+                    if *s == ExecStatus::Running {
+                        (s.clone(), c.render(), Vec::new(), String::new())
+                    } else {
+                        // 回合結束：改用完整內容，超過單則上限的部分分成多則訊息
+                        let full = c.render_full();
+                        let visible = uploads::strip_attach_markers(&full);
+                        let mut pages = split_for_discord(&visible, EMBED_PAGE_CHARS);
+                        let first = if pages.is_empty() {
+                            String::new()
+                        } else {
+                            pages.remove(0)
+                        };
+                        (s.clone(), first, pages, full)
+                    }
+                };
+                let total_pages = more_pages.len() + 1;
+
+                // 回合結束：先存逐字紀錄，成功才在最後的 embed 附上「📄 Full log」按鈕
+                let transcript_saved = if current_status == ExecStatus::Running {
+                    false
+                } else {
+                    let t = render_transcript.lock().await;
+                    if t.is_empty() {
+                        false
+                    } else {
+                        let content = transcript::format_file(
+                            channel_id_u64,
+                            render_msg_id.get(),
+                            &current_status,
+                            &t.render(),
+                        );
+                        drop(t);
+                        match transcript::save(
+                            &migrate::get_transcripts_dir(),
+                            channel_id_u64,
+                            render_msg_id.get(),
+                            &content,
+                        )
+                        .await
+                        {
+                            Ok(_) => true,
+                            Err(e) => {
+                                error!("❌ Failed to save transcript: {}", e);
+                                false
+                            }
+                        }
+                    }
+                };
+                // 佇列深度只在回覆進行中顯示，結束後交由下一輪的訊息呈現
+                let depth = if current_status == ExecStatus::Running {
+                    render_state.input_queue.depth(channel_id_u64)
+                } else {
+                    0
                 };
 
-                if desc != last_content || current_status != last_status {
+                if desc != last_content || current_status != last_status || depth != last_depth {
                     let i18n = render_i18n.read().await;
                     let (title, color, body) =
                         build_render_view(&i18n, &current_status, &desc, &render_assistant_name);
-                    let embed = CreateEmbed::new().title(title).color(color).description(body);
+                    let mut embed = CreateEmbed::new().title(title).color(color).description(body);
+                    let mut components = Vec::new();
+                    if depth > 0 {
+                        embed = embed.footer(CreateEmbedFooter::new(
+                            i18n.get_args("queue_depth", &[depth.to_string()]),
+                        ));
+                        components.push(queue::build_cancel_row(&i18n));
+                    } else if total_pages > 1 {
+                        embed = embed.footer(CreateEmbedFooter::new(format!("1/{}", total_pages)));
+                    }
+                    if transcript_saved {
+                        components
+                            .push(transcript::build_button_row(&i18n, render_msg_id.get()));
+                    }
+                    if current_status != ExecStatus::Running {
+                        final_embed = Some(embed.clone());
+                    }
 
                     if let Err(e) = render_msg
-                        .edit(&render_http, EditMessage::new().embed(embed))
+                        .edit(
+                            &render_http,
+                            EditMessage::new().embed(embed).components(components),
+                        )
                         .await
                     {
                         error!("❌ Render failed to edit message: {}", e);
+                        metrics::get().discord_edit_failed();
                     } else {
                         info!(
                             "📢 [EMBED-UPDATE-{}]: status={:?}, len={}",
@@ -276,21 +686,61 @@ impl Handler {
                         );
                         last_content = desc;
                         last_status = current_status.clone();
+                        last_depth = depth;
+                    }
+
+                    for (idx, page) in more_pages.into_iter().enumerate() {
+                        let embed = CreateEmbed::new()
+                            .color(color)
+                            .description(page)
+                            .footer(CreateEmbedFooter::new(format!(
+                                "{}/{}",
+                                idx + 2,
+                                total_pages
+                            )));
+                        if let Err(e) = render_channel_id
+                            .send_message(&render_http, CreateMessage::new().embed(embed))
+                            .await
+                        {
+                            error!("❌ Failed to send continuation page: {}", e);
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(embed) = final_embed.take() {
+                    let files = render_state
+                        .upload_manager
+                        .collect_outbox(channel_id_u64, turn_started, &full_text)
+                        .await;
+                    if !files.is_empty() {
+                        uploads::attach_outbound_files(&render_http, &mut render_msg, embed, files)
+                            .await;
                     }
                 }
 
                 if current_status != ExecStatus::Running {
+                    render_state.threads.refresh(channel_id_u64);
                     // 完工：從活躍任務中移除自己
                     let mut active = render_state.active_renders.lock().await;
-                    if let Some((active_msg_id, _)) = active.get(&channel_id_u64) {
-                        if *active_msg_id == render_msg_id {
+                    if let Some(render) = active.get(&channel_id_u64) {
+                        if render.message_id == Some(render_msg_id) {
                             active.remove(&channel_id_u64);
                             info!(
                                 "✅ Completed response registered as historical for channel {}",
                                 channel_id_u64
                             );
+                            render_state.input_queue.notify_idle(channel_id_u64);
                         }
                     }
+                    drop(active);
+                    if let Err(e) = render_state
+                        .inflight
+                        .finish(channel_id_u64, render_msg_id.get())
+                        .await
+                    {
+                        error!("❌ Failed to clear in-flight turn: {}", e);
+                    }
                     break;
                 }
             }
@@ -300,10 +750,64 @@ impl Handler {
         let mut rx = agent.subscribe_events();
         let writer_status = Arc::clone(&status);
         let writer_composer = Arc::clone(&composer);
+        let writer_transcript = Arc::clone(&transcript);
+        let writer_http = http.clone();
+        let writer_i18n = Arc::clone(&state.i18n);
+        let mut turn_recorder = usage::TurnRecorder::new(
+            Arc::clone(&state.usage),
+            channel_id_u64,
+            turn_user,
+            backend.clone(),
+            reservation,
+        );
+        // 回合結束（或任務被搶佔中止）時 drop，期間 session 不會被閒置回收
+        let turn_guard = state.session_manager.begin_turn(channel_id_u64);
         let writer_task = tokio::spawn(async move {
             loop {
-                match rx.recv().await {
-                    Ok(event) => {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        info!("⚠️ Writer lagged by {} messages", n);
+                        metrics::get().writer_lagged(n);
+                        continue;
+                    }
+                    // agent 沒送 AgentEnd 就消失：以錯誤收尾，訊息不會一直停在處理中
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        warn!(
+                            "⚠️ Agent of channel {} closed before the turn ended",
+                            channel_id_u64
+                        );
+                        agent::AgentEvent::AgentEnd {
+                            success: false,
+                            error: Some("Agent event stream closed".to_string()),
+                        }
+                    }
+                };
+                turn_guard.touch();
+                match event {
+                    agent::AgentEvent::Usage { usage } => turn_recorder.add(usage),
+                    agent::AgentEvent::PermissionRequest {
+                        token,
+                        title,
+                        detail,
+                        choices,
+                    } => {
+                        // 獨立任務：不隨本次 render 被搶佔而中斷，逾時仍能更新訊息
+                        tokio::spawn(commands::permission::post_request(
+                            writer_http.clone(),
+                            Arc::clone(&writer_i18n),
+                            channel_id,
+                            token,
+                            title,
+                            detail,
+                            choices,
+                        ));
+                    }
+                    event => {
+                        if let agent::AgentEvent::AgentEnd { success, .. } = &event {
+                            metrics::get().turn_finished(&backend, *success, turn_clock.elapsed());
+                        }
+                        writer_transcript.lock().await.record(&event);
                         let mut comp = writer_composer.lock().await;
                         let mut s = writer_status.lock().await;
                         let finished = apply_agent_event(&mut comp, &mut s, event);
@@ -313,22 +817,26 @@ impl Handler {
                             break;
                         }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        info!("⚠️ Writer lagged by {} messages", n);
-                        continue;
-                    }
-                    Err(_) => break,
                 }
                 tokio::task::yield_now().await;
             }
         });
 
-        // 登記新任務
+        // 登記新任務；準備期間已被搶佔或中止的話，位置屬於別人，停掉自己的任務
         handles.push(render_task);
         handles.push(writer_task);
         {
             let mut active = state.active_renders.lock().await;
-            active.insert(channel_id_u64, (discord_msg.id, handles));
+            match active.get_mut(&channel_id_u64) {
+                Some(render) if render.message_id == Some(discord_msg.id) => {
+                    render.handles = handles;
+                }
+                _ => {
+                    for h in handles {
+                        h.abort();
+                    }
+                }
+            }
         }
     }
 }
@@ -351,13 +859,7 @@ impl EventHandler for Handler {
         }
 
         let i18n = self.state.i18n.read().await;
-        let commands = commands::get_all_commands()
-            .into_iter()
-            .map(|cmd| cmd.create_command(&i18n))
-            .collect::<Vec<_>>();
-        drop(i18n);
-
-        match serenity::all::Command::set_global_commands(&ctx.http, commands).await {
+        match commands::register_global_commands(&ctx.http, &i18n).await {
             Ok(_) => info!("✅ Registered global commands"),
             Err(e) => error!("❌ Failed to register commands: {}", e),
         }
@@ -378,7 +880,30 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn thread_update(
+        &self,
+        _ctx: Context,
+        _old: Option<serenity::model::channel::GuildChannel>,
+        new: serenity::model::channel::GuildChannel,
+    ) {
+        if new.thread_metadata.is_some_and(|m| m.archived) {
+            threads::mark_archived(&self.state, new.id.get()).await;
+        }
+    }
+
+    async fn thread_delete(
+        &self,
+        _ctx: Context,
+        thread: serenity::model::channel::PartialGuildChannel,
+        _full_thread_data: Option<serenity::model::channel::GuildChannel>,
+    ) {
+        threads::mark_archived(&self.state, thread.id.get()).await;
+    }
+
     async fn message(&self, ctx: Context, msg: Message) {
+        if self.state.shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
         let mentioned = msg.mentions_me(&ctx).await.unwrap_or(false);
         if !should_process_message(msg.author.bot, msg.kind, false, mentioned) {
             return;
@@ -397,7 +922,7 @@ impl EventHandler for Handler {
 
         if !is_auth {
             if mentioned {
-                if let Ok(token) = self.state.auth.create_token("channel", &channel_id_str) {
+                if let Ok(token) = self.state.auth.create_token("channel", &channel_id_str).await {
                     let auth_msg = {
                         let i18n = self.state.i18n.read().await;
                         i18n.get_args("auth_required_cmd", &[token])
@@ -410,56 +935,118 @@ impl EventHandler for Handler {
             return;
         }
 
+        let mut channel_config = self.state.channels.snapshot();
+        // thread mode 開出的討論串裡，每則訊息都是對話的一部分，不需要 mention
+        let mention_only = mention_only && !channel_config.is_conversation_thread(&channel_id_str);
         if !should_process_message(false, msg.kind, mention_only, mentioned) {
             return;
         }
 
-        let channel_config = ChannelConfig::load().await.unwrap_or_default();
-        let agent_type = channel_config.get_agent_type(&channel_id_str);
+        let role_ids = msg
+            .member
+            .as_ref()
+            .map(|m| m.roles.clone())
+            .unwrap_or_default();
+        let role = self.resolve_role(msg.author.id, msg.guild_id, &role_ids);
+        if role < Role::User {
+            if mentioned {
+                let denied = {
+                    let i18n = self.state.i18n.read().await;
+                    i18n.get_args("role_required", &[Role::User.to_string(), role.to_string()])
+                };
+                let _ = msg.reply(&ctx.http, denied).await;
+            }
+            return;
+        }
+
+        // thread mode：每個頂層 mention 開一個討論串，之後的對話都在討論串內進行
+        let mut channel_id = msg.channel_id;
+        if channel_config.is_thread_mode(&channel_id_str) {
+            if !mentioned {
+                return;
+            }
+            let name = {
+                let i18n = self.state.i18n.read().await;
+                threads::thread_name(&msg.content, &i18n.get("thread_default_name"))
+            };
+            match threads::open_for_message(&ctx.http, &msg, name, &self.state.channels).await {
+                Ok(thread_id) => {
+                    channel_id = thread_id;
+                    channel_config = self.state.channels.snapshot();
+                }
+                Err(e) => {
+                    error!("❌ Failed to open conversation thread: {}", e);
+                    let failed = {
+                        let i18n = self.state.i18n.read().await;
+                        i18n.get_args("thread_create_failed", &[e.to_string()])
+                    };
+                    let _ = msg.reply(&ctx.http, failed).await;
+                    return;
+                }
+            }
+        }
+        let channel_id_str = channel_id.to_string();
+        if channel_config.is_conversation_thread(&channel_id_str) {
+            threads::mark_active(&self.state, channel_id.get()).await;
+        }
+
         let files = self
             .state
             .upload_manager
-            .stage_attachments(msg.channel_id.get(), &msg.attachments)
+            .stage_attachments(channel_id.get(), &msg.attachments)
             .await;
         let input = UserInput {
             text: msg.content.clone(),
             files,
+            user_id: Some(msg.author.id.get()),
         };
 
         let state = self.state.clone();
+        let (turn, input) =
+            match Handler::gate_input(&state, &ctx.http, channel_id, input, Some(msg.id)).await {
+                Gate::Start {
+                    turn,
+                    input,
+                    notice,
+                } => {
+                    if let Some(notice) = notice {
+                        let _ = msg.reply(&ctx.http, notice).await;
+                    }
+                    (turn, input)
+                }
+                Gate::Queued(depth) => {
+                    info!(
+                        "📥 Queued message for busy channel {} (depth {})",
+                        channel_id, depth
+                    );
+                    let _ = msg.react(&ctx.http, '⏳').await;
+                    return;
+                }
+                Gate::Refused(notice) => {
+                    if let Some(notice) = notice {
+                        let _ = msg.reply(&ctx.http, notice).await;
+                    }
+                    return;
+                }
+            };
+
         tokio::spawn(async move {
-            match state
-                .session_manager
-                .get_or_create_session(msg.channel_id.get(), agent_type, &state.backend_manager)
-                .await
+            if let Err(e) =
+                Handler::run_turn(state.clone(), ctx.http.clone(), channel_id, turn, input).await
             {
-                Ok((agent, is_new)) => {
-                    Handler::start_agent_loop(
-                        agent,
-                        ctx.http.clone(),
-                        msg.channel_id,
-                        state,
-                        Some(input),
-                        is_new,
+                error!("❌ Session error: {}", e);
+                let err_text = e.to_string();
+                let backend = state.channels.snapshot().get_agent_type(&channel_id.to_string());
+                let user_msg = {
+                    let i18n = state.i18n.read().await;
+                    crate::commands::agent::build_backend_error_message(
+                        &i18n,
+                        backend,
+                        &err_text,
+                        state.config.read().await.opencode.port,
                     )
-                    .await;
-                }
-                Err(e) => {
-                    error!("❌ Session error: {}", e);
-                    let err_text = e.to_string();
-                    let channel_config = ChannelConfig::load().await.unwrap_or_default();
-                    let backend = channel_config.get_agent_type(&msg.channel_id.to_string());
-                    let user_msg = {
-                        let i18n = state.i18n.read().await;
-                        crate::commands::agent::build_backend_error_message(
-                            &i18n,
-                            backend,
-                            &err_text,
-                            state.config.opencode.port,
-                        )
-                    };
-                    let _ = msg.reply(&ctx.http, user_msg).await;
-                }
+                };
+                let _ = msg.reply(&ctx.http, user_msg).await;
             }
         });
     }
@@ -493,20 +1080,47 @@ impl EventHandler for Handler {
                 return;
             }
 
-            let cmd_name = command.data.name.clone();
+            let Some(cmd) = commands::get_all_commands()
+                .into_iter()
+                .find(|cmd| cmd.name() == command.data.name)
+            else {
+                return;
+            };
+
+            let role_ids = command
+                .member
+                .as_ref()
+                .map(|m| m.roles.clone())
+                .unwrap_or_default();
+            let role = self.resolve_role(command.user.id, command.guild_id, &role_ids);
+            if role < cmd.required_role() {
+                let denied = self.role_denied_response(cmd.required_role(), role).await;
+                let _ = command.create_response(&ctx.http, denied).await;
+                return;
+            }
+
             let state = self.state.clone();
             let cmd_interaction = command.clone();
             tokio::spawn(async move {
-                for cmd in commands::get_all_commands() {
-                    if cmd.name() == cmd_name {
-                        let _ = cmd.execute(&ctx, &cmd_interaction, &state).await;
-                        break;
-                    }
-                }
+                let _ = cmd.execute(&ctx, &cmd_interaction, &state).await;
             });
         } else if let Interaction::Modal(modal) = interaction {
             let custom_id = modal.data.custom_id.as_str();
-            match route_modal(custom_id) {
+            let route = route_modal(custom_id);
+            let role_ids = modal
+                .member
+                .as_ref()
+                .map(|m| m.roles.clone())
+                .unwrap_or_default();
+            let role = self.resolve_role(modal.user.id, modal.guild_id, &role_ids);
+            if role < required_role_for_modal(route) {
+                let denied = self
+                    .role_denied_response(required_role_for_modal(route), role)
+                    .await;
+                let _ = modal.create_response(&ctx.http, denied).await;
+                return;
+            }
+            match route {
                 ModalRoute::CronSetup => {
                     let state = self.state.clone();
                     tokio::spawn(async move {
@@ -524,7 +1138,21 @@ impl EventHandler for Handler {
             }
         } else if let Interaction::Component(component) = interaction {
             let custom_id = component.data.custom_id.as_str();
-            match route_component(custom_id) {
+            let route = route_component(custom_id);
+            let role_ids = component
+                .member
+                .as_ref()
+                .map(|m| m.roles.clone())
+                .unwrap_or_default();
+            let role = self.resolve_role(component.user.id, component.guild_id, &role_ids);
+            if role < required_role_for_component(route) {
+                let denied = self
+                    .role_denied_response(required_role_for_component(route), role)
+                    .await;
+                let _ = component.create_response(&ctx.http, denied).await;
+                return;
+            }
+            match route {
                 ComponentRoute::Config => {
                     let _ = commands::config::handle_config_select(&ctx, &component, &self.state).await;
                 }
@@ -541,8 +1169,7 @@ impl EventHandler for Handler {
                     let state = self.state.clone();
                     tokio::spawn(async move {
                         let channel_id_str = component.channel_id.to_string();
-                        let channel_config = ChannelConfig::load().await.unwrap_or_default();
-                        let agent_type = channel_config.get_agent_type(&channel_id_str);
+                        let agent_type = state.channels.snapshot().get_agent_type(&channel_id_str);
 
                         if let Ok((agent, _)) = state
                             .session_manager
@@ -559,6 +1186,15 @@ impl EventHandler for Handler {
                         }
                     });
                 }
+                ComponentRoute::Transcript => {
+                    let _ = transcript::handle_button(&ctx, &component, &self.state).await;
+                }
+                ComponentRoute::QueueCancel => {
+                    let _ = queue::handle_cancel_button(&ctx, &component, &self.state).await;
+                }
+                ComponentRoute::Permission => {
+                    let _ = commands::permission::handle_button(&ctx, &component, &self.state).await;
+                }
                 ComponentRoute::Ignore => {}
             }
         }
@@ -567,16 +1203,22 @@ impl EventHandler for Handler {
 
 async fn run_bot() -> anyhow::Result<()> {
     migrate::run_migrations().await?;
-    let config = Arc::new(Config::load().await?);
+    let config = Config::load().await?;
+    let discord_token = config.discord_token.clone();
+    let i18n = I18n::new(&config.language);
+    apply_runtime_config(&config);
+    let config = Arc::new(RwLock::new(config));
     let cron_manager = Arc::new(CronManager::new().await?);
     if let Err(e) = cron_manager.load_from_disk().await {
         error!("❌ Failed to load cron jobs from disk: {}", e);
     }
+    let channels = Arc::new(channels::ChannelRegistry::load(state::StateStore::open())?);
     let state = Arc::new(AppState {
         config: config.clone(),
-        session_manager: Arc::new(SessionManager::new(config.clone())),
+        session_manager: Arc::new(SessionManager::new(config.clone(), Arc::clone(&channels))),
         auth: Arc::new(AuthManager::new()),
-        i18n: Arc::new(RwLock::new(I18n::new(&config.language))),
+        roles: Arc::new(RoleManager::new()?),
+        i18n: Arc::new(RwLock::new(i18n)),
         backend_manager: Arc::new(agent::manager::BackendManager::new(config.clone())),
         cron_manager,
         active_renders: Arc::new(Mutex::new(HashMap::new())),
@@ -585,9 +1227,20 @@ async fn run_bot() -> anyhow::Result<()> {
             std::time::Duration::from_secs(24 * 60 * 60),
             std::time::Duration::from_secs(10 * 60),
         )?),
+        input_queue: Arc::new(InputQueue::new()),
+        threads: Arc::new(ThreadTracker::new()),
+        started_at: chrono::Utc::now(),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        inflight: Arc::new(inflight::InflightStore::new()),
+        channels,
+        usage: Arc::new(usage::UsageStore::new()?),
+        budget: Arc::new(budget::BudgetGuard::new()?),
     });
+    if !state.roles.load().has_admin() {
+        warn!("⚠️ No admin assigned; run `agent-discord role grant <USER_ID> admin` on the host");
+    }
     let mut client = Client::builder(
-        &state.config.discord_token,
+        &discord_token,
         GatewayIntents::GUILD_MESSAGES
             | GatewayIntents::MESSAGE_CONTENT
             | GatewayIntents::GUILDS
@@ -604,55 +1257,231 @@ async fn run_bot() -> anyhow::Result<()> {
         .init(client.http.clone(), Arc::downgrade(&state))
         .await;
 
-    client.start().await?;
-    Ok(())
-}
+    if let Some(idle_rx) = state.input_queue.take_idle_receiver() {
+        tokio::spawn(run_queue_dispatcher(
+            state.clone(),
+            client.http.clone(),
+            idle_rx,
+        ));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::load_all_prompts;
-    use crate::migrate::{get_prompts_dir, BASE_DIR_ENV};
-    use std::sync::{Mutex, OnceLock};
-    use tempfile::tempdir;
+    let tracked = threads::resume_tracking(&state);
+    if tracked > 0 {
+        info!("🧵 Tracking {} open conversation thread(s)", tracked);
+    }
+    tokio::spawn(threads::run_idle_sweeper(
+        state.clone(),
+        client.http.clone(),
+    ));
+    tokio::spawn(supervisor::run(state.clone(), client.http.clone()));
+    tokio::spawn(channels::watch_sessions(
+        state.channels.subscribe(),
+        state.session_manager.clone(),
+    ));
+    // 上次執行中斷的回合：接回外部 server 上的，其餘標成中斷
+    tokio::spawn(inflight::resume_all(state.clone(), client.http.clone()));
+
+    // 本機控制通道 (reload 等 CLI 子指令)
+    let socket_path = migrate::get_control_socket_path();
+    match control::bind(&socket_path).await {
+        Ok(listener) => {
+            info!("🎛️ Control socket listening at {}", socket_path.display());
+            let control_state = state.clone();
+            let control_http = client.http.clone();
+            tokio::spawn(control::serve(listener, move |req| {
+                let state = control_state.clone();
+                let http = control_http.clone();
+                async move { control::dispatch(&state, &http, req).await }
+            }));
+        }
+        Err(e) => error!("❌ Failed to bind control socket: {}", e),
+    }
 
-    fn env_lock() -> &'static Mutex<()> {
-        static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
-        LOCK.get_or_init(|| Mutex::new(()))
+    // Prometheus 端點；listen 位址改了要重啟才會生效
+    let metrics_config = state.config.read().await.metrics.clone();
+    if metrics_config.enabled {
+        match tokio::net::TcpListener::bind(&metrics_config.listen).await {
+            Ok(listener) => {
+                info!("📈 Metrics listening at http://{}/metrics", metrics_config.listen);
+                let metrics_state = state.clone();
+                tokio::spawn(metrics::serve(listener, move || {
+                    let state = metrics_state.clone();
+                    async move {
+                        metrics::Gauges {
+                            live_sessions: metrics::count_by_backend(
+                                &state.session_manager.list_sessions().await,
+                            ),
+                            backend_restarts: state
+                                .backend_manager
+                                .health()
+                                .into_iter()
+                                .map(|h| (h.name, h.restarts))
+                                .collect(),
+                        }
+                    }
+                }));
+            }
+            Err(e) => error!(
+                "❌ Failed to bind metrics listener {}: {}",
+                metrics_config.listen, e
+            ),
+        }
     }
 
-    #[test]
-    fn test_load_all_prompts_creates_defaults_when_empty() {
-        let _guard = env_lock().lock().expect("lock");
-        let dir = tempdir().expect("tempdir");
-        // SAFETY: serialized by env lock
-        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+    // SIGTERM/Ctrl+C：收尾後關閉 gateway，client.start() 隨之返回
+    let shard_manager = client.shard_manager.clone();
+    let shutdown_state = state.clone();
+    let shutdown_http = client.http.clone();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        shutdown::run(&shutdown_state, shutdown_http, shutdown::SHUTDOWN_DEADLINE).await;
+        shard_manager.shutdown_all().await;
+    });
 
-        let out = load_all_prompts();
-        assert!(!out.trim().is_empty());
-        assert!(dir.path().join("prompts").exists());
+    client.start().await?;
+    info!("👋 Shutdown complete");
+    Ok(())
+}
 
-        // SAFETY: serialized by env lock
-        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+/// 頻道回覆結束後，依頻道的佇列策略送出下一則排隊的訊息
+async fn run_queue_dispatcher(
+    state: Arc<AppState>,
+    http: Arc<Http>,
+    mut idle_rx: tokio::sync::mpsc::UnboundedReceiver<u64>,
+) {
+    while let Some(channel_id_u64) = idle_rx.recv().await {
+        if state.shutting_down.load(Ordering::SeqCst) {
+            break;
+        }
+        let channel_id = ChannelId::new(channel_id_u64);
+        let policy = state
+            .channels
+            .snapshot()
+            .get_queue_policy(&channel_id_u64.to_string());
+        // 取出與保留位置在同一個鎖內完成，避免和新訊息同時開始
+        let (turn, input, sources) = {
+            let mut active = state.active_renders.lock().await;
+            if active.contains_key(&channel_id_u64) {
+                // 已有回合在跑，它結束時會再通知
+                continue;
+            }
+            let Some((input, sources)) = state.input_queue.take_next(channel_id_u64, policy)
+            else {
+                continue;
+            };
+            let turn = Handler::next_turn();
+            active.insert(
+                channel_id_u64,
+                ActiveRender {
+                    turn,
+                    message_id: None,
+                    handles: Vec::new(),
+                },
+            );
+            (turn, input, sources)
+        };
+        for source in sources {
+            let http = http.clone();
+            tokio::spawn(async move {
+                let _ = channel_id.delete_reaction(&http, source, None, '⏳').await;
+            });
+        }
+
+        let admission = budget::admit(&state, channel_id_u64, input.user_id).await;
+        if let Some(notice) = admission.notice {
+            let _ = channel_id.say(&http, notice).await;
+        }
+        if !admission.allowed {
+            // 被拒絕的訊息不會開始回合，釋放位置後直接輪到下一則
+            Handler::release_turn(&state, channel_id_u64, turn).await;
+            continue;
+        }
+        info!(
+            "📥 Dispatching queued input for channel {} ({} left)",
+            channel_id_u64,
+            state.input_queue.depth(channel_id_u64)
+        );
+
+        let state = (*state).clone();
+        let http = http.clone();
+        let input = TurnInput {
+            input,
+            reservation: admission.reservation,
+        };
+        tokio::spawn(async move {
+            if let Err(e) = Handler::run_turn(state, http, channel_id, turn, input).await {
+                error!("❌ Failed to dispatch queued input: {}", e);
+            }
+        });
     }
+}
 
-    #[test]
-    fn test_load_all_prompts_reads_existing_files_sorted() {
-        let _guard = env_lock().lock().expect("lock");
-        let dir = tempdir().expect("tempdir");
-        // SAFETY: serialized by env lock
-        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+async fn redeem_auth_token(token: &str) -> anyhow::Result<()> {
+    let auth = AuthManager::new();
+    let (type_, id) = auth.redeem_token(token.trim()).await?;
+    match type_.as_str() {
+        "channel" => println!("✅ Channel {} authorized (mention-only by default)", id),
+        "user" => println!("✅ User {} authorized", id),
+        other => println!("✅ Authorized {} {}", other, id),
+    }
+    notify_daemon_reload().await;
+    Ok(())
+}
 
-        let prompts_dir = get_prompts_dir();
-        std::fs::create_dir_all(&prompts_dir).expect("create prompts dir");
-        std::fs::write(prompts_dir.join("b.md"), "B").expect("write b");
-        std::fs::write(prompts_dir.join("a.md"), "A").expect("write a");
+/// 執行中的 daemon 快取了授權與角色，通知它重新讀取；沒在執行就下次啟動時生效
+async fn notify_daemon_reload() {
+    let socket_path = migrate::get_control_socket_path();
+    if control::send_request(&socket_path, &control::ControlRequest::Reload)
+        .await
+        .is_ok_and(|resp| resp.ok)
+    {
+        println!("🔄 Running daemon reloaded");
+    }
+}
 
-        let out = load_all_prompts();
-        assert_eq!(out, "A\n\nB");
+async fn manage_roles(action: RoleAction) -> anyhow::Result<()> {
+    let roles = RoleManager::new()?;
+    let target_of = |target: String, guild_role: bool| {
+        if guild_role {
+            RoleTarget::GuildRole(target)
+        } else {
+            RoleTarget::User(target)
+        }
+    };
+    match action {
+        RoleAction::Grant {
+            target,
+            role,
+            guild_role,
+        } => {
+            roles
+                .set(&target_of(target.clone(), guild_role), Some(role))
+                .await?;
+            println!("✅ {} now has role {}", target, role);
+        }
+        RoleAction::Revoke { target, guild_role } => {
+            roles
+                .set(&target_of(target.clone(), guild_role), None)
+                .await?;
+            println!("✅ Removed explicit role of {}", target);
+        }
+        RoleAction::List => {
+            println!("{}", roles.format_grants());
+            return Ok(());
+        }
+    }
+    notify_daemon_reload().await;
+    Ok(())
+}
 
-        // SAFETY: serialized by env lock
-        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+async fn send_control(request: control::ControlRequest) -> anyhow::Result<()> {
+    let socket_path = migrate::get_control_socket_path();
+    let resp = control::send_request(&socket_path, &request).await?;
+    if !resp.ok {
+        anyhow::bail!(resp.message);
     }
+    println!("{}", resp.message);
+    Ok(())
 }
 
 #[tokio::main]
@@ -662,6 +1491,27 @@ async fn main() -> anyhow::Result<()> {
     match cli.command {
         Some(Commands::Run) => run_bot().await?,
         Some(Commands::Version) => println!("v{}", env!("CARGO_PKG_VERSION")),
+        Some(Commands::Auth { token }) => redeem_auth_token(&token).await?,
+        Some(Commands::Role { action }) => manage_roles(action).await?,
+        Some(Commands::Reload) => send_control(control::ControlRequest::Reload).await?,
+        Some(Commands::Status) => send_control(control::ControlRequest::Status).await?,
+        Some(Commands::Sessions) => send_control(control::ControlRequest::Sessions).await?,
+        Some(Commands::Abort { channel }) => {
+            send_control(control::ControlRequest::Abort {
+                channel_id: channel,
+            })
+            .await?
+        }
+        Some(Commands::Clear { channel }) => {
+            send_control(control::ControlRequest::Clear {
+                channel_id: channel,
+            })
+            .await?
+        }
+        Some(Commands::Backends) => send_control(control::ControlRequest::Backends).await?,
+        Some(Commands::Cron {
+            action: CronAction::List,
+        }) => send_control(control::ControlRequest::CronList).await?,
         Some(Commands::Daemon { action }) => {
             let service_path = get_systemd_service_path()?;
 
@@ -709,7 +1559,54 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        _ => run_bot().await?,
+        None => run_bot().await?,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::load_all_prompts;
+    use crate::migrate::{get_prompts_dir, BASE_DIR_ENV};
+    use std::sync::{Mutex, OnceLock};
+    use tempfile::tempdir;
+
+    fn env_lock() -> &'static Mutex<()> {
+        static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+        LOCK.get_or_init(|| Mutex::new(()))
+    }
+
+    #[test]
+    fn test_load_all_prompts_creates_defaults_when_empty() {
+        let _guard = env_lock().lock().expect("lock");
+        let dir = tempdir().expect("tempdir");
+        // SAFETY: serialized by env lock
+        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+
+        let out = load_all_prompts();
+        assert!(!out.trim().is_empty());
+        assert!(dir.path().join("prompts").exists());
+
+        // SAFETY: serialized by env lock
+        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+    }
+
+    #[test]
+    fn test_load_all_prompts_reads_existing_files_sorted() {
+        let _guard = env_lock().lock().expect("lock");
+        let dir = tempdir().expect("tempdir");
+        // SAFETY: serialized by env lock
+        unsafe { std::env::set_var(BASE_DIR_ENV, dir.path()) };
+
+        let prompts_dir = get_prompts_dir();
+        std::fs::create_dir_all(&prompts_dir).expect("create prompts dir");
+        std::fs::write(prompts_dir.join("b.md"), "B").expect("write b");
+        std::fs::write(prompts_dir.join("a.md"), "A").expect("write a");
+
+        let out = load_all_prompts();
+        assert_eq!(out, "A\n\nB");
+
+        // SAFETY: serialized by env lock
+        unsafe { std::env::remove_var(BASE_DIR_ENV) };
+    }
+}
@@ -0,0 +1,427 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, warn};
+
+const PREFIX: &str = "agent_discord";
+/// 回合長度的 histogram 分界（秒）
+const DURATION_BUCKETS: [f64; 10] = [1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+/// 只需要讀到 request line，header 再長就不理了
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// 每個分界各自的次數（非累計），輸出時才累加
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = DURATION_BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// 行程內的計數器；抓取時由 render 輸出 Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    prompts: Mutex<BTreeMap<String, u64>>,
+    /// (backend, success)
+    turns: Mutex<BTreeMap<(String, bool), u64>>,
+    turn_duration: Mutex<BTreeMap<String, Histogram>>,
+    discord_edit_failures: AtomicU64,
+    writer_lagged_events: AtomicU64,
+    upload_bytes: AtomicU64,
+    cron_runs: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+/// 抓取當下才讀得到的數值
+#[derive(Clone, Debug, Default)]
+pub struct Gauges {
+    pub live_sessions: Vec<(String, usize)>,
+    pub backend_restarts: Vec<(String, u32)>,
+}
+
+/// 全域計數器：render 迴圈、writer、uploads、cron 等處直接累加
+pub fn get() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn bump<K: Ord>(map: &Mutex<BTreeMap<K, u64>>, key: K) {
+    *lock(map).entry(key).or_default() += 1;
+}
+
+/// Prometheus label 值需跳脫反斜線、雙引號與換行
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+impl Metrics {
+    pub fn prompt_sent(&self, backend: &str) {
+        bump(&self.prompts, backend.to_string());
+    }
+
+    /// 收到 AgentEnd 時記錄結果與回合長度
+    pub fn turn_finished(&self, backend: &str, success: bool, elapsed: Duration) {
+        bump(&self.turns, (backend.to_string(), success));
+        lock(&self.turn_duration)
+            .entry(backend.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn discord_edit_failed(&self) {
+        self.discord_edit_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn writer_lagged(&self, skipped: u64) {
+        self.writer_lagged_events
+            .fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn upload_staged(&self, bytes: u64) {
+        self.upload_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// result：started、queued（頻道忙碌）、skipped（關機或預算）、failed（建立 session 失敗）
+    pub fn cron_run(&self, result: &'static str) {
+        bump(&self.cron_runs, result);
+    }
+
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "prompts_total",
+            "counter",
+            "Prompts sent to a backend.",
+        );
+        for (backend, n) in lock(&self.prompts).iter() {
+            let _ = writeln!(
+                out,
+                "{}_prompts_total{{backend=\"{}\"}} {}",
+                PREFIX,
+                escape(backend),
+                n
+            );
+        }
+
+        header(
+            &mut out,
+            "turns_total",
+            "counter",
+            "Finished turns by AgentEnd result.",
+        );
+        for ((backend, success), n) in lock(&self.turns).iter() {
+            let result = if *success { "success" } else { "failure" };
+            let _ = writeln!(
+                out,
+                "{}_turns_total{{backend=\"{}\",result=\"{}\"}} {}",
+                PREFIX,
+                escape(backend),
+                result,
+                n
+            );
+        }
+
+        header(
+            &mut out,
+            "turn_duration_seconds",
+            "histogram",
+            "Time from prompt to AgentEnd.",
+        );
+        for (backend, h) in lock(&self.turn_duration).iter() {
+            let backend = escape(backend);
+            let mut cumulative = 0;
+            for (bound, n) in DURATION_BUCKETS.iter().zip(h.buckets.iter()) {
+                cumulative += n;
+                let _ = writeln!(
+                    out,
+                    "{}_turn_duration_seconds_bucket{{backend=\"{}\",le=\"{}\"}} {}",
+                    PREFIX, backend, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_turn_duration_seconds_bucket{{backend=\"{}\",le=\"+Inf\"}} {}",
+                PREFIX, backend, h.count
+            );
+            let _ = writeln!(
+                out,
+                "{}_turn_duration_seconds_sum{{backend=\"{}\"}} {}",
+                PREFIX, backend, h.sum
+            );
+            let _ = writeln!(
+                out,
+                "{}_turn_duration_seconds_count{{backend=\"{}\"}} {}",
+                PREFIX, backend, h.count
+            );
+        }
+
+        for (name, help, value) in [
+            (
+                "discord_edit_failures_total",
+                "Failed message edits in the render loop.",
+                &self.discord_edit_failures,
+            ),
+            (
+                "writer_lagged_events_total",
+                "Agent events the writer task skipped because the broadcast channel lagged.",
+                &self.writer_lagged_events,
+            ),
+            (
+                "upload_staged_bytes_total",
+                "Bytes of Discord attachments staged for backends.",
+                &self.upload_bytes,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{}_{} {}", PREFIX, name, value.load(Ordering::Relaxed));
+        }
+
+        header(
+            &mut out,
+            "cron_runs_total",
+            "counter",
+            "Cron job triggers by outcome.",
+        );
+        for (result, n) in lock(&self.cron_runs).iter() {
+            let _ = writeln!(
+                out,
+                "{}_cron_runs_total{{result=\"{}\"}} {}",
+                PREFIX, result, n
+            );
+        }
+
+        header(
+            &mut out,
+            "live_sessions",
+            "gauge",
+            "Agent sessions held in memory.",
+        );
+        for (backend, n) in &gauges.live_sessions {
+            let _ = writeln!(
+                out,
+                "{}_live_sessions{{backend=\"{}\"}} {}",
+                PREFIX,
+                escape(backend),
+                n
+            );
+        }
+
+        header(
+            &mut out,
+            "backend_restarts_total",
+            "counter",
+            "Backend restarts by the supervisor.",
+        );
+        for (backend, n) in &gauges.backend_restarts {
+            let _ = writeln!(
+                out,
+                "{}_backend_restarts_total{{backend=\"{}\"}} {}",
+                PREFIX,
+                escape(backend),
+                n
+            );
+        }
+        out
+    }
+}
+
+/// 依後端分組計數 session
+pub fn count_by_backend(sessions: &[(u64, String)]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, backend) in sessions {
+        *counts.entry(backend.clone()).or_default() += 1;
+    }
+    counts.into_iter().collect()
+}
+
+/// 每條連線處理一個 GET；只有 /metrics 回 200
+pub async fn serve<F, Fut>(listener: TcpListener, gauges: F)
+where
+    F: Fn() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Gauges> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let gauges = gauges.clone();
+                tokio::spawn(async move {
+                    let handled =
+                        tokio::time::timeout(REQUEST_TIMEOUT, handle_connection(stream, gauges))
+                            .await;
+                    match handled {
+                        Ok(Err(e)) => warn!("⚠️ Metrics connection error: {}", e),
+                        Err(_) => warn!("⚠️ Metrics request timed out"),
+                        Ok(Ok(())) => {}
+                    }
+                });
+            }
+            Err(e) => {
+                error!("❌ Metrics listener accept failed: {}", e);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection<F, Fut>(mut stream: TcpStream, gauges: F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Gauges>,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let mut parts = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let path = target.map(|t| t.split('?').next().unwrap_or(t));
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            get().render(&gauges().await),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_outputs_counters_histograms_and_gauges() {
+        let metrics = Metrics::default();
+        metrics.prompt_sent("pi");
+        metrics.prompt_sent("pi");
+        metrics.turn_finished("pi", true, Duration::from_secs(3));
+        metrics.turn_finished("pi", false, Duration::from_secs(4000));
+        metrics.writer_lagged(5);
+        metrics.cron_run("started");
+        let gauges = Gauges {
+            live_sessions: count_by_backend(&[(1, "pi".into()), (2, "acp:\"x\"".into())]),
+            backend_restarts: vec![("opencode".into(), 2)],
+        };
+
+        let out = metrics.render(&gauges);
+        let has = |line: &str| out.lines().any(|l| l == line);
+        assert!(has("# TYPE agent_discord_prompts_total counter"));
+        assert!(has("agent_discord_prompts_total{backend=\"pi\"} 2"));
+        assert!(has(
+            "agent_discord_turns_total{backend=\"pi\",result=\"success\"} 1"
+        ));
+        assert!(has(
+            "agent_discord_turns_total{backend=\"pi\",result=\"failure\"} 1"
+        ));
+        assert!(has(
+            "agent_discord_turn_duration_seconds_bucket{backend=\"pi\",le=\"2.5\"} 0"
+        ));
+        assert!(has(
+            "agent_discord_turn_duration_seconds_bucket{backend=\"pi\",le=\"5\"} 1"
+        ));
+        // 超過最大分界的只算進 +Inf
+        assert!(has(
+            "agent_discord_turn_duration_seconds_bucket{backend=\"pi\",le=\"1800\"} 1"
+        ));
+        assert!(has(
+            "agent_discord_turn_duration_seconds_bucket{backend=\"pi\",le=\"+Inf\"} 2"
+        ));
+        assert!(has(
+            "agent_discord_turn_duration_seconds_sum{backend=\"pi\"} 4003"
+        ));
+        assert!(has("agent_discord_writer_lagged_events_total 5"));
+        assert!(has("agent_discord_discord_edit_failures_total 0"));
+        assert!(has("agent_discord_cron_runs_total{result=\"started\"} 1"));
+        assert!(has(
+            "agent_discord_live_sessions{backend=\"acp:\\\"x\\\"\"} 1"
+        ));
+        assert!(has(
+            "agent_discord_backend_restarts_total{backend=\"opencode\"} 2"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_metrics_and_404() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(serve(listener, || async {
+            Gauges {
+                live_sessions: vec![("kilo".into(), 3)],
+                ..Default::default()
+            }
+        }));
+
+        let client = reqwest::Client::new();
+        let resp = client
+            .get(format!("http://{}/metrics", addr))
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert!(resp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/plain; version=0.0.4")));
+        let body = resp.text().await?;
+        assert!(body.contains("agent_discord_live_sessions{backend=\"kilo\"} 3"));
+
+        let missing = client.get(format!("http://{}/", addr)).send().await?;
+        assert_eq!(missing.status(), 404);
+        Ok(())
+    }
+}
@@ -1,9 +1,9 @@
 use serde_json::json;
 use std::path::{Path, PathBuf};
 use tokio::fs;
-use tracing::info;
+use tracing::{info, warn};
 
-const CURRENT_VERSION: u32 = 1;
+const CURRENT_VERSION: u32 = 3;
 const OLD_BASE_DIR: &str = ".pi/discord-rs";
 const NEW_BASE_DIR: &str = ".agent-discord-rs";
 pub const BASE_DIR_ENV: &str = "AGENT_DISCORD_BASE_DIR";
@@ -80,6 +80,13 @@ pub async fn run_migrations() -> anyhow::Result<()> {
         fs::create_dir_all(new_dir.join("uploads")).await?;
     }
 
+    if current_version < 2 {
+        migrate_v1_to_v2(&new_dir).await?;
+    }
+    if current_version < 3 {
+        migrate_v2_to_v3(&new_dir).await?;
+    }
+
     write_version(&version_file, CURRENT_VERSION).await?;
     Ok(())
 }
@@ -290,6 +297,95 @@ port = 4096
     Ok(())
 }
 
+/// v1 → v2：auth.json 只有「已授權」的概念，遷移時全域授權的使用者明確給 user，
+/// 其他人（頻道授權）則使用預設角色。admin 一律由主機上的 `role grant` 明確指定。
+async fn migrate_v1_to_v2(base_dir: &Path) -> anyhow::Result<()> {
+    let roles_path = base_dir.join("roles.json");
+    if roles_path.exists() {
+        return Ok(());
+    }
+    let registry: crate::auth::Registry = match fs::read_to_string(base_dir.join("auth.json")).await
+    {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => return Ok(()),
+    };
+
+    let mut roles = crate::roles::RoleRegistry::default();
+    for user_id in registry.users.keys() {
+        roles.users.insert(user_id.clone(), crate::roles::Role::User);
+    }
+    fs::write(&roles_path, serde_json::to_string_pretty(&roles)?).await?;
+    info!(
+        "✅ Migration from v1 to v2 completed ({} user(s) seeded)",
+        roles.users.len()
+    );
+    warn!("⚠️ No admin assigned yet; run `agent-discord role grant <USER_ID> admin` on the host");
+    Ok(())
+}
+
+/// v2 → v3 要併入 state.json 的舊檔案
+const V2_STATE_FILES: [&str; 6] = [
+    "channel_config.json",
+    "auth.json",
+    "roles.json",
+    "pending_tokens.json",
+    "cron_jobs.json",
+    "inflight.json",
+];
+
+/// 讀取 v2 的 JSON 檔；不存在或空檔視為預設值，內容損毀則中止遷移，避免資料被丟棄
+async fn read_v2_file<T: serde::de::DeserializeOwned + Default>(path: &Path) -> anyhow::Result<T> {
+    match fs::read_to_string(path).await {
+        Ok(content) if content.trim().is_empty() => Ok(T::default()),
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Cannot migrate {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// v2 → v3：channel_config.json、auth.json、roles.json、pending_tokens.json、cron_jobs.json
+/// 與 inflight.json 合併成 state.json，舊檔改名為 *.v2.bak 保留
+async fn migrate_v2_to_v3(base_dir: &Path) -> anyhow::Result<()> {
+    let store = crate::state::StateStore::with_path(base_dir.join("state.json"));
+    if store.path().exists() {
+        return Ok(());
+    }
+    let [channels, auth, roles, pending, cron_jobs, inflight] =
+        V2_STATE_FILES.map(|name| base_dir.join(name));
+    let state = crate::state::State {
+        channels: read_v2_file(&channels).await?,
+        auth: read_v2_file(&auth).await?,
+        roles: read_v2_file(&roles).await?,
+        pending: read_v2_file(&pending).await?,
+        cron_jobs: read_v2_file(&cron_jobs).await?,
+        inflight: read_v2_file(&inflight).await?,
+        ..Default::default()
+    };
+    let summary = format!(
+        "{} channel(s), {} authorized user(s), {} authorized channel(s), {} role grant(s), \
+         {} cron job(s)",
+        state.channels.channels.len(),
+        state.auth.users.len(),
+        state.auth.channels.len(),
+        state.roles.users.len() + state.roles.guild_roles.len(),
+        state.cron_jobs.len()
+    );
+    store.update(|s| {
+        *s = state;
+        Ok(())
+    })?;
+
+    for name in V2_STATE_FILES {
+        let path = base_dir.join(name);
+        if path.exists() {
+            fs::rename(&path, base_dir.join(format!("{}.v2.bak", name))).await?;
+        }
+    }
+    info!("✅ Migration from v2 to v3 completed ({})", summary);
+    Ok(())
+}
+
 pub fn get_base_dir() -> PathBuf {
     if let Ok(v) = std::env::var(BASE_DIR_ENV) {
         if !v.trim().is_empty() {
@@ -316,8 +412,12 @@ pub fn get_config_path() -> PathBuf {
     get_base_dir().join("config.toml")
 }
 
-pub fn get_channel_config_path() -> PathBuf {
-    get_base_dir().join("channel_config.json")
+pub fn get_state_path() -> PathBuf {
+    get_base_dir().join("state.json")
+}
+
+pub fn get_usage_path() -> PathBuf {
+    get_base_dir().join("usage.jsonl")
 }
 
 pub fn get_sessions_dir(agent_type: &str) -> PathBuf {
@@ -332,6 +432,19 @@ pub fn get_uploads_dir() -> PathBuf {
     get_base_dir().join("uploads")
 }
 
+pub fn get_outbox_dir() -> PathBuf {
+    get_base_dir().join("outbox")
+}
+
+pub fn get_transcripts_dir() -> PathBuf {
+    get_base_dir().join("transcripts")
+}
+
+/// socket 放在 0700 的 run 目錄下，bind 之後、chmod 之前其他使用者也連不到
+pub fn get_control_socket_path() -> PathBuf {
+    get_base_dir().join("run").join("control.sock")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -442,4 +555,101 @@ mod tests {
             .expect("read cfg");
         assert!(cfg.contains("assistant_name = \"Agent\""));
     }
+
+    #[tokio::test]
+    async fn test_migrate_v1_to_v2_seeds_authorized_users_without_admin() {
+        let dir = tempdir().expect("dir");
+        fs::write(
+            dir.path().join("auth.json"),
+            r#"{"users":{"42":{"authorized_at":"2026-01-01T00:00:00Z"}},"channels":{"7":{"authorized_at":"2026-01-01T00:00:00Z","mention_only":true}}}"#,
+        )
+        .await
+        .expect("write auth");
+
+        migrate_v1_to_v2(dir.path()).await.expect("migrate");
+
+        let content = fs::read_to_string(dir.path().join("roles.json"))
+            .await
+            .expect("read roles");
+        let roles: crate::roles::RoleRegistry = serde_json::from_str(&content).expect("parse");
+        assert_eq!(roles.users.get("42"), Some(&crate::roles::Role::User));
+        assert!(!roles.has_admin());
+        assert_eq!(roles.users.len(), 1);
+        assert_eq!(roles.default_role, crate::roles::Role::User);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v2_to_v3_merges_json_files_into_state() {
+        let dir = tempdir().expect("dir");
+        fs::write(
+            dir.path().join("channel_config.json"),
+            r#"{"version":1,"channels":{"7":{"agent_type":"opencode","session_id":"ses-1","authorized_at":"2026-01-01T00:00:00Z"}}}"#,
+        )
+        .await
+        .expect("write channels");
+        fs::write(
+            dir.path().join("auth.json"),
+            r#"{"users":{"42":{"authorized_at":"2026-01-01T00:00:00Z"}}}"#,
+        )
+        .await
+        .expect("write auth");
+        fs::write(
+            dir.path().join("roles.json"),
+            r#"{"users":{"42":"admin"},"guild_roles":{"9":"operator"}}"#,
+        )
+        .await
+        .expect("write roles");
+
+        migrate_v2_to_v3(dir.path()).await.expect("migrate");
+
+        let store = crate::state::StateStore::with_path(dir.path().join("state.json"));
+        let state = store.read().expect("read state");
+        let entry = &state.channels.channels["7"];
+        assert_eq!(entry.agent_type, crate::agent::AgentType::Opencode);
+        assert_eq!(entry.session_id.as_deref(), Some("ses-1"));
+        assert!(state.auth.users.contains_key("42"));
+        assert_eq!(state.roles.users["42"], crate::roles::Role::Admin);
+        assert_eq!(state.roles.guild_roles["9"], crate::roles::Role::Operator);
+        assert!(state.pending.tokens.is_empty() && state.cron_jobs.is_empty());
+        assert!(!dir.path().join("channel_config.json").exists());
+        assert!(dir.path().join("channel_config.json.v2.bak").exists());
+        assert!(dir.path().join("auth.json.v2.bak").exists());
+        assert!(dir.path().join("roles.json.v2.bak").exists());
+
+        // 已有 state.json 時不再動它
+        fs::write(dir.path().join("auth.json"), "{}").await.expect("write auth");
+        migrate_v2_to_v3(dir.path()).await.expect("migrate again");
+        assert!(store.read().expect("read").auth.users.contains_key("42"));
+        assert!(dir.path().join("auth.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v2_to_v3_refuses_corrupt_files() {
+        let dir = tempdir().expect("dir");
+        fs::write(dir.path().join("cron_jobs.json"), "{broken")
+            .await
+            .expect("write cron");
+
+        assert!(migrate_v2_to_v3(dir.path()).await.is_err());
+        assert!(!dir.path().join("state.json").exists());
+        assert!(dir.path().join("cron_jobs.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v1_to_v2_keeps_existing_roles_file() {
+        let dir = tempdir().expect("dir");
+        fs::write(dir.path().join("auth.json"), r#"{"users":{"42":{"authorized_at":"2026-01-01T00:00:00Z"}}}"#)
+            .await
+            .expect("write auth");
+        fs::write(dir.path().join("roles.json"), "{}")
+            .await
+            .expect("write roles");
+
+        migrate_v1_to_v2(dir.path()).await.expect("migrate");
+
+        let content = fs::read_to_string(dir.path().join("roles.json"))
+            .await
+            .expect("read roles");
+        assert_eq!(content, "{}");
+    }
 }
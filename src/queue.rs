@@ -0,0 +1,205 @@
+use serenity::all::{
+    ButtonStyle, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, MessageId,
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+use crate::agent::UserInput;
+use crate::commands::agent::QueuePolicy;
+
+pub const CANCEL_BUTTON_ID: &str = "queue_cancel";
+
+/// 排隊中的一則輸入；記下來源訊息，送出時移除它的 ⏳
+struct Queued {
+    input: UserInput,
+    source: Option<MessageId>,
+}
+
+/// 每個頻道在回覆進行中收到的訊息。
+/// 回合結束（完成、中斷或開始失敗）時呼叫 `notify_idle`，再由 dispatcher 取出下一則。
+pub struct InputQueue {
+    pending: Mutex<HashMap<u64, VecDeque<Queued>>>,
+    idle_tx: mpsc::UnboundedSender<u64>,
+    idle_rx: Mutex<Option<mpsc::UnboundedReceiver<u64>>>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        let (idle_tx, idle_rx) = mpsc::unbounded_channel();
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            idle_tx,
+            idle_rx: Mutex::new(Some(idle_rx)),
+        }
+    }
+
+    /// 加入佇列，回傳加入後的深度
+    pub fn push(&self, channel_id: u64, input: UserInput, source: Option<MessageId>) -> usize {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = pending.entry(channel_id).or_default();
+        queue.push_back(Queued { input, source });
+        queue.len()
+    }
+
+    pub fn depth(&self, channel_id: u64) -> usize {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&channel_id)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// 清空頻道佇列，回傳被取消的數量
+    pub fn clear(&self, channel_id: u64) -> usize {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&channel_id)
+            .map_or(0, |q| q.len())
+    }
+
+    /// 依目前策略取出下一個要送出的輸入與其來源訊息；merge 會一次取出全部並合併
+    pub fn take_next(
+        &self,
+        channel_id: u64,
+        policy: QueuePolicy,
+    ) -> Option<(UserInput, Vec<MessageId>)> {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = pending.get_mut(&channel_id)?;
+        let taken: Vec<Queued> = match policy {
+            QueuePolicy::Merge => queue.drain(..).collect(),
+            QueuePolicy::Queue | QueuePolicy::Preempt => queue.pop_front().into_iter().collect(),
+        };
+        let sources = taken.iter().filter_map(|q| q.source).collect();
+        let mut inputs: Vec<UserInput> = taken.into_iter().map(|q| q.input).collect();
+        let input = if inputs.is_empty() {
+            None
+        } else if policy == QueuePolicy::Merge {
+            Some(merge_inputs(inputs))
+        } else {
+            inputs.pop()
+        };
+        let next = input.map(|input| (input, sources));
+        if queue.is_empty() {
+            pending.remove(&channel_id);
+        }
+        next
+    }
+
+    pub fn notify_idle(&self, channel_id: u64) {
+        let _ = self.idle_tx.send(channel_id);
+    }
+
+    /// 只能取一次，交給 dispatcher 使用
+    pub fn take_idle_receiver(&self) -> Option<mpsc::UnboundedReceiver<u64>> {
+        self.idle_rx
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+}
+
+/// 把多則訊息合併成一個 prompt，附件依序保留
+pub fn merge_inputs(inputs: Vec<UserInput>) -> UserInput {
+    let mut merged = UserInput::default();
+    let mut texts = Vec::new();
+    for input in inputs {
+        if !input.text.trim().is_empty() {
+            texts.push(input.text);
+        }
+        merged.files.extend(input.files);
+        // 合併的 prompt 歸給第一個送出訊息的人
+        merged.user_id = merged.user_id.or(input.user_id);
+    }
+    merged.text = texts.join("\n\n");
+    merged
+}
+
+pub fn build_cancel_row(i18n: &crate::i18n::I18n) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new(CANCEL_BUTTON_ID)
+        .label(i18n.get("queue_cancel"))
+        .style(ButtonStyle::Secondary)])
+}
+
+pub async fn handle_cancel_button(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &crate::AppState,
+) -> anyhow::Result<()> {
+    let cancelled = state.input_queue.clear(interaction.channel_id.get());
+    let msg = {
+        let i18n = state.i18n.read().await;
+        i18n.get_args("queue_cancelled", &[cancelled.to_string()])
+    };
+    interaction
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(msg)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> UserInput {
+        UserInput::new_text(s.to_string())
+    }
+
+    #[test]
+    fn test_queue_policy_pops_in_order() {
+        let queue = InputQueue::new();
+        assert_eq!(queue.push(1, text("a"), None), 1);
+        assert_eq!(queue.push(1, text("b"), Some(MessageId::new(9))), 2);
+        assert_eq!(queue.depth(2), 0);
+
+        let (first, sources) = queue.take_next(1, QueuePolicy::Queue).expect("first");
+        assert_eq!(first.text, "a");
+        assert!(sources.is_empty());
+        assert_eq!(queue.depth(1), 1);
+        let (second, sources) = queue.take_next(1, QueuePolicy::Queue).expect("second");
+        assert_eq!(second.text, "b");
+        assert_eq!(sources, vec![MessageId::new(9)]);
+        assert!(queue.take_next(1, QueuePolicy::Queue).is_none());
+    }
+
+    #[test]
+    fn test_merge_policy_drains_everything_into_one_prompt() {
+        let queue = InputQueue::new();
+        queue.push(1, text("first"), Some(MessageId::new(1)));
+        queue.push(1, text("  "), None);
+        queue.push(1, text("second"), Some(MessageId::new(3)));
+
+        let (merged, sources) = queue.take_next(1, QueuePolicy::Merge).expect("merged");
+        assert_eq!(merged.text, "first\n\nsecond");
+        assert_eq!(sources, vec![MessageId::new(1), MessageId::new(3)]);
+        assert_eq!(queue.depth(1), 0);
+        assert!(queue.take_next(1, QueuePolicy::Merge).is_none());
+    }
+
+    #[test]
+    fn test_clear_reports_cancelled_count() {
+        let queue = InputQueue::new();
+        queue.push(7, text("x"), None);
+        queue.push(7, text("y"), None);
+        assert_eq!(queue.clear(7), 2);
+        assert_eq!(queue.clear(7), 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_notifications_reach_receiver_once() {
+        let queue = InputQueue::new();
+        let mut rx = queue.take_idle_receiver().expect("receiver");
+        assert!(queue.take_idle_receiver().is_none());
+        queue.notify_idle(42);
+        assert_eq!(rx.recv().await, Some(42));
+    }
+}
@@ -0,0 +1,307 @@
+use crate::state::StateStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// 權限等級，由低到高排序，比較時高等級涵蓋低等級
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// 只能查看（例如 /cron_list），不能對 agent 下指令
+    ReadOnly,
+    /// 可以對話、切換模型、中斷回覆
+    #[default]
+    User,
+    /// 可以切換後端、清除對話、管理排程與頻道設定
+    Operator,
+    /// 可以變更全域設定與授予角色
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::ReadOnly, Role::User, Role::Operator, Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::User => "user",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "read_only" | "readonly" => Ok(Role::ReadOnly),
+            "user" => Ok(Role::User),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            other => anyhow::bail!("Unknown role: {}", other),
+        }
+    }
+}
+
+/// state.json 中的角色授予
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RoleRegistry {
+    /// Discord user_id -> role
+    #[serde(default)]
+    pub users: HashMap<String, Role>,
+    /// Discord guild role_id -> role（以 guild_id 當 key 即代表該伺服器的 @everyone）
+    #[serde(default)]
+    pub guild_roles: HashMap<String, Role>,
+    /// 已授權但沒有任何明確角色時使用的預設值
+    #[serde(default)]
+    pub default_role: Role,
+}
+
+impl RoleRegistry {
+    /// 有明確授予（個人或 guild role）時取其中最高者，否則回到 default_role。
+    /// 因此可以透過明確授予 read_only 來降級某個使用者。
+    pub fn resolve(&self, user_id: &str, guild_id: Option<&str>, role_ids: &[String]) -> Role {
+        let explicit = self
+            .users
+            .get(user_id)
+            .into_iter()
+            .chain(guild_id.and_then(|g| self.guild_roles.get(g)))
+            .chain(role_ids.iter().filter_map(|r| self.guild_roles.get(r)))
+            .max()
+            .copied();
+        explicit.unwrap_or(self.default_role)
+    }
+
+    /// 是否有人（或任何 guild role、預設角色）具有 admin
+    pub fn has_admin(&self) -> bool {
+        self.default_role == Role::Admin
+            || self
+                .users
+                .values()
+                .chain(self.guild_roles.values())
+                .any(|r| *r == Role::Admin)
+    }
+}
+
+/// 授予對象
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoleTarget {
+    User(String),
+    GuildRole(String),
+}
+
+/// 角色授予的記憶體快取：每則訊息都要判斷角色，讀取不碰磁碟，
+/// 寫入先落到 state.json 再更新快取；主機上直接改檔後以 reload 重新讀取
+pub struct RoleManager {
+    store: StateStore,
+    cache: RwLock<Arc<RoleRegistry>>,
+}
+
+impl RoleManager {
+    pub fn new() -> Result<Self> {
+        Self::with_store(StateStore::open())
+    }
+
+    /// state.json 損毀時回傳錯誤，不以空白設定啟動
+    pub fn with_store(store: StateStore) -> Result<Self> {
+        let registry = store.read()?.roles;
+        Ok(Self {
+            store,
+            cache: RwLock::new(Arc::new(registry)),
+        })
+    }
+
+    pub fn load(&self) -> Arc<RoleRegistry> {
+        Arc::clone(&self.cache.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// 重新讀取 state.json（CLI 或手動編輯後）；讀取失敗時保留原本的快取
+    pub async fn reload(&self) -> Result<()> {
+        let store = self.store.clone();
+        let registry = tokio::task::spawn_blocking(move || store.read())
+            .await??
+            .roles;
+        self.replace(registry);
+        Ok(())
+    }
+
+    fn replace(&self, registry: RoleRegistry) {
+        *self.cache.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(registry);
+    }
+
+    pub fn resolve(&self, user_id: &str, guild_id: Option<&str>, role_ids: &[String]) -> Role {
+        self.load().resolve(user_id, guild_id, role_ids)
+    }
+
+    /// 設定角色；`None` 代表撤銷明確授予
+    pub async fn set(&self, target: &RoleTarget, role: Option<Role>) -> Result<()> {
+        let target = target.clone();
+        let registry = self
+            .store
+            .update_async(move |state| {
+                let (map, id) = match target {
+                    RoleTarget::User(id) => (&mut state.roles.users, id),
+                    RoleTarget::GuildRole(id) => (&mut state.roles.guild_roles, id),
+                };
+                match role {
+                    Some(r) => {
+                        map.insert(id, r);
+                    }
+                    None => {
+                        map.remove(&id);
+                    }
+                }
+                Ok(state.roles.clone())
+            })
+            .await?;
+        self.replace(registry);
+        Ok(())
+    }
+
+    pub fn format_grants(&self) -> String {
+        let reg = self.load();
+        let mut lines = vec![format!("default: {}", reg.default_role)];
+        let mut users: Vec<_> = reg.users.iter().collect();
+        users.sort();
+        for (id, role) in users {
+            lines.push(format!("user {}: {}", id, role));
+        }
+        let mut guild_roles: Vec<_> = reg.guild_roles.iter().collect();
+        guild_roles.sort();
+        for (id, role) in guild_roles {
+            lines.push(format!("role {}: {}", id, role));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_role_ordering_and_parsing() {
+        assert!(Role::Admin > Role::Operator);
+        assert!(Role::Operator > Role::User);
+        assert!(Role::User > Role::ReadOnly);
+        assert_eq!("read-only".parse::<Role>().expect("parse"), Role::ReadOnly);
+        assert_eq!("Admin".parse::<Role>().expect("parse"), Role::Admin);
+        assert!("root".parse::<Role>().is_err());
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse::<Role>().expect("round trip"), role);
+        }
+    }
+
+    #[test]
+    fn test_resolve_uses_default_without_explicit_grant() {
+        let reg = RoleRegistry::default();
+        assert_eq!(reg.resolve("u1", Some("g1"), &[]), Role::User);
+    }
+
+    #[test]
+    fn test_resolve_takes_highest_explicit_grant() {
+        let mut reg = RoleRegistry::default();
+        reg.guild_roles.insert("r_ops".into(), Role::Operator);
+        reg.guild_roles.insert("r_admin".into(), Role::Admin);
+        assert_eq!(
+            reg.resolve("u1", Some("g1"), &["r_ops".into()]),
+            Role::Operator
+        );
+        assert_eq!(
+            reg.resolve("u1", Some("g1"), &["r_ops".into(), "r_admin".into()]),
+            Role::Admin
+        );
+    }
+
+    #[test]
+    fn test_explicit_read_only_demotes_below_default() {
+        let mut reg = RoleRegistry::default();
+        reg.users.insert("u1".into(), Role::ReadOnly);
+        assert_eq!(reg.resolve("u1", None, &[]), Role::ReadOnly);
+    }
+
+    #[test]
+    fn test_guild_id_grant_applies_to_everyone_in_guild() {
+        let mut reg = RoleRegistry::default();
+        reg.guild_roles.insert("g1".into(), Role::Operator);
+        assert_eq!(reg.resolve("anyone", Some("g1"), &[]), Role::Operator);
+        assert_eq!(reg.resolve("anyone", Some("g2"), &[]), Role::User);
+    }
+
+    #[tokio::test]
+    async fn test_set_and_revoke_persist_to_disk() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = StateStore::with_path(dir.path().join("state.json"));
+        let manager = RoleManager::with_store(store.clone())?;
+
+        manager
+            .set(&RoleTarget::User("u1".into()), Some(Role::Admin))
+            .await?;
+        manager
+            .set(&RoleTarget::GuildRole("r1".into()), Some(Role::Operator))
+            .await?;
+        assert_eq!(manager.resolve("u1", None, &[]), Role::Admin);
+        assert_eq!(manager.resolve("u2", None, &["r1".into()]), Role::Operator);
+
+        manager.set(&RoleTarget::User("u1".into()), None).await?;
+        assert_eq!(manager.resolve("u1", None, &[]), Role::User);
+        assert!(manager.format_grants().contains("role r1: operator"));
+        assert_eq!(
+            store.read()?.roles.guild_roles.get("r1"),
+            Some(&Role::Operator)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cached_roles_change_only_after_reload() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = StateStore::with_path(dir.path().join("state.json"));
+        let manager = RoleManager::with_store(store.clone())?;
+        assert!(!manager.load().has_admin());
+
+        // 另一個行程（CLI）直接改檔，daemon 要 reload 才看得到
+        RoleManager::with_store(store)?
+            .set(&RoleTarget::User("u1".into()), Some(Role::Admin))
+            .await?;
+        assert_eq!(manager.resolve("u1", None, &[]), Role::User);
+        manager.reload().await?;
+        assert_eq!(manager.resolve("u1", None, &[]), Role::Admin);
+        assert!(manager.load().has_admin());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_state_is_an_error_and_not_overwritten() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = StateStore::with_path(dir.path().join("state.json"));
+        let manager = RoleManager::with_store(store.clone())?;
+        manager
+            .set(&RoleTarget::User("u1".into()), Some(Role::Admin))
+            .await?;
+
+        std::fs::write(store.path(), "{not json")?;
+        assert!(RoleManager::with_store(store.clone()).is_err());
+        assert!(manager.reload().await.is_err());
+        assert!(manager
+            .set(&RoleTarget::User("u2".into()), Some(Role::User))
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_to_string(store.path())?, "{not json");
+        // 讀取失敗時沿用原本的快取
+        assert_eq!(manager.resolve("u1", None, &[]), Role::Admin);
+        Ok(())
+    }
+}
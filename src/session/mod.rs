@@ -1,20 +1,85 @@
-use crate::agent::{AgentType, AiAgent, CopilotAgent, KiloAgent, OpencodeAgent, PiAgent};
+use crate::agent::acp::AcpCommand;
+use crate::agent::{
+    copilot, AcpAgent, AgentType, AiAgent, KiloAgent, OpenAiAgent, OpencodeAgent, PiAgent,
+};
+use crate::channels::ChannelRegistry;
 use crate::config::Config;
 use crate::migrate;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+use tracing::info;
 
 pub struct SessionManager {
     sessions: Arc<RwLock<HashMap<u64, Arc<dyn AiAgent>>>>,
-    config: Arc<Config>,
+    /// 每個頻道最後一次取用 session 的時間，供閒置回收與 LRU 使用
+    last_used: StdMutex<HashMap<u64, Instant>>,
+    /// 回合進行中的頻道，回收時一律略過；值為回合編號，舊回合的 guard 不會清掉新回合
+    in_turn: StdMutex<HashMap<u64, u64>>,
+    next_turn: std::sync::atomic::AtomicU64,
+    config: Arc<RwLock<Config>>,
+    channels: Arc<ChannelRegistry>,
+}
+
+/// 頻道回合進行中的標記；回合結束或任務被中止而 drop 時清除
+pub struct TurnGuard {
+    manager: Arc<SessionManager>,
+    channel_id: u64,
+    turn: u64,
+}
+
+impl TurnGuard {
+    /// 收到 agent 事件時更新最後使用時間
+    pub fn touch(&self) {
+        self.manager.touch(self.channel_id);
+    }
+}
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        let mut in_turn = self.manager.in_turn.lock().unwrap_or_else(|e| e.into_inner());
+        if in_turn.get(&self.channel_id) == Some(&self.turn) {
+            in_turn.remove(&self.channel_id);
+        }
+        drop(in_turn);
+        self.manager.touch(self.channel_id);
+    }
 }
 
 impl SessionManager {
-    pub fn new(config: Arc<Config>) -> Self {
+    pub fn new(config: Arc<RwLock<Config>>, channels: Arc<ChannelRegistry>) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            last_used: StdMutex::new(HashMap::new()),
+            in_turn: StdMutex::new(HashMap::new()),
+            next_turn: std::sync::atomic::AtomicU64::new(0),
             config,
+            channels,
+        }
+    }
+
+    fn touch(&self, channel_id: u64) {
+        self.last_used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(channel_id, Instant::now());
+    }
+
+    /// 標記頻道開始一個回合，持有回傳的 guard 期間 session 不會被回收
+    pub fn begin_turn(self: &Arc<Self>, channel_id: u64) -> TurnGuard {
+        let turn = self
+            .next_turn
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.in_turn
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(channel_id, turn);
+        self.touch(channel_id);
+        TurnGuard {
+            manager: Arc::clone(self),
+            channel_id,
+            turn,
         }
     }
 
@@ -28,16 +93,15 @@ impl SessionManager {
             let sessions = self.sessions.read().await;
             if let Some(session) = sessions.get(&channel_id) {
                 if session.agent_type() == agent_type.to_string() {
+                    self.touch(channel_id);
                     return Ok((session.clone(), false));
                 }
             }
         }
 
         let channel_id_str = channel_id.to_string();
-        let channel_config = crate::commands::agent::ChannelConfig::load()
-            .await
-            .unwrap_or_default();
-        let entry = channel_config.channels.get(&channel_id_str);
+        let entry = self.channels.get(&channel_id_str);
+        let entry = entry.as_ref();
 
         let model_opt = entry.and_then(|e| {
             if let (Some(p), Some(m)) = (&e.model_provider, &e.model_id) {
@@ -48,44 +112,116 @@ impl SessionManager {
         });
 
         let existing_sid = entry.and_then(|e| e.session_id.clone());
+        let variant = entry.and_then(|e| e.variant.clone());
+        let server_agent = entry.and_then(|e| e.server_agent.clone());
+        let server = entry.and_then(|e| e.server.clone());
 
-        let session: Arc<dyn AiAgent> = match agent_type {
+        // 允許清單可能在綁定後被改掉，每次建立 session 都重新檢查
+        let workdir = match entry.and_then(|e| e.workdir.as_deref()) {
+            Some(dir) => Some(self.config.read().await.workspace.resolve(dir)?),
+            None => None,
+        };
+        let workdir_str = workdir.as_ref().map(|p| p.to_string_lossy().to_string());
+
+        // 被回收的 Pi session 會從既有的 session 檔接續，不算全新對話
+        let mut resumed = false;
+        let session: Arc<dyn AiAgent> = match &agent_type {
             AgentType::Pi => {
                 let session_dir = migrate::get_sessions_dir("pi");
                 std::fs::create_dir_all(&session_dir)?;
-                let (pi_agent, _) = PiAgent::new(channel_id, &session_dir).await?;
+                let session_file = session_dir.join(format!("discord-rs-{}.jsonl", channel_id));
+                resumed = std::fs::metadata(&session_file)
+                    .map(|m| m.len() > 0)
+                    .unwrap_or(false);
+                let (pi_agent, _) = PiAgent::new(channel_id, &session_dir, workdir.as_deref()).await?;
                 pi_agent
             }
             AgentType::Opencode => {
-                let port = backend_manager.ensure_backend(&AgentType::Opencode).await?;
-                let api_url = format!("http://127.0.0.1:{}", port);
-                let api_key = self.config.opencode.password.clone().unwrap_or_default();
+                let endpoint = backend_manager
+                    .resolve_server(&AgentType::Opencode, server.as_deref())
+                    .await?;
 
                 let agent = OpencodeAgent::new(
                     channel_id,
-                    api_url,
-                    api_key,
+                    endpoint,
                     existing_sid,
                     model_opt,
                     "opencode",
+                    workdir_str,
+                    Arc::clone(&self.channels),
                 )
                 .await?;
+                agent.restore_prompt_options(variant, server_agent).await;
+                backend_manager.attach_event_stream(&agent).await;
 
-                self.persist_sid(channel_id, AgentType::Opencode, agent.session_id.clone())
+                self.persist_sid(channel_id, AgentType::Opencode, agent.session_id())
                     .await?;
                 agent
             }
             AgentType::Copilot => {
-                let agent = CopilotAgent::new(channel_id, existing_sid, model_opt).await?;
+                let agent = AcpAgent::new(
+                    copilot::command(),
+                    channel_id,
+                    existing_sid,
+                    model_opt,
+                    workdir_str,
+                    Arc::clone(&self.channels),
+                )
+                .await?;
                 self.persist_sid(channel_id, AgentType::Copilot, agent.session_id())
                     .await?;
                 agent
             }
+            AgentType::Acp(name) => {
+                let command = {
+                    let config = self.config.read().await;
+                    AcpCommand::from_config(name, config.acp_agent(name)?)
+                };
+                let agent = AcpAgent::new(
+                    command,
+                    channel_id,
+                    existing_sid,
+                    model_opt,
+                    workdir_str,
+                    Arc::clone(&self.channels),
+                )
+                .await?;
+                self.persist_sid(channel_id, agent_type.clone(), agent.session_id())
+                    .await?;
+                agent
+            }
+            AgentType::OpenAiCompat => {
+                let config = self.config.read().await.openai.clone();
+                let session_dir = migrate::get_sessions_dir("openai");
+                OpenAiAgent::new(
+                    channel_id,
+                    config,
+                    &session_dir,
+                    model_opt,
+                    workdir,
+                    Arc::clone(&self.channels),
+                )
+                .await?
+            }
             AgentType::Kilo => {
-                let port = backend_manager.ensure_backend(&AgentType::Kilo).await?;
-                let api_url = format!("http://127.0.0.1:{}", port);
+                let endpoint = backend_manager
+                    .resolve_server(&AgentType::Kilo, server.as_deref())
+                    .await?;
 
-                let agent = KiloAgent::new(channel_id, api_url, existing_sid, model_opt).await?;
+                let agent = KiloAgent::new(
+                    channel_id,
+                    endpoint,
+                    existing_sid,
+                    model_opt,
+                    workdir_str,
+                    Arc::clone(&self.channels),
+                )
+                .await?;
+                agent
+                    .inner()
+                    .restore_prompt_options(variant, server_agent)
+                    .await;
+                backend_manager.attach_event_stream(agent.inner()).await;
 
                 self.persist_sid(channel_id, AgentType::Kilo, agent.session_id())
                     .await?;
@@ -97,8 +233,16 @@ impl SessionManager {
             let mut sessions = self.sessions.write().await;
             sessions.insert(channel_id, session.clone());
         }
+        self.touch(channel_id);
+        backend_manager.mark_running(&agent_type.to_string());
+        if agent_type == AgentType::Pi {
+            // 新的這個還沒開始回合，明確排除以免剛建立就被回收
+            self.evict_pi_sessions_except(Some(channel_id)).await;
+        }
 
-        let is_brand_new = if let Ok(state) = session.get_state().await {
+        let is_brand_new = if resumed {
+            false
+        } else if let Ok(state) = session.get_state().await {
             state.message_count == 0
         } else {
             true
@@ -116,50 +260,208 @@ impl SessionManager {
         let entry = channel_config
             .channels
             .entry(channel_id.to_string())
-            .or_insert_with(|| crate::commands::agent::ChannelEntry {
-                agent_type: agent_type.clone(),
-                authorized_at: chrono::Utc::now().to_rfc3339(),
-                mention_only: true,
-                session_id: None,
-                model_provider: None,
-                model_id: None,
-                assistant_name: None,
-            });
+            .or_insert_with(|| crate::commands::agent::ChannelEntry::new(agent_type));
 
         entry.session_id = Some(sid);
     }
 
-    async fn persist_sid(
+    pub async fn persist_sid(
         &self,
         channel_id: u64,
         agent_type: AgentType,
         sid: String,
     ) -> anyhow::Result<()> {
         let channel_id_str = channel_id.to_string();
-        let mut channel_config = crate::commands::agent::ChannelConfig::load()
+        self.channels
+            .update(|channel_config| {
+                Self::apply_sid(channel_config, &channel_id_str, agent_type, sid);
+                Ok(())
+            })
             .await
-            .unwrap_or_default();
+    }
+
+    /// 取得已存在的 session，不會建立新的
+    pub async fn get_session(&self, channel_id: u64) -> Option<Arc<dyn AiAgent>> {
+        let session = self.sessions.read().await.get(&channel_id).cloned();
+        if session.is_some() {
+            self.touch(channel_id);
+        }
+        session
+    }
 
-        Self::apply_sid(&mut channel_config, &channel_id_str, agent_type, sid);
-        channel_config.save().await?;
-        Ok(())
+    /// 目前記憶體中的 session：(channel_id, agent_type)，依頻道排序
+    pub async fn list_sessions(&self) -> Vec<(u64, String)> {
+        let sessions = self.sessions.read().await;
+        let mut list: Vec<(u64, String)> = sessions
+            .iter()
+            .map(|(id, agent)| (*id, agent.agent_type()))
+            .collect();
+        list.sort_by_key(|(id, _)| *id);
+        list
     }
 
     pub async fn remove_session(&self, channel_id: u64) {
         let mut sessions = self.sessions.write().await;
-        sessions.remove(&channel_id);
+        if let Some(agent) = sessions.remove(&channel_id) {
+            // 其他 task 可能還握著 Arc，直接結束子進程而不是等 Drop
+            agent.terminate();
+        }
+        self.last_used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&channel_id);
+    }
+
+    /// 頻道的 session 不是 agent_type 時移除它，回傳是否移除；
+    /// 已經換成新後端的 session 不受影響
+    pub async fn remove_if_backend_differs(&self, channel_id: u64, agent_type: &AgentType) -> bool {
+        let stale = self
+            .sessions
+            .read()
+            .await
+            .get(&channel_id)
+            .is_some_and(|agent| agent.agent_type() != agent_type.to_string());
+        if stale {
+            self.remove_session(channel_id).await;
+        }
+        stale
+    }
+
+    /// 關機時移除所有 session 並結束它們專屬的後端進程，回傳 session 數
+    pub async fn shutdown(&self) -> usize {
+        let sessions = std::mem::take(&mut *self.sessions.write().await);
+        for agent in sessions.values() {
+            agent.terminate();
+        }
+        futures::future::join_all(sessions.values().map(|agent| agent.wait_exit())).await;
+        self.last_used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        sessions.len()
+    }
+
+    /// 依 `[pi]` 設定回收閒置或超出上限的 Pi 子進程，回傳被回收的頻道
+    pub async fn evict_pi_sessions(&self) -> Vec<u64> {
+        self.evict_pi_sessions_except(None).await
+    }
+
+    async fn evict_pi_sessions_except(&self, keep: Option<u64>) -> Vec<u64> {
+        let pi = self.config.read().await.pi.clone();
+        let idle_timeout = (pi.idle_timeout_mins > 0)
+            .then(|| Duration::from_secs(pi.idle_timeout_mins * 60));
+        let evicted = self
+            .evict_sessions(&AgentType::Pi, idle_timeout, pi.max_live_sessions, keep)
+            .await;
+        if !evicted.is_empty() {
+            info!("♻️ Evicted idle pi sessions: {:?}", evicted);
+        }
+        evicted
+    }
+
+    /// 先從 map 移除再結束進程，supervisor 就不會把它當成崩潰重啟
+    async fn evict_sessions(
+        &self,
+        agent_type: &AgentType,
+        idle_timeout: Option<Duration>,
+        max_live: usize,
+        keep: Option<u64>,
+    ) -> Vec<u64> {
+        let name = agent_type.to_string();
+        let now = Instant::now();
+        let mut sessions = self.sessions.write().await;
+        let mut last_used = self.last_used.lock().unwrap_or_else(|e| e.into_inner());
+        let in_turn = self.in_turn.lock().unwrap_or_else(|e| e.into_inner());
+        let candidates: Vec<(u64, Instant, bool)> = sessions
+            .iter()
+            .filter(|(_, agent)| agent.agent_type() == name)
+            .map(|(id, _)| {
+                let used = last_used.get(id).copied().unwrap_or(now);
+                (*id, used, in_turn.contains_key(id) || keep == Some(*id))
+            })
+            .collect();
+        drop(in_turn);
+        let evicted = select_evictions(candidates, now, idle_timeout, max_live);
+        for id in &evicted {
+            if let Some(agent) = sessions.remove(id) {
+                agent.terminate();
+            }
+            last_used.remove(id);
+        }
+        evicted
+    }
+
+    /// 移除某個後端的所有 session（例如共用的 server 重啟），回傳受影響的頻道
+    pub async fn remove_sessions_of(&self, agent_type: &AgentType) -> Vec<u64> {
+        let name = agent_type.to_string();
+        let mut sessions = self.sessions.write().await;
+        let mut channels: Vec<u64> = sessions
+            .iter()
+            .filter(|(_, agent)| agent.agent_type() == name)
+            .map(|(id, _)| *id)
+            .collect();
+        channels.sort_unstable();
+        for id in &channels {
+            sessions.remove(id);
+        }
+        channels
+    }
+
+    /// 移除後端進程已結束的 session：(channel_id, agent_type, 原因)
+    pub async fn take_dead_sessions(&self) -> Vec<(u64, AgentType, String)> {
+        let mut sessions = self.sessions.write().await;
+        let mut dead: Vec<(u64, AgentType, String)> = sessions
+            .iter()
+            .filter_map(|(id, agent)| {
+                let reason = agent.exit_reason()?;
+                let agent_type = agent.agent_type().parse().ok()?;
+                Some((*id, agent_type, reason))
+            })
+            .collect();
+        dead.sort_by_key(|(id, _, _)| *id);
+        for (id, _, _) in &dead {
+            sessions.remove(id);
+        }
+        dead
     }
 }
 
+/// 候選為 (channel_id, 最後使用時間, 是否使用中)；由最久沒用的開始，
+/// 回收閒置逾時的，以及讓存活數降到上限所需的；使用中的一律保留
+fn select_evictions(
+    mut candidates: Vec<(u64, Instant, bool)>,
+    now: Instant,
+    idle_timeout: Option<Duration>,
+    max_live: usize,
+) -> Vec<u64> {
+    candidates.sort_by_key(|(id, used, _)| (*used, *id));
+    let mut live = candidates.len();
+    let mut evicted = Vec::new();
+    for (id, used, busy) in candidates {
+        if busy {
+            continue;
+        }
+        let idle = idle_timeout.is_some_and(|t| now.saturating_duration_since(used) >= t);
+        let over_cap = max_live > 0 && live > max_live;
+        if idle || over_cap {
+            evicted.push(id);
+            live -= 1;
+        }
+    }
+    evicted
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::agent::{AiAgent, MockAgent};
+    use tempfile::tempdir;
 
     #[tokio::test]
     async fn test_remove_session_clears_cached_agent() {
-        let config = Arc::new(Config::default());
-        let manager = SessionManager::new(config);
+        let config = Arc::new(RwLock::new(Config::default()));
+        let dir = tempdir().expect("tempdir");
+        let manager = SessionManager::new(config, ChannelRegistry::in_dir(dir.path()));
         let channel_id = 42_u64;
         let mock_agent: Arc<dyn AiAgent> = Arc::new(MockAgent::new());
 
@@ -175,6 +477,168 @@ mod tests {
         assert!(!sessions.contains_key(&channel_id));
     }
 
+    #[tokio::test]
+    async fn test_list_sessions_reports_sorted_channels() {
+        let dir = tempdir().expect("tempdir");
+        let manager = SessionManager::new(
+            Arc::new(RwLock::new(Config::default())),
+            ChannelRegistry::in_dir(dir.path()),
+        );
+        {
+            let mut sessions = manager.sessions.write().await;
+            sessions.insert(7, Arc::new(MockAgent::new()) as Arc<dyn AiAgent>);
+            sessions.insert(3, Arc::new(MockAgent::new()) as Arc<dyn AiAgent>);
+        }
+
+        let list = manager.list_sessions().await;
+        assert_eq!(list.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![3, 7]);
+        assert!(manager.get_session(7).await.is_some());
+        assert!(manager.get_session(8).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dead_and_per_backend_sessions_are_removed() {
+        let dir = tempdir().expect("tempdir");
+        let manager = SessionManager::new(
+            Arc::new(RwLock::new(Config::default())),
+            ChannelRegistry::in_dir(dir.path()),
+        );
+        let crashed = MockAgent::of_kind("pi");
+        *crashed.exit_reason.lock().unwrap() = Some("exited with signal: 9".into());
+        {
+            let mut sessions = manager.sessions.write().await;
+            sessions.insert(1, Arc::new(crashed) as Arc<dyn AiAgent>);
+            sessions.insert(2, Arc::new(MockAgent::of_kind("pi")) as Arc<dyn AiAgent>);
+            sessions.insert(3, Arc::new(MockAgent::of_kind("kilo")) as Arc<dyn AiAgent>);
+            sessions.insert(4, Arc::new(MockAgent::of_kind("kilo")) as Arc<dyn AiAgent>);
+        }
+
+        let dead = manager.take_dead_sessions().await;
+        assert_eq!(
+            dead,
+            vec![(1, AgentType::Pi, "exited with signal: 9".to_string())]
+        );
+        assert!(manager.take_dead_sessions().await.is_empty());
+
+        assert_eq!(manager.remove_sessions_of(&AgentType::Kilo).await, vec![3, 4]);
+        let left: Vec<u64> = manager.list_sessions().await.iter().map(|(id, _)| *id).collect();
+        assert_eq!(left, vec![2]);
+
+        // 頻道換後端時只丟掉類型不符的 session
+        assert!(!manager.remove_if_backend_differs(2, &AgentType::Pi).await);
+        assert!(manager.remove_if_backend_differs(2, &AgentType::Kilo).await);
+        assert!(manager.get_session(2).await.is_none());
+    }
+
+    #[test]
+    fn test_select_evictions_idle_then_lru_skipping_busy() {
+        let now = Instant::now();
+        let mins = |m: u64| now - Duration::from_secs(m * 60);
+        let candidates = vec![
+            (1, mins(40), false),
+            (2, mins(50), true),
+            (3, mins(5), false),
+            (4, mins(10), false),
+            (5, mins(1), false),
+        ];
+        let timeout = Some(Duration::from_secs(30 * 60));
+
+        // 只有 1 閒置逾時；2 雖然更久但正在使用
+        assert_eq!(select_evictions(candidates.clone(), now, timeout, 0), vec![1]);
+        // 上限 3：先收 1，再依 LRU 收 4
+        assert_eq!(select_evictions(candidates.clone(), now, timeout, 3), vec![1, 4]);
+        // 不設閒置逾時時只看上限
+        assert_eq!(select_evictions(candidates.clone(), now, None, 4), vec![1]);
+        assert!(select_evictions(candidates, now, None, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_evict_sessions_terminates_idle_pi_only() {
+        use std::sync::atomic::Ordering;
+        let dir = tempdir().expect("tempdir");
+        let manager = Arc::new(SessionManager::new(
+            Arc::new(RwLock::new(Config::default())),
+            ChannelRegistry::in_dir(dir.path()),
+        ));
+        let idle = MockAgent::of_kind("pi");
+        let idle_flag = Arc::clone(&idle.terminated);
+        let busy = MockAgent::of_kind("pi");
+        let busy_flag = Arc::clone(&busy.terminated);
+        {
+            let mut sessions = manager.sessions.write().await;
+            sessions.insert(1, Arc::new(idle) as Arc<dyn AiAgent>);
+            sessions.insert(2, Arc::new(busy) as Arc<dyn AiAgent>);
+            sessions.insert(3, Arc::new(MockAgent::of_kind("kilo")) as Arc<dyn AiAgent>);
+        }
+        // 正在回覆的頻道標記為回合中；只握著 Arc 不算使用中
+        let busy_turn = manager.begin_turn(2);
+        let _idle_handle = manager.get_session(1).await.expect("session 1");
+        let old = Instant::now() - Duration::from_secs(3600);
+        manager.last_used.lock().unwrap().extend([(1, old), (2, old), (3, old)]);
+
+        let evicted = manager
+            .evict_sessions(&AgentType::Pi, Some(Duration::from_secs(60)), 0, None)
+            .await;
+        assert_eq!(evicted, vec![1]);
+        assert!(idle_flag.load(Ordering::SeqCst));
+        assert!(!busy_flag.load(Ordering::SeqCst));
+
+        // 舊回合的 guard 晚於新回合 drop 時，不會清掉新回合的標記
+        let newer_turn = manager.begin_turn(2);
+        drop(busy_turn);
+        assert!(manager.in_turn.lock().unwrap().contains_key(&2));
+        drop(newer_turn);
+        assert!(manager.in_turn.lock().unwrap().is_empty());
+
+        let left: Vec<u64> = manager.list_sessions().await.iter().map(|(id, _)| *id).collect();
+        assert_eq!(left, vec![2, 3]);
+        // 回收不是崩潰，supervisor 不該看到它
+        assert!(manager.take_dead_sessions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_session_terminates_backend_process() {
+        let dir = tempdir().expect("tempdir");
+        let manager = SessionManager::new(
+            Arc::new(RwLock::new(Config::default())),
+            ChannelRegistry::in_dir(dir.path()),
+        );
+        let agent = MockAgent::of_kind("pi");
+        let flag = Arc::clone(&agent.terminated);
+        manager
+            .sessions
+            .write()
+            .await
+            .insert(9, Arc::new(agent) as Arc<dyn AiAgent>);
+
+        manager.remove_session(9).await;
+        assert!(flag.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(manager.get_session(9).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_terminates_every_session() {
+        let dir = tempdir().expect("tempdir");
+        let manager = SessionManager::new(
+            Arc::new(RwLock::new(Config::default())),
+            ChannelRegistry::in_dir(dir.path()),
+        );
+        let mut flags = Vec::new();
+        for (id, kind) in [(1, "pi"), (2, "copilot"), (3, "kilo")] {
+            let agent = MockAgent::of_kind(kind);
+            flags.push(Arc::clone(&agent.terminated));
+            manager
+                .sessions
+                .write()
+                .await
+                .insert(id, Arc::new(agent) as Arc<dyn AiAgent>);
+        }
+
+        assert_eq!(manager.shutdown().await, 3);
+        assert!(flags.iter().all(|f| f.load(std::sync::atomic::Ordering::SeqCst)));
+        assert!(manager.list_sessions().await.is_empty());
+    }
+
     #[test]
     fn test_apply_sid_creates_channel_entry_when_missing() {
         let mut cfg = crate::commands::agent::ChannelConfig::default();
@@ -199,6 +663,7 @@ mod tests {
                 model_provider: Some("p".to_string()),
                 model_id: Some("m".to_string()),
                 assistant_name: Some("a".to_string()),
+                ..Default::default()
             },
         );
         SessionManager::apply_sid(&mut cfg, "1002", AgentType::Kilo, "new-sid".to_string());
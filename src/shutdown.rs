@@ -0,0 +1,193 @@
+use serenity::all::{ChannelId, CreateEmbed, EditMessage, Http, MessageId};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+use crate::inflight::is_resumable;
+
+/// 收到停止訊號後，整理回覆與寫檔的時間上限；超過就直接結束子進程
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+const INTERRUPTED_COLOR: u32 = 0x808080;
+
+/// 等待 SIGTERM（systemctl stop）或 Ctrl+C
+pub async fn wait_for_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = term.recv() => info!("🛑 Received SIGTERM"),
+                _ = ctrl_c => info!("🛑 Received Ctrl+C"),
+            }
+        }
+        Err(e) => {
+            error!("❌ Failed to install SIGTERM handler: {}", e);
+            let _ = ctrl_c.await;
+            info!("🛑 Received Ctrl+C");
+        }
+    }
+}
+
+/// 停止接收訊息、把進行中的回覆標成中斷（外部 server 上的留待重啟後接回）、
+/// 寫回排程、頻道設定與用量，最後結束所有子進程
+pub async fn run(state: &crate::AppState, http: Arc<Http>, deadline: Duration) {
+    state.shutting_down.store(true, Ordering::SeqCst);
+    info!("🛑 Shutting down (deadline {:?})", deadline);
+
+    if tokio::time::timeout(deadline, drain(state, http))
+        .await
+        .is_err()
+    {
+        warn!("⚠️ Shutdown deadline exceeded, stopping child processes now");
+    }
+
+    let sessions = state.session_manager.shutdown().await;
+    let runtimes = crate::agent::acp::shutdown_runtimes().await;
+    let servers = state.backend_manager.shutdown().await;
+    info!(
+        "👋 Stopped {} sessions, ACP runtimes {:?}, servers {:?}",
+        sessions, runtimes, servers
+    );
+}
+
+async fn drain(state: &crate::AppState, http: Arc<Http>) {
+    let active: Vec<(u64, MessageId)> = {
+        let mut active = state.active_renders.lock().await;
+        active
+            .drain()
+            .filter_map(|(channel_id, render)| {
+                // 先停掉 render 任務，免得它蓋掉中斷訊息
+                for h in render.handles {
+                    h.abort();
+                }
+                // 還在準備中的回合沒有訊息要標記
+                render.message_id.map(|msg_id| (channel_id, msg_id))
+            })
+            .collect()
+    };
+
+    let (title, hint) = interrupted_texts(state).await;
+    let channel_config = state.channels.snapshot();
+    let inflight = state.inflight.load().await.unwrap_or_else(|e| {
+        error!("❌ Failed to read in-flight turns: {}", e);
+        Default::default()
+    });
+    let config = state.config.read().await.clone();
+    let mut tasks = JoinSet::new();
+    for (channel_id, msg_id) in active {
+        // 外部 server 上的回合會繼續跑，留著紀錄讓下次啟動接回
+        let resumable = inflight
+            .get(&channel_id)
+            .filter(|t| t.message_id == msg_id.get())
+            .is_some_and(|t| {
+                let entry = channel_config.channels.get(&channel_id.to_string());
+                is_resumable(&config, t, entry)
+            });
+        if resumable {
+            info!(
+                "⏸️ Leaving turn in channel {} running for resume",
+                channel_id
+            );
+            continue;
+        }
+        let agent = state.session_manager.get_session(channel_id).await;
+        let http = Arc::clone(&http);
+        let inflight = Arc::clone(&state.inflight);
+        let (title, hint) = (title.clone(), hint.clone());
+        tasks.spawn(async move {
+            if let Some(agent) = agent {
+                if let Err(e) = agent.abort().await {
+                    warn!("⚠️ Failed to abort agent in channel {}: {}", channel_id, e);
+                }
+            }
+            mark_interrupted(&http, ChannelId::new(channel_id), msg_id, &title, &hint).await;
+            if let Err(e) = inflight.finish(channel_id, msg_id.get()).await {
+                error!("❌ Failed to clear in-flight turn {}: {}", channel_id, e);
+            }
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+
+    if let Err(e) = state.cron_manager.shutdown().await {
+        error!("❌ Failed to flush cron jobs: {}", e);
+    }
+    if let Err(e) = state.channels.flush().await {
+        error!("❌ Failed to flush state: {}", e);
+    }
+    if let Err(e) = state.usage.flush().await {
+        error!("❌ Failed to flush usage: {}", e);
+    }
+}
+
+/// 中斷訊息的標題與說明
+pub async fn interrupted_texts(state: &crate::AppState) -> (String, String) {
+    let i18n = state.i18n.read().await;
+    (
+        i18n.get("turn_interrupted"),
+        i18n.get("turn_interrupted_hint"),
+    )
+}
+
+/// 保留已輸出的內容，只把標題與顏色改成中斷狀態
+pub async fn mark_interrupted(
+    http: &Http,
+    channel_id: ChannelId,
+    msg_id: MessageId,
+    title: &str,
+    hint: &str,
+) {
+    let previous = match channel_id.message(http, msg_id).await {
+        Ok(msg) => msg.embeds.first().and_then(|e| e.description.clone()),
+        Err(e) => {
+            warn!("⚠️ Cannot fetch interrupted message {}: {}", msg_id, e);
+            None
+        }
+    };
+    let embed = interrupted_embed(title, hint, previous.as_deref());
+    if let Err(e) = channel_id
+        .edit_message(
+            http,
+            msg_id,
+            EditMessage::new().embed(embed).components(vec![]),
+        )
+        .await
+    {
+        error!("❌ Failed to mark message {} as interrupted: {}", msg_id, e);
+    }
+}
+
+fn interrupted_description(hint: &str, previous: Option<&str>) -> String {
+    match previous.map(str::trim) {
+        Some(prev) if !prev.is_empty() => format!("{}\n\n*{}*", prev, hint),
+        _ => format!("*{}*", hint),
+    }
+}
+
+fn interrupted_embed(title: &str, hint: &str, previous: Option<&str>) -> CreateEmbed {
+    CreateEmbed::new()
+        .title(title)
+        .color(INTERRUPTED_COLOR)
+        .description(interrupted_description(hint, previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupted_description_keeps_partial_output() {
+        assert_eq!(
+            interrupted_description("Send again.", Some("partial answer\n")),
+            "partial answer\n\n*Send again.*"
+        );
+        assert_eq!(
+            interrupted_description("Send again.", Some("  ")),
+            "*Send again.*"
+        );
+        assert_eq!(
+            interrupted_description("Send again.", None),
+            "*Send again.*"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::auth::{PendingStore, Registry};
+use crate::budget::BudgetOverrides;
+use crate::commands::agent::ChannelConfig;
+use crate::cron::manager::CronJobInfo;
+use crate::inflight::InflightTurn;
+use crate::migrate;
+use crate::roles::RoleRegistry;
+use anyhow::Result;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// state.json 的內容：頻道設定、授權、角色、待兌換的 token、排程、預算解除與進行中的回合，
+/// 整份一起讀寫
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct State {
+    #[serde(default)]
+    pub channels: ChannelConfig,
+    #[serde(default)]
+    pub auth: Registry,
+    #[serde(default)]
+    pub pending: PendingStore,
+    #[serde(default)]
+    pub cron_jobs: HashMap<Uuid, CronJobInfo>,
+    #[serde(default)]
+    pub budget_overrides: BudgetOverrides,
+    #[serde(default)]
+    pub roles: RoleRegistry,
+    /// channel_id -> 進行中的回合
+    #[serde(default)]
+    pub inflight: BTreeMap<u64, InflightTurn>,
+}
+
+/// 所有持久化狀態的唯一入口。更新時持有 fs2 排他鎖做 read-modify-write，
+/// 先寫暫存檔並 fsync 再改名，讀取端永遠看到完整的一份
+#[derive(Clone, Debug)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    pub fn open() -> Self {
+        Self::with_path(migrate::get_state_path())
+    }
+
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 鎖在旁邊的 state.lock 上，state.json 本身會被改名取代
+    fn lock(&self) -> Result<File> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path.with_extension("lock"))?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    /// 檔案不存在時回傳空狀態；內容損毀時回傳錯誤，避免被空狀態覆蓋
+    pub fn read(&self) -> Result<State> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) if content.trim().is_empty() => Ok(State::default()),
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("{} is invalid: {}", self.path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// f 回傳錯誤時不寫入
+    pub fn update<R>(&self, f: impl FnOnce(&mut State) -> Result<R>) -> Result<R> {
+        let _lock = self.lock()?;
+        let mut state = self.read()?;
+        let out = f(&mut state)?;
+        self.write(&state)?;
+        Ok(out)
+    }
+
+    /// 在 blocking 執行緒上做 `update`，fs2 鎖與 fsync 不會卡住 tokio worker
+    pub async fn update_async<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut State) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.update(f)).await?
+    }
+
+    /// 等待進行中的更新完成，同樣不佔用 tokio worker
+    pub async fn flush_async(&self) -> Result<()> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.flush()).await?
+    }
+
+    fn write(&self, state: &State) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// 等待進行中的更新完成（關機用）
+    pub fn flush(&self) -> Result<()> {
+        self.lock().map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::agent::ChannelEntry;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_update_is_atomic_across_threads() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(StateStore::with_path(dir.path().join("state.json")));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        store
+                            .update(|s| {
+                                s.channels
+                                    .channels
+                                    .insert(format!("{}-{}", i, j), ChannelEntry::default());
+                                Ok(())
+                            })
+                            .expect("update");
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().expect("join");
+        }
+        // 沒有鎖時並行的 read-modify-write 會互相蓋掉
+        assert_eq!(store.read().expect("read").channels.channels.len(), 80);
+        assert!(!dir.path().join("state.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_failed_update_writes_nothing_and_corrupt_file_is_an_error() {
+        let dir = tempdir().expect("tempdir");
+        let store = StateStore::with_path(dir.path().join("state.json"));
+        let err = store
+            .update(|s| {
+                s.channels
+                    .channels
+                    .insert("1".to_string(), ChannelEntry::default());
+                anyhow::bail!("nope")
+            })
+            .map(|_: ()| ())
+            .expect_err("update should fail");
+        assert_eq!(err.to_string(), "nope");
+        assert!(store.read().expect("read").channels.channels.is_empty());
+
+        fs::write(store.path(), "{not json").expect("write");
+        assert!(store.read().is_err());
+        assert!(store.update(|_| Ok(())).is_err());
+        assert_eq!(fs::read_to_string(store.path()).expect("read"), "{not json");
+    }
+}
@@ -0,0 +1,256 @@
+use serenity::all::{ChannelId, CreateMessage, Http};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::agent::events::backoff_delay;
+use crate::agent::AgentType;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 等待重啟的後端
+#[derive(Debug)]
+struct PendingRestart {
+    agent_type: AgentType,
+    reason: String,
+    attempt: u32,
+    next_at: Instant,
+    /// 共用 server（OpenCode/Kilo）需要先把 server 拉起來
+    restart_server: bool,
+    /// 需要重新接上 session 並通知的頻道
+    channels: BTreeSet<u64>,
+    /// 後端已經恢復，剩下的只是接回失敗、等待重試的頻道
+    recovered: bool,
+}
+
+/// 一次重啟的結果：接回的頻道，以及接回失敗的頻道與原因
+#[derive(Debug, Default)]
+struct RestartOutcome {
+    reattached: Vec<u64>,
+    failed: BTreeMap<u64, String>,
+}
+
+/// 記錄崩潰；同一個後端已在等待重啟時只合併受影響的頻道
+fn schedule(
+    pending: &mut HashMap<String, PendingRestart>,
+    agent_type: AgentType,
+    reason: String,
+    restart_server: bool,
+    channels: impl IntoIterator<Item = u64>,
+    now: Instant,
+) {
+    let restart = pending
+        .entry(agent_type.to_string())
+        .or_insert_with(|| PendingRestart {
+            agent_type,
+            reason: reason.clone(),
+            attempt: 0,
+            next_at: now + backoff_delay(0),
+            restart_server: false,
+            channels: BTreeSet::new(),
+            recovered: false,
+        });
+    restart.reason = reason;
+    restart.restart_server |= restart_server;
+    restart.recovered = false;
+    restart.channels.extend(channels);
+}
+
+async fn collect_crashes(state: &crate::AppState, pending: &mut HashMap<String, PendingRestart>) {
+    let now = Instant::now();
+    for (agent_type, reason) in state.backend_manager.reap_dead().await {
+        let channels = state.session_manager.remove_sessions_of(&agent_type).await;
+        schedule(pending, agent_type, reason, true, channels, now);
+    }
+    for (channel_id, agent_type, reason) in state.session_manager.take_dead_sessions().await {
+        warn!(
+            "💥 {} backend of channel {} {}",
+            agent_type, channel_id, reason
+        );
+        state
+            .backend_manager
+            .mark_failed(&agent_type.to_string(), &reason);
+        schedule(pending, agent_type, reason, false, [channel_id], now);
+    }
+}
+
+/// 把接回失敗的頻道留下來依退避重試；server 已經起來就不用再拉一次
+fn retry_failed(
+    mut restart: PendingRestart,
+    failed: impl IntoIterator<Item = u64>,
+    now: Instant,
+) -> PendingRestart {
+    restart.channels = failed.into_iter().collect();
+    restart.restart_server = false;
+    restart.recovered = true;
+    restart.attempt += 1;
+    restart.next_at = now + backoff_delay(restart.attempt);
+    restart
+}
+
+/// 重新啟動後端並替仍使用它的頻道重建 session；只有 server 起不來才整體失敗，
+/// 個別頻道接回失敗不影響其他頻道
+async fn restart(
+    state: &crate::AppState,
+    restart: &PendingRestart,
+) -> anyhow::Result<RestartOutcome> {
+    if restart.restart_server {
+        state
+            .backend_manager
+            .ensure_backend(&restart.agent_type)
+            .await?;
+    }
+    // 崩潰後才切換後端的頻道就不用接回了
+    let channel_config = state.channels.snapshot();
+    let mut outcome = RestartOutcome::default();
+    for channel_id in &restart.channels {
+        if channel_config.get_agent_type(&channel_id.to_string()) != restart.agent_type {
+            continue;
+        }
+        let result = state
+            .session_manager
+            .get_or_create_session(
+                *channel_id,
+                restart.agent_type.clone(),
+                &state.backend_manager,
+            )
+            .await;
+        match result {
+            Ok(_) => outcome.reattached.push(*channel_id),
+            Err(e) => {
+                outcome.failed.insert(*channel_id, e.to_string());
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+async fn notify(http: &Http, channels: impl IntoIterator<Item = u64>, msg: &str) {
+    for channel_id in channels {
+        if let Err(e) = ChannelId::new(channel_id)
+            .send_message(http, CreateMessage::new().content(msg))
+            .await
+        {
+            error!("❌ Failed to post backend notice to {}: {}", channel_id, e);
+        }
+    }
+}
+
+/// 監看所有後端進程，崩潰時以指數退避重啟並通知受影響的頻道；順便回收閒置的 Pi 進程
+pub async fn run(state: Arc<crate::AppState>, http: Arc<Http>) {
+    let mut pending: HashMap<String, PendingRestart> = HashMap::new();
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        collect_crashes(&state, &mut pending).await;
+        // 閒置的 Pi 子進程直接回收，下一則訊息會從 session 檔接續
+        state.session_manager.evict_pi_sessions().await;
+
+        let now = Instant::now();
+        let due: Vec<String> = pending
+            .iter()
+            .filter(|(_, r)| r.next_at <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in due {
+            let Some(mut pending_restart) = pending.remove(&name) else {
+                continue;
+            };
+            match restart(&state, &pending_restart).await {
+                Ok(outcome) => {
+                    if !pending_restart.recovered {
+                        info!("🔁 Backend {} restarted", name);
+                        state.backend_manager.count_restart(&name);
+                    }
+                    let msg = state.i18n.read().await.get_args(
+                        "backend_restarted",
+                        &[name.clone(), pending_restart.reason.clone()],
+                    );
+                    notify(&http, outcome.reattached, &msg).await;
+                    if outcome.failed.is_empty() {
+                        continue;
+                    }
+                    for (channel_id, err) in &outcome.failed {
+                        error!(
+                            "❌ Re-attaching channel {} to {} failed (attempt {}): {}",
+                            channel_id,
+                            name,
+                            pending_restart.attempt + 1,
+                            err
+                        );
+                        // 和整體失敗一樣，只在第一次失敗時通知
+                        if pending_restart.attempt == 0 {
+                            let msg = state
+                                .i18n
+                                .read()
+                                .await
+                                .get_args("backend_down", &[name.clone(), err.clone()]);
+                            notify(&http, [*channel_id], &msg).await;
+                        }
+                    }
+                    let failed = outcome.failed.into_keys();
+                    pending.insert(name, retry_failed(pending_restart, failed, now));
+                }
+                Err(e) => {
+                    let err = e.to_string();
+                    error!(
+                        "❌ Restarting backend {} failed (attempt {}): {}",
+                        name,
+                        pending_restart.attempt + 1,
+                        err
+                    );
+                    state.backend_manager.mark_failed(&name, &err);
+                    // 只在第一次失敗時通知，之後靜默重試直到恢復
+                    if pending_restart.attempt == 0 {
+                        let msg = state
+                            .i18n
+                            .read()
+                            .await
+                            .get_args("backend_down", &[name.clone(), err]);
+                        notify(&http, pending_restart.channels.iter().copied(), &msg).await;
+                    }
+                    pending_restart.attempt += 1;
+                    pending_restart.next_at = now + backoff_delay(pending_restart.attempt);
+                    pending.insert(name, pending_restart);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_failed_keeps_only_failed_channels() {
+        let mut pending = HashMap::new();
+        let now = Instant::now();
+        schedule(&mut pending, AgentType::Kilo, "a".into(), true, [1, 2, 3], now);
+        let restart = pending.remove("kilo").expect("kilo");
+
+        let retry = retry_failed(restart, [2], now);
+        assert_eq!(retry.channels.iter().copied().collect::<Vec<_>>(), vec![2]);
+        assert!(!retry.restart_server);
+        assert!(retry.recovered);
+        assert_eq!(retry.attempt, 1);
+        assert_eq!(retry.next_at, now + backoff_delay(1));
+    }
+
+    #[test]
+    fn test_schedule_merges_channels_of_the_same_backend() {
+        let mut pending = HashMap::new();
+        let now = Instant::now();
+        schedule(&mut pending, AgentType::Pi, "a".into(), false, [3], now);
+        schedule(&mut pending, AgentType::Pi, "b".into(), false, [1, 3], now);
+        schedule(&mut pending, AgentType::Kilo, "c".into(), true, [], now);
+
+        assert_eq!(pending.len(), 2);
+        let pi = &pending["pi"];
+        assert_eq!(pi.channels.iter().copied().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(pi.reason, "b");
+        assert!(!pi.restart_server);
+        assert_eq!(pi.next_at, now + backoff_delay(0));
+        assert!(pending["kilo"].restart_server);
+    }
+}
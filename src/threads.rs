@@ -0,0 +1,251 @@
+use serenity::all::{AutoArchiveDuration, ChannelId, CreateThread, EditThread, Http, Message};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::channels::ChannelRegistry;
+
+/// Discord 討論串名稱上限為 100 字元，取短一點較好閱讀
+const THREAD_NAME_MAX_CHARS: usize = 50;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// 封存的討論串設定保留的天數：涵蓋月預算與 30 天用量明細，之後移除
+const ARCHIVED_RETENTION_DAYS: i64 = 35;
+
+/// 記錄 bot 開出的對話討論串最後活動時間，用來封存閒置的討論串
+#[derive(Default)]
+pub struct ThreadTracker {
+    last_active: Mutex<HashMap<u64, Instant>>,
+}
+
+impl ThreadTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn touch(&self, thread_id: u64) {
+        self.touch_at(thread_id, Instant::now());
+    }
+
+    fn touch_at(&self, thread_id: u64, at: Instant) {
+        self.last_active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(thread_id, at);
+    }
+
+    pub fn forget(&self, thread_id: u64) {
+        self.last_active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&thread_id);
+    }
+
+    /// 只更新已追蹤的討論串（例如回覆結束時）
+    pub fn refresh(&self, thread_id: u64) {
+        if let Some(at) = self
+            .last_active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&thread_id)
+        {
+            *at = Instant::now();
+        }
+    }
+
+    /// 取出閒置超過 `idle` 的討論串並停止追蹤
+    pub fn take_idle(&self, now: Instant, idle: Duration) -> Vec<u64> {
+        let mut last_active = self.last_active.lock().unwrap_or_else(|e| e.into_inner());
+        let expired: Vec<u64> = last_active
+            .iter()
+            .filter(|(_, at)| now.saturating_duration_since(**at) >= idle)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            last_active.remove(id);
+        }
+        expired
+    }
+}
+
+/// 由觸發訊息產生討論串名稱：去掉 mention，取第一個非空行
+pub fn thread_name(content: &str, fallback: &str) -> String {
+    let first_line = content
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .filter(|w| !(w.starts_with("<@") && w.ends_with('>')))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .find(|line| !line.is_empty());
+    let Some(first_line) = first_line else {
+        return fallback.to_string();
+    };
+    let mut name: String = first_line.chars().take(THREAD_NAME_MAX_CHARS).collect();
+    if name.chars().count() < first_line.chars().count() {
+        name.push('…');
+    }
+    name
+}
+
+/// 在觸發訊息上開討論串，並讓它繼承父頻道的設定
+pub async fn open_for_message(
+    http: &Http,
+    msg: &Message,
+    name: String,
+    channels: &ChannelRegistry,
+) -> anyhow::Result<ChannelId> {
+    let thread = msg
+        .channel_id
+        .create_thread_from_message(
+            http,
+            msg.id,
+            CreateThread::new(name).auto_archive_duration(AutoArchiveDuration::OneDay),
+        )
+        .await?;
+    let (parent, thread_id) = (msg.channel_id.to_string(), thread.id.to_string());
+    channels.update(|config| {
+        config.spawn_thread_entry(&parent, &thread_id);
+        Ok(())
+    }).await?;
+    info!(
+        "🧵 Opened conversation thread {} from channel {}",
+        thread.id, msg.channel_id
+    );
+    Ok(thread.id)
+}
+
+/// 討論串被封存或刪除：釋放 session 與佇列、停止追蹤，並在設定中標記封存時間
+pub async fn mark_archived(state: &crate::AppState, thread_id: u64) {
+    state.threads.forget(thread_id);
+    state.session_manager.remove_session(thread_id).await;
+    state.input_queue.clear(thread_id);
+    let id = thread_id.to_string();
+    if !state.channels.snapshot().is_conversation_thread(&id) {
+        return;
+    }
+    match state
+        .channels
+        .update(|config| Ok(config.set_thread_archived(&id, Some(chrono::Utc::now()))))
+        .await
+    {
+        Ok(true) => info!("🗄️ Conversation thread {} marked as archived", thread_id),
+        Ok(false) => {}
+        Err(e) => error!("❌ Failed to mark thread {} as archived: {}", thread_id, e),
+    }
+}
+
+/// 封存的討論串又有新訊息（Discord 會自動解除封存）：取消標記並重新追蹤
+pub async fn mark_active(state: &crate::AppState, thread_id: u64) {
+    state.threads.touch(thread_id);
+    let id = thread_id.to_string();
+    let archived = state
+        .channels
+        .get(&id)
+        .is_some_and(|e| e.archived_at.is_some());
+    if archived {
+        if let Err(e) = state
+            .channels
+            .update(|config| Ok(config.set_thread_archived(&id, None)))
+            .await
+        {
+            error!("❌ Failed to reopen thread {}: {}", thread_id, e);
+        }
+    }
+}
+
+/// 重啟後繼續追蹤尚未封存的討論串，閒置計時從啟動時算起
+pub fn resume_tracking(state: &crate::AppState) -> usize {
+    let open = state.channels.snapshot().open_conversation_threads();
+    for thread_id in &open {
+        state.threads.touch(*thread_id);
+    }
+    open.len()
+}
+
+/// 移除封存超過保留期的討論串設定
+async fn prune_archived(state: &crate::AppState) {
+    let now = chrono::Utc::now();
+    let retention = chrono::Duration::days(ARCHIVED_RETENTION_DAYS);
+    // 先在快取上確認，沒有要移除的就不寫檔
+    if state
+        .channels
+        .snapshot()
+        .expired_threads(now, retention)
+        .is_empty()
+    {
+        return;
+    }
+    match state
+        .channels
+        .update(|config| Ok(config.prune_archived_threads(now, retention)))
+        .await
+    {
+        Ok(pruned) => info!("🧹 Removed {} archived thread setting(s)", pruned.len()),
+        Err(e) => error!("❌ Failed to prune archived threads: {}", e),
+    }
+}
+
+/// 定期封存閒置的對話討論串，並釋放其 session
+pub async fn run_idle_sweeper(state: Arc<crate::AppState>, http: Arc<Http>) {
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        prune_archived(&state).await;
+        let idle_mins = state.config.read().await.threads.idle_archive_mins;
+        if idle_mins == 0 {
+            continue;
+        }
+        let idle = state
+            .threads
+            .take_idle(Instant::now(), Duration::from_secs(idle_mins * 60));
+        for thread_id in idle {
+            // 仍在回覆中的討論串不算閒置
+            if state.active_renders.lock().await.contains_key(&thread_id) {
+                state.threads.touch(thread_id);
+                continue;
+            }
+            match ChannelId::new(thread_id)
+                .edit_thread(&http, EditThread::new().archived(true))
+                .await
+            {
+                Ok(_) => info!("🗄️ Archived idle conversation thread {}", thread_id),
+                Err(e) => error!("❌ Failed to archive thread {}: {}", thread_id, e),
+            }
+            mark_archived(&state, thread_id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_name_strips_mentions_and_truncates() {
+        assert_eq!(thread_name("<@123> fix the build", "Chat"), "fix the build");
+        assert_eq!(thread_name("<@!123>", "Chat"), "Chat");
+        assert_eq!(
+            thread_name("<@1>\n\nsecond line\nthird", "Chat"),
+            "second line"
+        );
+        let long = "a".repeat(THREAD_NAME_MAX_CHARS + 5);
+        let name = thread_name(&long, "Chat");
+        assert_eq!(name.chars().count(), THREAD_NAME_MAX_CHARS + 1);
+        assert!(name.ends_with('…'));
+    }
+
+    #[test]
+    fn test_take_idle_returns_only_expired_threads_once() {
+        let tracker = ThreadTracker::new();
+        let start = Instant::now();
+        tracker.touch_at(1, start);
+        tracker.touch_at(2, start + Duration::from_secs(50));
+        tracker.refresh(3);
+
+        let now = start + Duration::from_secs(60);
+        assert_eq!(tracker.take_idle(now, Duration::from_secs(30)), vec![1]);
+        assert!(tracker.take_idle(now, Duration::from_secs(30)).is_empty());
+        assert_eq!(tracker.take_idle(now, Duration::from_secs(5)), vec![2]);
+    }
+}
@@ -0,0 +1,418 @@
+use serenity::all::{
+    ButtonStyle, ComponentInteraction, Context, CreateActionRow, CreateAttachment, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::agent::{AgentEvent, ContentType};
+use crate::composer::{Block, BlockType};
+use crate::ExecStatus;
+
+/// 每個頻道保留的逐字紀錄數量，超過時刪除最舊的
+const KEEP_PER_CHANNEL: usize = 50;
+
+pub const BUTTON_PREFIX: &str = "transcript:";
+
+/// 一個回合的完整紀錄，和 `apply_agent_event` 吃同一串事件，但不做任何截斷或 prune
+#[derive(Default)]
+pub struct Transcript {
+    blocks: Vec<Block>,
+}
+
+impl Transcript {
+    fn find(&mut self, id: Option<&str>, block_type: &BlockType) -> Option<&mut Block> {
+        match id {
+            Some(id) => self
+                .blocks
+                .iter_mut()
+                .find(|b| b.id.as_deref() == Some(id) && &b.block_type == block_type),
+            None => self
+                .blocks
+                .last_mut()
+                .filter(|b| b.id.is_none() && &b.block_type == block_type),
+        }
+    }
+
+    fn append(&mut self, id: Option<String>, block_type: BlockType, delta: &str) {
+        if delta.is_empty() {
+            return;
+        }
+        match self.find(id.as_deref(), &block_type) {
+            Some(block) => block.content.push_str(delta),
+            None => {
+                let mut block = Block::new(block_type, delta.to_string());
+                block.id = id;
+                self.blocks.push(block);
+            }
+        }
+    }
+
+    fn replace(&mut self, id: Option<String>, block_type: BlockType, content: String) {
+        if content.is_empty() {
+            return;
+        }
+        match self.find(id.as_deref(), &block_type) {
+            Some(block) => {
+                if content.len() >= block.content.len() {
+                    block.content = content;
+                }
+            }
+            None => {
+                let mut block = Block::new(block_type, content);
+                block.id = id;
+                self.blocks.push(block);
+            }
+        }
+    }
+
+    fn set_tool_call(&mut self, id: Option<String>, label: String) {
+        match self.find(id.as_deref(), &BlockType::ToolCall) {
+            Some(block) => block.label = Some(label),
+            None => self
+                .blocks
+                .push(Block::with_label(BlockType::ToolCall, label, id)),
+        }
+    }
+
+    pub fn record(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::MessageUpdate {
+                thinking,
+                text,
+                is_delta: true,
+                id,
+            } => {
+                self.append(id.clone(), BlockType::Thinking, thinking);
+                self.append(id.clone(), BlockType::Text, text);
+            }
+            AgentEvent::MessageUpdate {
+                thinking,
+                text,
+                is_delta: false,
+                id,
+            } => {
+                let think_id = id.clone().unwrap_or_else(|| "think".into());
+                let text_id = id.clone().unwrap_or_else(|| "text".into());
+                self.replace(Some(think_id), BlockType::Thinking, thinking.clone());
+                self.replace(Some(text_id), BlockType::Text, text.clone());
+            }
+            AgentEvent::ContentSync { items } => {
+                for item in items {
+                    match &item.type_ {
+                        ContentType::ToolCall(name) => {
+                            self.set_tool_call(item.id.clone(), name.clone())
+                        }
+                        ContentType::Thinking => {
+                            self.replace(item.id.clone(), BlockType::Thinking, item.content.clone())
+                        }
+                        ContentType::Text => {
+                            self.replace(item.id.clone(), BlockType::Text, item.content.clone())
+                        }
+                        ContentType::ToolOutput => self.replace(
+                            item.id.clone(),
+                            BlockType::ToolOutput,
+                            item.content.clone(),
+                        ),
+                    }
+                }
+            }
+            AgentEvent::ToolExecutionStart { id, name } => {
+                self.set_tool_call(Some(id.clone()), name.clone());
+            }
+            AgentEvent::ToolExecutionUpdate { id, output } => {
+                // 工具輸出可能是累積的完整內容，也可能變短（例如重新執行），一律以最新為準
+                match self.find(Some(id), &BlockType::ToolOutput) {
+                    Some(block) => block.content = output.clone(),
+                    None => self.replace(Some(id.clone()), BlockType::ToolOutput, output.clone()),
+                }
+            }
+            AgentEvent::AgentEnd {
+                success: false,
+                error: Some(message),
+            }
+            | AgentEvent::Error { message } => {
+                self.blocks
+                    .push(Block::new(BlockType::Text, format!("> ❌ {}", message)));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn render(&self) -> String {
+        self.blocks
+            .iter()
+            .map(Block::render_transcript)
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+pub fn file_name(turn_id: u64) -> String {
+    format!("turn-{}.md", turn_id)
+}
+
+pub fn format_file(channel_id: u64, turn_id: u64, status: &ExecStatus, body: &str) -> String {
+    let status = match status {
+        ExecStatus::Running => "running".to_string(),
+        ExecStatus::Success => "success".to_string(),
+        ExecStatus::Error(e) => format!("error: {}", e),
+    };
+    format!(
+        "# Turn {}\n\n- channel: {}\n- status: {}\n- saved_at: {}\n\n---\n\n{}\n",
+        turn_id,
+        channel_id,
+        status,
+        chrono::Utc::now().to_rfc3339(),
+        body
+    )
+}
+
+fn channel_dir(root: &Path, channel_id: u64) -> PathBuf {
+    root.join(channel_id.to_string())
+}
+
+/// 寫入 `<root>/<channel_id>/turn-<turn_id>.md`，並只保留最新的幾份
+pub async fn save(
+    root: &Path,
+    channel_id: u64,
+    turn_id: u64,
+    content: &str,
+) -> anyhow::Result<PathBuf> {
+    let dir = channel_dir(root, channel_id);
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(file_name(turn_id));
+    tokio::fs::write(&path, content).await?;
+    prune(&dir).await;
+    Ok(path)
+}
+
+pub async fn load(root: &Path, channel_id: u64, turn_id: u64) -> anyhow::Result<Vec<u8>> {
+    let path = channel_dir(root, channel_id).join(file_name(turn_id));
+    Ok(tokio::fs::read(&path).await?)
+}
+
+/// 超過上傳上限時保留開頭與結尾，中間換成省略說明；切點落在 UTF-8 字元邊界
+pub fn fit_upload(bytes: Vec<u8>, limit: usize) -> Vec<u8> {
+    if bytes.len() <= limit {
+        return bytes;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let notice = |omitted: usize| {
+        format!(
+            "\n\n… [{} bytes omitted: log exceeds the upload limit] …\n\n",
+            omitted
+        )
+    };
+    // 以最長的說明預留空間，省略的位元組數不會超過全文長度
+    let keep = limit.saturating_sub(notice(text.len()).len()) / 2;
+    let mut head = keep.min(text.len());
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - keep;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    format!("{}{}{}", &text[..head], notice(tail - head), &text[tail..]).into_bytes()
+}
+
+async fn prune(dir: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    // turn id 是 Discord snowflake，數字越大越新
+    let mut turns = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name
+            .strip_prefix("turn-")
+            .and_then(|s| s.strip_suffix(".md"))
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            turns.push((id, entry.path()));
+        }
+    }
+    if turns.len() <= KEEP_PER_CHANNEL {
+        return;
+    }
+    turns.sort_by_key(|(id, _)| *id);
+    for (_, path) in turns.iter().take(turns.len() - KEEP_PER_CHANNEL) {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            warn!("Failed to prune transcript {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn build_button_row(i18n: &crate::i18n::I18n, turn_id: u64) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new(format!(
+        "{}{}",
+        BUTTON_PREFIX, turn_id
+    ))
+    .label(i18n.get("transcript_button"))
+    .style(ButtonStyle::Secondary)])
+}
+
+pub fn parse_button_id(custom_id: &str) -> Option<u64> {
+    custom_id.strip_prefix(BUTTON_PREFIX)?.parse().ok()
+}
+
+/// 「📄 Full log」按鈕：把該回合的紀錄上傳為附件
+pub async fn handle_button(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &crate::AppState,
+) -> anyhow::Result<()> {
+    let channel_id = interaction.channel_id.get();
+    let loaded = match parse_button_id(&interaction.data.custom_id) {
+        Some(turn_id) => {
+            match load(&crate::migrate::get_transcripts_dir(), channel_id, turn_id).await {
+                Ok(bytes) => {
+                    let limit = crate::uploads::DISCORD_ATTACHMENT_BYTES as usize;
+                    if bytes.len() > limit {
+                        info!(
+                            "✂️ Transcript {} is {} bytes, truncating to the upload limit",
+                            turn_id,
+                            bytes.len()
+                        );
+                    }
+                    Some((turn_id, fit_upload(bytes, limit)))
+                }
+                Err(e) => {
+                    warn!(
+                        "⚠️ Cannot load transcript {} of channel {}: {}",
+                        turn_id, channel_id, e
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let response = match loaded {
+        Some((turn_id, bytes)) => CreateInteractionResponseMessage::new()
+            .add_file(CreateAttachment::bytes(bytes, file_name(turn_id))),
+        None => {
+            let i18n = state.i18n.read().await;
+            CreateInteractionResponseMessage::new()
+                .content(i18n.get("transcript_missing"))
+                .ephemeral(true)
+        }
+    };
+
+    interaction
+        .create_response(&ctx.http, CreateInteractionResponse::Message(response))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::ContentItem;
+    use tempfile::tempdir;
+
+    fn delta(text: &str, id: &str) -> AgentEvent {
+        AgentEvent::MessageUpdate {
+            thinking: String::new(),
+            text: text.to_string(),
+            is_delta: true,
+            id: Some(id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_record_keeps_every_block_in_order_untruncated() {
+        let mut t = Transcript::default();
+        t.record(&AgentEvent::MessageUpdate {
+            thinking: "let me look".into(),
+            text: String::new(),
+            is_delta: true,
+            id: Some("m1".into()),
+        });
+        t.record(&AgentEvent::ToolExecutionStart {
+            id: "c1".into(),
+            name: "bash: ls".into(),
+        });
+        let long_output = "x".repeat(5000);
+        t.record(&AgentEvent::ToolExecutionUpdate {
+            id: "c1".into(),
+            output: long_output.clone(),
+        });
+        for i in 0..15 {
+            t.record(&delta(&format!("part {} ", i), &format!("t{}", i)));
+        }
+
+        let out = t.render();
+        let think = out.find("let me look").expect("thinking");
+        let tool = out.find("### bash: ls").expect("tool call");
+        let output = out.find(&long_output).expect("full tool output");
+        assert!(think < tool && tool < output);
+        assert!(out.contains("part 0") && out.contains("part 14"));
+    }
+
+    #[test]
+    fn test_record_sync_and_errors() {
+        let mut t = Transcript::default();
+        t.record(&delta("hel", "a"));
+        t.record(&delta("lo", "a"));
+        t.record(&AgentEvent::ContentSync {
+            items: vec![
+                ContentItem {
+                    type_: ContentType::Text,
+                    content: "hello".into(),
+                    id: Some("a".into()),
+                },
+                ContentItem {
+                    type_: ContentType::ToolCall("grep".into()),
+                    content: String::new(),
+                    id: Some("g".into()),
+                },
+            ],
+        });
+        t.record(&AgentEvent::Error {
+            message: "boom".into(),
+        });
+        assert_eq!(t.render(), "hello\n\n### grep\n\n> ❌ boom");
+    }
+
+    #[tokio::test]
+    async fn test_save_load_and_prune() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        for turn in 0..(KEEP_PER_CHANNEL as u64 + 3) {
+            save(dir.path(), 9, turn, &format!("turn {}", turn)).await?;
+        }
+        assert!(load(dir.path(), 9, 0).await.is_err());
+        assert_eq!(load(dir.path(), 9, 3).await?, b"turn 3");
+        assert!(load(dir.path(), 8, 3).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit_upload_keeps_head_and_tail_within_limit() {
+        let small = b"short log".to_vec();
+        assert_eq!(fit_upload(small.clone(), 100), small);
+
+        let text = format!("{}{}{}", "開頭".repeat(100), "x".repeat(1000), "結尾".repeat(100));
+        let out = String::from_utf8(fit_upload(text.clone().into_bytes(), 500)).expect("utf8");
+        assert!(out.len() <= 500);
+        assert!(out.starts_with("開頭") && out.ends_with("結尾"));
+        assert!(out.contains("bytes omitted"));
+    }
+
+    #[test]
+    fn test_button_id_round_trip_and_file_header() {
+        assert_eq!(parse_button_id("transcript:123"), Some(123));
+        assert_eq!(parse_button_id("transcript:abc"), None);
+        let file = format_file(1, 2, &ExecStatus::Error("bad".into()), "body");
+        assert!(file.starts_with("# Turn 2\n"));
+        assert!(file.contains("- status: error: bad"));
+        assert!(file.trim_end().ends_with("body"));
+    }
+}
@@ -1,15 +1,35 @@
 use crate::agent::UploadedFile;
 use crate::migrate;
-use serenity::all::Attachment;
+use serenity::all::{Attachment, CreateAttachment, CreateEmbed, EditMessage, Message};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::Mutex;
 use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Discord 一般伺服器的單檔上傳上限
+pub(crate) const DISCORD_ATTACHMENT_BYTES: u64 = 10 * 1024 * 1024;
+/// Discord 單則訊息最多 10 個附件
+const MAX_OUTBOUND_FILES: usize = 10;
+
+/// agent 在回覆中要求附上檔案的標記：`[[attach:<path>]]`
+const ATTACH_MARKER_PREFIX: &str = "[[attach:";
+const ATTACH_MARKER_SUFFIX: &str = "]]";
+
+/// 回合結束後要上傳回 Discord 的檔案
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboundFile {
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub path: PathBuf,
+}
+
 pub struct UploadManager {
     client: reqwest::Client,
     root: PathBuf,
+    /// agent 產生檔案的放置處，和 uploads 同層：`outbox/<channel_id>/`
+    outbox_root: PathBuf,
     max_file_bytes: u64,
     ttl: Duration,
     cleanup_interval: Duration,
@@ -20,9 +40,12 @@ impl UploadManager {
     pub fn new(max_file_bytes: u64, ttl: Duration, cleanup_interval: Duration) -> anyhow::Result<Self> {
         let root = migrate::get_uploads_dir();
         std::fs::create_dir_all(&root)?;
+        let outbox_root = migrate::get_outbox_dir();
+        std::fs::create_dir_all(&outbox_root)?;
         Ok(Self {
             client: reqwest::Client::new(),
             root,
+            outbox_root,
             max_file_bytes,
             ttl,
             cleanup_interval,
@@ -54,7 +77,10 @@ impl UploadManager {
             }
 
             match self.download_one(channel_id, attachment).await {
-                Ok(file) => out.push(file),
+                Ok(file) => {
+                    crate::metrics::get().upload_staged(file.size);
+                    out.push(file)
+                }
                 Err(e) => warn!("Failed to stage attachment '{}': {}", attachment.filename, e),
             }
         }
@@ -62,6 +88,97 @@ impl UploadManager {
         out
     }
 
+    /// 頻道的 outbox 目錄，回合開始前先建立好讓 agent 可以直接寫入
+    pub async fn prepare_outbox(&self, channel_id: u64) -> PathBuf {
+        let dir = self.outbox_root.join(channel_id.to_string());
+        if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+            warn!("Failed to create outbox {}: {}", dir.display(), e);
+        }
+        dir
+    }
+
+    /// 收集本回合要附上的檔案：outbox 中 `since` 之後新增或修改的檔案，以及回覆中
+    /// `[[attach:...]]` 標記指到的檔案。標記只能指向 outbox 內，避免把設定檔等外流。
+    pub async fn collect_outbox(
+        &self,
+        channel_id: u64,
+        since: SystemTime,
+        text: &str,
+    ) -> Vec<OutboundFile> {
+        let dir = self.outbox_root.join(channel_id.to_string());
+        let Ok(canonical_dir) = tokio::fs::canonicalize(&dir).await else {
+            return Vec::new();
+        };
+
+        let mut candidates = Vec::new();
+        if let Ok(mut entries) = tokio::fs::read_dir(&dir).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                if metadata.is_file() && modified >= since {
+                    candidates.push(entry.path());
+                }
+            }
+        }
+        candidates.sort();
+        for marker in extract_attach_markers(text) {
+            let path = Path::new(&marker);
+            candidates.push(if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                dir.join(path)
+            });
+        }
+
+        let limit = self.max_file_bytes.min(DISCORD_ATTACHMENT_BYTES);
+        let mut out: Vec<OutboundFile> = Vec::new();
+        for candidate in candidates {
+            let Ok(path) = tokio::fs::canonicalize(&candidate).await else {
+                warn!("Outbox file not found: {}", candidate.display());
+                continue;
+            };
+            if !path.starts_with(&canonical_dir) {
+                warn!("Refusing to attach file outside outbox: {}", path.display());
+                continue;
+            }
+            if out.iter().any(|f| f.path == path) {
+                continue;
+            }
+            let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            if metadata.len() > limit {
+                warn!(
+                    "Skipping outbox file '{}' ({} bytes > max {} bytes)",
+                    path.display(),
+                    metadata.len(),
+                    limit
+                );
+                continue;
+            }
+            if out.len() >= MAX_OUTBOUND_FILES {
+                warn!("Too many outbox files, skipping {}", path.display());
+                continue;
+            }
+            let name = path
+                .file_name()
+                .map(|n| sanitize_filename(&n.to_string_lossy()))
+                .unwrap_or_else(|| "file.bin".to_string());
+            out.push(OutboundFile {
+                mime: guess_mime_from_name(&name),
+                name,
+                size: metadata.len(),
+                path,
+            });
+        }
+        out
+    }
+
     async fn maybe_cleanup(&self) {
         let mut lock = self.last_cleanup.lock().await;
         let should_run = match *lock {
@@ -82,7 +199,7 @@ impl UploadManager {
     }
 
     async fn cleanup_expired(&self) -> anyhow::Result<()> {
-        let mut stack = vec![self.root.clone()];
+        let mut stack = vec![self.root.clone(), self.outbox_root.clone()];
         let now = SystemTime::now();
         let mut removed = 0usize;
 
@@ -106,10 +223,8 @@ impl UploadManager {
                     .duration_since(modified)
                     .unwrap_or_else(|_| Duration::from_secs(0));
 
-                if age > self.ttl {
-                    if tokio::fs::remove_file(&path).await.is_ok() {
-                        removed += 1;
-                    }
+                if age > self.ttl && tokio::fs::remove_file(&path).await.is_ok() {
+                    removed += 1;
                 }
             }
         }
@@ -122,7 +237,7 @@ impl UploadManager {
     }
 
     async fn remove_empty_dirs(&self) -> anyhow::Result<()> {
-        let mut stack = vec![self.root.clone()];
+        let mut stack = vec![self.root.clone(), self.outbox_root.clone()];
         let mut dirs = Vec::new();
 
         while let Some(dir) = stack.pop() {
@@ -140,7 +255,7 @@ impl UploadManager {
 
         dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
         for dir in dirs {
-            if dir == self.root {
+            if dir == self.root || dir == self.outbox_root {
                 continue;
             }
             if is_dir_empty(&dir).await? {
@@ -214,6 +329,81 @@ fn sanitize_filename(name: &str) -> String {
     }
 }
 
+/// 把檔案附到回覆訊息上；第一張圖片同時當作 embed 的預覽圖
+pub async fn attach_outbound_files(
+    http: &serenity::http::Http,
+    msg: &mut Message,
+    embed: CreateEmbed,
+    files: Vec<OutboundFile>,
+) {
+    let mut edit = EditMessage::new();
+    let mut embed = Some(embed);
+    for file in &files {
+        let bytes = match tokio::fs::read(&file.path).await {
+            Ok(b) => b,
+            Err(e) => {
+                warn!("Failed to read outbox file {}: {}", file.path.display(), e);
+                continue;
+            }
+        };
+        if file.mime.starts_with("image/") {
+            if let Some(e) = embed.take() {
+                edit = edit.embed(e.image(format!("attachment://{}", file.name)));
+            }
+        }
+        edit = edit.new_attachment(CreateAttachment::bytes(bytes, file.name.clone()));
+    }
+
+    match msg.edit(http, edit).await {
+        Ok(_) => info!("📎 Attached {} outbox file(s) to message {}", files.len(), msg.id),
+        Err(e) => warn!("Failed to attach outbox files: {}", e),
+    }
+}
+
+/// 取出回覆中所有 `[[attach:<path>]]` 標記的路徑
+pub fn extract_attach_markers(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(ATTACH_MARKER_PREFIX) {
+        let after = &rest[start + ATTACH_MARKER_PREFIX.len()..];
+        let Some(end) = after.find(ATTACH_MARKER_SUFFIX) else {
+            break;
+        };
+        let path = after[..end].trim();
+        if !path.is_empty() && !path.contains('\n') {
+            out.push(path.to_string());
+        }
+        rest = &after[end + ATTACH_MARKER_SUFFIX.len()..];
+    }
+    out
+}
+
+/// 顯示給使用者的文字不需要標記本身
+pub fn strip_attach_markers(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(ATTACH_MARKER_PREFIX) {
+        let after = &rest[start + ATTACH_MARKER_PREFIX.len()..];
+        let Some(end) = after.find(ATTACH_MARKER_SUFFIX) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        rest = &after[end + ATTACH_MARKER_SUFFIX.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 給新 session 的提示，告訴 agent 如何把檔案交給使用者
+pub fn outbox_prompt_hint(outbox_dir: &Path) -> String {
+    format!(
+        "To give the user a file (image, patch, CSV, report...), save it in {} \
+         or write the marker [[attach:<file name in that directory>]] in your reply; \
+         it will be attached to your Discord message.",
+        outbox_dir.display()
+    )
+}
+
 fn guess_mime_from_name(name: &str) -> String {
     let lower = name.to_ascii_lowercase();
     if lower.ends_with(".png") {
@@ -231,6 +421,24 @@ fn guess_mime_from_name(name: &str) -> String {
     if lower.ends_with(".pdf") {
         return "application/pdf".to_string();
     }
+    if lower.ends_with(".svg") {
+        return "image/svg+xml".to_string();
+    }
+    if lower.ends_with(".csv") {
+        return "text/csv".to_string();
+    }
+    if lower.ends_with(".patch") || lower.ends_with(".diff") {
+        return "text/x-diff".to_string();
+    }
+    if lower.ends_with(".md") {
+        return "text/markdown".to_string();
+    }
+    if lower.ends_with(".txt") || lower.ends_with(".log") {
+        return "text/plain".to_string();
+    }
+    if lower.ends_with(".json") {
+        return "application/json".to_string();
+    }
     "application/octet-stream".to_string()
 }
 
@@ -243,6 +451,7 @@ mod tests {
     fn test_manager(root: PathBuf, ttl: Duration, cleanup_interval: Duration) -> UploadManager {
         UploadManager {
             client: reqwest::Client::new(),
+            outbox_root: root.join("outbox"),
             root,
             max_file_bytes: 1024 * 1024,
             ttl,
@@ -265,6 +474,8 @@ mod tests {
         assert_eq!(guess_mime_from_name("a.gif"), "image/gif");
         assert_eq!(guess_mime_from_name("a.webp"), "image/webp");
         assert_eq!(guess_mime_from_name("a.pdf"), "application/pdf");
+        assert_eq!(guess_mime_from_name("data.CSV"), "text/csv");
+        assert_eq!(guess_mime_from_name("fix.patch"), "text/x-diff");
         assert_eq!(
             guess_mime_from_name("unknown.bin"),
             "application/octet-stream"
@@ -306,4 +517,45 @@ mod tests {
         let second = *manager.last_cleanup.lock().await;
         assert_eq!(first, second);
     }
+
+    #[test]
+    fn test_attach_markers_extract_and_strip() {
+        let text = "Done.\n[[attach: chart.png ]] and [[attach:out/report.csv]] [[attach:";
+        assert_eq!(
+            extract_attach_markers(text),
+            vec!["chart.png".to_string(), "out/report.csv".to_string()]
+        );
+        assert_eq!(strip_attach_markers(text), "Done.\n and  [[attach:");
+    }
+
+    #[tokio::test]
+    async fn test_collect_outbox_picks_new_files_and_markers_inside_outbox_only() {
+        let dir = tempdir().expect("tempdir");
+        let manager = test_manager(
+            dir.path().to_path_buf(),
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        let outbox = manager.prepare_outbox(5).await;
+        tokio::fs::write(outbox.join("old.txt"), "old").await.expect("write");
+        let since = SystemTime::now() + Duration::from_secs(1);
+        // 標記可以指到 since 之前就存在的檔案；outbox 外的檔案一律拒絕
+        tokio::fs::write(dir.path().join("secret.toml"), "token").await.expect("write");
+
+        let text = format!(
+            "[[attach:old.txt]] [[attach:../../secret.toml]] [[attach:{}]] [[attach:missing.png]]",
+            dir.path().join("secret.toml").display()
+        );
+        let files = manager.collect_outbox(5, since, &text).await;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "old.txt");
+        assert_eq!(files[0].mime, "text/plain");
+        assert_eq!(files[0].size, 3);
+
+        let all_new = manager
+            .collect_outbox(5, SystemTime::UNIX_EPOCH, "[[attach:old.txt]]")
+            .await;
+        assert_eq!(all_new.len(), 1);
+        assert!(manager.collect_outbox(6, since, "").await.is_empty());
+    }
 }
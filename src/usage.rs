@@ -0,0 +1,499 @@
+use crate::agent::TurnUsage;
+use crate::migrate;
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use tracing::{error, warn};
+
+/// 超過這個天數的每日統計會在寫入與啟動整理時清掉
+const RETENTION_DAYS: i64 = 400;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UsageTotals {
+    #[serde(default)]
+    pub turns: u64,
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    #[serde(default)]
+    pub cache_write_tokens: u64,
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    /// 計入額度的 token：輸入加輸出，不含便宜的快取讀取
+    pub fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+
+    pub fn add(&mut self, other: &UsageTotals) {
+        self.turns += other.turns;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cost += other.cost;
+    }
+
+    fn add_turn(&mut self, usage: &TurnUsage) {
+        self.turns += 1;
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+        self.cache_read_tokens += usage.cache_read_tokens;
+        self.cache_write_tokens += usage.cache_write_tokens;
+        self.cost += usage.cost;
+    }
+}
+
+/// 某天、某頻道、某使用者、某模型的累計用量
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UsageBucket {
+    pub day: NaiveDate,
+    pub channel_id: u64,
+    /// 重啟後接回的回合不知道是誰送出的
+    #[serde(default)]
+    pub user_id: Option<u64>,
+    pub model: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+impl UsageBucket {
+    /// 一個回合的用量
+    fn turn(
+        day: NaiveDate,
+        channel_id: u64,
+        user_id: Option<u64>,
+        model: &str,
+        usage: &TurnUsage,
+    ) -> Self {
+        let mut totals = UsageTotals::default();
+        totals.add_turn(usage);
+        Self {
+            day,
+            channel_id,
+            user_id,
+            model: model.to_string(),
+            totals,
+        }
+    }
+}
+
+/// 用量帳本，以 UTC 日期分桶
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UsageLedger {
+    #[serde(default)]
+    pub buckets: Vec<UsageBucket>,
+}
+
+impl UsageLedger {
+    pub fn record(
+        &mut self,
+        day: NaiveDate,
+        channel_id: u64,
+        user_id: Option<u64>,
+        model: &str,
+        usage: &TurnUsage,
+    ) {
+        self.merge(UsageBucket::turn(day, channel_id, user_id, model, usage));
+        self.prune(day);
+    }
+
+    /// 併入同一天、頻道、使用者與模型的桶，沒有就新增
+    fn merge(&mut self, bucket: UsageBucket) {
+        let existing = self.buckets.iter_mut().find(|b| {
+            b.day == bucket.day
+                && b.channel_id == bucket.channel_id
+                && b.user_id == bucket.user_id
+                && b.model == bucket.model
+        });
+        match existing {
+            Some(b) => b.totals.add(&bucket.totals),
+            None => self.buckets.push(bucket),
+        }
+    }
+
+    fn prune(&mut self, today: NaiveDate) {
+        let oldest = today - chrono::Duration::days(RETENTION_DAYS);
+        self.buckets.retain(|b| b.day > oldest);
+    }
+
+    /// since（含）之後符合條件的合計
+    pub fn totals(&self, since: NaiveDate, filter: impl Fn(&UsageBucket) -> bool) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        for bucket in self.buckets.iter().filter(|b| b.day >= since && filter(b)) {
+            totals.add(&bucket.totals);
+        }
+        totals
+    }
+
+    /// 依 key 分組的合計，花費高的在前（同花費時 token 多的在前）
+    pub fn breakdown<K: Ord + Clone>(
+        &self,
+        since: NaiveDate,
+        filter: impl Fn(&UsageBucket) -> bool,
+        key: impl Fn(&UsageBucket) -> K,
+    ) -> Vec<(K, UsageTotals)> {
+        let mut groups: std::collections::BTreeMap<K, UsageTotals> = Default::default();
+        for bucket in self.buckets.iter().filter(|b| b.day >= since && filter(b)) {
+            groups.entry(key(bucket)).or_default().add(&bucket.totals);
+        }
+        let mut rows: Vec<(K, UsageTotals)> = groups.into_iter().collect();
+        rows.sort_by(|a, b| {
+            b.1.cost
+                .total_cmp(&a.1.cost)
+                .then_with(|| b.1.tokens().cmp(&a.1.tokens()))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        rows
+    }
+}
+
+/// 1234 -> 1.2k、2500000 -> 2.5M
+pub fn format_tokens(n: u64) -> String {
+    match n {
+        0..=999 => n.to_string(),
+        1_000..=999_999 => format!("{:.1}k", n as f64 / 1_000.0),
+        _ => format!("{:.1}M", n as f64 / 1_000_000.0),
+    }
+}
+
+pub fn format_cost(cost: f64) -> String {
+    if cost > 0.0 && cost < 0.01 {
+        format!("${:.4}", cost)
+    } else {
+        format!("${:.2}", cost)
+    }
+}
+
+enum WriteOp {
+    Append(UsageBucket),
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+/// 用量帳本的記憶體快取，和 state.json 分開存在 usage.jsonl：
+/// 每個回合由背景執行緒附加一行，啟動時合併成每桶一行並清掉過期的統計
+pub struct UsageStore {
+    ledger: RwLock<Arc<UsageLedger>>,
+    writer: mpsc::Sender<WriteOp>,
+    /// 已放行、還沒記帳的 prompt：reservation ID -> (頻道, 使用者)
+    in_flight: Mutex<HashMap<u64, (u64, Option<u64>)>>,
+    next_reservation: AtomicU64,
+}
+
+impl UsageStore {
+    pub fn new() -> Result<Self> {
+        Self::load(migrate::get_usage_path())
+    }
+
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut ledger = read_ledger(&path)?;
+        ledger.prune(chrono::Utc::now().date_naive());
+        compact(&path, &ledger)?;
+        let (writer, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("usage-writer".into())
+            .spawn(move || run_writer(&path, rx))?;
+        Ok(Self {
+            ledger: RwLock::new(Arc::new(ledger)),
+            writer,
+            in_flight: Mutex::new(HashMap::new()),
+            next_reservation: AtomicU64::new(0),
+        })
+    }
+
+    /// 先記進快取，寫檔交給背景執行緒；寫入失敗只記錄錯誤
+    pub fn record(&self, channel_id: u64, user_id: Option<u64>, model: &str, usage: &TurnUsage) {
+        let today = chrono::Utc::now().date_naive();
+        let bucket = UsageBucket::turn(today, channel_id, user_id, model, usage);
+        {
+            let mut ledger = self.ledger.write().unwrap_or_else(|e| e.into_inner());
+            let ledger = Arc::make_mut(&mut ledger);
+            ledger.merge(bucket.clone());
+            ledger.prune(today);
+        }
+        if self.writer.send(WriteOp::Append(bucket)).is_err() {
+            error!(
+                "❌ Usage writer stopped, turn in channel {} not saved",
+                channel_id
+            );
+        }
+    }
+
+    pub fn ledger(&self) -> Arc<UsageLedger> {
+        Arc::clone(&self.ledger.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// 先佔一個 prompt，回合記帳前持有
+    pub fn reserve(self: &Arc<Self>, channel_id: u64, user_id: Option<u64>) -> Reservation {
+        let id = self.next_reservation.fetch_add(1, Ordering::Relaxed);
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, (channel_id, user_id));
+        Reservation {
+            store: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// 佔住的 prompt，各算一個今天、沒有 token 的回合；預算判定時加在帳本上
+    pub fn in_flight(&self) -> UsageLedger {
+        let today = chrono::Utc::now().date_naive();
+        let turn = TurnUsage::default();
+        let mut ledger = UsageLedger::default();
+        let in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        for (channel_id, user_id) in in_flight.values() {
+            ledger.merge(UsageBucket::turn(today, *channel_id, *user_id, "", &turn));
+        }
+        ledger
+    }
+
+    /// 等待已記錄的用量寫完（關機用）
+    pub async fn flush(&self) -> Result<()> {
+        let (done, wait) = tokio::sync::oneshot::channel();
+        self.writer
+            .send(WriteOp::Flush(done))
+            .map_err(|_| anyhow::anyhow!("usage writer stopped"))?;
+        wait.await?;
+        Ok(())
+    }
+}
+
+/// 一行一桶；最後一行可能在當機時只寫了一半，讀不懂的行略過
+fn read_ledger(path: &Path) -> Result<UsageLedger> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(UsageLedger::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut ledger = UsageLedger::default();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(bucket) => ledger.merge(bucket),
+            Err(e) => warn!("⚠️ Skipping line {} of {}: {}", i + 1, path.display(), e),
+        }
+    }
+    Ok(ledger)
+}
+
+fn compact(path: &Path, ledger: &UsageLedger) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        for bucket in &ledger.buckets {
+            writeln!(file, "{}", serde_json::to_string(bucket)?)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn append(path: &Path, bucket: &UsageBucket) -> Result<()> {
+    let line = format!("{}\n", serde_json::to_string(bucket)?);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn run_writer(path: &Path, rx: mpsc::Receiver<WriteOp>) {
+    for op in rx {
+        match op {
+            WriteOp::Append(bucket) => {
+                if let Err(e) = append(path, &bucket) {
+                    error!("❌ Failed to record usage: {}", e);
+                }
+            }
+            WriteOp::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// 通過預算檢查、還沒記帳的 prompt；drop 時釋放
+pub struct Reservation {
+    store: Arc<UsageStore>,
+    id: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.store
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// 一個回合記一筆：收到 Usage 時累加，drop 時寫入。
+/// 回合被搶佔（task 被 abort）也會 drop，沒回報用量的後端同樣算一次 prompt
+pub struct TurnRecorder {
+    store: Arc<UsageStore>,
+    channel_id: u64,
+    user_id: Option<u64>,
+    backend: String,
+    usage: TurnUsage,
+    /// 進行中的回合也算進額度；記帳後才隨欄位一起釋放
+    _reservation: Reservation,
+}
+
+impl TurnRecorder {
+    /// 有預算檢查時先佔的 reservation 就接手，沒有（例如重啟後接回的回合）才另外佔一個
+    pub fn new(
+        store: Arc<UsageStore>,
+        channel_id: u64,
+        user_id: Option<u64>,
+        backend: String,
+        reservation: Option<Reservation>,
+    ) -> Self {
+        Self {
+            _reservation: reservation.unwrap_or_else(|| store.reserve(channel_id, user_id)),
+            store,
+            channel_id,
+            user_id,
+            backend,
+            usage: TurnUsage::default(),
+        }
+    }
+
+    pub fn add(&mut self, usage: TurnUsage) {
+        self.usage.add(usage);
+    }
+}
+
+impl Drop for TurnRecorder {
+    fn drop(&mut self) {
+        let model = self.usage.model.as_deref().unwrap_or(&self.backend);
+        self.store
+            .record(self.channel_id, self.user_id, model, &self.usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, d).expect("date")
+    }
+
+    fn usage(input: u64, output: u64, cost: f64) -> TurnUsage {
+        TurnUsage {
+            input_tokens: input,
+            output_tokens: output,
+            cost,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ledger_buckets_and_breakdowns() {
+        let mut ledger = UsageLedger::default();
+        ledger.record(day(1), 7, Some(1), "a/m1", &usage(100, 10, 0.01));
+        ledger.record(day(1), 7, Some(1), "a/m1", &usage(50, 5, 0.02));
+        ledger.record(day(9), 7, Some(2), "a/m2", &usage(10, 1, 0.5));
+        ledger.record(day(9), 8, Some(1), "a/m1", &usage(1, 1, 0.0));
+        assert_eq!(ledger.buckets.len(), 3);
+
+        let all = ledger.totals(day(1), |b| b.channel_id == 7);
+        assert_eq!(
+            (all.turns, all.input_tokens, all.output_tokens),
+            (3, 160, 16)
+        );
+        let recent = ledger.totals(day(2), |b| b.channel_id == 7);
+        assert_eq!(recent.turns, 1);
+
+        let by_model = ledger.breakdown(day(1), |b| b.channel_id == 7, |b| b.model.clone());
+        let models: Vec<&str> = by_model.iter().map(|(m, _)| m.as_str()).collect();
+        assert_eq!(models, ["a/m2", "a/m1"]);
+        let by_user = ledger.breakdown(day(1), |_| true, |b| b.user_id);
+        assert_eq!(by_user[0].0, Some(2));
+        assert_eq!(by_user[1].1.turns, 3);
+
+        // 過期的每日統計在下次寫入時清掉
+        ledger.record(
+            day(1) + chrono::Duration::days(RETENTION_DAYS),
+            7,
+            None,
+            "x",
+            &usage(1, 1, 0.0),
+        );
+        assert_eq!(ledger.buckets.len(), 3);
+        assert!(ledger.buckets.iter().all(|b| b.day >= day(9)));
+    }
+
+    #[tokio::test]
+    async fn test_store_appends_usage_and_compacts_on_load() {
+        let dir = tempdir().expect("tempdir");
+        let path = dir.path().join("usage.jsonl");
+        let store = UsageStore::load(path.clone()).expect("load");
+        store.record(7, Some(1), "pi", &usage(3, 4, 0.25));
+        store.record(7, Some(1), "pi", &usage(3, 4, 0.25));
+        store.record(8, None, "pi", &usage(1, 1, 0.0));
+        assert_eq!(store.ledger().buckets[0].totals.turns, 2);
+        store.flush().await.expect("flush");
+        drop(store);
+
+        // 每個回合一行；當機時寫到一半的行與過期的統計在載入時清掉
+        let old = UsageBucket::turn(NaiveDate::MIN, 7, None, "pi", &usage(1, 1, 0.0));
+        let mut file = OpenOptions::new().append(true).open(&path).expect("open");
+        writeln!(file, "{}", serde_json::to_string(&old).expect("json")).expect("write");
+        write!(file, "{{\"day\":").expect("write");
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).expect("read").lines().count(), 5);
+
+        let store = UsageStore::load(path.clone()).expect("reload");
+        let ledger = store.ledger();
+        assert_eq!(ledger.buckets.len(), 2);
+        assert_eq!(ledger.buckets[0].totals.turns, 2);
+        assert_eq!(ledger.buckets[0].totals.cost, 0.5);
+        assert_eq!(fs::read_to_string(&path).expect("read").lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_turn_recorder_counts_turns_without_usage() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(UsageStore::load(dir.path().join("usage.jsonl")).expect("load"));
+        drop(TurnRecorder::new(
+            Arc::clone(&store),
+            7,
+            Some(1),
+            "kilo".into(),
+            None,
+        ));
+        let mut recorder = TurnRecorder::new(Arc::clone(&store), 7, Some(1), "kilo".into(), None);
+        recorder.add(TurnUsage {
+            model: Some("p/m".into()),
+            ..usage(5, 5, 0.1)
+        });
+        recorder.add(usage(1, 1, 0.1));
+        drop(recorder);
+
+        let ledger = store.ledger();
+        let by_model = ledger.breakdown(NaiveDate::MIN, |_| true, |b| b.model.clone());
+        assert_eq!(by_model[0].0, "p/m");
+        assert_eq!((by_model[0].1.turns, by_model[0].1.tokens()), (1, 12));
+        assert_eq!((by_model[1].0.as_str(), by_model[1].1.turns), ("kilo", 1));
+    }
+}